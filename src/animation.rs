@@ -0,0 +1,547 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+use crate::graphics::{CanvasState, WebGlCanvas};
+use crate::scene::{Scene, SceneState};
+
+// How values get from one keyframe's to the next one's, set on the later
+// keyframe, or from a tween's start to its end.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+  Linear,
+  // Quadratic, starting slowly.
+  EaseIn,
+  // Quadratic, ending slowly.
+  EaseOut,
+  // Smoothstep, slow at both ends.
+  EaseInOut,
+  // Holds the earlier values and jumps at the end.
+  Step,
+  EaseInCubic,
+  EaseOutCubic,
+  EaseInOutCubic,
+  // Quarter and half cosine waves, gentler than the quadratics, e.g. for
+  // contrast ramps.
+  EaseInSine,
+  EaseOutSine,
+  EaseInOutSine,
+  // Overshoots the end by about a tenth and settles back.
+  EaseOutBack,
+}
+
+impl Easing {
+  // Progress for a fraction `t` of the way, both 0 at the start and 1 at
+  // the end.
+  fn apply(self, t: f32) -> f32 {
+    match self {
+      Easing::Linear => t,
+      Easing::EaseIn => t * t,
+      Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+      Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+      Easing::Step if t < 1.0 => 0.0,
+      Easing::Step => 1.0,
+      Easing::EaseInCubic => t * t * t,
+      Easing::EaseOutCubic => 1.0 - (1.0 - t).powi(3),
+      Easing::EaseInOutCubic if t < 0.5 => 4.0 * t * t * t,
+      Easing::EaseInOutCubic => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+      Easing::EaseInSine => 1.0 - (t * PI / 2.0).cos(),
+      Easing::EaseOutSine => (t * PI / 2.0).sin(),
+      Easing::EaseInOutSine => (1.0 - (t * PI).cos()) / 2.0,
+      Easing::EaseOutBack => {
+        let overshoot = 1.70158;
+        let u = t - 1.0;
+        1.0 + u * u * ((overshoot + 1.0) * u + overshoot)
+      }
+    }
+  }
+}
+
+fn interpolate(from: &[f32], to: &[f32], progress: f32) -> Vec<f32> {
+  from.iter().zip(to).map(|(a, b)| a + (b - a) * progress).collect()
+}
+
+// What of a scene node a track drives, with the values its keyframes take.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeProperty {
+  // x, y.
+  Position,
+  // Radians, clockwise.
+  Rotation,
+  // x, y.
+  Scale,
+  Z,
+  // r, g, b, a from 0 to 1, as `Color` components. Meshes, sprites and
+  // dot clouds only.
+  Color,
+  // The rest are of gratings only, one value each, in `Grating`'s units.
+  GratingPhase,
+  GratingContrast,
+  GratingOrientation,
+  GratingFrequency,
+  GratingRadius,
+}
+
+impl NodeProperty {
+  fn components(self) -> usize {
+    match self {
+      NodeProperty::Position | NodeProperty::Scale => 2,
+      NodeProperty::Color => 4,
+      _ => 1,
+    }
+  }
+}
+
+#[derive(Clone)]
+enum Target {
+  Uniform { canvas: Rc<RefCell<CanvasState>>, name: String },
+  Node { scene: Rc<RefCell<SceneState>>, node: usize, property: NodeProperty },
+}
+
+impl Target {
+  fn apply(&self, values: &[f32]) {
+    match self {
+      Target::Uniform { canvas, name } => {
+        let mut canvas = canvas.borrow_mut();
+        let program = canvas.program_mut();
+        match *values {
+          [x] => program.set_f32(name, x),
+          [x, y] => program.set_vec2(name, x, y),
+          [x, y, z] => program.set_vec3(name, x, y, z),
+          [x, y, z, w] => program.set_vec4(name, x, y, z, w),
+          _ => {}
+        }
+      }
+      // The node may have been removed since; the target is then gone.
+      Target::Node { scene, node, property } => {
+        let _ = scene.borrow_mut().animate(*node, *property, values);
+      }
+    }
+  }
+
+  fn is(&self, other: &Target) -> bool {
+    match (self, other) {
+      (Target::Uniform { canvas, name }, Target::Uniform { canvas: other_canvas, name: other_name }) => {
+        Rc::ptr_eq(canvas, other_canvas) && name == other_name
+      }
+      (
+        Target::Node { scene, node, property },
+        Target::Node {
+          scene: other_scene,
+          node: other_node,
+          property: other_property,
+        },
+      ) => Rc::ptr_eq(scene, other_scene) && node == other_node && property == other_property,
+      _ => false,
+    }
+  }
+}
+
+struct Keyframe {
+  time: f64,
+  values: Vec<f32>,
+  easing: Easing,
+}
+
+struct Track {
+  target: Target,
+  components: usize,
+  // By time, at most one per time.
+  keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+  // Values at `time` seconds, held before the first and after the last
+  // keyframe.
+  fn sample(&self, time: f64) -> Option<Vec<f32>> {
+    let next = self.keyframes.iter().position(|keyframe| keyframe.time > time);
+    let (from, to) = match next {
+      Some(0) => return self.keyframes.first().map(|keyframe| keyframe.values.clone()),
+      Some(next) => (&self.keyframes[next - 1], &self.keyframes[next]),
+      None => return self.keyframes.last().map(|keyframe| keyframe.values.clone()),
+    };
+    let progress = to.easing.apply(((time - from.time) / (to.time - from.time)) as f32);
+    Some(interpolate(&from.values, &to.values, progress))
+  }
+}
+
+pub(crate) struct TimelineState {
+  tracks: Vec<Track>,
+  playing: bool,
+  looping: bool,
+  // Seconds into the timeline.
+  position: f64,
+  // Timestamp of the last frame advanced while playing, in milliseconds.
+  last_frame: Option<f64>,
+  // Set by `seek`, so a paused timeline still shows where it was moved to.
+  dirty: bool,
+}
+
+impl TimelineState {
+  fn duration(&self) -> f64 {
+    self.tracks.iter().filter_map(|track| track.keyframes.last()).map(|keyframe| keyframe.time).fold(0.0, f64::max)
+  }
+}
+
+// Moves a playing timeline on to the frame at `time`, in milliseconds like
+// the timestamps `render` is given, and sets every track's values for it.
+pub(crate) fn advance(timeline: &Rc<RefCell<TimelineState>>, time: f64) {
+  let mut timeline = timeline.borrow_mut();
+  if timeline.playing {
+    if let Some(last) = timeline.last_frame {
+      timeline.position += (time - last).max(0.0) / 1000.0;
+    }
+    timeline.last_frame = Some(time);
+    let duration = timeline.duration();
+    if timeline.position >= duration {
+      if timeline.looping && duration > 0.0 {
+        timeline.position %= duration;
+      } else {
+        timeline.position = duration;
+        timeline.playing = false;
+      }
+    }
+  } else if !timeline.dirty {
+    return;
+  }
+  timeline.dirty = false;
+
+  let position = timeline.position;
+  for track in &timeline.tracks {
+    if let Some(values) = track.sample(position) {
+      track.target.apply(&values);
+    }
+  }
+}
+
+// Keyframed uniforms and scene node properties, played back by the
+// `RenderLoop` or `FrameExporter` it is attached to, which sets the values
+// for each frame just before rendering it. Each track holds one uniform or
+// property; keyframe times are seconds from the start of the timeline, and
+// a track holds its first and last values outside its keyframes. While
+// playing, or once sought while paused, the timeline overrides whatever
+// else sets the same values.
+#[wasm_bindgen]
+pub struct Timeline {
+  timeline: Rc<RefCell<TimelineState>>,
+}
+
+#[wasm_bindgen]
+impl Timeline {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Timeline {
+    Timeline {
+      timeline: Rc::new(RefCell::new(TimelineState {
+        tracks: Vec::new(),
+        playing: false,
+        looping: false,
+        position: 0.0,
+        last_frame: None,
+        dirty: false,
+      })),
+    }
+  }
+
+  // Adds a track for a `float` or `vec2` to `vec4` uniform of the canvas'
+  // program, taking `components` values per keyframe. Returns the track's
+  // index.
+  pub fn animate_uniform(&self, canvas: &WebGlCanvas, name: &str, components: u32) -> Result<usize> {
+    if !(1..=4).contains(&components) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a uniform track takes 1 to 4 components, not {}",
+        components
+      )));
+    }
+    let target = Target::Uniform {
+      canvas: canvas.state(),
+      name: name.to_string(),
+    };
+    Ok(self.add_track(target, components as usize))
+  }
+
+  // Adds a track for `property` of a node of `scene`. Returns the track's
+  // index.
+  pub fn animate_node(&self, scene: &Scene, node: usize, property: NodeProperty) -> Result<usize> {
+    let scene = scene.shared();
+    scene.borrow().check_animatable(node, property)?;
+    let target = Target::Node { scene, node, property };
+    Ok(self.add_track(target, property.components()))
+  }
+
+  // Sets the track's values at `time` seconds, replacing a keyframe there.
+  // `easing` shapes the way there from the keyframe before.
+  pub fn add_keyframe(&self, track: usize, time: f64, values: &[f32], easing: Easing) -> Result<()> {
+    if !time.is_finite() || time < 0.0 {
+      return Err(GestaltError::InvalidArgument(format!("keyframe time {} is not a time from the start", time)));
+    }
+    let mut timeline = self.timeline.borrow_mut();
+    let Some(track_state) = timeline.tracks.get_mut(track) else {
+      return Err(GestaltError::InvalidArgument(format!("no animation track {}", track)));
+    };
+    if values.len() != track_state.components {
+      return Err(GestaltError::InvalidArgument(format!(
+        "track {} takes {} values per keyframe, got {}",
+        track,
+        track_state.components,
+        values.len()
+      )));
+    }
+
+    let keyframe = Keyframe {
+      time,
+      values: values.to_vec(),
+      easing,
+    };
+    let keyframes = &mut track_state.keyframes;
+    match keyframes.iter().position(|existing| existing.time >= time) {
+      Some(index) if keyframes[index].time == time => keyframes[index] = keyframe,
+      Some(index) => keyframes.insert(index, keyframe),
+      None => keyframes.push(keyframe),
+    }
+    timeline.dirty = true;
+    Ok(())
+  }
+
+  // Starts or resumes playing from the current time with the next frame.
+  pub fn play(&self) {
+    let mut timeline = self.timeline.borrow_mut();
+    timeline.playing = true;
+    timeline.last_frame = None;
+  }
+
+  // Stops at the current time, leaving the values as they are.
+  pub fn pause(&self) {
+    self.timeline.borrow_mut().playing = false;
+  }
+
+  pub fn is_playing(&self) -> bool {
+    self.timeline.borrow().playing
+  }
+
+  // Goes to `time` seconds, shown with the next frame even if paused.
+  pub fn seek(&self, time: f64) -> Result<()> {
+    if !time.is_finite() || time < 0.0 {
+      return Err(GestaltError::InvalidArgument(format!("cannot seek to {} seconds", time)));
+    }
+    let mut timeline = self.timeline.borrow_mut();
+    timeline.position = time;
+    timeline.dirty = true;
+    Ok(())
+  }
+
+  // Seconds into the timeline as of the last frame.
+  pub fn time(&self) -> f64 {
+    self.timeline.borrow().position
+  }
+
+  // The time of the last keyframe of any track.
+  pub fn duration(&self) -> f64 {
+    self.timeline.borrow().duration()
+  }
+
+  // Whether playing wraps around to the start at the end instead of
+  // stopping there. Off to begin with.
+  pub fn set_looping(&self, looping: bool) {
+    self.timeline.borrow_mut().looping = looping;
+  }
+
+  // Removes every track, leaving the values where they were.
+  pub fn clear(&self) {
+    let mut timeline = self.timeline.borrow_mut();
+    timeline.tracks.clear();
+    timeline.position = 0.0;
+    timeline.playing = false;
+  }
+}
+
+impl Default for Timeline {
+  fn default() -> Timeline {
+    Timeline::new()
+  }
+}
+
+impl Timeline {
+  pub(crate) fn shared(&self) -> Rc<RefCell<TimelineState>> {
+    self.timeline.clone()
+  }
+
+  fn add_track(&self, target: Target, components: usize) -> usize {
+    let mut timeline = self.timeline.borrow_mut();
+    timeline.tracks.push(Track {
+      target,
+      components,
+      keyframes: Vec::new(),
+    });
+    timeline.tracks.len() - 1
+  }
+}
+
+// A uniform or scene node property for `Tweens::tween` and `MidiController`.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct AnimationTarget {
+  target: Target,
+  components: usize,
+}
+
+#[wasm_bindgen]
+impl AnimationTarget {
+
+  // A `float` or `vec2` to `vec4` uniform of the canvas' program, taking
+  // `components` values.
+  pub fn uniform(canvas: &WebGlCanvas, name: &str, components: u32) -> Result<AnimationTarget> {
+    if !(1..=4).contains(&components) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a uniform target takes 1 to 4 components, not {}",
+        components
+      )));
+    }
+    Ok(AnimationTarget {
+      target: Target::Uniform {
+        canvas: canvas.state(),
+        name: name.to_string(),
+      },
+      components: components as usize,
+    })
+  }
+
+  pub fn node(scene: &Scene, node: usize, property: NodeProperty) -> Result<AnimationTarget> {
+    let scene = scene.shared();
+    scene.borrow().check_animatable(node, property)?;
+    Ok(AnimationTarget {
+      target: Target::Node { scene, node, property },
+      components: property.components(),
+    })
+  }
+}
+
+impl AnimationTarget {
+  pub(crate) fn components(&self) -> usize {
+    self.components
+  }
+
+  pub(crate) fn set(&self, values: &[f32]) {
+    self.target.apply(values);
+  }
+}
+
+struct Tween {
+  id: u32,
+  target: Target,
+  from: Vec<f32>,
+  to: Vec<f32>,
+  // Seconds.
+  duration: f64,
+  easing: Easing,
+  // Timestamp of the first frame it was advanced on, in milliseconds.
+  start: Option<f64>,
+}
+
+pub(crate) struct TweenState {
+  tweens: Vec<Tween>,
+  next_id: u32,
+}
+
+// Sets the values of every running tween for the frame at `time`, in
+// milliseconds, and drops the tweens that have ended.
+pub(crate) fn advance_tweens(tweens: &Rc<RefCell<TweenState>>, time: f64) {
+  tweens.borrow_mut().tweens.retain_mut(|tween| {
+    let start = *tween.start.get_or_insert(time);
+    let elapsed = (time - start).max(0.0) / 1000.0;
+    let fraction = if tween.duration > 0.0 { (elapsed / tween.duration).min(1.0) } else { 1.0 };
+    let progress = tween.easing.apply(fraction as f32);
+    tween.target.apply(&interpolate(&tween.from, &tween.to, progress));
+    fraction < 1.0
+  });
+}
+
+// One-off transitions of uniforms and scene node properties, e.g. a cue
+// fading in or an aperture moving to a new place, advanced by the
+// `RenderLoop` or `FrameExporter` they are attached to. A tween starts with
+// the next frame, sets its target to exactly its end values on the frame
+// it runs out, and is then forgotten; a new tween of a target replaces one
+// still running.
+#[wasm_bindgen]
+pub struct Tweens {
+  tweens: Rc<RefCell<TweenState>>,
+}
+
+#[wasm_bindgen]
+impl Tweens {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Tweens {
+    Tweens {
+      tweens: Rc::new(RefCell::new(TweenState {
+        tweens: Vec::new(),
+        next_id: 0,
+      })),
+    }
+  }
+
+  // Moves `target` from `from` to `to` over `duration` seconds. Returns an
+  // id for `cancel` and `is_running`.
+  pub fn tween(&self, target: &AnimationTarget, from: &[f32], to: &[f32], duration: f64, easing: Easing) -> Result<u32> {
+    if from.len() != target.components || to.len() != target.components {
+      return Err(GestaltError::InvalidArgument(format!(
+        "the target takes {} values, got {} to {}",
+        target.components,
+        from.len(),
+        to.len()
+      )));
+    }
+    if !duration.is_finite() || duration < 0.0 {
+      return Err(GestaltError::InvalidArgument(format!("a tween cannot last {} seconds", duration)));
+    }
+
+    let mut tweens = self.tweens.borrow_mut();
+    tweens.tweens.retain(|tween| !tween.target.is(&target.target));
+    let id = tweens.next_id;
+    tweens.next_id += 1;
+    tweens.tweens.push(Tween {
+      id,
+      target: target.target.clone(),
+      from: from.to_vec(),
+      to: to.to_vec(),
+      duration,
+      easing,
+      start: None,
+    });
+    Ok(id)
+  }
+
+  // Stops a tween where it is.
+  pub fn cancel(&self, id: u32) {
+    self.tweens.borrow_mut().tweens.retain(|tween| tween.id != id);
+  }
+
+  pub fn cancel_all(&self) {
+    self.tweens.borrow_mut().tweens.clear();
+  }
+
+  pub fn is_running(&self, id: u32) -> bool {
+    self.tweens.borrow().tweens.iter().any(|tween| tween.id == id)
+  }
+
+  pub fn running_count(&self) -> usize {
+    self.tweens.borrow().tweens.len()
+  }
+}
+
+impl Default for Tweens {
+  fn default() -> Tweens {
+    Tweens::new()
+  }
+}
+
+impl Tweens {
+  pub(crate) fn shared(&self) -> Rc<RefCell<TweenState>> {
+    self.tweens.clone()
+  }
+}
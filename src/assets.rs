@@ -0,0 +1,231 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::{AudioBuffer, AudioContext, HtmlImageElement, WebGl2RenderingContext};
+
+use crate::error::{GestaltError, Result};
+use crate::graphics::WebGlCanvas;
+use crate::texture::{decode_image, Texture};
+
+// What an asset is loaded as.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetKind {
+  Image,
+  // Shader sources and other plain text.
+  Text,
+  Json,
+  Audio,
+}
+
+impl AssetKind {
+  fn name(self) -> &'static str {
+    match self {
+      AssetKind::Image => "an image",
+      AssetKind::Text => "text",
+      AssetKind::Json => "JSON",
+      AssetKind::Audio => "audio",
+    }
+  }
+}
+
+#[derive(Clone)]
+enum Asset {
+  Image(HtmlImageElement),
+  Text(String),
+  Json(JsValue),
+  Audio(AudioBuffer),
+}
+
+struct LoaderState {
+  queue: Vec<(String, AssetKind)>,
+  cache: HashMap<String, Asset>,
+  // Called with `(loaded, total, url)` as assets come in.
+  progress: Option<js_sys::Function>,
+  // Made on first use, only to decode audio.
+  audio_context: Option<AudioContext>,
+}
+
+impl LoaderState {
+  fn audio_context(&mut self) -> Result<AudioContext> {
+    if let Some(context) = &self.audio_context {
+      return Ok(context.clone());
+    }
+    let context = AudioContext::new()?;
+    self.audio_context = Some(context.clone());
+    Ok(context)
+  }
+
+  fn get(&self, url: &str) -> Result<Asset> {
+    self
+      .cache
+      .get(url)
+      .cloned()
+      .ok_or_else(|| GestaltError::InvalidArgument(format!("asset '{}' is not loaded", url)))
+  }
+}
+
+fn wrong_kind(url: &str, kind: AssetKind) -> GestaltError {
+  GestaltError::InvalidArgument(format!("asset '{}' is not {}", url, kind.name()))
+}
+
+pub(crate) async fn fetch(url: &str) -> Result<web_sys::Response> {
+  let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+  let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url)).await?.unchecked_into();
+  if !response.ok() {
+    return Err(GestaltError::InvalidArgument(format!(
+      "fetching '{}' failed with {} {}",
+      url,
+      response.status(),
+      response.status_text()
+    )));
+  }
+  Ok(response)
+}
+
+async fn fetch_asset(state: &Rc<RefCell<LoaderState>>, url: &str, kind: AssetKind) -> Result<Asset> {
+  Ok(match kind {
+    AssetKind::Image => Asset::Image(decode_image(url).await?),
+    AssetKind::Text => {
+      let text = JsFuture::from(fetch(url).await?.text()?).await?;
+      Asset::Text(text.as_string().unwrap_or_default())
+    }
+    AssetKind::Json => Asset::Json(JsFuture::from(fetch(url).await?.json()?).await?),
+    AssetKind::Audio => {
+      let data: js_sys::ArrayBuffer = JsFuture::from(fetch(url).await?.array_buffer()?).await?.unchecked_into();
+      let context = state.borrow_mut().audio_context()?;
+      Asset::Audio(JsFuture::from(context.decode_audio_data(&data)?).await?.unchecked_into())
+    }
+  })
+}
+
+// Fetches the images, shader sources, JSON configs and sounds an experiment
+// needs before it starts, all at once, reporting progress for a loading
+// bar. Assets are cached by URL, so adding one twice loads it once, and are
+// then taken out as textures, strings, parsed JSON or `AudioBuffer`s. Audio
+// buffers can be played through any `AudioContext`.
+#[wasm_bindgen]
+pub struct AssetLoader {
+  context: WebGl2RenderingContext,
+  state: Rc<RefCell<LoaderState>>,
+}
+
+#[wasm_bindgen]
+impl AssetLoader {
+
+  // Textures are made for `canvas`.
+  pub fn new(canvas: &WebGlCanvas) -> AssetLoader {
+    AssetLoader {
+      context: canvas.context(),
+      state: Rc::new(RefCell::new(LoaderState {
+        queue: Vec::new(),
+        cache: HashMap::new(),
+        progress: None,
+        audio_context: None,
+      })),
+    }
+  }
+
+  // Queues `url` for the next `load`, unless it is loaded or queued already.
+  pub fn add(&self, url: &str, kind: AssetKind) {
+    let mut state = self.state.borrow_mut();
+    if !state.cache.contains_key(url) && !state.queue.iter().any(|(queued, _)| queued == url) {
+      state.queue.push((url.to_string(), kind));
+    }
+  }
+
+  // Called with `(loaded, total, url)` each time an asset of the running
+  // `load` has come in; `undefined` removes it.
+  pub fn set_progress_callback(&self, callback: Option<js_sys::Function>) {
+    self.state.borrow_mut().progress = callback;
+  }
+
+  // Fetches everything queued in parallel. Resolves to the number of assets
+  // loaded, or rejects with the first failure; the others still load.
+  pub fn load(&self) -> js_sys::Promise {
+    let queue = std::mem::take(&mut self.state.borrow_mut().queue);
+    let total = queue.len() as u32;
+    let loaded = Rc::new(Cell::new(0));
+    let loads: js_sys::Array = queue
+      .into_iter()
+      .map(|(url, kind)| {
+        let (state, loaded) = (self.state.clone(), loaded.clone());
+        wasm_bindgen_futures::future_to_promise(async move {
+          let asset = fetch_asset(&state, &url, kind)
+            .await
+            .map_err(|error| GestaltError::InvalidArgument(format!("could not load '{}': {}", url, error)))?;
+          let progress = {
+            let mut state = state.borrow_mut();
+            state.cache.insert(url.clone(), asset);
+            state.progress.clone()
+          };
+          loaded.set(loaded.get() + 1);
+          if let Some(progress) = progress {
+            if let Err(error) = progress.call3(&JsValue::NULL, &loaded.get().into(), &total.into(), &url.into()) {
+              web_sys::console::error_2(&"Progress callback failed:".into(), &error);
+            }
+          }
+          Ok(JsValue::UNDEFINED)
+        })
+      })
+      .collect();
+    let all = js_sys::Promise::all(&loads);
+    wasm_bindgen_futures::future_to_promise(async move {
+      JsFuture::from(all).await?;
+      Ok(total.into())
+    })
+  }
+
+  pub fn is_loaded(&self, url: &str) -> bool {
+    self.state.borrow().cache.contains_key(url)
+  }
+
+  // Uploads the image loaded from `url` to a new texture.
+  pub fn texture(&self, url: &str) -> Result<Texture> {
+    Texture::from_image(&self.context, &self.image(url)?)
+  }
+
+  pub fn image(&self, url: &str) -> Result<HtmlImageElement> {
+    match self.state.borrow().get(url)? {
+      Asset::Image(image) => Ok(image),
+      _ => Err(wrong_kind(url, AssetKind::Image)),
+    }
+  }
+
+  pub fn text(&self, url: &str) -> Result<String> {
+    match self.state.borrow().get(url)? {
+      Asset::Text(text) => Ok(text),
+      _ => Err(wrong_kind(url, AssetKind::Text)),
+    }
+  }
+
+  pub fn json(&self, url: &str) -> Result<JsValue> {
+    match self.state.borrow().get(url)? {
+      Asset::Json(value) => Ok(value),
+      _ => Err(wrong_kind(url, AssetKind::Json)),
+    }
+  }
+
+  pub fn audio(&self, url: &str) -> Result<AudioBuffer> {
+    match self.state.borrow().get(url)? {
+      Asset::Audio(buffer) => Ok(buffer),
+      _ => Err(wrong_kind(url, AssetKind::Audio)),
+    }
+  }
+
+  // Drops `url` from the cache, so that it is fetched again when added.
+  pub fn remove(&self, url: &str) {
+    self.state.borrow_mut().cache.remove(url);
+  }
+
+  pub fn clear(&self) {
+    let mut state = self.state.borrow_mut();
+    state.queue.clear();
+    state.cache.clear();
+  }
+}
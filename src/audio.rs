@@ -0,0 +1,440 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::{AnalyserNode, AudioBuffer, AudioBufferSourceNode, AudioContext, AudioScheduledSourceNode, GainNode, WebGl2RenderingContext};
+
+use crate::assets::fetch;
+use crate::error::{GestaltError, Result};
+use crate::graphics::{document, WebGlCanvas};
+use crate::stimuli::check_positive;
+use crate::texture::{Texture, TextureFormat};
+
+#[derive(Clone, Debug)]
+struct Playback {
+  id: u32,
+  name: String,
+  // When the sound was asked to be heard and when it is expected to be, in
+  // milliseconds on the clock of frame timestamps.
+  scheduled: f64,
+  actual: f64,
+}
+
+impl Playback {
+  fn to_js(&self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"id".into(), &self.id.into());
+    let _ = js_sys::Reflect::set(&object, &"name".into(), &self.name.as_str().into());
+    let _ = js_sys::Reflect::set(&object, &"scheduled".into(), &self.scheduled.into());
+    let _ = js_sys::Reflect::set(&object, &"actual".into(), &self.actual.into());
+    let _ = js_sys::Reflect::set(&object, &"error".into(), &(self.actual - self.scheduled).into());
+    object.into()
+  }
+}
+
+struct AudioState {
+  context: AudioContext,
+  // Everything played goes through here to the speakers.
+  output: GainNode,
+  buffers: HashMap<String, AudioBuffer>,
+  // Scheduled sources not yet known to have ended, by playback id.
+  sources: Vec<(u32, AudioBufferSourceNode)>,
+  log: Vec<Playback>,
+  next_id: u32,
+}
+
+impl AudioState {
+  fn buffer(&self, name: &str) -> Result<AudioBuffer> {
+    self
+      .buffers
+      .get(name)
+      .cloned()
+      .ok_or_else(|| GestaltError::InvalidArgument(format!("no sound '{}'", name)))
+  }
+
+  // A context time and the frame timestamp clock's time at which that
+  // context time reaches the speakers, from `getOutputTimestamp` where the
+  // browser has it and from the reported latencies otherwise.
+  fn clock_pair(&self) -> Result<(f64, f64)> {
+    let context: &JsValue = self.context.as_ref();
+    let output_timestamp = js_sys::Reflect::get(context, &"getOutputTimestamp".into())?;
+    if let Some(function) = output_timestamp.dyn_ref::<js_sys::Function>() {
+      let stamp = function.call0(context)?;
+      let field = |name: &str| js_sys::Reflect::get(&stamp, &name.into()).ok().and_then(|value| value.as_f64());
+      // Zero until the context has started outputting.
+      if let (Some(context_time), Some(performance_time)) = (field("contextTime"), field("performanceTime")) {
+        if performance_time > 0.0 {
+          return Ok((context_time, performance_time));
+        }
+      }
+    }
+    let latency = ["baseLatency", "outputLatency"]
+      .iter()
+      .filter_map(|name| js_sys::Reflect::get(context, &(*name).into()).ok()?.as_f64())
+      .sum::<f64>();
+    Ok((self.context.current_time(), performance_now()? + latency * 1000.0))
+  }
+}
+
+pub(crate) fn performance_now() -> Result<f64> {
+  let performance = web_sys::window().and_then(|window| window.performance()).ok_or(GestaltError::NoWindow)?;
+  Ok(performance.now())
+}
+
+// Sounds for audiovisual stimuli, played through a `WebAudio` context of
+// their own at times on the same clock as frame timestamps and trial
+// onsets, i.e. `performance.now()`, and compensated for the output latency
+// the browser reports. Every playback is logged with the time it was asked
+// for and the time it is expected to reach the speakers, which are later
+// if it was scheduled too late; hardware latency beyond what the browser
+// knows of is not in either and needs measuring with a photodiode and a
+// microphone.
+//
+// Browsers only let audio start after a user gesture, so call `resume`
+// from a click or key handler before the first sound.
+#[wasm_bindgen]
+pub struct AudioPlayer {
+  state: Rc<RefCell<AudioState>>,
+}
+
+#[wasm_bindgen]
+impl AudioPlayer {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Result<AudioPlayer> {
+    let context = AudioContext::new()?;
+    let output = context.create_gain()?;
+    output.connect_with_audio_node(&context.destination())?;
+    Ok(AudioPlayer {
+      state: Rc::new(RefCell::new(AudioState {
+        context,
+        output,
+        buffers: HashMap::new(),
+        sources: Vec::new(),
+        log: Vec::new(),
+        next_id: 0,
+      })),
+    })
+  }
+
+  pub fn resume(&self) -> Result<js_sys::Promise> {
+    Ok(self.state.borrow().context.resume()?)
+  }
+
+  // Whether the context is running, i.e. sounds will be heard.
+  pub fn is_running(&self) -> bool {
+    self.state.borrow().context.state() == web_sys::AudioContextState::Running
+  }
+
+  // Fetches and decodes the sound at `url` as `name`. Resolves when it can
+  // be scheduled.
+  pub fn load(&self, name: &str, url: &str) -> js_sys::Promise {
+    let state = self.state.clone();
+    let (name, url) = (name.to_string(), url.to_string());
+    wasm_bindgen_futures::future_to_promise(async move {
+      let data: js_sys::ArrayBuffer = JsFuture::from(fetch(&url).await?.array_buffer()?).await?.unchecked_into();
+      let decoding = state.borrow().context.decode_audio_data(&data)?;
+      let buffer: AudioBuffer = JsFuture::from(decoding).await?.unchecked_into();
+      state.borrow_mut().buffers.insert(name, buffer);
+      Ok(JsValue::UNDEFINED)
+    })
+  }
+
+  // Adds a decoded sound, e.g. from `AssetLoader::audio`, as `name`.
+  pub fn add_buffer(&self, name: &str, buffer: &AudioBuffer) {
+    self.state.borrow_mut().buffers.insert(name.to_string(), buffer.clone());
+  }
+
+  // Makes a sine tone pip as `name`: `duration` seconds at `frequency` Hz
+  // with `ramp` second raised-cosine onset and offset ramps against clicks.
+  pub fn add_tone(&self, name: &str, frequency: f32, duration: f32, ramp: f32) -> Result<()> {
+    check_positive("tone frequency", frequency)?;
+    check_positive("tone duration", duration)?;
+    if ramp < 0.0 || 2.0 * ramp > duration {
+      return Err(GestaltError::InvalidArgument(format!(
+        "{} second ramps do not fit a {} second tone",
+        ramp, duration
+      )));
+    }
+
+    let mut state = self.state.borrow_mut();
+    let sample_rate = state.context.sample_rate();
+    let length = (duration * sample_rate).round().max(1.0) as usize;
+    let ramp_length = (ramp * sample_rate).round() as usize;
+    let samples: Vec<f32> = (0..length)
+      .map(|index| {
+        let edge = index.min(length - 1 - index);
+        let envelope = if edge < ramp_length {
+          0.5 - 0.5 * (std::f64::consts::PI * edge as f64 / ramp_length as f64).cos()
+        } else {
+          1.0
+        };
+        (envelope * (TAU * frequency as f64 * index as f64 / sample_rate as f64).sin()) as f32
+      })
+      .collect();
+    let buffer = state.context.create_buffer(1, length as u32, sample_rate)?;
+    buffer.copy_to_channel(&samples, 0)?;
+    state.buffers.insert(name.to_string(), buffer);
+    Ok(())
+  }
+
+  pub fn has_sound(&self, name: &str) -> bool {
+    self.state.borrow().buffers.contains_key(name)
+  }
+
+  // Duration of the sound in seconds.
+  pub fn sound_duration(&self, name: &str) -> Result<f64> {
+    Ok(self.state.borrow().buffer(name)?.duration())
+  }
+
+  // Plays `name` at `gain` so that it reaches the speakers at `time`, in
+  // milliseconds on the clock of frame timestamps; times already past play
+  // at once. Returns the playback's id in the log.
+  pub fn schedule(&self, name: &str, time: f64, gain: f32) -> Result<u32> {
+    let mut state = self.state.borrow_mut();
+    let buffer = state.buffer(name)?;
+    let context = state.context.clone();
+
+    let source = context.create_buffer_source()?;
+    source.set_buffer(Some(&buffer));
+    let amplifier = context.create_gain()?;
+    amplifier.gain().set_value(gain.max(0.0));
+    source.connect_with_audio_node(&amplifier)?;
+    amplifier.connect_with_audio_node(&state.output)?;
+
+    let (context_time, performance_time) = state.clock_pair()?;
+    let when = context_time + (time - performance_time) / 1000.0;
+    // Sources started in the past start straight away.
+    let start = when.max(context.current_time());
+    source.start_with_when(start)?;
+
+    let id = state.next_id;
+    state.next_id += 1;
+    state.sources.push((id, source.clone()));
+    // Ended sources are dropped from the list so they can be collected.
+    let ended = Rc::downgrade(&self.state);
+    let on_ended = Closure::once_into_js(move || {
+      if let Some(state) = ended.upgrade() {
+        if let Ok(mut state) = state.try_borrow_mut() {
+          state.sources.retain(|(playing, _)| *playing != id);
+        }
+      }
+    });
+    AudioScheduledSourceNode::set_onended(&source, Some(on_ended.unchecked_ref()));
+
+    state.log.push(Playback {
+      id,
+      name: name.to_string(),
+      scheduled: time,
+      actual: performance_time + (start - context_time) * 1000.0,
+    });
+    Ok(id)
+  }
+
+  // Like `schedule`, `delay` milliseconds after the timestamp of the frame
+  // `canvas` drew last, e.g. a beep with a flash shown on that frame for
+  // `delay` 0. Negative delays lead the frame.
+  pub fn schedule_after_frame(&self, canvas: &WebGlCanvas, name: &str, delay: f64, gain: f32) -> Result<u32> {
+    let frame = canvas.frame_clock().get();
+    self.schedule(name, frame + delay, gain)
+  }
+
+  // Milliseconds from now until a sound scheduled now would be heard, as
+  // far as the browser knows.
+  pub fn output_latency(&self) -> Result<f64> {
+    let state = self.state.borrow();
+    let (context_time, performance_time) = state.clock_pair()?;
+    Ok(performance_time + (state.context.current_time() - context_time) * 1000.0 - performance_now()?)
+  }
+
+  // Stops a scheduled or playing sound; its log entry stays.
+  pub fn cancel(&self, id: u32) -> Result<()> {
+    let mut state = self.state.borrow_mut();
+    if let Some(index) = state.sources.iter().position(|(playing, _)| *playing == id) {
+      let (_, source) = state.sources.remove(index);
+      AudioScheduledSourceNode::stop(&source)?;
+    }
+    Ok(())
+  }
+
+  pub fn stop_all(&self) -> Result<()> {
+    for (_, source) in std::mem::take(&mut self.state.borrow_mut().sources) {
+      AudioScheduledSourceNode::stop(&source)?;
+    }
+    Ok(())
+  }
+
+  // Every playback as `{ id, name, scheduled, actual, error }`, times in
+  // milliseconds on the frame timestamp clock and `error` being `actual`
+  // minus `scheduled`.
+  pub fn log(&self) -> js_sys::Array {
+    self.state.borrow().log.iter().map(Playback::to_js).collect()
+  }
+
+  pub fn clear_log(&self) {
+    self.state.borrow_mut().log.clear();
+  }
+
+  // Stops every sound and releases the audio hardware; the player cannot be
+  // used afterwards.
+  pub fn close(&self) -> Result<js_sys::Promise> {
+    self.stop_all()?;
+    Ok(self.state.borrow().context.close()?)
+  }
+}
+
+impl AudioPlayer {
+  pub(crate) fn context(&self) -> AudioContext {
+    self.state.borrow().context.clone()
+  }
+}
+
+// Sampler uniform the analysis of a bound `AudioAnalyser` is read from.
+pub(crate) const AUDIO_TEXTURE_UNIFORM: &str = "u_audio_fft";
+
+// A tap on sound for analysis, for `WebGlCanvas::bind_audio_texture` or
+// reading directly: the spectrum over `fft_size / 2` bins up to half the
+// sample rate, and the waveform of the latest `fft_size` samples.
+#[wasm_bindgen]
+pub struct AudioAnalyser {
+  analyser: AnalyserNode,
+}
+
+#[wasm_bindgen]
+impl AudioAnalyser {
+
+  // Analyses everything `player` plays. `fft_size` is a power of two from
+  // 32 to 32768.
+  pub fn new(player: &AudioPlayer, fft_size: u32) -> Result<AudioAnalyser> {
+    let state = player.state.borrow();
+    let analyser = new_analyser(&state.context, fft_size)?;
+    state.output.connect_with_audio_node(&analyser)?;
+    Ok(AudioAnalyser { analyser })
+  }
+
+  // Analyses the `<audio>` or `<video>` element with id `element_id`, e.g.
+  // music, routing its sound through `player`'s context to the speakers.
+  // An element can only be routed through one context, once.
+  pub fn from_media_element(player: &AudioPlayer, element_id: &str, fft_size: u32) -> Result<AudioAnalyser> {
+    let element = document()?
+      .get_element_by_id(element_id)
+      .ok_or_else(|| GestaltError::ElementNotFound(element_id.to_string()))?
+      .dyn_into::<web_sys::HtmlMediaElement>()
+      .map_err(|_| GestaltError::InvalidArgument(format!("element '{}' is not <audio> or <video>", element_id)))?;
+    let state = player.state.borrow();
+    let analyser = new_analyser(&state.context, fft_size)?;
+    let source = state.context.create_media_element_source(&element)?;
+    source.connect_with_audio_node(&analyser)?;
+    source.connect_with_audio_node(&state.output)?;
+    Ok(AudioAnalyser { analyser })
+  }
+
+  // Analyses a stream, e.g. a microphone from `getUserMedia`, without
+  // playing it.
+  pub fn from_stream(player: &AudioPlayer, stream: &web_sys::MediaStream, fft_size: u32) -> Result<AudioAnalyser> {
+    let state = player.state.borrow();
+    let analyser = new_analyser(&state.context, fft_size)?;
+    state.context.create_media_stream_source(stream)?.connect_with_audio_node(&analyser)?;
+    Ok(AudioAnalyser { analyser })
+  }
+
+  // How much each spectrum is averaged with the ones before, 0 to 1; 0.8
+  // to begin with.
+  pub fn set_smoothing(&self, smoothing: f64) {
+    self.analyser.set_smoothing_time_constant(smoothing.clamp(0.0, 1.0));
+  }
+
+  // Levels mapped to 0 and 1 in the spectrum, -100 and -30 dB to begin
+  // with.
+  pub fn set_decibel_range(&self, min: f64, max: f64) -> Result<()> {
+    if min >= max {
+      return Err(GestaltError::InvalidArgument(format!("cannot map {} to {} dB", min, max)));
+    }
+    // The analyser throws whenever its minimum is not below its maximum.
+    if min < self.analyser.max_decibels() {
+      self.analyser.set_min_decibels(min);
+      self.analyser.set_max_decibels(max);
+    } else {
+      self.analyser.set_max_decibels(max);
+      self.analyser.set_min_decibels(min);
+    }
+    Ok(())
+  }
+
+  pub fn bin_count(&self) -> u32 {
+    self.analyser.frequency_bin_count()
+  }
+
+  // The current spectrum, 0 to 255 per bin.
+  pub fn frequency_data(&self) -> Vec<u8> {
+    let mut data = vec![0; self.bin_count() as usize];
+    self.analyser.get_byte_frequency_data(&mut data);
+    data
+  }
+
+  // The current waveform, 128 being silence.
+  pub fn time_domain_data(&self) -> Vec<u8> {
+    let mut data = vec![0; self.analyser.fft_size() as usize];
+    self.analyser.get_byte_time_domain_data(&mut data);
+    data
+  }
+}
+
+impl AudioAnalyser {
+  pub(crate) fn node(&self) -> &AnalyserNode {
+    &self.analyser
+  }
+}
+
+fn new_analyser(context: &AudioContext, fft_size: u32) -> Result<AnalyserNode> {
+  if !fft_size.is_power_of_two() || !(32..=32768).contains(&fft_size) {
+    return Err(GestaltError::InvalidArgument(format!(
+      "an FFT size is a power of two from 32 to 32768, not {}",
+      fft_size
+    )));
+  }
+  let analyser = context.create_analyser()?;
+  analyser.set_fft_size(fft_size);
+  Ok(analyser)
+}
+
+// An analyser's output as a bins x 2 texture updated every render, as in
+// Shadertoy: the spectrum along the bottom row and the first bins' worth of
+// the waveform along the top one, both 0 to 1.
+pub(crate) struct AudioTexture {
+  analyser: AnalyserNode,
+  pub(crate) texture: Texture,
+  pub(crate) unit: u32,
+  data: Vec<u8>,
+}
+
+impl AudioTexture {
+  pub(crate) fn new(context: &WebGl2RenderingContext, analyser: &AudioAnalyser, unit: u32) -> Result<AudioTexture> {
+    Ok(AudioTexture {
+      analyser: analyser.node().clone(),
+      texture: Texture::new(context)?,
+      unit,
+      data: Vec::new(),
+    })
+  }
+
+  pub(crate) fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.texture = Texture::new(context)?;
+    Ok(())
+  }
+
+  pub(crate) fn update(&mut self) -> Result<()> {
+    let bins = self.analyser.frequency_bin_count() as usize;
+    let mut waveform = vec![0; self.analyser.fft_size() as usize];
+    self.analyser.get_byte_time_domain_data(&mut waveform);
+    self.data.resize(bins * 2, 0);
+    self.analyser.get_byte_frequency_data(&mut self.data[..bins]);
+    self.data[bins..].copy_from_slice(&waveform[..bins]);
+    self.texture.set_data(bins as u32, 2, TextureFormat::R, &self.data)
+  }
+}
@@ -0,0 +1,158 @@
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlVertexArrayObject};
+
+use crate::error::{GestaltError, Result};
+use crate::gl_state;
+use crate::shader::ShaderProgram;
+use crate::stats;
+
+// A growable list of interleaved float vertices, rebuilt on the CPU and
+// uploaded in one go before drawing. Used by the immediate-mode layers, which
+// collect everything drawn in a frame into a single buffer. An instanced
+// batch holds one entry per instance instead of per vertex.
+pub(crate) struct VertexBatch {
+  context: WebGl2RenderingContext,
+  vao: WebGlVertexArrayObject,
+  buffer: WebGlBuffer,
+  // Attribute location and component count of each interleaved attribute.
+  layout: Vec<(u32, i32)>,
+  instanced: bool,
+  stride: usize,
+  data: Vec<f32>,
+}
+
+impl VertexBatch {
+  pub(crate) fn new(context: &WebGl2RenderingContext, layout: &[(u32, i32)]) -> Result<VertexBatch> {
+    VertexBatch::with_layout(context, layout, false)
+  }
+
+  // A batch whose entries advance once per instance, see `draw_instanced`.
+  pub(crate) fn instanced(context: &WebGl2RenderingContext, layout: &[(u32, i32)]) -> Result<VertexBatch> {
+    VertexBatch::with_layout(context, layout, true)
+  }
+
+  fn with_layout(context: &WebGl2RenderingContext, layout: &[(u32, i32)], instanced: bool) -> Result<VertexBatch> {
+    let (vao, buffer) = create_objects(context, layout, instanced)?;
+
+    Ok(VertexBatch {
+      context: context.clone(),
+      vao,
+      buffer,
+      layout: layout.to_vec(),
+      instanced,
+      stride: layout.iter().map(|&(_, components)| components as usize).sum(),
+      data: Vec::new(),
+    })
+  }
+
+  // Recreates the GL objects on a restored context. Batched vertices are
+  // kept, as they are only uploaded when drawing.
+  pub(crate) fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    let (vao, buffer) = create_objects(context, &self.layout, self.instanced)?;
+    self.context = context.clone();
+    self.vao = vao;
+    self.buffer = buffer;
+    Ok(())
+  }
+
+  pub(crate) fn clear(&mut self) {
+    self.data.clear();
+  }
+
+  pub(crate) fn is_empty(&self) -> bool {
+    self.data.is_empty()
+  }
+
+  // Appends one vertex; `vertex` has all attributes in layout order.
+  pub(crate) fn push(&mut self, vertex: &[f32]) {
+    debug_assert_eq!(vertex.len(), self.stride);
+    self.data.extend_from_slice(vertex);
+  }
+
+  // Uploads the batched vertices and draws them as `mode` primitives.
+  pub(crate) fn draw(&self, mode: u32) {
+    if self.upload() {
+      self.context.draw_arrays(mode, 0, self.len());
+      stats::count_draw_call();
+    }
+  }
+
+  // Uploads the batched instances and draws `vertex_count` vertices of each.
+  // The vertex shader tells the vertices apart by `gl_VertexID`.
+  pub(crate) fn draw_instanced(&self, mode: u32, vertex_count: i32) {
+    if self.upload() {
+      self.context.draw_arrays_instanced(mode, 0, vertex_count, self.len());
+      stats::count_draw_call();
+    }
+  }
+
+  fn len(&self) -> i32 {
+    (self.data.len() / self.stride) as i32
+  }
+
+  // Binds the VAO and uploads the data, unless there is none.
+  fn upload(&self) -> bool {
+    if self.data.is_empty() {
+      return false;
+    }
+
+    let context = &self.context;
+    gl_state::bind_vertex_array(context, &self.vao);
+    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.buffer));
+
+    // No allocations while the view into wasm memory is alive.
+    unsafe {
+      let data_view = js_sys::Float32Array::view(&self.data);
+
+      context.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        &data_view,
+        WebGl2RenderingContext::STREAM_DRAW,
+      );
+    }
+    true
+  }
+}
+
+impl Drop for VertexBatch {
+  fn drop(&mut self) {
+    self.context.delete_vertex_array(Some(&self.vao));
+    self.context.delete_buffer(Some(&self.buffer));
+  }
+}
+
+fn create_objects(context: &WebGl2RenderingContext, layout: &[(u32, i32)], instanced: bool) -> Result<(WebGlVertexArrayObject, WebGlBuffer)> {
+  let vao = context
+    .create_vertex_array()
+    .ok_or(GestaltError::ResourceCreation("vertex array object"))?;
+  let buffer = context
+    .create_buffer()
+    .ok_or(GestaltError::ResourceCreation("vertex buffer"))?;
+
+  gl_state::bind_vertex_array(context, &vao);
+  context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+  let stride: i32 = layout.iter().map(|&(_, components)| components * 4).sum();
+  let mut offset = 0;
+  for &(location, components) in layout {
+    context.vertex_attrib_pointer_with_i32(location, components, WebGl2RenderingContext::FLOAT, false, stride, offset);
+    context.enable_vertex_attrib_array(location);
+    if instanced {
+      context.vertex_attrib_divisor(location, 1);
+    }
+    offset += components * 4;
+  }
+  Ok((vao, buffer))
+}
+
+// Looks up the locations of `program`'s inputs for a batch layout. Fails if
+// the compiler dropped one of them.
+pub(crate) fn attribute_layout(program: &ShaderProgram, attributes: &[(&str, i32)]) -> Result<Vec<(u32, i32)>> {
+  attributes
+    .iter()
+    .map(|&(name, components)| {
+      let location = program
+        .attribute_location(name)
+        .ok_or_else(|| GestaltError::MissingAttribute(name.to_string()))?;
+      Ok((location, components))
+    })
+    .collect()
+}
@@ -0,0 +1,371 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{PointerEvent, WheelEvent};
+
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+use crate::graphics::WebGlCanvas;
+use crate::math::{Mat4, Vec3};
+use crate::stimuli::{check_positive, Space, Units};
+
+// An orthographic view of a flat scene laid out in `Units`, so geometry can
+// be given in pixels or degrees instead of clip space. Like the stimuli,
+// pixels run from the top left and degrees from the centre of the canvas,
+// y running down in both. Panning moves the view over the scene and zoom
+// magnifies it about the unit origin, or any point with `zoom_at`.
+//
+// Hand it to `WebGlCanvas::set_camera`, which passes its matrix to the
+// vertex shader as `u_model_view_projection` each frame, fitted to the
+// drawing buffer size. The canvas keeps a copy; set it again after
+// changing the camera.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct Camera2D {
+  space: Space,
+  pan_x: f32,
+  pan_y: f32,
+  zoom: f32,
+}
+
+#[wasm_bindgen]
+impl Camera2D {
+
+  // `pixels_per_degree` is only used for degrees.
+  #[wasm_bindgen(constructor)]
+  pub fn new(units: Units, pixels_per_degree: f32) -> Result<Camera2D> {
+    if units == Units::Degrees {
+      check_positive("pixels per degree", pixels_per_degree)?;
+    }
+    let mut space = Space::new();
+    space.set(units, pixels_per_degree);
+    Ok(Camera2D { space, pan_x: 0.0, pan_y: 0.0, zoom: 1.0 })
+  }
+
+  // Shows the scene point (x, y) where the unit origin would be.
+  pub fn set_pan(&mut self, x: f32, y: f32) {
+    self.pan_x = x;
+    self.pan_y = y;
+  }
+
+  // Moves the view by (dx, dy) units of the scene, e.g. from a drag.
+  pub fn pan_by(&mut self, dx: f32, dy: f32) {
+    self.pan_x += dx;
+    self.pan_y += dy;
+  }
+
+  pub fn pan_x(&self) -> f32 {
+    self.pan_x
+  }
+
+  pub fn pan_y(&self) -> f32 {
+    self.pan_y
+  }
+
+  // Magnification, 1 by default; 2 shows everything twice as large.
+  pub fn set_zoom(&mut self, zoom: f32) -> Result<()> {
+    check_positive("zoom", zoom)?;
+    self.zoom = zoom;
+    Ok(())
+  }
+
+  pub fn zoom(&self) -> f32 {
+    self.zoom
+  }
+
+  // Multiplies the zoom by `factor`, keeping the scene point (x, y) where it
+  // is on screen, e.g. the point under the mouse when scrolling.
+  pub fn zoom_at(&mut self, factor: f32, x: f32, y: f32) -> Result<()> {
+    check_positive("zoom factor", factor)?;
+    self.pan_x = x - (x - self.pan_x) / factor;
+    self.pan_y = y - (y - self.pan_y) / factor;
+    self.zoom *= factor;
+    Ok(())
+  }
+
+  // The column-major matrix taking scene units to clip space on a drawing
+  // buffer of `width` x `height` pixels. Depth is passed through.
+  pub fn matrix(&self, width: u32, height: u32) -> Result<Vec<f32>> {
+    if width == 0 || height == 0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "camera needs a drawing buffer size, got {}x{}",
+        width, height
+      )));
+    }
+    let (origin_x, origin_y) = self.space.origin(width, height);
+    let (width, height) = (width as f32, height as f32);
+    let pixels_per_unit = self.space.scale() * self.zoom;
+    let scale_x = 2.0 * pixels_per_unit / width;
+    let scale_y = -2.0 * pixels_per_unit / height;
+    let offset_x = 2.0 * (origin_x - self.pan_x * pixels_per_unit) / width - 1.0;
+    let offset_y = 1.0 - 2.0 * (origin_y - self.pan_y * pixels_per_unit) / height;
+    Ok(vec![
+      scale_x, 0.0, 0.0, 0.0,
+      0.0, scale_y, 0.0, 0.0,
+      0.0, 0.0, 1.0, 0.0,
+      offset_x, offset_y, 0.0, 1.0,
+    ])
+  }
+
+  // The scene point shown at drawing buffer pixel (x, y) of a `width` x
+  // `height` buffer, as [x, y], e.g. to hit-test a click.
+  pub fn scene_point(&self, x: f32, y: f32, width: u32, height: u32) -> Vec<f32> {
+    let (origin_x, origin_y) = self.space.origin(width, height);
+    let pixels_per_unit = self.space.scale() * self.zoom;
+    vec![
+      (x - origin_x) / pixels_per_unit + self.pan_x,
+      (y - origin_y) / pixels_per_unit + self.pan_y,
+    ]
+  }
+}
+
+// Elevation stays short of the poles, where the view direction would line
+// up with the up vector.
+const MAX_ELEVATION: f32 = 89.0;
+
+// A perspective view of a 3D scene, orbiting a target point: the eye sits
+// `distance` away at `azimuth` degrees around the y axis, 0 being on the
+// +z side, and `elevation` degrees above the target. Hand it to
+// `WebGlCanvas::set_camera_3d`, which sets `u_model_view_projection` each
+// frame for the drawing buffer's aspect ratio, or add `OrbitControls` to
+// turn it with the mouse.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct Camera3D {
+  fov_y: f32,
+  near: f32,
+  far: f32,
+  target: [f32; 3],
+  distance: f32,
+  azimuth: f32,
+  elevation: f32,
+}
+
+#[wasm_bindgen]
+impl Camera3D {
+
+  // `fov_y` is the vertical field of view in degrees. Starts 5 units in
+  // front of the origin, seeing from 0.1 to 100 units.
+  #[wasm_bindgen(constructor)]
+  pub fn new(fov_y: f32) -> Result<Camera3D> {
+    if !(fov_y > 0.0 && fov_y < 180.0) {
+      return Err(GestaltError::InvalidArgument(format!("field of view must be within (0, 180) degrees, got {}", fov_y)));
+    }
+    Ok(Camera3D {
+      fov_y,
+      near: 0.1,
+      far: 100.0,
+      target: [0.0; 3],
+      distance: 5.0,
+      azimuth: 0.0,
+      elevation: 0.0,
+    })
+  }
+
+  pub fn set_clip_planes(&mut self, near: f32, far: f32) -> Result<()> {
+    if near.is_nan() || near <= 0.0 || far.is_nan() || far <= near {
+      return Err(GestaltError::InvalidArgument(format!(
+        "clip planes need 0 < near < far, got {} and {}",
+        near, far
+      )));
+    }
+    self.near = near;
+    self.far = far;
+    Ok(())
+  }
+
+  pub fn set_target(&mut self, x: f32, y: f32, z: f32) {
+    self.target = [x, y, z];
+  }
+
+  pub fn set_orbit(&mut self, distance: f32, azimuth: f32, elevation: f32) -> Result<()> {
+    check_positive("camera distance", distance)?;
+    self.distance = distance;
+    self.azimuth = azimuth;
+    self.elevation = elevation.clamp(-MAX_ELEVATION, MAX_ELEVATION);
+    Ok(())
+  }
+
+  // Turns the eye around the target by the given degrees.
+  pub fn orbit(&mut self, azimuth: f32, elevation: f32) {
+    self.azimuth = (self.azimuth + azimuth) % 360.0;
+    self.elevation = (self.elevation + elevation).clamp(-MAX_ELEVATION, MAX_ELEVATION);
+  }
+
+  // Multiplies the distance to the target by `factor`; below 1 moves
+  // closer.
+  pub fn dolly(&mut self, factor: f32) -> Result<()> {
+    check_positive("dolly factor", factor)?;
+    self.distance = (self.distance * factor).clamp(self.near, self.far);
+    Ok(())
+  }
+
+  pub fn distance(&self) -> f32 {
+    self.distance
+  }
+
+  pub fn azimuth(&self) -> f32 {
+    self.azimuth
+  }
+
+  pub fn elevation(&self) -> f32 {
+    self.elevation
+  }
+
+  // Where the eye is, as [x, y, z].
+  pub fn eye(&self) -> Vec<f32> {
+    self.eye_position().to_vec()
+  }
+
+  // The column-major projection times view matrix for a drawing buffer of
+  // `width` x `height` pixels.
+  pub fn matrix(&self, width: u32, height: u32) -> Result<Vec<f32>> {
+    if width == 0 || height == 0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "camera needs a drawing buffer size, got {}x{}",
+        width, height
+      )));
+    }
+    let projection = Mat4::perspective(self.fov_y, width as f32 / height as f32, self.near, self.far)?;
+    let [eye_x, eye_y, eye_z] = self.eye_position();
+    let [target_x, target_y, target_z] = self.target;
+    let view = Mat4::look_at(
+      &Vec3::new(eye_x, eye_y, eye_z),
+      &Vec3::new(target_x, target_y, target_z),
+      &Vec3::new(0.0, 1.0, 0.0),
+    )?;
+    Ok(projection.multiply(&view).elements())
+  }
+}
+
+impl Camera3D {
+  fn eye_position(&self) -> [f32; 3] {
+    let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+    let [x, y, z] = self.target;
+    [
+      x + self.distance * elevation.cos() * azimuth.sin(),
+      y + self.distance * elevation.sin(),
+      z + self.distance * elevation.cos() * azimuth.cos(),
+    ]
+  }
+}
+
+// Whichever camera a canvas draws its geometry through.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Camera {
+  Flat(Camera2D),
+  Perspective(Camera3D),
+}
+
+impl Camera {
+  pub(crate) fn matrix(&self, width: u32, height: u32) -> Result<Vec<f32>> {
+    match self {
+      Camera::Flat(camera) => camera.matrix(width, height),
+      Camera::Perspective(camera) => camera.matrix(width, height),
+    }
+  }
+}
+
+// Turns a canvas' `Camera3D` by hand: dragging orbits it around its target
+// and the mouse wheel or touchpad moves it closer or further. Works on
+// whatever 3D camera the canvas has at the time, and stops when dropped.
+#[wasm_bindgen]
+pub struct OrbitControls {
+  degrees_per_pixel: Rc<Cell<f32>>,
+  _listeners: Vec<EventListener>,
+}
+
+#[wasm_bindgen]
+impl OrbitControls {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<OrbitControls> {
+    let element = canvas.element()?;
+    let degrees_per_pixel = Rc::new(Cell::new(DEFAULT_DEGREES_PER_PIXEL));
+    // The pointer dragging and where it was last.
+    let drag: Rc<Cell<Option<(i32, i32, i32)>>> = Rc::new(Cell::new(None));
+    let mut listeners = Vec::new();
+
+    let down_drag = drag.clone();
+    let down_element = element.clone();
+    listeners.push(EventListener::new(&element, "pointerdown", move |event| {
+      let Some(event) = event.dyn_ref::<PointerEvent>() else {
+        return;
+      };
+      if down_drag.get().is_none() && event.button() == 0 {
+        let _ = down_element.set_pointer_capture(event.pointer_id());
+        down_drag.set(Some((event.pointer_id(), event.client_x(), event.client_y())));
+      }
+    })?);
+
+    let move_drag = drag.clone();
+    let move_state = Rc::downgrade(&canvas.state());
+    let move_degrees = degrees_per_pixel.clone();
+    listeners.push(EventListener::new(&element, "pointermove", move |event| {
+      let Some(event) = event.dyn_ref::<PointerEvent>() else {
+        return;
+      };
+      let Some((id, x, y)) = move_drag.get() else {
+        return;
+      };
+      if event.pointer_id() != id {
+        return;
+      }
+      move_drag.set(Some((id, event.client_x(), event.client_y())));
+      let (dx, dy) = ((event.client_x() - x) as f32, (event.client_y() - y) as f32);
+      if let Some(state) = move_state.upgrade() {
+        if let Some(camera) = state.borrow_mut().camera_3d_mut() {
+          // The scene follows the pointer.
+          camera.orbit(-dx * move_degrees.get(), dy * move_degrees.get());
+        }
+      }
+    })?);
+
+    for kind in ["pointerup", "pointercancel"] {
+      let up_drag = drag.clone();
+      listeners.push(EventListener::new(&element, kind, move |event| {
+        let Some(event) = event.dyn_ref::<PointerEvent>() else {
+          return;
+        };
+        if up_drag.get().is_some_and(|(id, _, _)| id == event.pointer_id()) {
+          up_drag.set(None);
+        }
+      })?);
+    }
+
+    let wheel_state = Rc::downgrade(&canvas.state());
+    listeners.push(EventListener::new(&element, "wheel", move |event| {
+      let Some(event) = event.dyn_ref::<WheelEvent>() else {
+        return;
+      };
+      let Some(state) = wheel_state.upgrade() else {
+        return;
+      };
+      let mut state = state.borrow_mut();
+      if let Some(camera) = state.camera_3d_mut() {
+        // Keep the page from scrolling while over the scene.
+        event.prevent_default();
+        let _ = camera.dolly((event.delta_y() * DOLLY_PER_WHEEL_PIXEL).exp() as f32);
+      }
+    })?);
+
+    Ok(OrbitControls {
+      degrees_per_pixel,
+      _listeners: listeners,
+    })
+  }
+
+  // Degrees the camera turns per CSS pixel dragged, 0.3 by default.
+  pub fn set_sensitivity(&self, degrees_per_pixel: f32) -> Result<()> {
+    check_positive("orbit sensitivity", degrees_per_pixel)?;
+    self.degrees_per_pixel.set(degrees_per_pixel);
+    Ok(())
+  }
+}
+
+const DEFAULT_DEGREES_PER_PIXEL: f32 = 0.3;
+
+// Wheel movement is in pixels for mice and touchpads alike; 100 pixels, one
+// notch of a typical mouse wheel, dollies by about 10%.
+const DOLLY_PER_WHEEL_PIXEL: f64 = 0.001;
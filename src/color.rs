@@ -0,0 +1,205 @@
+use wasm_bindgen::prelude::*;
+
+// CIE D65 white point, the reference white of sRGB, in XYZ.
+const WHITE_D65: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+// Linear sRGB to XYZ and back, for the D65 white point.
+pub(crate) const RGB_TO_XYZ: [[f32; 3]; 3] = [
+  [0.4124564, 0.3575761, 0.1804375],
+  [0.2126729, 0.7151522, 0.072175],
+  [0.0193339, 0.119192, 0.9503041],
+];
+const XYZ_TO_RGB: [[f32; 3]; 3] = [
+  [3.2404542, -1.5371385, -0.4985314],
+  [-0.969266, 1.8760108, 0.041556],
+  [0.0556434, -0.2040259, 1.0572252],
+];
+
+// A colour with sRGB-encoded components and straight alpha, all nominally
+// 0 to 1. These are the values a shader writes to an 8-bit canvas, i.e.
+// what the display receives. Colours converted from other spaces may fall
+// outside the sRGB gamut, see `in_gamut`; GL clamps them when drawing.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+  pub r: f32,
+  pub g: f32,
+  pub b: f32,
+  pub a: f32,
+}
+
+#[wasm_bindgen]
+impl Color {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new(r: f32, g: f32, b: f32, a: f32) -> Color {
+    Color { r, g, b, a }
+  }
+
+  // An opaque grey of sRGB value `value`.
+  pub fn gray(value: f32) -> Color {
+    Color::new(value, value, value, 1.0)
+  }
+
+  // sRGB 0.5 grey, the background the stimulus renderers assume unless
+  // told otherwise, e.g. for `WebGlCanvas::set_clear_color` under contrast
+  // stimuli.
+  pub fn mid_gray() -> Color {
+    Color::gray(0.5)
+  }
+
+  // From linear-light RGB, proportional to emitted intensity.
+  pub fn from_linear(r: f32, g: f32, b: f32, a: f32) -> Color {
+    Color::new(linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a)
+  }
+
+  // Hue in degrees, saturation and lightness 0 to 1.
+  pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Color {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    from_hue_chroma(hue, chroma, lightness - chroma / 2.0)
+  }
+
+  // Hue in degrees, saturation and value 0 to 1.
+  pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Color {
+    let chroma = value * saturation;
+    from_hue_chroma(hue, chroma, value - chroma)
+  }
+
+  // From CIE L*a*b* relative to D65 white, L* running from 0 to 100.
+  pub fn from_lab(l: f32, a: f32, b: f32) -> Color {
+    let fy = (l + 16.0) / 116.0;
+    let f = [fy + a / 500.0, fy, fy - b / 200.0];
+    let xyz = [0, 1, 2].map(|i| lab_f_inverse(f[i]) * WHITE_D65[i]);
+    let [r, g, b] = mul(&XYZ_TO_RGB, xyz);
+    Color::from_linear(r, g, b, 1.0)
+  }
+
+  // A copy with alpha `a`.
+  pub fn with_alpha(&self, a: f32) -> Color {
+    Color { a, ..*self }
+  }
+
+  // Linear-light r, g, b, a.
+  pub fn linear(&self) -> Vec<f32> {
+    let [r, g, b] = self.linear_rgb();
+    vec![r, g, b, self.a]
+  }
+
+  // Hue in degrees, saturation, lightness.
+  pub fn hsl(&self) -> Vec<f32> {
+    let (max, min) = self.extremes();
+    let lightness = (max + min) / 2.0;
+    let chroma = max - min;
+    let saturation = if chroma == 0.0 { 0.0 } else { chroma / (1.0 - (2.0 * lightness - 1.0).abs()) };
+    vec![self.hue(), saturation, lightness]
+  }
+
+  // Hue in degrees, saturation, value.
+  pub fn hsv(&self) -> Vec<f32> {
+    let (max, min) = self.extremes();
+    let saturation = if max == 0.0 { 0.0 } else { (max - min) / max };
+    vec![self.hue(), saturation, max]
+  }
+
+  // CIE L*, a*, b* relative to D65 white.
+  pub fn lab(&self) -> Vec<f32> {
+    let xyz = mul(&RGB_TO_XYZ, self.linear_rgb());
+    let [fx, fy, fz] = [0, 1, 2].map(|i| lab_f(xyz[i] / WHITE_D65[i]));
+    vec![116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+  }
+
+  // Relative luminance, the Y of CIE XYZ, 0 to 1.
+  pub fn luminance(&self) -> f32 {
+    mul(&RGB_TO_XYZ, self.linear_rgb())[1]
+  }
+
+  // Whether the display can show this colour, i.e. r, g and b lie within
+  // 0 to 1, give or take rounding.
+  pub fn in_gamut(&self) -> bool {
+    [self.r, self.g, self.b].iter().all(|&c| (-1e-4..=1.0 + 1e-4).contains(&c))
+  }
+}
+
+impl Color {
+  pub(crate) fn to_array(self) -> [f32; 4] {
+    [self.r, self.g, self.b, self.a]
+  }
+
+  pub(crate) fn linear_rgb(&self) -> [f32; 3] {
+    [srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b)]
+  }
+
+  fn extremes(&self) -> (f32, f32) {
+    (self.r.max(self.g).max(self.b), self.r.min(self.g).min(self.b))
+  }
+
+  fn hue(&self) -> f32 {
+    let (max, min) = self.extremes();
+    let chroma = max - min;
+    if chroma == 0.0 {
+      return 0.0;
+    }
+    let sector = if max == self.r {
+      ((self.g - self.b) / chroma).rem_euclid(6.0)
+    } else if max == self.g {
+      (self.b - self.r) / chroma + 2.0
+    } else {
+      (self.r - self.g) / chroma + 4.0
+    };
+    sector * 60.0
+  }
+}
+
+// The sRGB transfer function, piecewise as in IEC 61966-2-1.
+pub(crate) fn srgb_to_linear(c: f32) -> f32 {
+  if c <= 0.04045 {
+    c / 12.92
+  } else {
+    ((c + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+pub(crate) fn linear_to_srgb(c: f32) -> f32 {
+  if c <= 0.0031308 {
+    c * 12.92
+  } else {
+    1.055 * c.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+fn from_hue_chroma(hue: f32, chroma: f32, min: f32) -> Color {
+  let sector = hue.rem_euclid(360.0) / 60.0;
+  let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+  let (r, g, b) = match sector as u32 {
+    0 => (chroma, x, 0.0),
+    1 => (x, chroma, 0.0),
+    2 => (0.0, chroma, x),
+    3 => (0.0, x, chroma),
+    4 => (x, 0.0, chroma),
+    _ => (chroma, 0.0, x),
+  };
+  Color::new(r + min, g + min, b + min, 1.0)
+}
+
+// CIELAB's compression of XYZ ratios, linear near black.
+fn lab_f(t: f32) -> f32 {
+  const DELTA: f32 = 6.0 / 29.0;
+  if t > DELTA * DELTA * DELTA {
+    t.cbrt()
+  } else {
+    t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+  }
+}
+
+fn lab_f_inverse(f: f32) -> f32 {
+  const DELTA: f32 = 6.0 / 29.0;
+  if f > DELTA {
+    f * f * f
+  } else {
+    3.0 * DELTA * DELTA * (f - 4.0 / 29.0)
+  }
+}
+
+fn mul(matrix: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+  matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
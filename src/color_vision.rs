@@ -0,0 +1,118 @@
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::error::{GestaltError, Result};
+use crate::shader::{ShaderProgram, FULLSCREEN_VERT_SHADER};
+
+// Applies a 3x3 matrix to the colour in linear light. Unless display
+// correction follows, the input is sRGB-encoded like the canvas and is
+// decoded first and encoded again after.
+const COLOR_VISION_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_input;
+uniform mat3 u_matrix;
+uniform bool u_linear_input;
+
+in vec2 v_uv;
+
+out vec4 outColor;
+
+vec3 decode(vec3 c)
+{
+  return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+}
+
+vec3 encode(vec3 c)
+{
+  return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+void main()
+{
+  vec4 color = texture(u_input, v_uv);
+  vec3 rgb = clamp(color.rgb, 0.0, 1.0);
+  if (!u_linear_input) {
+    rgb = decode(rgb);
+  }
+  // Uploaded row by row, so the row vector product applies the matrix as
+  // written in Rust.
+  rgb = clamp(rgb * u_matrix, 0.0, 1.0);
+  if (!u_linear_input) {
+    rgb = encode(rgb);
+  }
+  outColor = vec4(rgb, color.a);
+}
+"##;
+
+// The dichromacy simulated: a missing L (`Protanopia`), M (`Deuteranopia`)
+// or S (`Tritanopia`) cone type.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorVisionDeficiency {
+  Protanopia,
+  Deuteranopia,
+  Tritanopia,
+}
+
+impl ColorVisionDeficiency {
+  // Linear RGB to linear RGB, row major, from Machado, Oliveira & Fernandes
+  // (2009) at severity 1.
+  fn matrix(self) -> [f32; 9] {
+    match self {
+      ColorVisionDeficiency::Protanopia => [
+        0.152286, 1.052583, -0.204868,
+        0.114503, 0.786281, 0.099216,
+        -0.003882, -0.048116, 1.051998,
+      ],
+      ColorVisionDeficiency::Deuteranopia => [
+        0.367322, 0.860646, -0.227968,
+        0.280085, 0.672501, 0.047413,
+        -0.011820, 0.042940, 0.968881,
+      ],
+      ColorVisionDeficiency::Tritanopia => [
+        1.255528, -0.076749, -0.178779,
+        -0.078411, 0.930809, 0.147602,
+        0.004733, 0.691367, 0.303900,
+      ],
+    }
+  }
+}
+
+// A `PostProcessChain` pass previewing the image as a colour-deficient
+// observer would see it. It runs after the user's passes and before
+// display correction, so it acts on the colours the stimuli were meant to
+// have.
+pub(crate) struct ColorVisionSimulation {
+  program: ShaderProgram,
+}
+
+impl ColorVisionSimulation {
+  // `severity` from 0 to 1 mixes between normal vision and dichromacy, a
+  // rough stand-in for the anomalous trichromacies.
+  pub(crate) fn new(context: &WebGl2RenderingContext, deficiency: ColorVisionDeficiency, severity: f32) -> Result<ColorVisionSimulation> {
+    if !(0.0..=1.0).contains(&severity) {
+      return Err(GestaltError::InvalidArgument(format!("severity must be 0 to 1, got {}", severity)));
+    }
+    let mut matrix = deficiency.matrix();
+    for (index, value) in matrix.iter_mut().enumerate() {
+      let identity = if index % 4 == 0 { 1.0 } else { 0.0 };
+      *value = identity + severity * (*value - identity);
+    }
+    let mut program = ShaderProgram::new(context, FULLSCREEN_VERT_SHADER, COLOR_VISION_FRAG_SHADER)?;
+    program.set_mat3("u_matrix", &matrix)?;
+    Ok(ColorVisionSimulation { program })
+  }
+
+  // `linear_input` is whether the scene holds linear intensities, i.e.
+  // display correction is on.
+  pub(crate) fn prepare(&mut self, linear_input: bool) -> &mut ShaderProgram {
+    self.program.set_i32("u_linear_input", linear_input as i32);
+    &mut self.program
+  }
+
+  pub(crate) fn restore(&mut self) -> Result<()> {
+    self.program.restore()
+  }
+}
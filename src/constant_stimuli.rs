@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+use crate::random::Rng;
+
+pub(crate) struct ConstantState {
+  levels: Vec<f32>,
+  repetitions: u32,
+  // Level index of every trial, in running order.
+  order: Vec<usize>,
+  // Trials done.
+  current: usize,
+  trials: Vec<u32>,
+  correct: Vec<u32>,
+}
+
+impl ConstantState {
+  fn shuffle(&mut self, seed: u32) {
+    let mut order: Vec<usize> = (0..self.levels.len()).flat_map(|level| (0..self.repetitions).map(move |_| level)).collect();
+    Rng::new(seed as u64).shuffle(&mut order);
+    self.order = order;
+    self.current = 0;
+    self.trials = vec![0; self.levels.len()];
+    self.correct = vec![0; self.levels.len()];
+  }
+
+  pub(crate) fn intensity(&self) -> f32 {
+    self.order.get(self.current).map_or(f32::NAN, |&level| self.levels[level])
+  }
+
+  pub(crate) fn is_finished(&self) -> bool {
+    self.current >= self.order.len()
+  }
+
+  pub(crate) fn update(&mut self, correct: bool) {
+    let Some(&level) = self.order.get(self.current) else {
+      return;
+    };
+    self.trials[level] += 1;
+    if correct {
+      self.correct[level] += 1;
+    }
+    self.current += 1;
+  }
+}
+
+// The method of constant stimuli: every level `repetitions` times, in an
+// order shuffled from a seed, with right responses tallied per level for
+// fitting a psychometric function. Run it by hand with `intensity` and
+// `update`, or with `TrialRunner::run_constant_stimuli`.
+#[wasm_bindgen]
+pub struct ConstantStimuli {
+  state: Rc<RefCell<ConstantState>>,
+}
+
+#[wasm_bindgen]
+impl ConstantStimuli {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new(levels: &[f32], repetitions: u32, seed: u32) -> Result<ConstantStimuli> {
+    if levels.is_empty() || repetitions == 0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "constant stimuli need levels and repetitions, got {} levels x {}",
+        levels.len(),
+        repetitions
+      )));
+    }
+    let mut state = ConstantState {
+      levels: levels.to_vec(),
+      repetitions,
+      order: Vec::new(),
+      current: 0,
+      trials: Vec::new(),
+      correct: Vec::new(),
+    };
+    state.shuffle(seed);
+    Ok(ConstantStimuli {
+      state: Rc::new(RefCell::new(state)),
+    })
+  }
+
+  pub fn levels(&self) -> Vec<f32> {
+    self.state.borrow().levels.clone()
+  }
+
+  pub fn trial_count(&self) -> usize {
+    self.state.borrow().order.len()
+  }
+
+  // Trials done so far.
+  pub fn completed(&self) -> usize {
+    self.state.borrow().current
+  }
+
+  // The level of every trial in running order.
+  pub fn sequence(&self) -> Vec<f32> {
+    let state = self.state.borrow();
+    state.order.iter().map(|&level| state.levels[level]).collect()
+  }
+
+  // The level for the next trial, NaN once finished.
+  pub fn intensity(&self) -> f32 {
+    self.state.borrow().intensity()
+  }
+
+  // Records the response to the current trial and moves on. Ignored once
+  // finished.
+  pub fn update(&self, correct: bool) {
+    self.state.borrow_mut().update(correct);
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.state.borrow().is_finished()
+  }
+
+  // Per level, in the order given: trials done, right responses and their
+  // proportion, NaN for levels without trials yet.
+  pub fn trials_per_level(&self) -> Vec<u32> {
+    self.state.borrow().trials.clone()
+  }
+
+  pub fn correct_per_level(&self) -> Vec<u32> {
+    self.state.borrow().correct.clone()
+  }
+
+  pub fn proportions(&self) -> Vec<f32> {
+    let state = self.state.borrow();
+    state.correct.iter().zip(&state.trials).map(|(&correct, &trials)| correct as f32 / trials as f32).collect()
+  }
+
+  // Starts over with a new order from `seed`, clearing the tallies.
+  pub fn reshuffle(&self, seed: u32) {
+    self.state.borrow_mut().shuffle(seed);
+  }
+}
+
+impl ConstantStimuli {
+  pub(crate) fn shared(&self) -> Rc<RefCell<ConstantState>> {
+    self.state.clone()
+  }
+}
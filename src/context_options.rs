@@ -0,0 +1,105 @@
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGlContextAttributes, WebGlPowerPreference};
+
+// Which GPU the browser should pick on machines with two.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerPreference {
+  Default,
+  // The integrated GPU, to save battery.
+  LowPower,
+  // The discrete GPU, for demanding stimuli.
+  HighPerformance,
+}
+
+// How a canvas' WebGL context is set up, for `WebGlCanvas::with_options`
+// and the like. Starts out as the browser defaults and is changed by
+// chaining, e.g. `new ContextOptions().antialias(false).alpha(false)`;
+// each call returns a changed copy.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContextOptions {
+  antialias: bool,
+  alpha: bool,
+  depth: bool,
+  stencil: bool,
+  preserve_drawing_buffer: bool,
+  power_preference: PowerPreference,
+}
+
+#[wasm_bindgen]
+impl ContextOptions {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> ContextOptions {
+    ContextOptions {
+      antialias: true,
+      alpha: true,
+      depth: true,
+      stencil: false,
+      preserve_drawing_buffer: false,
+      power_preference: PowerPreference::Default,
+    }
+  }
+
+  // Multisampled edges on the canvas itself, on by default. Scenes drawn
+  // through post-processing or feedback go via textures and are not
+  // multisampled either way; turn it off for exact pixels, e.g. for
+  // gratings near the resolution limit.
+  pub fn antialias(&self, antialias: bool) -> ContextOptions {
+    ContextOptions { antialias, ..*self }
+  }
+
+  // Whether the canvas is blended with the page behind it where the scene
+  // is transparent. Off gives an opaque canvas, which browsers composite
+  // faster.
+  pub fn alpha(&self, alpha: bool) -> ContextOptions {
+    ContextOptions { alpha, ..*self }
+  }
+
+  // A depth buffer for the canvas, on by default and needed for
+  // `WebGlCanvas::set_depth_test`. Render targets always have one.
+  pub fn depth(&self, depth: bool) -> ContextOptions {
+    ContextOptions { depth, ..*self }
+  }
+
+  // A stencil buffer for the canvas, off by default and needed for
+  // `WebGlCanvas::set_stencil_mask`.
+  pub fn stencil(&self, stencil: bool) -> ContextOptions {
+    ContextOptions { stencil, ..*self }
+  }
+
+  // Keeps the drawing buffer after a frame was shown, so the page can read
+  // the canvas any time, e.g. with `toBlob`. `capture_frame` does not need
+  // this. Can cost some performance.
+  pub fn preserve_drawing_buffer(&self, preserve_drawing_buffer: bool) -> ContextOptions {
+    ContextOptions { preserve_drawing_buffer, ..*self }
+  }
+
+  pub fn power_preference(&self, power_preference: PowerPreference) -> ContextOptions {
+    ContextOptions { power_preference, ..*self }
+  }
+}
+
+impl Default for ContextOptions {
+  fn default() -> ContextOptions {
+    ContextOptions::new()
+  }
+}
+
+impl ContextOptions {
+  pub(crate) fn to_attributes(self) -> WebGlContextAttributes {
+    let attributes = WebGlContextAttributes::new();
+    attributes.set_antialias(self.antialias);
+    attributes.set_alpha(self.alpha);
+    attributes.set_depth(self.depth);
+    attributes.set_stencil(self.stencil);
+    attributes.set_preserve_drawing_buffer(self.preserve_drawing_buffer);
+    attributes.set_power_preference(match self.power_preference {
+      PowerPreference::Default => WebGlPowerPreference::Default,
+      PowerPreference::LowPower => WebGlPowerPreference::LowPower,
+      PowerPreference::HighPerformance => WebGlPowerPreference::HighPerformance,
+    });
+    attributes
+  }
+}
@@ -0,0 +1,112 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+use crate::stimuli::check_positive;
+
+// The physical setup a display is viewed in, for converting degrees of
+// visual angle to drawing buffer pixels. Hand `pixels_per_degree` to the
+// `set_units` of the stimuli and to `Camera2D` to lay them out in degrees,
+// and speeds in degrees per second follow.
+//
+// `pixels_per_degree` is exact at the centre of the screen only: a degree
+// covers more of a flat screen further out. The `_exact` conversions take
+// that into account, for large stimuli and eccentric positions.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayGeometry {
+  screen_width: f32,
+  viewing_distance: f32,
+  resolution: u32,
+}
+
+#[wasm_bindgen]
+impl DisplayGeometry {
+
+  // `screen_width` is the visible width of the screen and
+  // `viewing_distance` the distance from the eyes to its centre, both in
+  // cm; `resolution` is the screen's width in pixels, which should match
+  // the drawing buffer's pixels, i.e. count device pixels.
+  #[wasm_bindgen(constructor)]
+  pub fn new(screen_width: f32, viewing_distance: f32, resolution: u32) -> Result<DisplayGeometry> {
+    check_positive("screen width", screen_width)?;
+    check_positive("viewing distance", viewing_distance)?;
+    if resolution == 0 {
+      return Err(GestaltError::InvalidArgument("resolution must be positive, got 0".into()));
+    }
+    Ok(DisplayGeometry { screen_width, viewing_distance, resolution })
+  }
+
+  // Takes the resolution from `screen.width` and `devicePixelRatio`, for a
+  // canvas filling a screen of known size.
+  pub fn from_screen(screen_width: f32, viewing_distance: f32) -> Result<DisplayGeometry> {
+    let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+    let width = window.screen()?.width()? as f64 * window.device_pixel_ratio();
+    DisplayGeometry::new(screen_width, viewing_distance, width.round() as u32)
+  }
+
+  pub fn screen_width(&self) -> f32 {
+    self.screen_width
+  }
+
+  pub fn viewing_distance(&self) -> f32 {
+    self.viewing_distance
+  }
+
+  pub fn resolution(&self) -> u32 {
+    self.resolution
+  }
+
+  pub fn pixels_per_cm(&self) -> f32 {
+    self.resolution as f32 / self.screen_width
+  }
+
+  // At the centre of the screen.
+  pub fn pixels_per_degree(&self) -> f32 {
+    self.viewing_distance * 1f32.to_radians() * self.pixels_per_cm()
+  }
+
+  // Width of the whole screen in degrees.
+  pub fn screen_width_degrees(&self) -> f32 {
+    self.pixels_to_degrees_exact(self.resolution as f32)
+  }
+
+  pub fn degrees_to_pixels(&self, degrees: f32) -> f32 {
+    degrees * self.pixels_per_degree()
+  }
+
+  pub fn pixels_to_degrees(&self, pixels: f32) -> f32 {
+    pixels / self.pixels_per_degree()
+  }
+
+  // Pixels spanned by `degrees` centred on the line of sight, e.g. the
+  // width of a stimulus at fixation.
+  pub fn degrees_to_pixels_exact(&self, degrees: f32) -> f32 {
+    2.0 * self.viewing_distance * (degrees.to_radians() / 2.0).tan() * self.pixels_per_cm()
+  }
+
+  pub fn pixels_to_degrees_exact(&self, pixels: f32) -> f32 {
+    (2.0 * (pixels / self.pixels_per_cm() / (2.0 * self.viewing_distance)).atan()).to_degrees()
+  }
+
+  // Distance from the centre of the screen, in pixels, of a point at
+  // `eccentricity` degrees from fixation there.
+  pub fn eccentricity_to_pixels(&self, eccentricity: f32) -> f32 {
+    self.viewing_distance * eccentricity.to_radians().tan() * self.pixels_per_cm()
+  }
+
+  pub fn pixels_to_eccentricity(&self, pixels: f32) -> f32 {
+    (pixels / self.pixels_per_cm() / self.viewing_distance).atan().to_degrees()
+  }
+
+  // A speed in degrees per second as pixels per frame at `frame_rate`, at
+  // the centre of the screen.
+  pub fn pixels_per_frame(&self, degrees_per_second: f32, frame_rate: f32) -> Result<f32> {
+    check_positive("frame rate", frame_rate)?;
+    Ok(self.degrees_to_pixels(degrees_per_second) / frame_rate)
+  }
+
+  // Cycles per degree as cycles per pixel, for gratings drawn in pixels.
+  pub fn cycles_per_pixel(&self, cycles_per_degree: f32) -> f32 {
+    cycles_per_degree / self.pixels_per_degree()
+  }
+}
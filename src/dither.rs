@@ -0,0 +1,107 @@
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::error::{GestaltError, Result};
+use crate::render_target::{supported_precision, TargetPrecision};
+use crate::shader::{ShaderProgram, FULLSCREEN_VERT_SHADER};
+
+// Adds uniform noise of plus or minus half an output step before the image
+// is quantized for the display, the same for all channels so greys stay
+// grey. A hashed pixel position (and frame) makes the noise, which needs no
+// texture.
+const DITHER_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_input;
+// Steps of the output, 255 for 8 bits.
+uniform float u_levels;
+uniform int u_frame;
+
+in vec2 v_uv;
+
+out vec4 outColor;
+
+uint hash(uvec3 v)
+{
+  v = v * 1664525u + 1013904223u;
+  v.x += v.y * v.z;
+  v.y += v.z * v.x;
+  v.z += v.x * v.y;
+  v ^= v >> 16u;
+  v.x += v.y * v.z;
+  v.y += v.z * v.x;
+  v.z += v.x * v.y;
+  return v.x;
+}
+
+void main()
+{
+  vec4 color = texture(u_input, v_uv);
+  uint seed = hash(uvec3(uvec2(gl_FragCoord.xy), uint(u_frame)));
+  float noise = float(seed >> 8u) / 16777216.0 - 0.5;
+  outColor = vec4(color.rgb + noise / u_levels, color.a);
+}
+"##;
+
+// `Spatial` uses the same noise pattern every frame, `Temporal` a new one
+// each frame, which averages out over time as well as space and is less
+// visible at high refresh rates.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DitherMode {
+  Spatial,
+  Temporal,
+}
+
+// The last pass of a `PostProcessChain` when dithering is on: the "noisy
+// bit" method, which lets an 8-bit display show mean luminances between
+// its steps.
+pub(crate) struct Dithering {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  mode: DitherMode,
+  frame: i32,
+}
+
+impl Dithering {
+  // `bits` is the output depth per channel of the display.
+  pub(crate) fn new(context: &WebGl2RenderingContext, mode: DitherMode, bits: u32) -> Result<Dithering> {
+    if !(1..=16).contains(&bits) {
+      return Err(GestaltError::InvalidArgument(format!("output depth must be 1 to 16 bits, got {}", bits)));
+    }
+    enable_extension(context)?;
+    let mut program = ShaderProgram::new(context, FULLSCREEN_VERT_SHADER, DITHER_FRAG_SHADER)?;
+    program.set_f32("u_levels", ((1u32 << bits) - 1) as f32);
+    program.set_i32("u_frame", 0);
+    Ok(Dithering {
+      context: context.clone(),
+      program,
+      mode,
+      frame: 0,
+    })
+  }
+
+  // Moves temporal noise on a frame and returns the program, ready to draw.
+  pub(crate) fn prepare(&mut self) -> &mut ShaderProgram {
+    if self.mode == DitherMode::Temporal {
+      self.frame = self.frame.wrapping_add(1);
+      self.program.set_i32("u_frame", self.frame);
+    }
+    &mut self.program
+  }
+
+  pub(crate) fn restore(&mut self) -> Result<()> {
+    enable_extension(&self.context)?;
+    self.program.restore()
+  }
+}
+
+// The scene has to be drawn to float targets to keep steps finer than the
+// display's for the noise to bring out.
+fn enable_extension(context: &WebGl2RenderingContext) -> Result<()> {
+  if !supported_precision(context, TargetPrecision::HalfFloat)?.is_float() {
+    return Err(GestaltError::MissingExtension("EXT_color_buffer_float"));
+  }
+  Ok(())
+}
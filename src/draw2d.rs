@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::svg::SvgPath;
+
+const DRAW2D_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 position;
+in vec4 color;
+
+uniform vec2 u_resolution;
+
+out vec4 v_color;
+
+void main()
+{
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+  v_color = color;
+}
+"##;
+
+const DRAW2D_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+in vec4 v_color;
+
+out vec4 outColor;
+
+void main()
+{
+  outColor = v_color;
+}
+"##;
+
+// Largest distance, in pixels, between a circle and the polygon it is drawn
+// as.
+const CIRCLE_TOLERANCE: f32 = 0.25;
+
+// Immediate-mode shape drawing on top of a canvas' scene, for fixation
+// crosses, frames and the like. Coordinates are drawing-buffer pixels with
+// the origin at the top left, as in a 2D canvas. Shapes drawn between two
+// `render` calls are collected into one vertex buffer and drawn with alpha
+// blending after the canvas geometry, then dropped; with auto-clear off they
+// stay until `clear`.
+#[wasm_bindgen]
+pub struct Draw2D {
+  shapes: Rc<RefCell<Shapes>>,
+  layer: LayerSlot,
+}
+
+struct Shapes {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  color: [f32; 4],
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl Draw2D {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<Draw2D> {
+    let context = canvas.context();
+    let program = ShaderProgram::new(&context, DRAW2D_VERT_SHADER, DRAW2D_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("position", 2), ("color", 4)])?;
+    let batch = VertexBatch::new(&context, &layout)?;
+
+    let shapes = Rc::new(RefCell::new(Shapes {
+      context,
+      program,
+      batch,
+      color: [1.0, 1.0, 1.0, 1.0],
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(shapes.clone(), Layer::Overlay);
+    Ok(Draw2D { shapes, layer })
+  }
+
+  // Layer the shapes are drawn in. `Overlay` to begin with, so fixation
+  // marks and the like sit above the stimuli.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Blending of the shapes, from the next frame on. `Alpha` to begin with.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.shapes.borrow_mut().blend_mode = mode;
+  }
+
+  // Colour of the shapes drawn from now on, components 0 to 1. Starts out
+  // opaque white.
+  pub fn set_color(&self, r: f32, g: f32, b: f32, a: f32) {
+    self.shapes.borrow_mut().color = [r, g, b, a];
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.shapes.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.shapes.borrow_mut().batch.clear();
+  }
+
+  pub fn fill_rect(&self, x: f32, y: f32, width: f32, height: f32) {
+    let mut shapes = self.shapes.borrow_mut();
+    shapes.quad([(x, y), (x + width, y), (x + width, y + height), (x, y + height)]);
+  }
+
+  pub fn fill_circle(&self, x: f32, y: f32, radius: f32) {
+    let segments = circle_segments(radius);
+    let mut shapes = self.shapes.borrow_mut();
+    let point = |index: usize| {
+      let angle = index as f32 / segments as f32 * 2.0 * PI;
+      (x + radius * angle.cos(), y + radius * angle.sin())
+    };
+    for index in 0..segments {
+      shapes.triangle((x, y), point(index), point(index + 1));
+    }
+  }
+
+  // Fills a simple (not self-intersecting) polygon, convex or not, given as
+  // a flat list of x, y pairs.
+  pub fn fill_polygon(&self, points: &[f32]) -> Result<()> {
+    if points.len() < 6 || !points.len().is_multiple_of(2) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a polygon needs at least three x, y pairs, got {} floats",
+        points.len()
+      )));
+    }
+
+    let points: Vec<(f32, f32)> = points.chunks(2).map(|point| (point[0], point[1])).collect();
+    let mut shapes = self.shapes.borrow_mut();
+    for [a, b, c] in triangulate(&points) {
+      shapes.triangle(points[a], points[b], points[c]);
+    }
+    Ok(())
+  }
+
+  // Fills an SVG path, in pixels once transformed to them.
+  pub fn fill_path(&self, path: &SvgPath) {
+    let vertices = path.triangles();
+    let mut shapes = self.shapes.borrow_mut();
+    for triangle in vertices.chunks_exact(6) {
+      shapes.triangle((triangle[0], triangle[1]), (triangle[2], triangle[3]), (triangle[4], triangle[5]));
+    }
+  }
+
+  // A straight line `width` pixels wide, with square ends at the end points.
+  pub fn stroke_line(&self, x0: f32, y0: f32, x1: f32, y1: f32, width: f32) {
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0.0 {
+      return;
+    }
+    let (nx, ny) = (-dy / length * width * 0.5, dx / length * width * 0.5);
+
+    let mut shapes = self.shapes.borrow_mut();
+    shapes.quad([(x0 + nx, y0 + ny), (x1 + nx, y1 + ny), (x1 - nx, y1 - ny), (x0 - nx, y0 - ny)]);
+  }
+}
+
+impl Shapes {
+  fn triangle(&mut self, a: (f32, f32), b: (f32, f32), c: (f32, f32)) {
+    let [red, green, blue, alpha] = self.color;
+    for (x, y) in [a, b, c] {
+      self.batch.push(&[x, y, red, green, blue, alpha]);
+    }
+  }
+
+  // Corners in order around the quad.
+  fn quad(&mut self, corners: [(f32, f32); 4]) {
+    self.triangle(corners[0], corners[1], corners[2]);
+    self.triangle(corners[0], corners[2], corners[3]);
+  }
+}
+
+impl Overlay for Shapes {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw(WebGl2RenderingContext::TRIANGLES);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
+
+// Enough segments to keep within `CIRCLE_TOLERANCE` of a true circle.
+fn circle_segments(radius: f32) -> usize {
+  if radius <= CIRCLE_TOLERANCE {
+    return 8;
+  }
+  let step = 2.0 * (1.0 - CIRCLE_TOLERANCE / radius).acos();
+  ((2.0 * PI / step).ceil() as usize).clamp(8, 512)
+}
+
+// Splits a simple polygon into triangles by ear clipping. Returns vertex
+// index triples. Points repeated at another index, as where holes are
+// bridged in, do not block ears. Whatever is left when no ear can be found,
+// as happens with self-intersecting input, is fanned.
+pub(crate) fn triangulate(points: &[(f32, f32)]) -> Vec<[usize; 3]> {
+  let area: f32 = (0..points.len())
+    .map(|i| {
+      let (a, b) = (points[i], points[(i + 1) % points.len()]);
+      a.0 * b.1 - b.0 * a.1
+    })
+    .sum();
+  let orientation = if area >= 0.0 { 1.0 } else { -1.0 };
+
+  let mut remaining: Vec<usize> = (0..points.len()).collect();
+  let mut triangles = Vec::with_capacity(points.len() - 2);
+
+  while remaining.len() > 3 {
+    let count = remaining.len();
+    let ear = (0..count).find(|&i| {
+      let (a, b, c) = (remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]);
+      let (pa, pb, pc) = (points[a], points[b], points[c]);
+      cross(pa, pb, pc) * orientation > 0.0
+        && !remaining
+          .iter()
+          .any(|&j| ![pa, pb, pc].contains(&points[j]) && in_triangle(points[j], pa, pb, pc))
+    });
+
+    match ear {
+      Some(i) => {
+        triangles.push([remaining[(i + count - 1) % count], remaining[i], remaining[(i + 1) % count]]);
+        remaining.remove(i);
+      }
+      None => break,
+    }
+  }
+
+  for i in 1..remaining.len() - 1 {
+    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+  }
+  triangles
+}
+
+fn cross(a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+  (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0)
+}
+
+fn in_triangle(p: (f32, f32), a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> bool {
+  let d1 = cross(a, b, p);
+  let d2 = cross(b, c, p);
+  let d3 = cross(c, a, p);
+  let negative = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+  let positive = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+  !(negative && positive)
+}
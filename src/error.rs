@@ -0,0 +1,82 @@
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+
+use crate::shader::ShaderDiagnostic;
+
+// Everything that can go wrong inside the crate. Converts into a JavaScript
+// `Error` so exported functions can hand it straight back to the caller.
+#[derive(Debug)]
+pub enum GestaltError {
+  NoWindow,
+  NoDocument,
+  CanvasNotFound(String),
+  ElementNotFound(String),
+  NotACanvas(String),
+  WebGl2Unavailable,
+  ShaderCompile { stage: &'static str, log: String, diagnostics: Vec<ShaderDiagnostic> },
+  ProgramLink(String),
+  MissingAttribute(String),
+  ResourceCreation(&'static str),
+  // A GL object made on another canvas' context.
+  ForeignContext(&'static str),
+  // A WebGL extension the browser or GPU does not offer.
+  MissingExtension(&'static str),
+  InvalidArgument(String),
+  // An exception thrown by a browser API.
+  Js(JsValue),
+}
+
+pub type Result<T> = std::result::Result<T, GestaltError>;
+
+impl fmt::Display for GestaltError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GestaltError::NoWindow => write!(f, "no global `window` exists"),
+      GestaltError::NoDocument => write!(f, "window has no `document`"),
+      GestaltError::CanvasNotFound(id) => write!(f, "canvas id '{}' not found", id),
+      GestaltError::ElementNotFound(id) => write!(f, "element id '{}' not found", id),
+      GestaltError::NotACanvas(id) => write!(f, "element '{}' is not a <canvas>", id),
+      GestaltError::WebGl2Unavailable => write!(f, "WebGL2 is not supported by this browser; `WebGl1Canvas` offers a basic fallback"),
+      GestaltError::ShaderCompile { stage, log, .. } => write!(f, "{} shader failed to compile: {}", stage, log),
+      GestaltError::ProgramLink(log) => write!(f, "shader program failed to link: {}", log),
+      GestaltError::MissingAttribute(name) => write!(f, "vertex shader has no `{}` attribute", name),
+      GestaltError::ResourceCreation(what) => write!(f, "could not create {}", what),
+      GestaltError::ForeignContext(what) => {
+        write!(f, "{} belongs to another canvas; WebGL objects cannot be shared between canvases", what)
+      }
+      GestaltError::MissingExtension(name) => write!(f, "the WebGL extension {} is not available", name),
+      GestaltError::InvalidArgument(message) => write!(f, "{}", message),
+      GestaltError::Js(value) => match value.as_string() {
+        Some(message) => write!(f, "{}", message),
+        None => write!(f, "{:?}", value),
+      },
+    }
+  }
+}
+
+impl std::error::Error for GestaltError {}
+
+impl From<JsValue> for GestaltError {
+  fn from(value: JsValue) -> GestaltError {
+    GestaltError::Js(value)
+  }
+}
+
+impl From<GestaltError> for JsValue {
+  fn from(error: GestaltError) -> JsValue {
+    match error {
+      // Keep browser exceptions as they are, stack trace and all.
+      GestaltError::Js(value) => value,
+      // Editors can use `stage` and `diagnostics` to mark the failing lines.
+      GestaltError::ShaderCompile { stage, ref diagnostics, .. } => {
+        let js_error = js_sys::Error::new(&error.to_string());
+        let list: js_sys::Array = diagnostics.iter().map(ShaderDiagnostic::to_js).collect();
+        let _ = js_sys::Reflect::set(&js_error, &"stage".into(), &stage.into());
+        let _ = js_sys::Reflect::set(&js_error, &"diagnostics".into(), &list);
+        js_error.into()
+      }
+      error => js_sys::Error::new(&error.to_string()).into(),
+    }
+  }
+}
@@ -0,0 +1,38 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use web_sys::{Event, EventTarget};
+
+use crate::error::Result;
+
+// A DOM event listener that stays registered for as long as this value
+// lives, and is removed again when it is dropped.
+pub(crate) struct EventListener {
+  target: EventTarget,
+  kind: &'static str,
+  callback: Closure<dyn FnMut(Event)>,
+}
+
+impl EventListener {
+  pub(crate) fn new<F>(target: &EventTarget, kind: &'static str, callback: F) -> Result<EventListener>
+  where
+    F: FnMut(Event) + 'static,
+  {
+    let callback = Closure::wrap(Box::new(callback) as Box<dyn FnMut(Event)>);
+    target.add_event_listener_with_callback(kind, callback.as_ref().unchecked_ref())?;
+
+    Ok(EventListener {
+      target: target.clone(),
+      kind,
+      callback,
+    })
+  }
+}
+
+impl Drop for EventListener {
+  fn drop(&mut self) {
+    let _ = self
+      .target
+      .remove_event_listener_with_callback(self.kind, self.callback.as_ref().unchecked_ref());
+  }
+}
@@ -0,0 +1,786 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::constant_stimuli::{ConstantState, ConstantStimuli};
+use crate::error::{GestaltError, Result};
+use crate::gaze::{GazeSample, GazeSource, GazeTracker};
+use crate::session::JsJson;
+use crate::staircase::{Staircase, StaircaseState};
+use crate::stimuli::check_positive;
+use crate::trial_order::TrialOrder;
+
+// Frame intervals needed before the refresh period is estimated from them,
+// and the most kept for the estimate.
+const MIN_INTERVALS: usize = 10;
+const MAX_INTERVALS: usize = 120;
+
+// One trial as given to `TrialRunner::add_trial`.
+struct Trial {
+  params: JsValue,
+  // Frames the stimulus is shown for.
+  duration: u32,
+  // Frames after the stimulus to wait for a response, 0 for no limit.
+  response_window: u32,
+}
+
+// A psychophysical procedure choosing the intensity of each trial.
+enum Procedure {
+  Staircase(Rc<RefCell<StaircaseState>>),
+  Constant(Rc<RefCell<ConstantState>>),
+}
+
+impl Procedure {
+  fn intensity(&self) -> f32 {
+    match self {
+      Procedure::Staircase(staircase) => staircase.borrow().intensity(),
+      Procedure::Constant(constant) => constant.borrow().intensity(),
+    }
+  }
+
+  fn is_finished(&self) -> bool {
+    match self {
+      Procedure::Staircase(staircase) => staircase.borrow().is_finished(),
+      Procedure::Constant(constant) => constant.borrow().is_finished(),
+    }
+  }
+
+  fn update(&self, correct: bool) {
+    match self {
+      Procedure::Staircase(staircase) => staircase.borrow_mut().update(correct),
+      Procedure::Constant(constant) => constant.borrow_mut().update(correct),
+    }
+  }
+}
+
+// Trials made on demand from a procedure, see `TrialRunner::run_staircase`.
+struct TrialSource {
+  procedure: Procedure,
+  params: JsValue,
+  duration: u32,
+  response_window: u32,
+}
+
+impl TrialSource {
+  fn trial(&self) -> Trial {
+    let params = js_sys::Object::new();
+    if let Some(template) = self.params.dyn_ref::<js_sys::Object>() {
+      js_sys::Object::assign(&params, template);
+    }
+    let intensity = self.procedure.intensity();
+    let _ = js_sys::Reflect::set(&params, &"intensity".into(), &intensity.into());
+    Trial {
+      params: params.into(),
+      duration: self.duration,
+      response_window: self.response_window,
+    }
+  }
+}
+
+// Watches frame timestamps for dropped frames: intervals of more than one
+// and a half refresh periods, each counting as the whole periods missed.
+#[derive(Default)]
+pub(crate) struct FrameMonitor {
+  last: Option<f64>,
+  intervals: VecDeque<f64>,
+  // Nominal refresh rate; without one the period is the median interval.
+  frame_rate: Option<f64>,
+  dropped: u32,
+}
+
+impl FrameMonitor {
+  pub(crate) fn period(&self) -> Option<f64> {
+    if let Some(frame_rate) = self.frame_rate {
+      return Some(1000.0 / frame_rate);
+    }
+    if self.intervals.len() < MIN_INTERVALS {
+      return None;
+    }
+    let mut intervals: Vec<f64> = self.intervals.iter().copied().collect();
+    intervals.sort_by(f64::total_cmp);
+    Some(intervals[intervals.len() / 2])
+  }
+
+  // Takes the timestamp of a new frame, returning the frames dropped before
+  // it.
+  pub(crate) fn tick(&mut self, time: f64) -> u32 {
+    let Some(last) = self.last.replace(time) else {
+      return 0;
+    };
+    let interval = time - last;
+    let dropped = match self.period() {
+      Some(period) if interval > 1.5 * period => ((interval / period).round() as u32).saturating_sub(1),
+      _ => 0,
+    };
+    self.intervals.push_back(interval);
+    if self.intervals.len() > MAX_INTERVALS {
+      self.intervals.pop_front();
+    }
+    self.dropped += dropped;
+    dropped
+  }
+
+  pub(crate) fn dropped(&self) -> u32 {
+    self.dropped
+  }
+
+  pub(crate) fn reset(&mut self) {
+    *self = FrameMonitor {
+      frame_rate: self.frame_rate,
+      ..FrameMonitor::default()
+    };
+  }
+}
+
+// What happened in a completed trial. Onset and offset are the timestamps
+// of the first frame with and without the stimulus.
+pub(crate) struct TrialRecord {
+  index: usize,
+  params: JsValue,
+  response: Option<Response>,
+  onset: f64,
+  offset: f64,
+  // While the stimulus was shown.
+  dropped_frames: u32,
+  // From onset to the end of the trial, if the runner has a gaze tracker.
+  gaze: Option<Vec<GazeSample>>,
+}
+
+#[derive(Clone)]
+struct Response {
+  value: JsValue,
+  // Frames and milliseconds since stimulus onset.
+  frame: u32,
+  time: f64,
+}
+
+impl TrialRecord {
+  fn to_js(&self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"index".into(), &(self.index as u32).into());
+    let _ = js_sys::Reflect::set(&object, &"params".into(), &self.params);
+    let _ = js_sys::Reflect::set(&object, &"onset".into(), &self.onset.into());
+    let _ = js_sys::Reflect::set(&object, &"offset".into(), &self.offset.into());
+    let _ = js_sys::Reflect::set(&object, &"droppedFrames".into(), &self.dropped_frames.into());
+    let (value, frame, time) = match &self.response {
+      Some(response) => (response.value.clone(), response.frame.into(), response.time.into()),
+      None => (JsValue::NULL, JsValue::NULL, JsValue::NULL),
+    };
+    let _ = js_sys::Reflect::set(&object, &"response".into(), &value);
+    let _ = js_sys::Reflect::set(&object, &"responseFrame".into(), &frame);
+    let _ = js_sys::Reflect::set(&object, &"responseTime".into(), &time);
+    if let Some(gaze) = &self.gaze {
+      let samples: js_sys::Array = gaze.iter().copied().map(GazeSample::to_js).collect();
+      let _ = js_sys::Reflect::set(&object, &"gaze".into(), &samples);
+    }
+    object.into()
+  }
+}
+
+impl TrialRecord {
+  // Reads a record made by `to_js` back, e.g. from storage.
+  fn from_js(value: &JsValue) -> Result<TrialRecord> {
+    let field = |name: &str| js_sys::Reflect::get(value, &name.into());
+    let number = |name: &str| {
+      field(name)?
+        .as_f64()
+        .ok_or_else(|| GestaltError::InvalidArgument(format!("trial record has no `{}`", name)))
+    };
+    let value = field("response")?;
+    let response = if value.is_null() || value.is_undefined() {
+      None
+    } else {
+      Some(Response {
+        value,
+        frame: number("responseFrame")? as u32,
+        time: number("responseTime")?,
+      })
+    };
+    let gaze = match field("gaze")?.dyn_into::<js_sys::Array>() {
+      Ok(samples) => Some(samples.iter().map(|sample| GazeSample::from_js(&sample)).collect::<Result<Vec<_>>>()?),
+      Err(_) => None,
+    };
+    Ok(TrialRecord {
+      index: number("index")? as usize,
+      params: field("params")?,
+      response,
+      onset: number("onset")?,
+      offset: number("offset")?,
+      dropped_frames: number("droppedFrames")? as u32,
+      gaze,
+    })
+  }
+
+  // For procedures: a truthy response is right, anything else or none at
+  // all wrong.
+  fn is_correct(&self) -> bool {
+    self.response.as_ref().is_some_and(|response| response.value.is_truthy())
+  }
+}
+
+// With the fields of `to_js`.
+impl Serialize for TrialRecord {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let response = self.response.as_ref();
+    let mut record = serializer.serialize_struct("TrialRecord", 9)?;
+    record.serialize_field("index", &self.index)?;
+    record.serialize_field("params", &JsJson(&self.params))?;
+    record.serialize_field("onset", &self.onset)?;
+    record.serialize_field("offset", &self.offset)?;
+    record.serialize_field("droppedFrames", &self.dropped_frames)?;
+    record.serialize_field("response", &response.map(|response| JsJson(&response.value)))?;
+    record.serialize_field("responseFrame", &response.map(|response| response.frame))?;
+    record.serialize_field("responseTime", &response.map(|response| response.time))?;
+    match &self.gaze {
+      Some(gaze) => record.serialize_field("gaze", gaze)?,
+      None => record.skip_field("gaze")?,
+    }
+    record.end()
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+  Idle,
+  // Starts the next trial on the next frame.
+  Starting,
+  Stimulus,
+  Response,
+  Done,
+}
+
+impl Phase {
+  fn name(self) -> &'static str {
+    match self {
+      Phase::Idle | Phase::Starting => "idle",
+      Phase::Stimulus => "stimulus",
+      Phase::Response => "response",
+      Phase::Done => "done",
+    }
+  }
+}
+
+// A callback due once the runner is no longer borrowed, so that callbacks
+// may call back into it.
+enum Call {
+  Setup(usize),
+  Teardown(usize),
+  Phase(Phase, usize),
+  // A trial's record, by position in the records.
+  Record(usize),
+  Complete,
+}
+
+// What a runner's observer hears about.
+pub(crate) enum RunnerEvent<'a> {
+  // A phase name, as from `TrialRunner::phase`, and the trial it is for.
+  Phase(&'static str, usize),
+  Record(&'a TrialRecord),
+}
+
+pub(crate) type Observer = Rc<dyn Fn(RunnerEvent)>;
+
+#[derive(Default)]
+struct Callbacks {
+  setup: Option<js_sys::Function>,
+  teardown: Option<js_sys::Function>,
+  phase: Option<js_sys::Function>,
+  complete: Option<js_sys::Function>,
+}
+
+pub(crate) struct Runner {
+  trials: Vec<Trial>,
+  source: Option<TrialSource>,
+  records: Vec<TrialRecord>,
+  callbacks: Callbacks,
+  // Called with each trial's record as it completes, e.g. to store it.
+  checkpoint: Option<Rc<dyn Fn(JsValue)>>,
+  // Told about phase changes and records, e.g. to stream them elsewhere.
+  observer: Option<Observer>,
+  // Logs each trial's gaze samples with its record.
+  gaze: Option<Rc<dyn GazeSource>>,
+  phase: Phase,
+  current: usize,
+  // Frames since stimulus onset, and the onset's and offset's frame times.
+  frame: u32,
+  onset: f64,
+  offset: f64,
+  response: Option<Response>,
+  monitor: FrameMonitor,
+  // Dropped during the current trial's stimulus.
+  dropped: u32,
+}
+
+impl Runner {
+  pub(crate) fn records(&self) -> &[TrialRecord] {
+    &self.records
+  }
+
+  pub(crate) fn frame_interval(&self) -> Option<f64> {
+    self.monitor.period()
+  }
+
+  pub(crate) fn dropped_frames(&self) -> u32 {
+    self.monitor.dropped
+  }
+
+  pub(crate) fn set_checkpoint(&mut self, checkpoint: Option<Rc<dyn Fn(JsValue)>>) {
+    self.checkpoint = checkpoint;
+  }
+
+  pub(crate) fn set_observer(&mut self, observer: Option<Observer>) {
+    self.observer = observer;
+  }
+
+  // Starts the trials from the first, discarding earlier results.
+  pub(crate) fn start(&mut self) -> Result<()> {
+    if self.trials.is_empty() {
+      return Err(GestaltError::InvalidArgument("there are no trials to run".into()));
+    }
+    self.source = None;
+    self.records.clear();
+    self.current = 0;
+    self.monitor.dropped = 0;
+    self.phase = Phase::Starting;
+    Ok(())
+  }
+
+  pub(crate) fn stop(&mut self) {
+    self.phase = Phase::Idle;
+  }
+
+  // Restarts the run after the trials of `records`, replaying their
+  // responses into the procedure, if there is one. The first trial left
+  // starts on the next frame.
+  pub(crate) fn resume(&mut self, records: &js_sys::Array) -> Result<()> {
+    let records = records.iter().map(|record| TrialRecord::from_js(&record)).collect::<Result<Vec<_>>>()?;
+    match &self.source {
+      Some(source) => {
+        self.trials.clear();
+        for record in &records {
+          source.procedure.update(record.is_correct());
+          self.trials.push(Trial {
+            params: record.params.clone(),
+            duration: source.duration,
+            response_window: source.response_window,
+          });
+        }
+      }
+      None if self.trials.is_empty() => {
+        return Err(GestaltError::InvalidArgument("there are no trials to run".into()));
+      }
+      None if records.len() > self.trials.len() => {
+        return Err(GestaltError::InvalidArgument(format!(
+          "{} trial records for {} trials",
+          records.len(),
+          self.trials.len()
+        )));
+      }
+      None => {}
+    }
+    self.current = records.len();
+    self.records = records;
+    self.monitor.dropped = 0;
+    self.phase = Phase::Starting;
+    Ok(())
+  }
+
+  // Moves on by one frame at frame time `time`, returning the callbacks
+  // due.
+  fn advance(&mut self, time: f64) -> Vec<Call> {
+    let mut calls = Vec::new();
+    if matches!(self.phase, Phase::Idle | Phase::Done) {
+      // Gaps between runs are not dropped frames.
+      self.monitor.last = None;
+    } else {
+      let dropped = self.monitor.tick(time);
+      if self.phase == Phase::Stimulus {
+        self.dropped += dropped;
+      }
+    }
+
+    match self.phase {
+      Phase::Idle | Phase::Done => {}
+      Phase::Starting => self.begin_next_trial(time, &mut calls),
+      Phase::Stimulus => {
+        self.frame += 1;
+        if self.frame >= self.trials[self.current].duration {
+          self.offset = time;
+          calls.push(Call::Teardown(self.current));
+          if self.response.is_some() {
+            self.end_trial(time, &mut calls);
+          } else {
+            self.phase = Phase::Response;
+            calls.push(Call::Phase(Phase::Response, self.current));
+          }
+        }
+      }
+      Phase::Response => {
+        self.frame += 1;
+        let trial = &self.trials[self.current];
+        let timed_out = trial.response_window > 0 && self.frame >= trial.duration + trial.response_window;
+        if self.response.is_some() || timed_out {
+          self.end_trial(time, &mut calls);
+        }
+      }
+    }
+    calls
+  }
+
+  // Starts trial `current`, made first if it comes from a procedure, or
+  // ends the run if there is none.
+  fn begin_next_trial(&mut self, time: f64, calls: &mut Vec<Call>) {
+    if self.current == self.trials.len() {
+      match &self.source {
+        Some(source) if !source.procedure.is_finished() => {
+          let trial = source.trial();
+          self.trials.push(trial);
+        }
+        _ => {
+          self.phase = Phase::Done;
+          calls.push(Call::Phase(Phase::Done, self.current.saturating_sub(1)));
+          calls.push(Call::Complete);
+          return;
+        }
+      }
+    }
+
+    self.phase = Phase::Stimulus;
+    self.frame = 0;
+    self.onset = time;
+    self.dropped = 0;
+    self.response = None;
+    calls.push(Call::Setup(self.current));
+    calls.push(Call::Phase(Phase::Stimulus, self.current));
+  }
+
+  // Records the current trial and starts the next one on this same frame,
+  // if there is one.
+  fn end_trial(&mut self, time: f64, calls: &mut Vec<Call>) {
+    let record = TrialRecord {
+      index: self.current,
+      params: self.trials[self.current].params.clone(),
+      response: self.response.take(),
+      onset: self.onset,
+      offset: self.offset,
+      dropped_frames: self.dropped,
+      gaze: self.gaze.as_ref().map(|gaze| gaze.samples(self.onset, time)),
+    };
+    if let Some(source) = &self.source {
+      source.procedure.update(record.is_correct());
+    }
+    calls.push(Call::Record(self.records.len()));
+    self.records.push(record);
+    self.current += 1;
+    self.begin_next_trial(time, calls);
+  }
+}
+
+// Advances `runner` by a frame and then makes the callbacks that are due.
+pub(crate) fn advance(runner: &Rc<RefCell<Runner>>, time: f64) {
+  let calls = runner.borrow_mut().advance(time);
+  for call in calls {
+    // Observers only pass events on, so the runner may stay borrowed.
+    {
+      let runner = runner.borrow();
+      if let Some(observer) = &runner.observer {
+        match &call {
+          Call::Phase(phase, index) => observer(RunnerEvent::Phase(phase.name(), *index)),
+          Call::Record(position) => {
+            if let Some(record) = runner.records.get(*position) {
+              observer(RunnerEvent::Record(record));
+            }
+          }
+          _ => {}
+        }
+      }
+    }
+
+    if let Call::Record(position) = call {
+      let (checkpoint, record) = {
+        let runner = runner.borrow();
+        (runner.checkpoint.clone(), runner.records.get(position).map(TrialRecord::to_js))
+      };
+      if let (Some(checkpoint), Some(record)) = (checkpoint, record) {
+        checkpoint(record);
+      }
+      continue;
+    }
+
+    let (callback, arguments) = {
+      let runner = runner.borrow();
+      let params = |index: usize| runner.trials[index].params.clone();
+      match call {
+        Call::Setup(index) => (runner.callbacks.setup.clone(), [params(index), (index as u32).into()]),
+        Call::Teardown(index) => (runner.callbacks.teardown.clone(), [params(index), (index as u32).into()]),
+        Call::Phase(phase, index) => (runner.callbacks.phase.clone(), [phase.name().into(), (index as u32).into()]),
+        Call::Complete => (runner.callbacks.complete.clone(), [JsValue::UNDEFINED, JsValue::UNDEFINED]),
+        Call::Record(_) => continue,
+      }
+    };
+    if let Some(callback) = callback {
+      if let Err(error) = callback.call2(&JsValue::NULL, &arguments[0], &arguments[1]) {
+        web_sys::console::error_2(&"Trial callback failed:".into(), &error);
+      }
+    }
+  }
+}
+
+// Steps through a list of trials frame by frame. Each trial shows its
+// stimulus for a number of frames and then waits for a response for a
+// number of frames more; JS callbacks set the stimulus up and tear it down
+// and hear about every phase change. The runner moves on once per call to
+// `advance`, or once per frame when attached to a `RenderLoop`, which
+// advances it before rendering so that a trial's setup shows in the same
+// frame. Durations are counted in frames rather than milliseconds, and
+// frames the browser drops, seen as long gaps between frame timestamps, are
+// counted and logged with each trial's actual onset and offset.
+#[wasm_bindgen]
+pub struct TrialRunner {
+  runner: Rc<RefCell<Runner>>,
+}
+
+#[wasm_bindgen]
+impl TrialRunner {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> TrialRunner {
+    TrialRunner {
+      runner: Rc::new(RefCell::new(Runner {
+        trials: Vec::new(),
+        source: None,
+        records: Vec::new(),
+        callbacks: Callbacks::default(),
+        checkpoint: None,
+        observer: None,
+        gaze: None,
+        phase: Phase::Idle,
+        current: 0,
+        frame: 0,
+        onset: 0.0,
+        offset: 0.0,
+        response: None,
+        monitor: FrameMonitor::default(),
+        dropped: 0,
+      })),
+    }
+  }
+
+  // Appends a trial. `params` is passed to the callbacks as is. A response
+  // window of 0 waits for `respond` however long it takes.
+  pub fn add_trial(&self, params: JsValue, duration: u32, response_window: u32) -> Result<()> {
+    if duration == 0 {
+      return Err(GestaltError::InvalidArgument("a trial must last at least one frame".into()));
+    }
+    self.runner.borrow_mut().trials.push(Trial { params, duration, response_window });
+    Ok(())
+  }
+
+  // Appends a trial per entry of `order`, with the params at that
+  // condition index in `conditions`.
+  pub fn add_trials(&self, conditions: &js_sys::Array, order: &TrialOrder, duration: u32, response_window: u32) -> Result<()> {
+    if duration == 0 {
+      return Err(GestaltError::InvalidArgument("a trial must last at least one frame".into()));
+    }
+    if let Some(&condition) = order.as_slice().iter().find(|&&condition| condition >= conditions.length()) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "condition {} is out of range for {} conditions",
+        condition,
+        conditions.length()
+      )));
+    }
+    let mut runner = self.runner.borrow_mut();
+    for &condition in order.as_slice() {
+      let params = conditions.get(condition);
+      runner.trials.push(Trial { params, duration, response_window });
+    }
+    Ok(())
+  }
+
+  pub fn trial_count(&self) -> usize {
+    self.runner.borrow().trials.len()
+  }
+
+  // The display's refresh rate, against which dropped frames are counted.
+  // Estimated from the frame timestamps to begin with.
+  pub fn set_frame_rate(&self, frame_rate: f32) -> Result<()> {
+    check_positive("frame rate", frame_rate)?;
+    self.runner.borrow_mut().monitor.frame_rate = Some(frame_rate as f64);
+    Ok(())
+  }
+
+  // The refresh period in milliseconds, NaN until there are enough frames
+  // to estimate it.
+  pub fn frame_interval(&self) -> f64 {
+    self.runner.borrow().monitor.period().unwrap_or(f64::NAN)
+  }
+
+  // Frames dropped since the run started, stimulus or not.
+  pub fn dropped_frames(&self) -> u32 {
+    self.runner.borrow().monitor.dropped
+  }
+
+  // Removes all trials and results, stopping the run.
+  pub fn clear(&self) {
+    let mut runner = self.runner.borrow_mut();
+    runner.trials.clear();
+    runner.source = None;
+    runner.records.clear();
+    runner.phase = Phase::Idle;
+  }
+
+  // Called with `(params, index)` before the first frame of a trial's
+  // stimulus; `undefined` removes it.
+  pub fn set_setup_callback(&self, callback: Option<js_sys::Function>) {
+    self.runner.borrow_mut().callbacks.setup = callback;
+  }
+
+  // Called with `(params, index)` before the first frame after a trial's
+  // stimulus.
+  pub fn set_teardown_callback(&self, callback: Option<js_sys::Function>) {
+    self.runner.borrow_mut().callbacks.teardown = callback;
+  }
+
+  // Called with `(phase, index)` when a trial enters the "stimulus" or
+  // "response" phase, or the run is "done".
+  pub fn set_phase_callback(&self, callback: Option<js_sys::Function>) {
+    self.runner.borrow_mut().callbacks.phase = callback;
+  }
+
+  // Logs the samples of `tracker` from each trial's onset to its end with
+  // the trial's record, as `gaze: [{ x, y, time }, ...]`.
+  pub fn attach_gaze_tracker(&self, tracker: &GazeTracker) {
+    self.runner.borrow_mut().gaze = Some(tracker.source());
+  }
+
+  pub fn detach_gaze_tracker(&self) {
+    self.runner.borrow_mut().gaze = None;
+  }
+
+  // Called once all trials are done.
+  pub fn set_complete_callback(&self, callback: Option<js_sys::Function>) {
+    self.runner.borrow_mut().callbacks.complete = callback;
+  }
+
+  // Runs the trials from the first, discarding earlier results. The first
+  // trial starts on the next frame.
+  pub fn start(&self) -> Result<()> {
+    self.runner.borrow_mut().start()
+  }
+
+  // Replaces the trials with ones made from `staircase` until it finishes,
+  // and starts them. Each trial's params are a copy of `params` with an
+  // `intensity` field from the staircase. A truthy response counts as
+  // right, anything else or none at all as wrong.
+  pub fn run_staircase(&self, staircase: &Staircase, params: JsValue, duration: u32, response_window: u32) -> Result<()> {
+    self.run_procedure(Procedure::Staircase(staircase.shared()), params, duration, response_window)
+  }
+
+  // Like `run_staircase`, with the levels of `constant_stimuli` in its
+  // shuffled order.
+  pub fn run_constant_stimuli(
+    &self,
+    constant_stimuli: &ConstantStimuli,
+    params: JsValue,
+    duration: u32,
+    response_window: u32,
+  ) -> Result<()> {
+    self.run_procedure(Procedure::Constant(constant_stimuli.shared()), params, duration, response_window)
+  }
+
+  // Picks a run up again after the trials in `records`, as returned by
+  // `results()` and saved, e.g. by a `SessionStore`, before a reload. The
+  // trials have to be set up as before: the same list, or a fresh procedure
+  // made the same way, into which the saved responses are replayed.
+  pub fn resume(&self, records: &js_sys::Array) -> Result<()> {
+    self.runner.borrow_mut().resume(records)
+  }
+
+  // Abandons the run, keeping the results so far.
+  pub fn stop(&self) {
+    self.runner.borrow_mut().stop();
+  }
+
+  pub fn is_running(&self) -> bool {
+    !matches!(self.runner.borrow().phase, Phase::Idle | Phase::Done)
+  }
+
+  // "idle", "stimulus", "response" or "done".
+  pub fn phase(&self) -> String {
+    self.runner.borrow().phase.name().to_string()
+  }
+
+  // Index of the trial under way.
+  pub fn current_trial(&self) -> Option<usize> {
+    let runner = self.runner.borrow();
+    matches!(runner.phase, Phase::Stimulus | Phase::Response).then_some(runner.current)
+  }
+
+  // Moves on by one frame. `time` is the frame's timestamp in
+  // milliseconds, as passed to `requestAnimationFrame` callbacks.
+  pub fn advance(&self, time: f64) {
+    advance(&self.runner, time);
+  }
+
+  // Records the response to the current trial, the first one only.
+  // `time` is when it happened on the same clock as frame timestamps,
+  // e.g. an event's `timeStamp`. A response during the response phase ends
+  // the trial on the next frame; during the stimulus the trial ends with
+  // it. Returns whether the response was taken.
+  pub fn respond(&self, value: JsValue, time: f64) -> bool {
+    let mut runner = self.runner.borrow_mut();
+    if !matches!(runner.phase, Phase::Stimulus | Phase::Response) || runner.response.is_some() {
+      return false;
+    }
+    runner.response = Some(Response {
+      value,
+      frame: runner.frame,
+      time: time - runner.onset,
+    });
+    true
+  }
+
+  // Completed trials as `{ index, params, onset, offset, droppedFrames,
+  // response, responseFrame, responseTime }` objects, the response fields
+  // null without a response, plus `gaze` with a gaze tracker attached.
+  // Onset and offset are the timestamps of the first frame with and
+  // without the stimulus.
+  pub fn results(&self) -> js_sys::Array {
+    self.runner.borrow().records.iter().map(TrialRecord::to_js).collect()
+  }
+}
+
+impl Default for TrialRunner {
+  fn default() -> TrialRunner {
+    TrialRunner::new()
+  }
+}
+
+impl TrialRunner {
+  pub(crate) fn shared(&self) -> Rc<RefCell<Runner>> {
+    self.runner.clone()
+  }
+
+  fn run_procedure(&self, procedure: Procedure, params: JsValue, duration: u32, response_window: u32) -> Result<()> {
+    if duration == 0 {
+      return Err(GestaltError::InvalidArgument("a trial must last at least one frame".into()));
+    }
+    let mut runner = self.runner.borrow_mut();
+    runner.trials.clear();
+    runner.source = Some(TrialSource {
+      procedure,
+      params,
+      duration,
+      response_window,
+    });
+    runner.records.clear();
+    runner.current = 0;
+    runner.monitor.dropped = 0;
+    runner.phase = Phase::Starting;
+    Ok(())
+  }
+}
@@ -0,0 +1,77 @@
+use web_sys::{WebGl2RenderingContext, WebGlFramebuffer};
+
+use crate::error::Result;
+use crate::gl_state;
+use crate::render_target::{RenderTarget, TargetPrecision};
+use crate::shader::ShaderProgram;
+
+// Two render targets the scene alternates between, so each frame can sample
+// the one before it through `uniform sampler2D u_previous_frame`. After
+// drawing, the new frame is copied out to wherever the scene would have gone.
+pub(crate) struct FeedbackBuffers {
+  context: WebGl2RenderingContext,
+  unit: u32,
+  targets: Vec<RenderTarget>,
+  current: usize,
+}
+
+impl FeedbackBuffers {
+  pub(crate) fn new(context: &WebGl2RenderingContext, unit: u32) -> FeedbackBuffers {
+    FeedbackBuffers {
+      context: context.clone(),
+      unit,
+      targets: Vec::new(),
+      current: 0,
+    }
+  }
+
+  // Binds the current target for drawing and the previous frame for
+  // sampling. Resizing discards the history, as does a change of
+  // `precision`, which has to match where the frame is copied out to: float
+  // and fixed-point buffers cannot be blitted into each other.
+  pub(crate) fn begin(&mut self, program: &mut ShaderProgram, width: u32, height: u32, precision: TargetPrecision) -> Result<()> {
+    if self.targets.iter().any(|target| target.precision() != precision) {
+      self.targets.clear();
+    }
+    for target in &mut self.targets {
+      target.resize(width, height)?;
+    }
+    while self.targets.len() < 2 {
+      self.targets.push(RenderTarget::with_precision(&self.context, width, height, precision)?);
+    }
+
+    let previous = &self.targets[1 - self.current];
+    gl_state::bind_texture(&self.context, self.unit, previous.texture().raw());
+    program.set_i32("u_previous_frame", self.unit as i32);
+
+    self.targets[self.current].bind();
+    Ok(())
+  }
+
+  // Copies the frame just drawn to `destination` (the canvas for `None`),
+  // leaves that bound, and swaps the buffers.
+  pub(crate) fn finish(&mut self, destination: Option<&WebGlFramebuffer>) {
+    let Some(target) = self.targets.get(self.current) else {
+      return;
+    };
+    let (width, height) = (target.width() as i32, target.height() as i32);
+
+    self.context.bind_framebuffer(WebGl2RenderingContext::READ_FRAMEBUFFER, Some(target.framebuffer()));
+    self.context.bind_framebuffer(WebGl2RenderingContext::DRAW_FRAMEBUFFER, destination);
+    self.context.blit_framebuffer(
+      0, 0, width, height,
+      0, 0, width, height,
+      WebGl2RenderingContext::COLOR_BUFFER_BIT,
+      WebGl2RenderingContext::NEAREST,
+    );
+    self.context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, destination);
+
+    self.current = 1 - self.current;
+  }
+
+  pub(crate) fn restore(&mut self) {
+    // Recreated on the next `begin`.
+    self.targets.clear();
+    self.current = 0;
+  }
+}
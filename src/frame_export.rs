@@ -0,0 +1,204 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::animation::{self, Timeline, TimelineState, TweenState, Tweens};
+use crate::error::{GestaltError, Result};
+use crate::experiment::{self, Runner, TrialRunner};
+use crate::graphics::{read_frame, CanvasState, WebGlCanvas};
+use crate::render_target::{unbind_render_target, RenderTarget};
+use crate::stimuli::check_positive;
+
+struct Export {
+  canvas: Rc<RefCell<CanvasState>>,
+  context: WebGl2RenderingContext,
+  target: Rc<RenderTarget>,
+  runner: Option<Rc<RefCell<Runner>>>,
+  timeline: Option<Rc<RefCell<TimelineState>>>,
+  tweens: Option<Rc<RefCell<TweenState>>>,
+  callback: Option<js_sys::Function>,
+  cancelled: Rc<Cell<bool>>,
+}
+
+impl Export {
+  // Renders the frames one after another, encoding each as a PNG if
+  // `png`, and returns the PNGs.
+  async fn run(self, frame_count: u32, frame_rate: f64, start_time: f64, png: bool) -> Result<js_sys::Array> {
+    let blobs = js_sys::Array::new();
+    let (width, height) = (self.target.width(), self.target.height());
+    for index in 0..frame_count {
+      if self.cancelled.get() {
+        break;
+      }
+      let time = start_time + index as f64 * 1000.0 / frame_rate;
+      if let Some(runner) = &self.runner {
+        experiment::advance(runner, time);
+      }
+      if let Some(timeline) = &self.timeline {
+        animation::advance(timeline, time);
+      }
+      if let Some(tweens) = &self.tweens {
+        animation::advance_tweens(tweens, time);
+      }
+      self.canvas.borrow_mut().render_offscreen(time, &self.target);
+      let image = read_frame(&self.context, width, height, false);
+      unbind_render_target(&self.context);
+      let image = image?;
+
+      if png {
+        let encoded = js_sys::Promise::new(&mut |resolve, reject| {
+          if let Err(error) = image.to_blob(&resolve) {
+            let _ = reject.call1(&JsValue::NULL, &error);
+          }
+        });
+        blobs.push(&JsFuture::from(encoded).await?);
+      }
+      if let Some(callback) = &self.callback {
+        let result = callback.call3(&JsValue::NULL, &image, &index.into(), &time.into())?;
+        if let Some(promise) = result.dyn_ref::<js_sys::Promise>() {
+          JsFuture::from(promise.clone()).await?;
+        }
+      }
+    }
+    Ok(blobs)
+  }
+}
+
+// Renders a canvas' scene frame by frame with a fixed timestep into an
+// offscreen target of its own size, as fast as the frames can be made and
+// not in real time, to archive exact stimulus movies. Every frame gets the
+// timestamp it would have had at the given frame rate, and an attached
+// trial runner, timeline or tweens are advanced by the same timestamps, so
+// the same export gives the same frames. Video textures play in real time
+// and are not exact. Stop a running `RenderLoop` first, as it would render
+// in between.
+#[wasm_bindgen]
+pub struct FrameExporter {
+  canvas: Rc<RefCell<CanvasState>>,
+  context: WebGl2RenderingContext,
+  target: Rc<RenderTarget>,
+  runner: Option<Rc<RefCell<Runner>>>,
+  timeline: Option<Rc<RefCell<TimelineState>>>,
+  tweens: Option<Rc<RefCell<TweenState>>>,
+  callback: Option<js_sys::Function>,
+  // Set while an export runs, and cleared to stop it.
+  running: Rc<Cell<bool>>,
+  cancelled: Rc<Cell<bool>>,
+}
+
+#[wasm_bindgen]
+impl FrameExporter {
+
+  // Frames are `width` x `height` pixels, whatever the canvas' size.
+  pub fn new(canvas: &WebGlCanvas, width: u32, height: u32) -> Result<FrameExporter> {
+    if width == 0 || height == 0 {
+      return Err(GestaltError::InvalidArgument(format!("frames cannot be {} x {} pixels", width, height)));
+    }
+    let context = canvas.context();
+    Ok(FrameExporter {
+      canvas: canvas.state(),
+      target: Rc::new(RenderTarget::new(&context, width, height)?),
+      context,
+      runner: None,
+      timeline: None,
+      tweens: None,
+      callback: None,
+      running: Rc::new(Cell::new(false)),
+      cancelled: Rc::new(Cell::new(false)),
+    })
+  }
+
+  // Advances `runner` before each frame, as a `RenderLoop` would.
+  pub fn attach_trial_runner(&mut self, runner: &TrialRunner) {
+    self.runner = Some(runner.shared());
+  }
+
+  pub fn detach_trial_runner(&mut self) {
+    self.runner = None;
+  }
+
+  // Advances `timeline` by the frames' timestamps, so exports of keyframed
+  // stimuli are exact too.
+  pub fn attach_timeline(&mut self, timeline: &Timeline) {
+    self.timeline = Some(timeline.shared());
+  }
+
+  pub fn detach_timeline(&mut self) {
+    self.timeline = None;
+  }
+
+  pub fn attach_tweens(&mut self, tweens: &Tweens) {
+    self.tweens = Some(tweens.shared());
+  }
+
+  pub fn detach_tweens(&mut self) {
+    self.tweens = None;
+  }
+
+  // Called with `(image, index, time)` after each frame, `image` being a
+  // canvas holding the frame, e.g. to pass `new VideoFrame(image, {
+  // timestamp })` to a WebCodecs `VideoEncoder`. A returned promise is
+  // waited for before the next frame. `undefined` removes it.
+  pub fn set_frame_callback(&mut self, callback: Option<js_sys::Function>) {
+    self.callback = callback;
+  }
+
+  // Renders `frame_count` frames at `frame_rate` frames per second, the
+  // first with timestamp `start_time` in milliseconds. Resolves to the
+  // frames as PNG `Blob`s.
+  pub fn export_png(&self, frame_count: u32, frame_rate: f32, start_time: f64) -> Result<js_sys::Promise> {
+    self.export(frame_count, frame_rate, start_time, true)
+  }
+
+  // Like `export_png`, only passing the frames to the frame callback.
+  // Resolves to nothing.
+  pub fn export_frames(&self, frame_count: u32, frame_rate: f32, start_time: f64) -> Result<js_sys::Promise> {
+    self.export(frame_count, frame_rate, start_time, false)
+  }
+
+  // Stops a running export after the frame under way; it resolves to the
+  // frames made so far.
+  pub fn cancel(&self) {
+    self.cancelled.set(true);
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.running.get()
+  }
+}
+
+impl FrameExporter {
+  fn export(&self, frame_count: u32, frame_rate: f32, start_time: f64, png: bool) -> Result<js_sys::Promise> {
+    check_positive("frame rate", frame_rate)?;
+    if self.running.get() {
+      return Err(GestaltError::InvalidArgument("an export is already running".into()));
+    }
+    let export = Export {
+      canvas: self.canvas.clone(),
+      context: self.context.clone(),
+      target: self.target.clone(),
+      runner: self.runner.clone(),
+      timeline: self.timeline.clone(),
+      tweens: self.tweens.clone(),
+      callback: self.callback.clone(),
+      cancelled: self.cancelled.clone(),
+    };
+    let running = self.running.clone();
+    running.set(true);
+    self.cancelled.set(false);
+    Ok(wasm_bindgen_futures::future_to_promise(async move {
+      let blobs = export.run(frame_count, frame_rate as f64, start_time, png).await;
+      running.set(false);
+      match blobs {
+        Ok(blobs) if png => Ok(blobs.into()),
+        Ok(_) => Ok(JsValue::UNDEFINED),
+        Err(error) => Err(error.into()),
+      }
+    }))
+  }
+}
@@ -0,0 +1,124 @@
+use web_sys::WebGl2RenderingContext;
+
+use crate::error::{GestaltError, Result};
+use crate::gl_state;
+use crate::shader::{ShaderProgram, FULLSCREEN_VERT_SHADER};
+use crate::texture::{Texture, TextureFormat};
+
+// Maps the linear intensities the scene was drawn with to the values the
+// display needs to emit them. The lookup table holds one row of r, g, b
+// output values per entry, for inputs evenly spaced from 0 to 1; in between
+// entries it is interpolated linearly. Texels are fetched directly, so float
+// textures need no filtering extension.
+const GAMMA_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_input;
+uniform vec3 u_inverse_gamma;
+uniform sampler2D u_lut;
+// Entries in `u_lut`, 0 to use `u_inverse_gamma` instead.
+uniform int u_lut_size;
+
+in vec2 v_uv;
+
+out vec4 outColor;
+
+vec3 lookup(vec3 value)
+{
+  vec3 position = clamp(value, 0.0, 1.0) * float(u_lut_size - 1);
+  ivec3 below = ivec3(floor(position));
+  ivec3 above = min(below + 1, u_lut_size - 1);
+  vec3 t = position - vec3(below);
+  vec3 result;
+  for (int channel = 0; channel < 3; channel++) {
+    float low = texelFetch(u_lut, ivec2(below[channel], 0), 0)[channel];
+    float high = texelFetch(u_lut, ivec2(above[channel], 0), 0)[channel];
+    result[channel] = mix(low, high, t[channel]);
+  }
+  return result;
+}
+
+void main()
+{
+  vec4 color = texture(u_input, v_uv);
+  vec3 corrected = u_lut_size > 0
+    ? lookup(color.rgb)
+    : pow(clamp(color.rgb, 0.0, 1.0), u_inverse_gamma);
+  outColor = vec4(corrected, color.a);
+}
+"##;
+
+// The texture unit of the lookup table; unit 0 holds the pass input.
+const LUT_UNIT: u32 = 1;
+
+// The final pass of a `PostProcessChain` when display correction is on.
+pub(crate) struct GammaCorrection {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  // Kept to upload again on a restored context.
+  lut: Option<(Vec<f32>, Texture)>,
+}
+
+impl GammaCorrection {
+  pub(crate) fn new(context: &WebGl2RenderingContext) -> Result<GammaCorrection> {
+    let mut program = ShaderProgram::new(context, FULLSCREEN_VERT_SHADER, GAMMA_FRAG_SHADER)?;
+    program.set_i32("u_lut", LUT_UNIT as i32);
+    program.set_i32("u_lut_size", 0);
+    program.set_vec3("u_inverse_gamma", 1.0, 1.0, 1.0);
+    Ok(GammaCorrection {
+      context: context.clone(),
+      program,
+      lut: None,
+    })
+  }
+
+  // Compensates a display whose output is proportional to its input raised
+  // to `gamma`, per channel.
+  pub(crate) fn set_gamma(&mut self, r: f32, g: f32, b: f32) -> Result<()> {
+    if [r, g, b].iter().any(|&gamma| gamma.is_nan() || gamma <= 0.0) {
+      return Err(GestaltError::InvalidArgument(format!("gamma must be positive, got {}, {}, {}", r, g, b)));
+    }
+    self.program.set_vec3("u_inverse_gamma", 1.0 / r, 1.0 / g, 1.0 / b);
+    self.program.set_i32("u_lut_size", 0);
+    self.lut = None;
+    Ok(())
+  }
+
+  // `table` holds r, g, b output values for each of at least two entries.
+  pub(crate) fn set_lut(&mut self, table: &[f32]) -> Result<()> {
+    if !table.len().is_multiple_of(3) || table.len() < 6 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a lookup table needs r, g, b values for at least 2 entries, got {} floats",
+        table.len()
+      )));
+    }
+    let mut texture = Texture::new(&self.context)?;
+    upload_lut(&mut texture, table)?;
+    self.program.set_i32("u_lut_size", (table.len() / 3) as i32);
+    self.lut = Some((table.to_vec(), texture));
+    Ok(())
+  }
+
+  // Binds the lookup table and returns the program, ready to draw.
+  pub(crate) fn prepare(&mut self) -> &mut ShaderProgram {
+    if let Some((_, texture)) = &self.lut {
+      gl_state::bind_texture(&self.context, LUT_UNIT, texture.raw());
+    }
+    &mut self.program
+  }
+
+  pub(crate) fn restore(&mut self) -> Result<()> {
+    self.program.restore()?;
+    if let Some((table, texture)) = &mut self.lut {
+      *texture = Texture::new(&self.context)?;
+      upload_lut(texture, table)?;
+    }
+    Ok(())
+  }
+}
+
+fn upload_lut(texture: &mut Texture, table: &[f32]) -> Result<()> {
+  texture.set_f32_data((table.len() / 3) as u32, 1, TextureFormat::Rgb, table)?;
+  texture.set_filter(WebGl2RenderingContext::NEAREST);
+  Ok(())
+}
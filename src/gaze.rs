@@ -0,0 +1,164 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use web_sys::HtmlCanvasElement;
+
+use crate::error::{GestaltError, Result};
+use crate::graphics::WebGlCanvas;
+use crate::stimuli::check_positive;
+
+// How long samples are kept to begin with, in milliseconds.
+const DEFAULT_RETENTION: f64 = 60_000.0;
+
+// Where the participant looked, in normalized canvas coordinates like
+// `Mouse`, and when, on the clock of frame timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub(crate) struct GazeSample {
+  pub(crate) x: f32,
+  pub(crate) y: f32,
+  pub(crate) time: f64,
+}
+
+impl GazeSample {
+  pub(crate) fn to_js(self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"x".into(), &self.x.into());
+    let _ = js_sys::Reflect::set(&object, &"y".into(), &self.y.into());
+    let _ = js_sys::Reflect::set(&object, &"time".into(), &self.time.into());
+    object.into()
+  }
+
+  // Reads a sample made by `to_js` back.
+  pub(crate) fn from_js(value: &JsValue) -> Result<GazeSample> {
+    let number = |name: &str| {
+      js_sys::Reflect::get(value, &name.into())?
+        .as_f64()
+        .ok_or_else(|| GestaltError::InvalidArgument(format!("gaze sample has no `{}`", name)))
+    };
+    Ok(GazeSample {
+      x: number("x")? as f32,
+      y: number("y")? as f32,
+      time: number("time")?,
+    })
+  }
+}
+
+// Anything that knows where the participant looks: the canvas reads the
+// latest sample into a uniform and the trial runner logs a trial's samples
+// with its record.
+pub(crate) trait GazeSource {
+  fn latest(&self) -> Option<GazeSample>;
+  // Samples from `start` up to but not including `end`, oldest first.
+  fn samples(&self, start: f64, end: f64) -> Vec<GazeSample>;
+}
+
+struct GazeBuffer {
+  samples: VecDeque<GazeSample>,
+  // Samples older than this many milliseconds before the newest are dropped.
+  retention: f64,
+}
+
+impl GazeBuffer {
+  fn push(&mut self, sample: GazeSample) {
+    // Trackers may deliver late; keep the buffer in time order.
+    let position = self.samples.iter().rposition(|other| other.time <= sample.time).map_or(0, |index| index + 1);
+    self.samples.insert(position, sample);
+    let newest = self.samples.back().map_or(sample.time, |newest| newest.time);
+    while self.samples.front().is_some_and(|oldest| oldest.time < newest - self.retention) {
+      self.samples.pop_front();
+    }
+  }
+}
+
+impl GazeSource for RefCell<GazeBuffer> {
+  fn latest(&self) -> Option<GazeSample> {
+    self.borrow().samples.back().copied()
+  }
+
+  fn samples(&self, start: f64, end: f64) -> Vec<GazeSample> {
+    let buffer = self.borrow();
+    buffer
+      .samples
+      .iter()
+      .filter(|sample| sample.time >= start && sample.time < end)
+      .copied()
+      .collect()
+  }
+}
+
+// Takes gaze samples from an eye tracker, be it WebGazer in the page or a
+// hardware tracker relayed over a socket, and hands them to a canvas, which
+// sets `uniform vec2 u_gaze` from the latest one for gaze-contingent
+// displays, and to a trial runner, which logs each trial's samples with its
+// record. Samples are kept for a minute to begin with.
+#[wasm_bindgen]
+pub struct GazeTracker {
+  element: HtmlCanvasElement,
+  buffer: Rc<RefCell<GazeBuffer>>,
+}
+
+#[wasm_bindgen]
+impl GazeTracker {
+
+  // Samples are placed relative to `canvas`.
+  pub fn new(canvas: &WebGlCanvas) -> Result<GazeTracker> {
+    Ok(GazeTracker {
+      element: canvas.element()?,
+      buffer: Rc::new(RefCell::new(GazeBuffer {
+        samples: VecDeque::new(),
+        retention: DEFAULT_RETENTION,
+      })),
+    })
+  }
+
+  // A sample in normalized canvas coordinates, (0, 0) bottom left to
+  // (1, 1) top right, taken at `time` on the clock of frame timestamps,
+  // i.e. `performance.now()`.
+  pub fn push_gaze_sample(&self, x: f32, y: f32, time: f64) {
+    self.buffer.borrow_mut().push(GazeSample { x, y, time });
+  }
+
+  // A sample in CSS pixels from the top left of the viewport, as WebGazer
+  // and most trackers calibrated to the screen report them.
+  pub fn push_client_sample(&self, x: f64, y: f64, time: f64) {
+    let rect = self.element.get_bounding_client_rect();
+    if rect.width() <= 0.0 || rect.height() <= 0.0 {
+      return;
+    }
+    let x = (x - rect.left()) / rect.width();
+    let y = 1.0 - (y - rect.top()) / rect.height();
+    self.push_gaze_sample(x as f32, y as f32, time);
+  }
+
+  // Milliseconds of samples kept; trials longer than that lose their
+  // earliest ones.
+  pub fn set_retention(&self, milliseconds: f64) -> Result<()> {
+    check_positive("gaze retention", milliseconds as f32)?;
+    self.buffer.borrow_mut().retention = milliseconds;
+    Ok(())
+  }
+
+  // The latest sample as `{ x, y, time }`, or null before the first.
+  pub fn latest(&self) -> JsValue {
+    self.buffer.latest().map_or(JsValue::NULL, |sample| sample.to_js())
+  }
+
+  // Samples from `start` up to `end`, as `{ x, y, time }` objects.
+  pub fn samples(&self, start: f64, end: f64) -> js_sys::Array {
+    self.buffer.samples(start, end).into_iter().map(GazeSample::to_js).collect()
+  }
+
+  pub fn clear(&self) {
+    self.buffer.borrow_mut().samples.clear();
+  }
+}
+
+impl GazeTracker {
+  pub(crate) fn source(&self) -> Rc<dyn GazeSource> {
+    self.buffer.clone()
+  }
+}
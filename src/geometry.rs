@@ -0,0 +1,375 @@
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlVertexArrayObject};
+
+use crate::error::{GestaltError, Result};
+use crate::gl_state;
+use crate::shader::POSITION_LOCATION;
+use crate::stats;
+
+// What the vertices of a draw make up.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+  Triangles,
+  TriangleStrip,
+  TriangleFan,
+  // Every two vertices make a line, e.g. the edges of a wireframe.
+  Lines,
+  LineStrip,
+  LineLoop,
+  Points,
+}
+
+impl Primitive {
+  pub(crate) fn mode(self) -> u32 {
+    match self {
+      Primitive::Triangles => WebGl2RenderingContext::TRIANGLES,
+      Primitive::TriangleStrip => WebGl2RenderingContext::TRIANGLE_STRIP,
+      Primitive::TriangleFan => WebGl2RenderingContext::TRIANGLE_FAN,
+      Primitive::Lines => WebGl2RenderingContext::LINES,
+      Primitive::LineStrip => WebGl2RenderingContext::LINE_STRIP,
+      Primitive::LineLoop => WebGl2RenderingContext::LINE_LOOP,
+      Primitive::Points => WebGl2RenderingContext::POINTS,
+    }
+  }
+}
+
+// Vertex data living on the GPU, together with the vertex array object that
+// describes its layout. The vertices feed a single float attribute, e.g. the
+// `position` input of the vertex shader. With indices set, draws go through
+// `draw_elements` so vertices can be shared between primitives.
+//
+// Further attributes can be fed per instance, for drawing many copies of the
+// geometry in one call with `draw_instanced`.
+//
+// A CPU copy of the data is kept so everything can be re-uploaded after the
+// WebGL context was lost.
+pub struct Geometry {
+  context: WebGl2RenderingContext,
+  vao: WebGlVertexArrayObject,
+  vertex_buffer: WebGlBuffer,
+  index_buffer: Option<WebGlBuffer>,
+  attribute_location: u32,
+  components: i32,
+  vertices: Vec<f32>,
+  indices: Option<Vec<u32>>,
+  instance_attributes: Vec<InstanceAttribute>,
+}
+
+// An attribute that advances once every `divisor` instances instead of once
+// per vertex.
+struct InstanceAttribute {
+  location: u32,
+  components: i32,
+  divisor: u32,
+  buffer: WebGlBuffer,
+  data: Vec<f32>,
+}
+
+impl Geometry {
+  pub fn new(context: &WebGl2RenderingContext, attribute_location: u32) -> Result<Geometry> {
+    let (vao, vertex_buffer) = create_objects(context)?;
+
+    Ok(Geometry {
+      context: context.clone(),
+      vao,
+      vertex_buffer,
+      index_buffer: None,
+      attribute_location,
+      components: 2,
+      vertices: Vec::new(),
+      indices: None,
+      instance_attributes: Vec::new(),
+    })
+  }
+
+  // Two triangles covering all of clip space, fed to `position`.
+  pub fn fullscreen_quad(context: &WebGl2RenderingContext) -> Result<Geometry> {
+    let mut quad = Geometry::new(context, POSITION_LOCATION)?;
+    quad.set_vertices(&[-1.0, -1.0,
+                         1.0, -1.0,
+                         1.0,  1.0,
+                        -1.0, -1.0,
+                         1.0,  1.0,
+                        -1.0,  1.0 ], 2)?;
+    Ok(quad)
+  }
+
+  // Uploads `vertices`, replacing whatever was there before. This is cheap
+  // enough to be called every frame for animated geometry.
+  pub fn set_vertices(&mut self, vertices: &[f32], components_per_vertex: u32) -> Result<()> {
+    if !(1..=4).contains(&components_per_vertex) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "vertices need 1 to 4 components, got {}",
+        components_per_vertex
+      )));
+    }
+    if !vertices.len().is_multiple_of(components_per_vertex as usize) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "{} floats do not divide into vertices of {} components",
+        vertices.len(),
+        components_per_vertex
+      )));
+    }
+
+    self.components = components_per_vertex as i32;
+    self.vertices.clear();
+    self.vertices.extend_from_slice(vertices);
+    self.upload_vertices();
+    Ok(())
+  }
+
+  // Uploads an index list into the element array buffer, creating it on first
+  // use. Indices refer to vertices of the last `set_vertices` call.
+  pub fn set_indices(&mut self, indices: &[u32]) -> Result<()> {
+    let vertex_count = self.vertex_count();
+    if let Some(&index) = indices.iter().find(|&&index| index as i32 >= vertex_count) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "index {} is out of range for {} vertices",
+        index, vertex_count
+      )));
+    }
+
+    if self.index_buffer.is_none() {
+      self.index_buffer = Some(
+        self.context
+          .create_buffer()
+          .ok_or(GestaltError::ResourceCreation("index buffer"))?,
+      );
+    }
+
+    let stored = self.indices.get_or_insert_with(Vec::new);
+    stored.clear();
+    stored.extend_from_slice(indices);
+    self.upload_indices();
+    Ok(())
+  }
+
+  // Sets the per-instance data of the attribute at `location`, creating its
+  // buffer on first use. `divisor` instances share each value; 1 gives every
+  // instance its own.
+  pub fn set_instance_attribute(&mut self, location: u32, data: &[f32], components: u32, divisor: u32) -> Result<()> {
+    if !(1..=4).contains(&components) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "instance attributes need 1 to 4 components, got {}",
+        components
+      )));
+    }
+    if divisor == 0 {
+      return Err(GestaltError::InvalidArgument(String::from("instance divisor must be at least 1")));
+    }
+
+    let index = match self.instance_attributes.iter().position(|attribute| attribute.location == location) {
+      Some(index) => index,
+      None => {
+        let buffer = self
+          .context
+          .create_buffer()
+          .ok_or(GestaltError::ResourceCreation("instance buffer"))?;
+        self.instance_attributes.push(InstanceAttribute {
+          location,
+          components: 0,
+          divisor: 0,
+          buffer,
+          data: Vec::new(),
+        });
+        self.instance_attributes.len() - 1
+      }
+    };
+
+    let attribute = &mut self.instance_attributes[index];
+    attribute.components = components as i32;
+    attribute.divisor = divisor;
+    attribute.data.clear();
+    attribute.data.extend_from_slice(data);
+    upload_instance_attribute(&self.context, &self.vao, attribute);
+    Ok(())
+  }
+
+  pub fn remove_instance_attribute(&mut self, location: u32) {
+    if let Some(index) = self.instance_attributes.iter().position(|attribute| attribute.location == location) {
+      let attribute = self.instance_attributes.remove(index);
+      gl_state::bind_vertex_array(&self.context, &self.vao);
+      self.context.disable_vertex_attrib_array(location);
+      self.context.delete_buffer(Some(&attribute.buffer));
+    }
+  }
+
+  // Drops the index buffer, going back to drawing the vertices in order.
+  pub fn clear_indices(&mut self) {
+    if let Some(buffer) = self.index_buffer.take() {
+      gl_state::bind_vertex_array(&self.context, &self.vao);
+      self.context.bind_buffer(WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, None);
+      self.context.delete_buffer(Some(&buffer));
+    }
+    self.indices = None;
+  }
+
+  // Recreates all GL objects from the CPU copy, for use on a fresh context.
+  pub fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    let (vao, vertex_buffer) = create_objects(context)?;
+    self.context = context.clone();
+    self.vao = vao;
+    self.vertex_buffer = vertex_buffer;
+    self.upload_vertices();
+
+    self.index_buffer = None;
+    if self.indices.is_some() {
+      self.index_buffer = Some(
+        context
+          .create_buffer()
+          .ok_or(GestaltError::ResourceCreation("index buffer"))?,
+      );
+      self.upload_indices();
+    }
+
+    for attribute in &mut self.instance_attributes {
+      attribute.buffer = context
+        .create_buffer()
+        .ok_or(GestaltError::ResourceCreation("instance buffer"))?;
+      upload_instance_attribute(context, &self.vao, attribute);
+    }
+    Ok(())
+  }
+
+  // Deletes the GL objects. The CPU copy stays, but the geometry cannot be
+  // drawn afterwards.
+  pub(crate) fn delete(&self) {
+    let context = &self.context;
+    context.delete_vertex_array(Some(&self.vao));
+    context.delete_buffer(Some(&self.vertex_buffer));
+    context.delete_buffer(self.index_buffer.as_ref());
+    for attribute in &self.instance_attributes {
+      context.delete_buffer(Some(&attribute.buffer));
+    }
+  }
+
+  // Draws the geometry as primitives of the given kind, e.g. `TRIANGLES`.
+  pub fn draw(&self, mode: u32) {
+    gl_state::bind_vertex_array(&self.context, &self.vao);
+    match &self.indices {
+      Some(indices) => self.context.draw_elements_with_i32(
+        mode,
+        indices.len() as i32,
+        WebGl2RenderingContext::UNSIGNED_INT,
+        0,
+      ),
+      None => self.context.draw_arrays(mode, 0, self.vertex_count()),
+    }
+    stats::count_draw_call();
+  }
+
+  // Draws `instance_count` copies of the geometry in one call. Instance
+  // attributes tell the copies apart.
+  pub fn draw_instanced(&self, mode: u32, instance_count: i32) {
+    gl_state::bind_vertex_array(&self.context, &self.vao);
+    match &self.indices {
+      Some(indices) => self.context.draw_elements_instanced_with_i32(
+        mode,
+        indices.len() as i32,
+        WebGl2RenderingContext::UNSIGNED_INT,
+        0,
+        instance_count,
+      ),
+      None => self.context.draw_arrays_instanced(mode, 0, self.vertex_count(), instance_count),
+    }
+    stats::count_draw_call();
+  }
+
+  fn vertex_count(&self) -> i32 {
+    (self.vertices.len() / self.components as usize) as i32
+  }
+
+  fn upload_vertices(&self) {
+    let context = &self.context;
+    gl_state::bind_vertex_array(context, &self.vao);
+    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.vertex_buffer));
+
+    // See `Float32Array::view`: no allocations may happen while the view is
+    // alive, as that could move the `WebAssembly.Memory` buffer under it.
+    unsafe {
+      let vertices_view = js_sys::Float32Array::view(&self.vertices);
+
+      context.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        &vertices_view,
+        WebGl2RenderingContext::DYNAMIC_DRAW,
+      );
+    }
+
+    context.vertex_attrib_pointer_with_i32(
+      self.attribute_location,
+      self.components,
+      WebGl2RenderingContext::FLOAT,
+      false,
+      0,
+      0,
+    );
+    context.enable_vertex_attrib_array(self.attribute_location);
+  }
+
+  fn upload_indices(&self) {
+    let (Some(buffer), Some(indices)) = (&self.index_buffer, &self.indices) else {
+      return;
+    };
+
+    // The element array binding is VAO state, so bind the VAO first.
+    let context = &self.context;
+    gl_state::bind_vertex_array(context, &self.vao);
+    context.bind_buffer(WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, Some(buffer));
+
+    // Same caveat as for the vertex upload: no allocations while viewing.
+    unsafe {
+      let indices_view = js_sys::Uint32Array::view(indices);
+
+      context.buffer_data_with_array_buffer_view(
+        WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+        &indices_view,
+        WebGl2RenderingContext::DYNAMIC_DRAW,
+      );
+    }
+  }
+}
+
+impl Drop for Geometry {
+  fn drop(&mut self) {
+    self.delete();
+  }
+}
+
+fn upload_instance_attribute(context: &WebGl2RenderingContext, vao: &WebGlVertexArrayObject, attribute: &InstanceAttribute) {
+  gl_state::bind_vertex_array(context, vao);
+  context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&attribute.buffer));
+
+  // No allocations while the view into wasm memory is alive.
+  unsafe {
+    let data_view = js_sys::Float32Array::view(&attribute.data);
+
+    context.buffer_data_with_array_buffer_view(
+      WebGl2RenderingContext::ARRAY_BUFFER,
+      &data_view,
+      WebGl2RenderingContext::DYNAMIC_DRAW,
+    );
+  }
+
+  context.vertex_attrib_pointer_with_i32(
+    attribute.location,
+    attribute.components,
+    WebGl2RenderingContext::FLOAT,
+    false,
+    0,
+    0,
+  );
+  context.enable_vertex_attrib_array(attribute.location);
+  context.vertex_attrib_divisor(attribute.location, attribute.divisor);
+}
+
+fn create_objects(context: &WebGl2RenderingContext) -> Result<(WebGlVertexArrayObject, WebGlBuffer)> {
+  let vao = context
+    .create_vertex_array()
+    .ok_or(GestaltError::ResourceCreation("vertex array object"))?;
+  let vertex_buffer = context
+    .create_buffer()
+    .ok_or(GestaltError::ResourceCreation("vertex buffer"))?;
+  Ok((vao, vertex_buffer))
+}
@@ -0,0 +1,219 @@
+use std::cell::RefCell;
+
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlTexture, WebGlVertexArrayObject};
+
+// How a draw is combined with what is already in the framebuffer. Colours
+// drawn are straight, i.e. not multiplied by their alpha, except with
+// `Premultiplied`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+  // Covers the background by alpha, for transparent dots and soft-edged
+  // apertures. The default for everything drawn on top of the scene.
+  Alpha,
+  // Adds the colour, weighted by alpha, so overlapping draws sum up, e.g.
+  // superimposed gratings or light.
+  Additive,
+  // Multiplies the background by the colour, darkening it as a filter
+  // would. Alpha is ignored; fade by drawing closer to white.
+  Multiply,
+  // Like `Alpha` for colours already multiplied by their alpha, e.g. from
+  // premultiplied textures.
+  Premultiplied,
+}
+
+// What the GL was last told per context, for the state the renderers switch
+// between their draws: the program, the vertex array, the texture bound to
+// each unit, the blend mode, depth and stencil testing and the scissor
+// rectangle. Setting any of them to what it already is then costs no GL
+// call, which adds up with many overlays in a frame. Only works if
+// everything in the crate changes this state through here. `None` is not
+// known, and is always set.
+#[derive(Default)]
+struct Mirror {
+  program: Option<WebGlProgram>,
+  vertex_array: Option<WebGlVertexArrayObject>,
+  // Counted from `TEXTURE0`.
+  active_unit: Option<u32>,
+  // Indexed by unit, as far as units were used.
+  textures: Vec<Option<WebGlTexture>>,
+  blending: Option<bool>,
+  blend_mode: Option<BlendMode>,
+  depth_test: Option<bool>,
+  stencil_test: Option<bool>,
+  scissor_test: Option<bool>,
+  // As last set while the test was on.
+  scissor_box: Option<[i32; 4]>,
+  // Used instead of any blend mode asked for while set.
+  blend_override: Option<BlendMode>,
+}
+
+thread_local! {
+  // Few contexts exist at a time, so a list does.
+  static MIRRORS: RefCell<Vec<(WebGl2RenderingContext, Mirror)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn with_mirror<R>(context: &WebGl2RenderingContext, f: impl FnOnce(&mut Mirror) -> R) -> R {
+  MIRRORS.with(|mirrors| {
+    let mut mirrors = mirrors.borrow_mut();
+    let index = match mirrors.iter().position(|(known, _)| known == context) {
+      Some(index) => index,
+      None => {
+        mirrors.push((context.clone(), Mirror::default()));
+        mirrors.len() - 1
+      }
+    };
+    f(&mut mirrors[index].1)
+  })
+}
+
+pub(crate) fn use_program(context: &WebGl2RenderingContext, program: &WebGlProgram) {
+  with_mirror(context, |mirror| {
+    if mirror.program.as_ref() != Some(program) {
+      context.use_program(Some(program));
+      mirror.program = Some(program.clone());
+    }
+  });
+}
+
+pub(crate) fn bind_vertex_array(context: &WebGl2RenderingContext, vertex_array: &WebGlVertexArrayObject) {
+  with_mirror(context, |mirror| {
+    if mirror.vertex_array.as_ref() != Some(vertex_array) {
+      context.bind_vertex_array(Some(vertex_array));
+      mirror.vertex_array = Some(vertex_array.clone());
+    }
+  });
+}
+
+// Binds `texture` as the `TEXTURE_2D` of `unit`, counted from `TEXTURE0`,
+// and leaves `unit` active.
+pub(crate) fn bind_texture(context: &WebGl2RenderingContext, unit: u32, texture: &WebGlTexture) {
+  with_mirror(context, |mirror| {
+    if mirror.active_unit != Some(unit) {
+      context.active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
+      mirror.active_unit = Some(unit);
+    }
+    let unit = unit as usize;
+    if mirror.textures.len() <= unit {
+      mirror.textures.resize(unit + 1, None);
+    }
+    if mirror.textures[unit].as_ref() != Some(texture) {
+      context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(texture));
+      mirror.textures[unit] = Some(texture.clone());
+    }
+  });
+}
+
+// Binds `texture` to whichever unit is active, to upload to it or change
+// its parameters.
+pub(crate) fn bind_texture_for_update(context: &WebGl2RenderingContext, texture: &WebGlTexture) {
+  let unit = with_mirror(context, |mirror| mirror.active_unit).unwrap_or(0);
+  bind_texture(context, unit, texture);
+}
+
+// Blends the following draws by `mode`, or turns blending off for `None`.
+pub(crate) fn set_blend_mode(context: &WebGl2RenderingContext, mode: Option<BlendMode>) {
+  with_mirror(context, |mirror| {
+    let mode = mode.map(|mode| mirror.blend_override.unwrap_or(mode));
+    let blending = mode.is_some();
+    if mirror.blending != Some(blending) {
+      if blending {
+        context.enable(WebGl2RenderingContext::BLEND);
+      } else {
+        context.disable(WebGl2RenderingContext::BLEND);
+      }
+      mirror.blending = Some(blending);
+    }
+
+    let Some(mode) = mode else {
+      return;
+    };
+    if mirror.blend_mode != Some(mode) {
+      let (source, destination) = match mode {
+        BlendMode::Alpha => (WebGl2RenderingContext::SRC_ALPHA, WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA),
+        BlendMode::Additive => (WebGl2RenderingContext::SRC_ALPHA, WebGl2RenderingContext::ONE),
+        BlendMode::Multiply => (WebGl2RenderingContext::DST_COLOR, WebGl2RenderingContext::ZERO),
+        BlendMode::Premultiplied => (WebGl2RenderingContext::ONE, WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA),
+      };
+      context.blend_func(source, destination);
+      mirror.blend_mode = Some(mode);
+    }
+  });
+}
+
+// Makes the following draws that blend at all blend by `mode`, whatever
+// they ask for, until called again with `None`. Used for layers.
+pub(crate) fn set_blend_override(context: &WebGl2RenderingContext, mode: Option<BlendMode>) {
+  with_mirror(context, |mirror| mirror.blend_override = mode);
+}
+
+pub(crate) fn set_depth_test(context: &WebGl2RenderingContext, depth_test: bool) {
+  with_mirror(context, |mirror| {
+    if mirror.depth_test == Some(depth_test) {
+      return;
+    }
+    if depth_test {
+      context.enable(WebGl2RenderingContext::DEPTH_TEST);
+    } else {
+      context.disable(WebGl2RenderingContext::DEPTH_TEST);
+    }
+    mirror.depth_test = Some(depth_test);
+  });
+}
+
+pub(crate) fn set_stencil_test(context: &WebGl2RenderingContext, stencil_test: bool) {
+  with_mirror(context, |mirror| {
+    if mirror.stencil_test == Some(stencil_test) {
+      return;
+    }
+    if stencil_test {
+      context.enable(WebGl2RenderingContext::STENCIL_TEST);
+    } else {
+      context.disable(WebGl2RenderingContext::STENCIL_TEST);
+    }
+    mirror.stencil_test = Some(stencil_test);
+  });
+}
+
+// Clips the following draws to `rect`, x, y, width and height from the
+// bottom left, or to nothing for `None`.
+pub(crate) fn set_scissor(context: &WebGl2RenderingContext, rect: Option<[i32; 4]>) {
+  with_mirror(context, |mirror| {
+    let scissor_test = rect.is_some();
+    if mirror.scissor_test != Some(scissor_test) {
+      if scissor_test {
+        context.enable(WebGl2RenderingContext::SCISSOR_TEST);
+      } else {
+        context.disable(WebGl2RenderingContext::SCISSOR_TEST);
+      }
+      mirror.scissor_test = Some(scissor_test);
+    }
+
+    let Some(rect) = rect else {
+      return;
+    };
+    if mirror.scissor_box != Some(rect) {
+      context.scissor(rect[0], rect[1], rect[2], rect[3]);
+      mirror.scissor_box = Some(rect);
+    }
+  });
+}
+
+// The scissor rectangle the following draws are clipped to, if any.
+pub(crate) fn scissor(context: &WebGl2RenderingContext) -> Option<[i32; 4]> {
+  with_mirror(context, |mirror| match mirror.scissor_test {
+    Some(true) => mirror.scissor_box,
+    _ => None,
+  })
+}
+
+// Forgets what was set, e.g. for a restored context.
+pub(crate) fn reset(context: &WebGl2RenderingContext) {
+  with_mirror(context, |mirror| *mirror = Mirror::default());
+}
+
+// Drops the mirror of a context no longer drawn with.
+pub(crate) fn forget(context: &WebGl2RenderingContext) {
+  MIRRORS.with(|mirrors| mirrors.borrow_mut().retain(|(known, _)| known != context));
+}
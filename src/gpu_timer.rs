@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::{WebGl2RenderingContext, WebGlQuery};
+
+use crate::error::Result;
+
+const EXTENSION: &str = "EXT_disjoint_timer_query_webgl2";
+// From the extension, which web-sys has no constants for.
+const TIME_ELAPSED_EXT: u32 = 0x88BF;
+const GPU_DISJOINT_EXT: u32 = 0x8FBB;
+
+// Frames whose results are averaged.
+const HISTORY: usize = 60;
+// Frames of queries left in flight before the oldest are given up on.
+const MAX_PENDING: usize = 8;
+
+// The parts of a frame that are timed, in the order they are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Section {
+  Scene,
+  Overlays,
+  PostProcess,
+}
+
+const SECTIONS: usize = 3;
+
+// Times the sections of each frame on the GPU with timer queries. Results
+// come in a few frames late; frames during which the GPU was disturbed,
+// e.g. by a power state change, are dropped as the extension asks.
+pub(crate) struct GpuTimer {
+  context: WebGl2RenderingContext,
+  // The section being timed and its query.
+  active: Option<(Section, WebGlQuery)>,
+  // Queries of the frame under way.
+  frame: Vec<(Section, WebGlQuery)>,
+  pending: VecDeque<Vec<(Section, WebGlQuery)>>,
+  // Finished queries for reuse.
+  free: Vec<WebGlQuery>,
+  // Milliseconds per section of the last frames measured.
+  history: VecDeque<[f64; SECTIONS]>,
+}
+
+impl GpuTimer {
+  // None if the browser or GPU cannot time queries.
+  pub(crate) fn new(context: &WebGl2RenderingContext) -> Result<Option<GpuTimer>> {
+    if context.get_extension(EXTENSION)?.is_none() {
+      return Ok(None);
+    }
+    Ok(Some(GpuTimer {
+      context: context.clone(),
+      active: None,
+      frame: Vec::new(),
+      pending: VecDeque::new(),
+      free: Vec::new(),
+      history: VecDeque::new(),
+    }))
+  }
+
+  // Ends the section being timed, if any, and starts timing `section`.
+  pub(crate) fn begin(&mut self, section: Section) {
+    self.end_section();
+    let Some(query) = self.free.pop().or_else(|| self.context.create_query()) else {
+      return;
+    };
+    self.context.begin_query(TIME_ELAPSED_EXT, &query);
+    self.active = Some((section, query));
+  }
+
+  // Ends the frame's last section and collects results that have come in.
+  pub(crate) fn end_frame(&mut self) {
+    self.end_section();
+    if !self.frame.is_empty() {
+      self.pending.push_back(std::mem::take(&mut self.frame));
+    }
+    while self.pending.len() > MAX_PENDING {
+      for (_, query) in self.pending.pop_front().unwrap_or_default() {
+        self.context.delete_query(Some(&query));
+      }
+    }
+    self.collect();
+  }
+
+  // Mean milliseconds per section over the last frames measured.
+  pub(crate) fn means(&self) -> Option<[f64; SECTIONS]> {
+    if self.history.is_empty() {
+      return None;
+    }
+    let mut sums = [0.0; SECTIONS];
+    for frame in &self.history {
+      for (sum, time) in sums.iter_mut().zip(frame) {
+        *sum += time;
+      }
+    }
+    Some(sums.map(|sum| sum / self.history.len() as f64))
+  }
+
+  pub(crate) fn frames_measured(&self) -> usize {
+    self.history.len()
+  }
+
+  fn end_section(&mut self) {
+    if let Some((section, query)) = self.active.take() {
+      self.context.end_query(TIME_ELAPSED_EXT);
+      self.frame.push((section, query));
+    }
+  }
+
+  fn collect(&mut self) {
+    let disjoint = self.context.get_parameter(GPU_DISJOINT_EXT).map(|value| value.is_truthy()).unwrap_or(true);
+    while let Some(frame) = self.pending.front() {
+      // Queries finish in order, so the last one of a frame is enough.
+      let available = frame.last().is_some_and(|(_, query)| {
+        self
+          .context
+          .get_query_parameter(query, WebGl2RenderingContext::QUERY_RESULT_AVAILABLE)
+          .is_truthy()
+      });
+      if !available && !disjoint {
+        break;
+      }
+
+      let frame = self.pending.pop_front().unwrap_or_default();
+      let mut times = [0.0; SECTIONS];
+      for (section, query) in frame {
+        if !disjoint {
+          let nanoseconds = self.context.get_query_parameter(&query, WebGl2RenderingContext::QUERY_RESULT);
+          times[section as usize] += nanoseconds.as_f64().unwrap_or(0.0) / 1e6;
+        }
+        self.free.push(query);
+      }
+      if !disjoint {
+        self.history.push_back(times);
+        if self.history.len() > HISTORY {
+          self.history.pop_front();
+        }
+      }
+    }
+  }
+}
+
+impl Drop for GpuTimer {
+  fn drop(&mut self) {
+    if let Some((_, query)) = self.active.take() {
+      self.context.end_query(TIME_ELAPSED_EXT);
+      self.context.delete_query(Some(&query));
+    }
+    let queries = self.frame.drain(..).chain(self.pending.drain(..).flatten()).map(|(_, query)| query);
+    for query in queries.chain(self.free.drain(..)) {
+      self.context.delete_query(Some(&query));
+    }
+  }
+}
+
+// `{ scene, overlays, postProcess, total, frames }`: mean milliseconds of
+// GPU time over `frames` frames.
+pub(crate) fn means_to_js(means: [f64; SECTIONS], frames: usize) -> JsValue {
+  let object = js_sys::Object::new();
+  let _ = js_sys::Reflect::set(&object, &"scene".into(), &means[Section::Scene as usize].into());
+  let _ = js_sys::Reflect::set(&object, &"overlays".into(), &means[Section::Overlays as usize].into());
+  let _ = js_sys::Reflect::set(&object, &"postProcess".into(), &means[Section::PostProcess as usize].into());
+  let _ = js_sys::Reflect::set(&object, &"total".into(), &means.iter().sum::<f64>().into());
+  let _ = js_sys::Reflect::set(&object, &"frames".into(), &(frames as u32).into());
+  object.into()
+}
@@ -1,186 +1,1513 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::JsCast;
+
+use crate::audio::{AudioAnalyser, AudioTexture, AUDIO_TEXTURE_UNIFORM};
+use crate::camera::{Camera, Camera2D, Camera3D};
+use crate::color::Color;
+use crate::color_vision::ColorVisionDeficiency;
+use crate::context_options::ContextOptions;
+use crate::dither::DitherMode;
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+use crate::feedback::FeedbackBuffers;
+use crate::gaze::{GazeSource, GazeTracker};
+use crate::geometry::{Geometry, Primitive};
+use crate::gl_state::{self, BlendMode};
+use crate::gpu_timer::{self, GpuTimer, Section};
+use crate::input::{Mouse, MouseState, PointerState, Pointers, MAX_SHADER_POINTERS};
+use crate::layers::{Layer, LayerSettings, LayerSlot};
+use crate::math::{Mat3, Mat4};
+use crate::post_process::PostProcessChain;
+use crate::render_target::{supported_precision, RenderTarget, TargetPrecision};
+use crate::resources;
+use crate::shader::{ActiveVariable, ShaderProgram, FULLSCREEN_VERT_SHADER, MODEL_VIEW_PROJECTION, POSITION_LOCATION};
+use crate::shadertoy::{self, Shadertoy};
+use crate::stencil::{StencilMask, StencilMode};
+use crate::texture::{Texture, TextureFormat, VideoTexture};
+use crate::viewport::{self, Viewport};
+
+use web_sys::{WebGl2RenderingContext, WebGlTexture};
+
+// Shaders used by `WebGlCanvas::new`.
+pub(crate) const DEFAULT_VERT_SHADER: &str = r##"#version 300 es
 
-use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlShader, WebGlUniformLocation};
+in vec2 position;
+
+out vec2 Position;
+
+void main()
+{
+  gl_Position = vec4(position, 0.0, 1.0);
+}
+"##;
+
+pub(crate) const DEFAULT_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform float u_time;
+
+in vec2 Position;
+
+out vec4 outColor;
+
+void main()
+{
+  float x = Position.x;
+  float y = Position.y;
+  vec3 modColour = (0.5*sin(u_time + x*y)+0.5)*vec3(1.0, 1.0, 1.0);
+  outColor = vec4(modColour, 1.0);
+}
+"##;
 
+// How `WebGlCanvas::enter_fullscreen` fits the canvas to the screen. Both
+// keep one drawing buffer pixel per device pixel, so sizes in pixels and
+// degrees of visual angle stay as they were.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FullscreenMode {
+  // The drawing buffer keeps its size and is centred, unscaled, with black
+  // bars around it; a buffer larger than the screen is cropped.
+  Letterbox,
+  // The drawing buffer is resized to fill the screen, showing more of the
+  // scene around its centre.
+  Fill,
+}
+
+// Handle exported to JavaScript. The GL state lives behind a shared pointer so
+// that helpers such as `RenderLoop` can drive the same canvas.
+//
+// A page can hold any number of canvases, e.g. a stimulus and a preview
+// panel. Each has a WebGL context of its own, so textures and render targets
+// belong to the canvas that made them and are refused by any other; load
+// them once per canvas instead. Whatever draws on one canvas with the same
+// shaders shares the compiled programs.
 #[wasm_bindgen]
 pub struct WebGlCanvas {
-  canvas: web_sys::HtmlCanvasElement,
+  state: Rc<RefCell<CanvasState>>,
+  resize_listener: RefCell<Option<ResizeListener>>,
+  fullscreen_listener: RefCell<Option<EventListener>>,
+  context_listeners: RefCell<Vec<EventListener>>,
+}
+
+pub(crate) struct CanvasState {
+  canvas: Surface,
   context: WebGl2RenderingContext,
-  vert_shader: WebGlShader,
-  frag_shader: WebGlShader,
-  program: WebGlProgram,
-  vertices: [f32; 6],
+  program: ShaderProgram,
+  geometry: Geometry,
+  post_process: PostProcessChain,
+  feedback: Option<FeedbackBuffers>,
+  mouse: Option<Rc<RefCell<MouseState>>>,
+  pointers: Option<Rc<RefCell<Vec<PointerState>>>>,
+  gaze: Option<Rc<dyn GazeSource>>,
+  shadertoy: Option<Shadertoy>,
+  // Draw this many instances of `geometry` instead of a single one.
+  instance_count: Option<u32>,
+  primitive: Primitive,
+  // Sets the model-view-projection matrix each frame, for the current size.
+  camera: Option<Camera>,
+  depth_test: bool,
+  clear_depth: f32,
+  stencil_mask: Option<StencilMask>,
+  // Where the geometry goes, the whole drawing buffer unless set.
+  viewport: Option<Viewport>,
+  // Clips the geometry and overlays.
+  scissor: Option<Viewport>,
+  clear_color: Color,
+  clear_enabled: bool,
+  // How the geometry is blended over the clear colour; not at all unless
+  // set.
+  blend_mode: Option<BlendMode>,
+  context_lost: bool,
+  // Set by `destroy`, after which nothing is drawn.
+  destroyed: bool,
+  // Textures bound to sampler uniforms, by texture unit.
+  textures: HashMap<u32, WebGlTexture>,
+  videos: Vec<VideoTexture>,
+  audio: Option<AudioTexture>,
+  // Screenshots requested for the next frame rendered.
+  captures: Vec<Capture>,
+  gpu_timer: Option<GpuTimer>,
+  // Drawn on top of the geometry each frame, layer by layer, for as long
+  // as their owners (e.g. a `Draw2D`) are alive.
+  overlays: Vec<(Weak<RefCell<dyn Overlay>>, LayerSlot)>,
+  // Indexed by `Layer::index`.
+  layers: [LayerSettings; 4],
+  // The timestamp passed to `render` for the frame being drawn or last
+  // drawn, in milliseconds at full precision, for overlays that time
+  // events against frames.
+  frame_clock: Rc<Cell<f64>>,
+}
+
+// A pending `capture_frame`, settled once the next frame is drawn.
+struct Capture {
+  data_url: bool,
+  resolve: js_sys::Function,
+  reject: js_sys::Function,
+}
+
+// Something drawn into the scene after the canvas' own geometry.
+pub(crate) trait Overlay {
+  // Draws into the current framebuffer of `width` x `height` pixels. `time`
+  // is in seconds, as passed to `render`.
+  fn draw(&mut self, width: u32, height: u32, time: f32);
+  // Rebuilds GL objects on a restored context.
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()>;
 }
 
 // Public methods, exported to JavaScript.
 #[wasm_bindgen]
 impl WebGlCanvas {
 
-  pub fn new(canvas_id: &str) -> Result<WebGlCanvas, JsValue> {
-    let document = web_sys::window().unwrap().document().unwrap();
-    let canvas = document.get_element_by_id(&canvas_id).unwrap();
-    let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+  pub fn new(canvas_id: &str) -> Result<WebGlCanvas> {
+    WebGlCanvas::with_shaders(canvas_id, DEFAULT_VERT_SHADER, DEFAULT_FRAG_SHADER)
+  }
 
-    let context = canvas
-        .get_context("webgl2")?
-        .unwrap()
-        .dyn_into::<WebGl2RenderingContext>()?;
-
-    let vert_shader = compile_shader(
-        &context,
-        WebGl2RenderingContext::VERTEX_SHADER,
-        r##"#version 300 es
- 
-        in vec2 position;
-    
-        out vec2 Position;
-    
-        void main()
-        {
-          gl_Position = vec4(position, 0.0, 1.0);
-        }
-        "##,
-    )?;
-
-    let frag_shader = compile_shader(
-        &context,
-        WebGl2RenderingContext::FRAGMENT_SHADER,
-        r##"#version 300 es
-        precision highp float;
-
-        uniform float u_time;
-        
-        in vec2 Position;
-    
-        out vec4 outColor;
-    
-        void main()
-        {
-          float x = Position.x;
-          float y = Position.y;
-          vec3 modColour = (0.5*sin(u_time + x*y)+0.5)*vec3(1.0, 1.0, 1.0);
-          outColor = vec4(modColour, 1.0);
+  // Like `new`, but compiles the given GLSL sources instead of the built-in
+  // shaders. Compile and link errors are returned as the GL info log.
+  pub fn with_shaders(canvas_id: &str, vert_src: &str, frag_src: &str) -> Result<WebGlCanvas> {
+    WebGlCanvas::with_options(canvas_id, vert_src, frag_src, &ContextOptions::new())
+  }
+
+  // Like `with_shaders`, setting up the WebGL context as `options` say.
+  pub fn with_options(canvas_id: &str, vert_src: &str, frag_src: &str, options: &ContextOptions) -> Result<WebGlCanvas> {
+    let document = document()?;
+    let canvas = document
+        .get_element_by_id(canvas_id)
+        .ok_or_else(|| GestaltError::CanvasNotFound(canvas_id.to_string()))?
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .map_err(|_| GestaltError::NotACanvas(canvas_id.to_string()))?;
+    WebGlCanvas::on_surface(Surface::Element(canvas), vert_src, frag_src, options)
+  }
+
+  // Draws into an `OffscreenCanvas`, for rendering in a worker: the page
+  // calls `transfer_canvas_to_offscreen` and posts the result to a worker,
+  // which loads this module and passes it here. Frames are timed and drawn
+  // off the main thread, out of the way of its layout and garbage
+  // collection. There is no page to listen to or measure in a worker, so
+  // input, fullscreen and `observe_resize` are not available; forward those
+  // from the page with `postMessage` and size the canvas with
+  // `set_drawing_buffer_size`. Frame timestamps are on the worker's clock,
+  // which starts when the worker does.
+  pub fn from_offscreen(canvas: web_sys::OffscreenCanvas) -> Result<WebGlCanvas> {
+    WebGlCanvas::from_offscreen_with_shaders(canvas, DEFAULT_VERT_SHADER, DEFAULT_FRAG_SHADER)
+  }
+
+  pub fn from_offscreen_with_shaders(canvas: web_sys::OffscreenCanvas, vert_src: &str, frag_src: &str) -> Result<WebGlCanvas> {
+    WebGlCanvas::from_offscreen_with_options(canvas, vert_src, frag_src, &ContextOptions::new())
+  }
+
+  pub fn from_offscreen_with_options(
+    canvas: web_sys::OffscreenCanvas,
+    vert_src: &str,
+    frag_src: &str,
+    options: &ContextOptions,
+  ) -> Result<WebGlCanvas> {
+    WebGlCanvas::on_surface(Surface::Offscreen(canvas), vert_src, frag_src, options)
+  }
+
+  // `fullscreen_shader` on an `OffscreenCanvas`.
+  pub fn offscreen_fullscreen_shader(canvas: web_sys::OffscreenCanvas, frag_src: &str) -> Result<WebGlCanvas> {
+    let canvas = WebGlCanvas::from_offscreen_with_shaders(canvas, FULLSCREEN_VERT_SHADER, frag_src)?;
+    canvas.use_fullscreen_quad()?;
+    Ok(canvas)
+  }
+
+  // Runs `frag_src` over the whole canvas, drawn as two triangles. The shader
+  // gets `in vec2 v_uv`, running from (0, 0) at the bottom left to (1, 1) at
+  // the top right, plus the usual `u_time`.
+  pub fn fullscreen_shader(canvas_id: &str, frag_src: &str) -> Result<WebGlCanvas> {
+    WebGlCanvas::fullscreen_shader_with_options(canvas_id, frag_src, &ContextOptions::new())
+  }
+
+  pub fn fullscreen_shader_with_options(canvas_id: &str, frag_src: &str, options: &ContextOptions) -> Result<WebGlCanvas> {
+    let canvas = WebGlCanvas::with_options(canvas_id, FULLSCREEN_VERT_SHADER, frag_src, options)?;
+    canvas.use_fullscreen_quad()?;
+    Ok(canvas)
+  }
+
+  // Hosts a Shadertoy shader: `src` defines `mainImage(out vec4, in vec2)`
+  // and may use `iResolution`, `iTime`, `iTimeDelta`, `iFrame`, `iMouse`,
+  // `iDate` and `iChannel0..3`, all of which are provided. It is drawn over
+  // the whole canvas. Attach channel textures with `set_shadertoy_channel`.
+  pub fn shadertoy(canvas_id: &str, src: &str) -> Result<WebGlCanvas> {
+    let canvas = WebGlCanvas::fullscreen_shader(canvas_id, &shadertoy::wrap_source(src))?;
+    let mouse = Mouse::new(&canvas)?;
+    canvas.state.borrow_mut().shadertoy = Some(Shadertoy::new(mouse));
+    Ok(canvas)
+  }
+
+  // Binds `texture` to `iChannel<channel>`, for channels 0 to 3.
+  pub fn set_shadertoy_channel(&self, channel: u32, texture: &Texture) -> Result<()> {
+    if channel > 3 {
+      return Err(GestaltError::InvalidArgument(format!("Shadertoy has channels 0 to 3, not {}", channel)));
+    }
+    let mut state = self.state.borrow_mut();
+    let state = &mut *state;
+    let shadertoy = state
+      .shadertoy
+      .as_mut()
+      .ok_or_else(|| GestaltError::InvalidArgument(String::from("canvas is not in Shadertoy mode")))?;
+    texture.check_context(&state.context)?;
+    shadertoy.set_channel_resolution(channel as usize, texture.width(), texture.height());
+    state.set_uniform_texture(&format!("iChannel{}", channel), texture.raw(), channel);
+    Ok(())
+  }
+
+  // Compiles `frag_src` and swaps it in for the current fragment shader,
+  // keeping uniform values, texture bindings and attribute locations. If it
+  // fails to compile or link, the error is returned and the old shader stays
+  // in use. In Shadertoy mode `frag_src` is a `mainImage` source as well.
+  pub fn replace_fragment_shader(&self, frag_src: &str) -> Result<()> {
+    self.state.borrow_mut().replace_fragment_shader(frag_src)
+  }
+
+  // The active uniforms of the current program, as `{ name, type, glType,
+  // size, location }` objects. `type` is the GLSL type name, e.g. "float".
+  pub fn uniforms(&self) -> js_sys::Array {
+    self.state.borrow().program.active_uniforms().iter().map(ActiveVariable::to_js).collect()
+  }
+
+  // The active vertex attributes, in the same form as `uniforms`.
+  pub fn attributes(&self) -> js_sys::Array {
+    self.state.borrow().program.active_attributes().iter().map(ActiveVariable::to_js).collect()
+  }
+
+  // True between a `webglcontextlost` event and the matching restore. All GL
+  // objects and uniform values are rebuilt on restore, but image textures
+  // have to be uploaded again.
+  pub fn is_context_lost(&self) -> bool {
+    self.state.borrow().context_lost
+  }
+
+  // Times the scene, the overlays and post-processing of every frame on the
+  // GPU, to tell whether dropped frames are down to the GPU or to the
+  // script. Returns false where the GPU or browser cannot time, often for
+  // privacy reasons.
+  pub fn enable_gpu_timing(&self) -> Result<bool> {
+    let mut state = self.state.borrow_mut();
+    if state.gpu_timer.is_none() {
+      state.gpu_timer = GpuTimer::new(&state.context)?;
+    }
+    Ok(state.gpu_timer.is_some())
+  }
+
+  pub fn disable_gpu_timing(&self) {
+    self.state.borrow_mut().gpu_timer = None;
+  }
+
+  // `{ scene, overlays, postProcess, total, frames }`, the mean GPU
+  // milliseconds over the last `frames` frames measured, up to 60. Results
+  // lag a few frames behind. Null until there are any.
+  pub fn gpu_timings(&self) -> JsValue {
+    let state = self.state.borrow();
+    match state.gpu_timer.as_ref().and_then(|timer| Some((timer.means()?, timer.frames_measured()))) {
+      Some((means, frames)) => gpu_timer::means_to_js(means, frames),
+      None => JsValue::NULL,
+    }
+  }
+
+  // Hides or shows everything drawn in `layer`, e.g. debug info. Hidden
+  // renderers still run with their drawing discarded, so animations and
+  // timed sequences carry on as if shown.
+  pub fn set_layer_visible(&self, layer: Layer, visible: bool) {
+    self.state.borrow_mut().layers[layer.index()].visible = visible;
+  }
+
+  pub fn is_layer_visible(&self, layer: Layer) -> bool {
+    self.state.borrow().layers[layer.index()].visible
+  }
+
+  // Blends everything in `layer` by `mode` instead of each renderer's own
+  // blend mode; `undefined` goes back to those.
+  pub fn set_layer_blend_mode(&self, layer: Layer, mode: Option<BlendMode>) {
+    self.state.borrow_mut().layers[layer.index()].blend_mode = mode;
+  }
+
+  // Colour the canvas is cleared to before each frame, black by default;
+  // `Color::mid_gray()` matches the stimulus renderers' backgrounds.
+  pub fn set_clear_color(&self, color: &Color) {
+    self.state.borrow_mut().clear_color = *color;
+  }
+
+  // Whether each frame starts from the clear colour, as it does by default.
+  // Off draws over the previous frame, for accumulation effects such as
+  // trails; the drawing buffer only keeps it with
+  // `ContextOptions::preserve_drawing_buffer` or post-processing. Depth and
+  // stencil are still cleared when in use.
+  pub fn set_clear_enabled(&self, enabled: bool) {
+    self.state.borrow_mut().clear_enabled = enabled;
+  }
+
+  // Blends the canvas' geometry by `mode`, e.g. `Additive` for instances
+  // that should sum where they overlap. `undefined` draws it opaque, as it
+  // is by default.
+  pub fn set_blend_mode(&self, mode: Option<BlendMode>) {
+    self.state.borrow_mut().blend_mode = mode;
+  }
+
+  pub fn render(&self, time: f32) {
+    self.state.borrow_mut().render(time as f64);
+  }
+
+  // Deletes the canvas' GL objects and stops listening for its events now,
+  // instead of once the canvas and everything drawing it, such as a
+  // `RenderLoop`, are freed; e.g. when a single-page app leaves the page.
+  // Renderers drawing on top keep their objects until they are freed
+  // themselves. The canvas draws nothing afterwards.
+  pub fn destroy(&self) {
+    self.resize_listener.borrow_mut().take();
+    self.fullscreen_listener.borrow_mut().take();
+    self.context_listeners.borrow_mut().clear();
+    self.state.borrow_mut().destroy();
+  }
+
+  pub fn is_destroyed(&self) -> bool {
+    self.state.borrow().destroyed
+  }
+
+  // A PNG of the next frame rendered, as it appears on screen, for
+  // documenting stimuli. Resolves to a `Blob` once the frame is drawn, so a
+  // render loop has to be running or `render` called.
+  pub fn capture_frame(&self) -> js_sys::Promise {
+    self.request_capture(false)
+  }
+
+  // Like `capture_frame`, resolving to a `data:image/png` URL instead.
+  pub fn capture_frame_data_url(&self) -> js_sys::Promise {
+    self.request_capture(true)
+  }
+
+  // Sizes the drawing buffer to the canvas' CSS size times
+  // `devicePixelRatio` and resets the viewport to cover it, so output stays
+  // sharp on HiDPI screens. Returns whether the buffer size changed.
+  pub fn handle_resize(&self) -> bool {
+    self.state.borrow_mut().resize()
+  }
+
+  // Sets the drawing buffer to `width` x `height` pixels and resets the
+  // viewport to cover it, for an `OffscreenCanvas`, which cannot measure
+  // itself, or a fixed resolution on the page.
+  pub fn set_drawing_buffer_size(&self, width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "drawing buffer size must be positive, got {}x{}",
+        width, height
+      )));
+    }
+    self.state.borrow_mut().set_size(width, height);
+    Ok(())
+  }
+
+  // Calls `handle_resize` automatically whenever the canvas' layout size
+  // changes, and once right away.
+  pub fn observe_resize(&self) -> Result<()> {
+    if self.resize_listener.borrow().is_some() {
+      return Ok(());
+    }
+    let canvas = self.element()?;
+
+    let state = Rc::downgrade(&self.state);
+    let callback = Closure::wrap(Box::new(move || {
+      if let Some(state) = state.upgrade() {
+        state.borrow_mut().resize();
+      }
+    }) as Box<dyn FnMut()>);
+
+    let observer = web_sys::ResizeObserver::new(callback.as_ref().unchecked_ref())?;
+    observer.observe(&canvas);
+
+    *self.resize_listener.borrow_mut() = Some(ResizeListener { observer, _callback: callback });
+    Ok(())
+  }
+
+  pub fn unobserve_resize(&self) {
+    self.resize_listener.borrow_mut().take();
+  }
+
+  // Shows the canvas fullscreen, fitted to the screen by `mode`. Browsers
+  // only allow this from a user gesture such as a click or key press. The
+  // canvas is set back when fullscreen ends, whether by `exit_fullscreen`
+  // or the user pressing Escape.
+  pub fn enter_fullscreen(&self, mode: FullscreenMode) -> Result<()> {
+    let document = document()?;
+    let canvas = self.element()?;
+    let state = Rc::downgrade(&self.state);
+    let change_document = document.clone();
+    let change_canvas = canvas.clone();
+    let listener = EventListener::new(&document, "fullscreenchange", move |_| {
+      let Some(state) = state.upgrade() else {
+        return;
+      };
+      let mut state = state.borrow_mut();
+      let fullscreen = change_document.fullscreen_element().is_some_and(|element| element == *change_canvas.as_ref());
+      let result = match (fullscreen, mode) {
+        // The browser stretches fullscreen elements; this keeps the pixels
+        // as they are.
+        (true, FullscreenMode::Letterbox) => change_canvas.style().set_property("object-fit", "none"),
+        _ => change_canvas.style().remove_property("object-fit").map(|_| ()),
+      };
+      if let Err(error) = result {
+        web_sys::console::error_2(&"Failed to fit the canvas to the screen:".into(), &error);
+      }
+      if mode == FullscreenMode::Fill {
+        state.resize();
+      }
+    })?;
+    *self.fullscreen_listener.borrow_mut() = Some(listener);
+    canvas.request_fullscreen()?;
+    Ok(())
+  }
+
+  pub fn exit_fullscreen(&self) -> Result<()> {
+    let document = document()?;
+    if self.is_fullscreen() {
+      document.exit_fullscreen();
+    }
+    Ok(())
+  }
+
+  pub fn is_fullscreen(&self) -> bool {
+    let Ok(canvas) = self.element() else {
+      return false;
+    };
+    document()
+      .ok()
+      .and_then(|document| document.fullscreen_element())
+      .is_some_and(|element| element == *canvas.as_ref())
+  }
+
+  // Replaces the drawn vertices. `vertices` is a flat list with
+  // `components_per_vertex` floats per vertex, fed to the `position`
+  // attribute. May be called every frame.
+  pub fn set_vertices(&self, vertices: &[f32], components_per_vertex: u32) -> Result<()> {
+    self.state.borrow_mut().set_vertices(vertices, components_per_vertex)
+  }
+
+  // Switches to indexed drawing: `indices` pick vertices from the last
+  // `set_vertices` call, so shared vertices need only be uploaded once.
+  pub fn set_indices(&self, indices: &[u32]) -> Result<()> {
+    self.state.borrow_mut().set_indices(indices)
+  }
+
+  pub fn clear_indices(&self) {
+    self.state.borrow_mut().clear_indices();
+  }
+
+  // Feeds `data` to the vertex shader input `name` once per instance (or per
+  // `divisor` instances), with `components` floats each. Only used when
+  // drawing instanced, see `set_instance_count`.
+  pub fn set_instance_attribute(&self, name: &str, data: &[f32], components: u32, divisor: u32) -> Result<()> {
+    let mut state = self.state.borrow_mut();
+    let location = state
+      .program
+      .attribute_location(name)
+      .ok_or_else(|| GestaltError::MissingAttribute(name.to_string()))?;
+    state.geometry.set_instance_attribute(location, data, components, divisor)
+  }
+
+  pub fn remove_instance_attribute(&self, name: &str) {
+    let mut state = self.state.borrow_mut();
+    if let Some(location) = state.program.attribute_location(name) {
+      state.geometry.remove_instance_attribute(location);
+    }
+  }
+
+  // Makes `render` draw `count` instances of the geometry in one call;
+  // 0 goes back to a single, non-instanced draw.
+  pub fn set_instance_count(&self, count: u32) {
+    self.state.borrow_mut().instance_count = if count > 0 { Some(count) } else { None };
+  }
+
+  // How `render` joins up the vertices, triangles by default. `Lines`
+  // draws e.g. the edges of a wireframe.
+  pub fn set_primitive(&self, primitive: Primitive) {
+    self.state.borrow_mut().primitive = primitive;
+  }
+
+  // Hides geometry behind what was already drawn nearer, for 3D scenes;
+  // off by default. Overlays are always drawn on top.
+  pub fn set_depth_test(&self, enabled: bool) {
+    self.state.borrow_mut().depth_test = enabled;
+  }
+
+  // The depth the depth buffer is cleared to each frame, 1 (farthest) by
+  // default.
+  pub fn set_clear_depth(&self, depth: f32) -> Result<()> {
+    if !(0.0..=1.0).contains(&depth) {
+      return Err(GestaltError::InvalidArgument(format!("clear depth must be within [0, 1], got {}", depth)));
+    }
+    self.state.borrow_mut().clear_depth = depth;
+    Ok(())
+  }
+
+  // Clips the geometry and overlays to within or outside the triangles in
+  // `vertices`, x, y pairs in drawing-buffer pixels from the top left, from
+  // the next frame on. Needs a canvas made with `ContextOptions::stencil`.
+  pub fn set_stencil_mask(&self, vertices: &[f32], mode: StencilMode) -> Result<()> {
+    let mut state = self.state.borrow_mut();
+    let has_stencil = state.context.get_context_attributes().and_then(|attributes| attributes.get_stencil());
+    if has_stencil != Some(true) {
+      return Err(GestaltError::InvalidArgument(String::from(
+        "the canvas has no stencil buffer, see ContextOptions::stencil",
+      )));
+    }
+    state.stencil_mask = Some(StencilMask::new(&state.context, vertices, mode)?);
+    Ok(())
+  }
+
+  pub fn clear_stencil_mask(&self) {
+    self.state.borrow_mut().stencil_mask = None;
+  }
+
+  // Draws the geometry into `viewport` only, from the next frame on, e.g.
+  // to leave room for panels drawn by a `Scene`. The clear colour still
+  // fills the canvas. Shaders get the region as `uniform vec4 u_viewport`,
+  // x, y, width and height in `gl_FragCoord` pixels, and cameras take its
+  // aspect ratio.
+  pub fn set_viewport(&self, viewport: &Viewport) {
+    self.state.borrow_mut().viewport = Some(*viewport);
+  }
+
+  pub fn clear_viewport(&self) {
+    self.state.borrow_mut().viewport = None;
+  }
+
+  // Clips the geometry and overlays to `scissor` from the next frame on.
+  // Unlike `set_viewport` this does not move or scale anything. The clear
+  // colour still fills the canvas.
+  pub fn set_scissor(&self, scissor: &Viewport) {
+    self.state.borrow_mut().scissor = Some(*scissor);
+  }
+
+  pub fn clear_scissor(&self) {
+    self.state.borrow_mut().scissor = None;
+  }
+
+  // Sets `uniform mat4 u_model_view_projection`, the convention for 3D
+  // vertex shaders: `gl_Position = u_model_view_projection * vec4(position,
+  // 1.0)` with three components per vertex. `matrix` is column-major, e.g.
+  // from `projection.multiply(view).multiply(model).elements()`.
+  pub fn set_model_view_projection(&self, matrix: &[f32]) -> Result<()> {
+    self.state.borrow_mut().program.set_mat4(MODEL_VIEW_PROJECTION, matrix)
+  }
+
+  // Lays the geometry out in `camera`'s units from the next frame on, see
+  // `Camera2D`. Overrides `set_model_view_projection`.
+  pub fn set_camera(&self, camera: &Camera2D) {
+    self.state.borrow_mut().camera = Some(Camera::Flat(*camera));
+  }
+
+  // Like `set_camera`, viewing 3D geometry through `camera`.
+  pub fn set_camera_3d(&self, camera: &Camera3D) {
+    self.state.borrow_mut().camera = Some(Camera::Perspective(*camera));
+  }
+
+  // The 3D camera as it is now, e.g. after `OrbitControls` turned it.
+  pub fn camera_3d(&self) -> Option<Camera3D> {
+    match self.state.borrow().camera {
+      Some(Camera::Perspective(camera)) => Some(camera),
+      _ => None,
+    }
+  }
+
+  // Stops setting the model-view-projection matrix from a camera.
+  pub fn clear_camera(&self) {
+    self.state.borrow_mut().camera = None;
+  }
+
+  // Uniform setters. Names the program does not use (or which the GLSL
+  // compiler optimized away) are silently ignored, like in plain WebGL.
+
+  pub fn set_uniform_f32(&self, name: &str, value: f32) {
+    self.state.borrow_mut().program.set_f32(name, value);
+  }
+
+  pub fn set_uniform_i32(&self, name: &str, value: i32) {
+    self.state.borrow_mut().program.set_i32(name, value);
+  }
+
+  pub fn set_uniform_vec2(&self, name: &str, x: f32, y: f32) {
+    self.state.borrow_mut().program.set_vec2(name, x, y);
+  }
+
+  pub fn set_uniform_vec3(&self, name: &str, x: f32, y: f32, z: f32) {
+    self.state.borrow_mut().program.set_vec3(name, x, y, z);
+  }
+
+  pub fn set_uniform_vec4(&self, name: &str, x: f32, y: f32, z: f32, w: f32) {
+    self.state.borrow_mut().program.set_vec4(name, x, y, z, w);
+  }
+
+  // Sets a `vec4` uniform to `color`'s sRGB-encoded components and alpha.
+  pub fn set_uniform_color(&self, name: &str, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.state.borrow_mut().program.set_vec4(name, r, g, b, a);
+  }
+
+  // `matrix` holds 9 floats in column-major order, as GLSL expects.
+  pub fn set_uniform_mat3(&self, name: &str, matrix: &[f32]) -> Result<()> {
+    self.state.borrow_mut().program.set_mat3(name, matrix)
+  }
+
+  // `matrix` holds 16 floats in column-major order, as GLSL expects.
+  pub fn set_uniform_mat4(&self, name: &str, matrix: &[f32]) -> Result<()> {
+    self.state.borrow_mut().program.set_mat4(name, matrix)
+  }
+
+  // The same for matrices composed with `Mat3` and `Mat4`.
+
+  pub fn set_uniform_matrix3(&self, name: &str, matrix: &Mat3) -> Result<()> {
+    self.state.borrow_mut().program.set_mat3(name, matrix.as_slice())
+  }
+
+  pub fn set_uniform_matrix4(&self, name: &str, matrix: &Mat4) -> Result<()> {
+    self.state.borrow_mut().program.set_mat4(name, matrix.as_slice())
+  }
+
+  // Binds `texture` to texture unit `unit` and points the sampler uniform
+  // `name` at it. The binding is kept and re-applied on every render. The
+  // texture must have been made with this canvas.
+  pub fn set_uniform_texture(&self, name: &str, texture: &Texture, unit: u32) -> Result<()> {
+    let mut state = self.state.borrow_mut();
+    texture.check_context(&state.context)?;
+    state.set_uniform_texture(name, texture.raw(), unit);
+    Ok(())
+  }
+
+  // Appends a fullscreen post-processing pass, returning its index. Once
+  // there are passes, `render` draws the scene offscreen and runs them in
+  // order, the last one drawing to the canvas. `frag_src` reads the previous
+  // output from `uniform sampler2D u_input` at `in vec2 v_uv`, and also gets
+  // `u_resolution` and `u_time`.
+  pub fn add_post_process_pass(&self, frag_src: &str) -> Result<usize> {
+    self.state.borrow_mut().post_process.add_pass(frag_src)
+  }
+
+  pub fn remove_post_process_pass(&self, index: usize) -> Result<()> {
+    self.state.borrow_mut().post_process.remove_pass(index)
+  }
+
+  pub fn clear_post_process(&self) {
+    self.state.borrow_mut().post_process.clear();
+  }
+
+  pub fn set_post_process_uniform_f32(&self, index: usize, name: &str, value: f32) -> Result<()> {
+    self.state.borrow_mut().post_process.pass_mut(index)?.set_f32(name, value);
+    Ok(())
+  }
+
+  pub fn set_post_process_uniform_i32(&self, index: usize, name: &str, value: i32) -> Result<()> {
+    self.state.borrow_mut().post_process.pass_mut(index)?.set_i32(name, value);
+    Ok(())
+  }
+
+  pub fn set_post_process_uniform_vec2(&self, index: usize, name: &str, x: f32, y: f32) -> Result<()> {
+    self.state.borrow_mut().post_process.pass_mut(index)?.set_vec2(name, x, y);
+    Ok(())
+  }
+
+  pub fn set_post_process_uniform_vec4(&self, index: usize, name: &str, x: f32, y: f32, z: f32, w: f32) -> Result<()> {
+    self.state.borrow_mut().post_process.pass_mut(index)?.set_vec4(name, x, y, z, w);
+    Ok(())
+  }
+
+  // Shows the image as an observer with `deficiency` would see it, mixed
+  // with normal vision by `severity` from 0 to 1, for previewing stimuli.
+  // Runs after the other passes and before display correction; the scene
+  // is taken to be sRGB-encoded unless display correction is on.
+  pub fn set_color_vision_simulation(&self, deficiency: ColorVisionDeficiency, severity: f32) -> Result<()> {
+    self.state.borrow_mut().post_process.set_simulation(deficiency, severity)
+  }
+
+  pub fn clear_color_vision_simulation(&self) {
+    self.state.borrow_mut().post_process.clear_simulation();
+  }
+
+  // Corrects the final image for a display whose light output is its input
+  // raised to the measured `r`, `g` and `b` gammas. Shader outputs then
+  // become proportional to emitted luminance instead of being sRGB-encoded.
+  // Runs as the last post-processing pass.
+  pub fn set_display_gamma(&self, r: f32, g: f32, b: f32) -> Result<()> {
+    self.state.borrow_mut().post_process.correction_mut()?.set_gamma(r, g, b)
+  }
+
+  // Like `set_display_gamma`, with a measured lookup table instead: r, g, b
+  // output values for evenly spaced linear intensities from 0 to 1,
+  // interpolated in between.
+  pub fn set_display_lut(&self, table: &[f32]) -> Result<()> {
+    self.state.borrow_mut().post_process.correction_mut()?.set_lut(table)
+  }
+
+  pub fn clear_display_correction(&self) {
+    self.state.borrow_mut().post_process.clear_correction();
+  }
+
+  // Dithers the final image with "noisy bit" noise of plus or minus half a
+  // step of a display with `bits` per channel, so contrasts finer than
+  // 1/255 come out right on average over neighbouring pixels, and with
+  // `Temporal` over frames too, e.g. for contrast detection thresholds.
+  // The scene is then drawn at least at `HalfFloat` precision, which needs
+  // `EXT_color_buffer_float`. Runs last, after display correction.
+  pub fn set_dithering(&self, mode: DitherMode, bits: u32) -> Result<()> {
+    self.state.borrow_mut().post_process.set_dithering(mode, bits)
+  }
+
+  pub fn clear_dithering(&self) {
+    self.state.borrow_mut().post_process.clear_dithering();
+  }
+
+  // Renders the scene into alternating offscreen buffers so the shader can
+  // sample the previous frame from `uniform sampler2D u_previous_frame`,
+  // bound to texture unit `unit`. Useful for trails, reaction-diffusion and
+  // other iterative effects.
+  pub fn enable_feedback(&self, unit: u32) {
+    let mut state = self.state.borrow_mut();
+    let feedback = FeedbackBuffers::new(&state.context, unit);
+    state.feedback = Some(feedback);
+  }
+
+  pub fn disable_feedback(&self) {
+    self.state.borrow_mut().feedback = None;
+  }
+
+  // Sets `uniform vec2 u_mouse` (normalized, origin bottom left) and
+  // `uniform int u_mouse_buttons` (bitmask) from `mouse` on every render.
+  pub fn bind_mouse(&self, mouse: &Mouse) {
+    self.state.borrow_mut().mouse = Some(mouse.state());
+  }
+
+  pub fn unbind_mouse(&self) {
+    self.state.borrow_mut().mouse = None;
+  }
+
+  // Sets `uniform vec4 u_pointers[8]` to (x, y, pressure, id) of up to eight
+  // active pointers and `uniform int u_pointer_count` on every render.
+  pub fn bind_pointers(&self, pointers: &Pointers) {
+    self.state.borrow_mut().pointers = Some(pointers.state());
+  }
+
+  pub fn unbind_pointers(&self) {
+    self.state.borrow_mut().pointers = None;
+  }
+
+  // Sets `uniform vec2 u_gaze` (normalized, origin bottom left) to the
+  // latest sample of `tracker` on every render, once there is one.
+  pub fn bind_gaze(&self, tracker: &GazeTracker) {
+    self.state.borrow_mut().gaze = Some(tracker.source());
+  }
+
+  pub fn unbind_gaze(&self) {
+    self.state.borrow_mut().gaze = None;
+  }
+
+  // Creates an offscreen target of the given size to render into.
+  pub fn create_render_target(&self, width: u32, height: u32) -> Result<RenderTarget> {
+    RenderTarget::new(&self.state.borrow().context, width, height)
+  }
+
+  // Like `create_render_target`, storing colour at `precision` or the
+  // finest below it the browser can render to; see `RenderTarget::precision`.
+  pub fn create_render_target_with_precision(&self, width: u32, height: u32, precision: TargetPrecision) -> Result<RenderTarget> {
+    let context = self.state.borrow().context.clone();
+    let precision = supported_precision(&context, precision)?;
+    RenderTarget::with_precision(&context, width, height, precision)
+  }
+
+  // Precision of the offscreen targets the scene and post-processing passes
+  // are drawn to, `Byte` to begin with, so low-contrast stimuli are not
+  // quantized between passes. Falls back to the finest the browser can
+  // render to, which is returned: `Float` to `HalfFloat` to `Byte`.
+  pub fn set_offscreen_precision(&self, precision: TargetPrecision) -> Result<TargetPrecision> {
+    self.state.borrow_mut().post_process.set_precision(precision)
+  }
+
+  // Like `set_uniform_texture`, with the color texture of `target`.
+  pub fn set_uniform_render_target(&self, name: &str, target: &RenderTarget, unit: u32) -> Result<()> {
+    let mut state = self.state.borrow_mut();
+    target.texture().check_context(&state.context)?;
+    state.set_uniform_texture(name, target.texture().raw(), unit);
+    Ok(())
+  }
+
+  // Creates a texture from raw 8-bit pixel data, see `Texture::set_data`.
+  pub fn create_texture_from_data(&self, width: u32, height: u32, format: TextureFormat, data: &[u8]) -> Result<Texture> {
+    let mut texture = Texture::new(&self.state.borrow().context)?;
+    texture.set_data(width, height, format, data)?;
+    Ok(texture)
+  }
+
+  // Creates a texture from raw float pixel data, see `Texture::set_f32_data`.
+  pub fn create_texture_from_f32_data(&self, width: u32, height: u32, format: TextureFormat, data: &[f32]) -> Result<Texture> {
+    let mut texture = Texture::new(&self.state.borrow().context)?;
+    texture.set_f32_data(width, height, format, data)?;
+    Ok(texture)
+  }
+
+  // Streams the `<video>` element with id `video_element_id` into the sampler
+  // uniform `name` on texture unit `unit`. A new frame is uploaded on every
+  // render once the video has advanced.
+  pub fn bind_video_texture(&self, video_element_id: &str, name: &str, unit: u32) -> Result<()> {
+    let document = document()?;
+    let video = document
+        .get_element_by_id(video_element_id)
+        .ok_or_else(|| GestaltError::ElementNotFound(video_element_id.to_string()))?
+        .dyn_into::<web_sys::HtmlVideoElement>()
+        .map_err(|_| GestaltError::InvalidArgument(format!("element '{}' is not a <video>", video_element_id)))?;
+
+    self.state.borrow_mut().bind_video_texture(video, name, unit)
+  }
+
+  pub fn unbind_video_texture(&self, video_element_id: &str) {
+    self.state.borrow_mut().unbind_video_texture(video_element_id);
+  }
+
+  // Streams `analyser`'s spectrum and waveform into `uniform sampler2D
+  // u_audio_fft` on texture unit `unit`, anew on every render. Spectrum
+  // bins run along `x` at `y` 0.25, the waveform at `y` 0.75, e.g.
+  // `texture(u_audio_fft, vec2(uv.x, 0.25)).r`.
+  pub fn bind_audio_texture(&self, analyser: &AudioAnalyser, unit: u32) -> Result<()> {
+    let mut state = self.state.borrow_mut();
+    state.unbind_audio_texture();
+    let audio = AudioTexture::new(&state.context, analyser, unit)?;
+    state.set_uniform_texture(AUDIO_TEXTURE_UNIFORM, audio.texture.raw(), unit);
+    state.audio = Some(audio);
+    Ok(())
+  }
+
+  pub fn unbind_audio_texture(&self) {
+    self.state.borrow_mut().unbind_audio_texture();
+  }
+
+  // Loads the image at `url` into a new `Texture`. Returns a promise.
+  pub fn load_texture(&self, url: String) -> js_sys::Promise {
+    let context = self.state.borrow().context.clone();
+    wasm_bindgen_futures::future_to_promise(async move {
+      let texture = Texture::load(context, url).await?;
+      Ok(texture.into())
+    })
+  }
+}
+
+impl WebGlCanvas {
+  fn on_surface(surface: Surface, vert_src: &str, frag_src: &str, options: &ContextOptions) -> Result<WebGlCanvas> {
+    let state = Rc::new(RefCell::new(CanvasState::new(surface, vert_src, frag_src, options)?));
+    let canvas = state.borrow().canvas.clone();
+
+    // Browsers only restore a lost context if the loss event was cancelled.
+    let lost_state = Rc::downgrade(&state);
+    let lost_listener = EventListener::new(canvas.event_target(), "webglcontextlost", move |event| {
+      event.prevent_default();
+      if let Some(state) = lost_state.upgrade() {
+        state.borrow_mut().context_lost = true;
+      }
+    })?;
+
+    let restored_state = Rc::downgrade(&state);
+    let restored_listener = EventListener::new(canvas.event_target(), "webglcontextrestored", move |_| {
+      if let Some(state) = restored_state.upgrade() {
+        if let Err(error) = state.borrow_mut().restore() {
+          web_sys::console::error_1(&format!("Failed to restore WebGL context: {}", error).into());
         }
-        "##,
-    )?;
-    let program = link_program(&context, &vert_shader, &frag_shader)?;
-    context.use_program(Some(&program));
-
-    let vertices: [f32; 6] = [0.0,  0.5,
-             0.5, -0.5,
-            -0.5, -0.5 ];
-
-    let position_attribute_location = context.get_attrib_location(&program, "position");
-//    let colour_attribute_location = context.get_attrib_location(&program, "colour");
-    let buffer = context.create_buffer().ok_or("Failed to create buffer")?;
-    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
-
-    // Note that `Float32Array::view` is somewhat dangerous (hence the
-    // `unsafe`!). This is creating a raw view into our module's
-    // `WebAssembly.Memory` buffer, but if we allocate more pages for ourself
-    // (aka do a memory allocation in Rust) it'll cause the buffer to change,
-    // causing the `Float32Array` to be invalid.
-    //
-    // As a result, after `Float32Array::view` we have to be very careful not to
-    // do any memory allocations before it's dropped.
-    unsafe {
-        let positions_array_buf_view = js_sys::Float32Array::view(&vertices);
-
-        context.buffer_data_with_array_buffer_view(
-            WebGl2RenderingContext::ARRAY_BUFFER,
-            &positions_array_buf_view,
-            WebGl2RenderingContext::STATIC_DRAW,
-        );
-    }
-
-    let vao = context
-        .create_vertex_array()
-        .ok_or("Could not create vertex array object")?;
-    context.bind_vertex_array(Some(&vao));
-
-    context.vertex_attrib_pointer_with_i32(position_attribute_location as u32, 2, WebGl2RenderingContext::FLOAT, false, 0, 0);
-    context.enable_vertex_attrib_array(position_attribute_location as u32);
-
-//    context.vertex_attrib_pointer_with_i32(colour_attribute_location as u32, 3, WebGl2RenderingContext::FLOAT, false, 5*4, 0);
-//    context.enable_vertex_attrib_array(colour_attribute_location as u32);
-  
-    context.bind_vertex_array(Some(&vao));
+      }
+    })?;
 
-  Ok(WebGlCanvas {
-    canvas,
-    context,
-    vert_shader,
-    frag_shader,
-    program,
-    vertices
-  })
+    Ok(WebGlCanvas {
+      state,
+      resize_listener: RefCell::new(None),
+      fullscreen_listener: RefCell::new(None),
+      context_listeners: RefCell::new(vec![lost_listener, restored_listener]),
+    })
+  }
+
+  fn use_fullscreen_quad(&self) -> Result<()> {
+    let mut state = self.state.borrow_mut();
+    state.geometry = Geometry::fullscreen_quad(&state.context)?;
+    Ok(())
+  }
+
+  pub(crate) fn state(&self) -> Rc<RefCell<CanvasState>> {
+    self.state.clone()
+  }
+
+  // The canvas on the page, for listening to input on it. Fails when
+  // drawing into an `OffscreenCanvas`.
+  pub(crate) fn element(&self) -> Result<web_sys::HtmlCanvasElement> {
+    self.state.borrow().canvas.element()
+  }
+
+  pub(crate) fn context(&self) -> WebGl2RenderingContext {
+    self.state.borrow().context.clone()
+  }
+
+  // Keeps drawing `overlay` each frame, in `layer` until moved through the
+  // returned slot, until it is dropped elsewhere.
+  pub(crate) fn add_overlay(&self, overlay: Rc<RefCell<dyn Overlay>>, layer: Layer) -> LayerSlot {
+    let slot = Rc::new(Cell::new(layer));
+    self.state.borrow_mut().overlays.push((Rc::downgrade(&overlay), slot.clone()));
+    slot
+  }
+
+  pub(crate) fn frame_clock(&self) -> Rc<Cell<f64>> {
+    self.state.borrow().frame_clock.clone()
+  }
+
+  fn request_capture(&self, data_url: bool) -> js_sys::Promise {
+    let state = self.state.clone();
+    js_sys::Promise::new(&mut move |resolve, reject| {
+      let mut state = state.borrow_mut();
+      if state.destroyed {
+        let _ = reject.call1(&JsValue::NULL, &js_sys::Error::new("the canvas was destroyed"));
+        return;
+      }
+      state.captures.push(Capture { data_url, resolve, reject });
+    })
+  }
 }
+
+impl CanvasState {
+  fn new(canvas: Surface, vert_src: &str, frag_src: &str, options: &ContextOptions) -> Result<CanvasState> {
+    let context = canvas
+        .get_context("webgl2", &options.to_attributes())?
+        .ok_or(GestaltError::WebGl2Unavailable)?
+        .dyn_into::<WebGl2RenderingContext>()
+        .map_err(|_| GestaltError::WebGl2Unavailable)?;
+
+    let program = ShaderProgram::new(&context, vert_src, frag_src)?;
+    if !program.has_attribute("position") {
+      return Err(GestaltError::MissingAttribute(String::from("position")));
+    }
+
+    // Start out with the demo triangle until the user uploads their own.
+    let mut geometry = Geometry::new(&context, POSITION_LOCATION)?;
+    geometry.set_vertices(&[0.0,  0.5,
+                            0.5, -0.5,
+                           -0.5, -0.5 ], 2)?;
+
+    Ok(CanvasState {
+      canvas,
+      post_process: PostProcessChain::new(&context)?,
+      feedback: None,
+      mouse: None,
+      shadertoy: None,
+      pointers: None,
+      gaze: None,
+      instance_count: None,
+      primitive: Primitive::Triangles,
+      camera: None,
+      depth_test: false,
+      clear_depth: 1.0,
+      stencil_mask: None,
+      viewport: None,
+      scissor: None,
+      clear_color: Color::new(0.0, 0.0, 0.0, 1.0),
+      clear_enabled: true,
+      blend_mode: None,
+      context,
+      program,
+      geometry,
+      context_lost: false,
+      destroyed: false,
+      textures: HashMap::new(),
+      videos: Vec::new(),
+      audio: None,
+      captures: Vec::new(),
+      gpu_timer: None,
+      overlays: Vec::new(),
+      layers: [LayerSettings::default(); 4],
+      frame_clock: Rc::new(Cell::new(0.0)),
+    })
+  }
+
+  fn destroy(&mut self) {
+    if self.destroyed {
+      return;
+    }
+    self.destroyed = true;
+    self.program.delete();
+    self.geometry.delete();
+    self.post_process.delete();
+    self.feedback = None;
+    self.gpu_timer = None;
+    self.videos.clear();
+    self.audio = None;
+    self.textures.clear();
+    self.overlays.clear();
+    self.shadertoy = None;
+    self.mouse = None;
+    self.pointers = None;
+    self.gaze = None;
+    for capture in self.captures.drain(..) {
+      let _ = capture.reject.call1(&JsValue::NULL, &js_sys::Error::new("the canvas was destroyed"));
+    }
+    gl_state::forget(&self.context);
+  }
+
+  // Rebuilds every GL object on the (restored) context.
+  fn restore(&mut self) -> Result<()> {
+    gl_state::reset(&self.context);
+    resources::forget(&self.context);
+    self.program.restore()?;
+    if self.gpu_timer.is_some() {
+      self.gpu_timer = GpuTimer::new(&self.context)?;
+    }
+    self.post_process.restore()?;
+    if let Some(feedback) = &mut self.feedback {
+      feedback.restore();
+    }
+    // Textures do not survive a context loss and have to be loaded again,
+    // except for videos which are simply streamed into new ones.
+    self.textures.clear();
+    self.geometry.restore(&self.context)?;
+    if let Some(mask) = &mut self.stencil_mask {
+      mask.restore(&self.context)?;
+    }
+
+    let mut videos = std::mem::take(&mut self.videos);
+    for video in &mut videos {
+      video.restore(&self.context)?;
+      self.set_uniform_texture(&video.name, video.texture.raw(), video.unit);
+    }
+    self.videos = videos;
+    let mut audio = self.audio.take();
+    if let Some(audio) = &mut audio {
+      audio.restore(&self.context)?;
+      self.set_uniform_texture(AUDIO_TEXTURE_UNIFORM, audio.texture.raw(), audio.unit);
+    }
+    self.audio = audio;
+
+    self.overlays.retain(|(overlay, _)| overlay.strong_count() > 0);
+    for (overlay, _) in &self.overlays {
+      if let Some(overlay) = overlay.upgrade() {
+        overlay.borrow_mut().restore(&self.context)?;
+      }
+    }
+
+    self.context.viewport(0, 0, self.canvas.width() as i32, self.canvas.height() as i32);
+    self.context_lost = false;
+    Ok(())
+  }
   
-  pub fn render(&self, time: f32) {
-    let time_location = self.context.get_uniform_location(
-      &self.program,
-      "u_time"
-    ).expect("WebGL program should have `u_time` uniform.");
+  fn resize(&mut self) -> bool {
+    // An `OffscreenCanvas` has no layout to measure and keeps the size it
+    // was given.
+    let Surface::Element(canvas) = &self.canvas else {
+      return self.set_size(self.canvas.width(), self.canvas.height());
+    };
+    let ratio = web_sys::window().map(|window| window.device_pixel_ratio()).unwrap_or(1.0);
+    // A hidden canvas has no layout size; keep at least one pixel.
+    let width = ((canvas.client_width() as f64 * ratio).round() as u32).max(1);
+    let height = ((canvas.client_height() as f64 * ratio).round() as u32).max(1);
+    self.set_size(width, height)
+  }
+
+  // Sizes the drawing buffer and resets the viewport to cover it. Returns
+  // whether the size changed.
+  fn set_size(&mut self, width: u32, height: u32) -> bool {
+    let changed = self.canvas.width() != width || self.canvas.height() != height;
+    if changed {
+      self.canvas.set_width(width);
+      self.canvas.set_height(height);
+    }
+    self.context.viewport(0, 0, width as i32, height as i32);
+    changed
+  }
+
+  pub(crate) fn camera_3d_mut(&mut self) -> Option<&mut Camera3D> {
+    match &mut self.camera {
+      Some(Camera::Perspective(camera)) => Some(camera),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn render(&mut self, frame_time: f64) {
+    self.render_into(frame_time, None);
+  }
+
+  // Renders a frame into `output` instead of the canvas, at the target's
+  // size, and leaves the target bound.
+  pub(crate) fn render_offscreen(&mut self, frame_time: f64, output: &RenderTarget) {
+    output.bind();
+    self.render_into(frame_time, Some(output));
+  }
+
+  fn render_into(&mut self, frame_time: f64, output: Option<&RenderTarget>) {
+    if self.context_lost || self.destroyed {
+      return;
+    }
+    self.frame_clock.set(frame_time);
+    let time = frame_time as f32;
+
+    let (drawing_width, drawing_height) = match output {
+      Some(output) => (output.width(), output.height()),
+      None => (self.context.drawing_buffer_width() as u32, self.context.drawing_buffer_height() as u32),
+    };
+    if let Err(error) = self.post_process.begin(drawing_width, drawing_height) {
+      web_sys::console::error_1(&format!("Failed to set up post-processing: {}", error).into());
+    }
+
+    if let Some(feedback) = &mut self.feedback {
+      let precision = self
+        .post_process
+        .scene_precision()
+        .or(output.map(RenderTarget::precision))
+        .unwrap_or(TargetPrecision::Byte);
+      if let Err(error) = feedback.begin(&mut self.program, drawing_width, drawing_height, precision) {
+        web_sys::console::error_1(&format!("Failed to set up feedback buffers: {}", error).into());
+      }
+    }
 
-    let vert_count = (self.vertices.len() / 2) as i32;
+    self.program.use_program();
 
-    self.context.clear_color(0.0, 0.0, 0.0, 1.0);
-    self.context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+    if let Some(timer) = &mut self.gpu_timer {
+      timer.begin(Section::Scene);
+    }
+    let [r, g, b, a] = self.clear_color.to_array();
+    self.context.clear_color(r, g, b, a);
+    let mut clear_bits = if self.clear_enabled { WebGl2RenderingContext::COLOR_BUFFER_BIT } else { 0 };
+    if self.depth_test {
+      self.context.clear_depth(self.clear_depth);
+      clear_bits |= WebGl2RenderingContext::DEPTH_BUFFER_BIT;
+    }
+    if self.stencil_mask.is_some() {
+      self.context.clear_stencil(0);
+      clear_bits |= WebGl2RenderingContext::STENCIL_BUFFER_BIT;
+    }
+    if clear_bits != 0 {
+      self.context.clear(clear_bits);
+    }
+    if let Some(mask) = &mut self.stencil_mask {
+      mask.apply(&self.context, drawing_width, drawing_height);
+      self.program.use_program();
+    }
   
-    self.context.uniform1f(Some(&time_location), (time/1000.0) as f32);
+    self.program.set_f32("u_time", time / 1000.0);
+    let region = self.viewport.unwrap_or(Viewport::new(0, 0, drawing_width, drawing_height));
+    let [region_x, region_y, region_width, region_height] = region.gl_rect(drawing_height);
+    self.program.set_vec4("u_viewport", region_x as f32, region_y as f32, region_width as f32, region_height as f32);
+    if let Some(camera) = &self.camera {
+      if let Ok(matrix) = camera.matrix(region.width, region.height) {
+        let _ = self.program.set_mat4(MODEL_VIEW_PROJECTION, &matrix);
+      }
+    }
+
+    if let Some(shadertoy) = &mut self.shadertoy {
+      shadertoy.update(&mut self.program, time, drawing_width, drawing_height);
+    }
+
+    if let Some(mouse) = &self.mouse {
+      let mouse = *mouse.borrow();
+      self.program.set_vec2("u_mouse", mouse.x, mouse.y);
+      self.program.set_i32("u_mouse_buttons", mouse.buttons as i32);
+    }
+
+    if let Some(pointers) = &self.pointers {
+      let pointers = pointers.borrow();
+      let mut values = [0.0; MAX_SHADER_POINTERS * 4];
+      for (value, pointer) in values.chunks_mut(4).zip(pointers.iter()) {
+        value.copy_from_slice(&[pointer.x, pointer.y, pointer.pressure, pointer.id as f32]);
+      }
+      self.program.set_vec4_array("u_pointers", &values);
+      self.program.set_i32("u_pointer_count", pointers.len().min(MAX_SHADER_POINTERS) as i32);
+    }
+
+    if let Some(sample) = self.gaze.as_ref().and_then(|gaze| gaze.latest()) {
+      self.program.set_vec2("u_gaze", sample.x, sample.y);
+    }
+
+    for video in &mut self.videos {
+      if let Err(error) = video.update() {
+        web_sys::console::error_1(&format!("Failed to upload video frame: {}", error).into());
+      }
+    }
+    if let Some(audio) = &mut self.audio {
+      if let Err(error) = audio.update() {
+        web_sys::console::error_1(&format!("Failed to upload audio analysis: {}", error).into());
+      }
+    }
+
+    for (unit, texture) in &self.textures {
+      gl_state::bind_texture(&self.context, *unit, texture);
+    }
+    gl_state::set_blend_mode(&self.context, self.blend_mode);
   
-    self.context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, vert_count);
-  }
-}
-
-fn compile_shader(
-    context: &WebGl2RenderingContext,
-    shader_type: u32,
-    source: &str,
-) -> Result<WebGlShader, String> {
-  let shader = context
-    .create_shader(shader_type)
-    .ok_or_else(|| String::from("Unable to create shader object"))?;
-  context.shader_source(&shader, source);
-  context.compile_shader(&shader);
-    
-  if context
-    .get_shader_parameter(&shader, WebGl2RenderingContext::COMPILE_STATUS)
-    .as_bool()
-    .unwrap_or(false)
-  {
-    Ok(shader)
-  } else {
-    Err(context
-        .get_shader_info_log(&shader)
-        .unwrap_or_else(|| String::from("Unknown error creating shader")))
-  }
-}
-
-fn link_program(
+    let scissor = self.scissor.map(|scissor| scissor.gl_rect(drawing_height));
+    if self.viewport.is_some() {
+      self.context.viewport(region_x, region_y, region_width, region_height);
+      gl_state::set_scissor(&self.context, Some(viewport::intersect(region.gl_rect(drawing_height), scissor)));
+    } else {
+      gl_state::set_scissor(&self.context, scissor);
+    }
+    gl_state::set_depth_test(&self.context, self.depth_test);
+    match self.instance_count {
+      Some(count) => self.geometry.draw_instanced(self.primitive.mode(), count as i32),
+      None => self.geometry.draw(self.primitive.mode()),
+    }
+    // Overlays are flat and drawn over the scene in order.
+    gl_state::set_depth_test(&self.context, false);
+    if self.viewport.is_some() {
+      self.context.viewport(0, 0, drawing_width as i32, drawing_height as i32);
+      gl_state::set_scissor(&self.context, scissor);
+    }
+
+    if let Some(timer) = &mut self.gpu_timer {
+      timer.begin(Section::Overlays);
+    }
+    self.overlays.retain(|(overlay, _)| overlay.strong_count() > 0);
+    for layer in Layer::ALL {
+      let settings = self.layers[layer.index()];
+      gl_state::set_blend_override(&self.context, settings.blend_mode);
+      if !settings.visible {
+        self.context.color_mask(false, false, false, false);
+      }
+      for (overlay, _) in self.overlays.iter().filter(|(_, slot)| slot.get() == layer) {
+        if let Some(overlay) = overlay.upgrade() {
+          overlay.borrow_mut().draw(drawing_width, drawing_height, time / 1000.0);
+        }
+      }
+      if !settings.visible {
+        self.context.color_mask(true, true, true, true);
+      }
+    }
+    gl_state::set_blend_override(&self.context, None);
+
+    gl_state::set_stencil_test(&self.context, false);
+    gl_state::set_scissor(&self.context, None);
+
+    if let Some(timer) = &mut self.gpu_timer {
+      timer.begin(Section::PostProcess);
+    }
+    if let Some(feedback) = &mut self.feedback {
+      let destination = self.post_process.scene_framebuffer().or(output.map(RenderTarget::framebuffer));
+      feedback.finish(destination);
+    }
+    self.post_process.finish(time, output);
+    if let Some(timer) = &mut self.gpu_timer {
+      timer.end_frame();
+    }
+
+    // The drawing buffer is only guaranteed to hold the frame until it is
+    // shown, so it is read back right away rather than through
+    // `preserveDrawingBuffer`, which slows every frame down.
+    if output.is_none() && !self.captures.is_empty() {
+      self.finish_captures();
+    }
+  }
+
+  fn finish_captures(&mut self) {
+    self.context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+    let width = self.context.drawing_buffer_width() as u32;
+    let height = self.context.drawing_buffer_height() as u32;
+    let image = read_frame(&self.context, width, height, true);
+    for capture in std::mem::take(&mut self.captures) {
+      let settled = match &image {
+        Ok(image) if capture.data_url => image
+          .to_data_url_with_type("image/png")
+          .and_then(|url| capture.resolve.call1(&JsValue::NULL, &url.into()))
+          .map(|_| ()),
+        Ok(image) => image.to_blob(&capture.resolve),
+        Err(error) => Err(js_sys::Error::new(&error.to_string()).into()),
+      };
+      if let Err(error) = settled {
+        let _ = capture.reject.call1(&JsValue::NULL, &error);
+      }
+    }
+  }
+
+  pub(crate) fn program_mut(&mut self) -> &mut ShaderProgram {
+    &mut self.program
+  }
+
+  pub(crate) fn replace_fragment_shader(&mut self, frag_src: &str) -> Result<()> {
+    match self.shadertoy {
+      Some(_) => self.program.replace_fragment_shader(&shadertoy::wrap_source(frag_src)),
+      None => self.program.replace_fragment_shader(frag_src),
+    }
+  }
+
+  fn set_vertices(&mut self, vertices: &[f32], components_per_vertex: u32) -> Result<()> {
+    self.geometry.set_vertices(vertices, components_per_vertex)?;
+    Ok(())
+  }
+
+  fn set_indices(&mut self, indices: &[u32]) -> Result<()> {
+    self.geometry.set_indices(indices)?;
+    Ok(())
+  }
+
+  fn clear_indices(&mut self) {
+    self.geometry.clear_indices();
+  }
+
+  fn set_uniform_texture(&mut self, name: &str, texture: &WebGlTexture, unit: u32) {
+    gl_state::bind_texture(&self.context, unit, texture);
+    self.textures.insert(unit, texture.clone());
+    self.program.set_i32(name, unit as i32);
+  }
+
+  fn bind_video_texture(&mut self, video: web_sys::HtmlVideoElement, name: &str, unit: u32) -> Result<()> {
+    self.unbind_video_texture(&video.id());
+    let video = VideoTexture::new(&self.context, video, name, unit)?;
+    self.set_uniform_texture(name, video.texture.raw(), unit);
+    self.videos.push(video);
+    Ok(())
+  }
+
+  fn unbind_audio_texture(&mut self) {
+    if let Some(audio) = self.audio.take() {
+      if self.textures.get(&audio.unit) == Some(audio.texture.raw()) {
+        self.textures.remove(&audio.unit);
+      }
+    }
+  }
+
+  fn unbind_video_texture(&mut self, video_element_id: &str) {
+    let textures = &mut self.textures;
+    self.videos.retain(|video| {
+      let keep = video.video.id() != video_element_id;
+      if !keep && textures.get(&video.unit) == Some(video.texture.raw()) {
+        textures.remove(&video.unit);
+      }
+      keep
+    });
+  }
+}
+
+// The GL objects delete themselves.
+impl Drop for CanvasState {
+  fn drop(&mut self) {
+    gl_state::forget(&self.context);
+    resources::forget(&self.context);
+  }
+}
+
+// Copies the `width` x `height` frame in the bound framebuffer to a 2D
+// canvas, for encoding. The canvas' own drawing buffer holds premultiplied
+// colours, which images do not.
+pub(crate) fn read_frame(
   context: &WebGl2RenderingContext,
-  vert_shader: &WebGlShader,
-  frag_shader: &WebGlShader,
-) -> Result<WebGlProgram, String> {
-  let program = context
-    .create_program()
-    .ok_or_else(|| String::from("Unable to create shader object"))?;
-    
-  context.attach_shader(&program, vert_shader);
-  context.attach_shader(&program, frag_shader);
-  context.link_program(&program);
-  
-  if context
-    .get_program_parameter(&program, WebGl2RenderingContext::LINK_STATUS)
-    .as_bool()
-    .unwrap_or(false)
-  {
-    Ok(program)
-  } else {
-    Err(context
-      .get_program_info_log(&program)
-      .unwrap_or_else(|| String::from("Unknown error creating program object")))
+  width: u32,
+  height: u32,
+  premultiplied: bool,
+) -> Result<web_sys::HtmlCanvasElement> {
+  let mut pixels = vec![0; width as usize * height as usize * 4];
+  context.read_pixels_with_opt_u8_array(
+    0,
+    0,
+    width as i32,
+    height as i32,
+    WebGl2RenderingContext::RGBA,
+    WebGl2RenderingContext::UNSIGNED_BYTE,
+    Some(&mut pixels),
+  )?;
+
+  // GL rows run bottom to top, image rows top to bottom.
+  let mut rows: Vec<u8> = pixels.chunks_exact(width as usize * 4).rev().flatten().copied().collect();
+  if premultiplied {
+    for pixel in rows.chunks_exact_mut(4) {
+      let alpha = pixel[3] as u32;
+      if alpha > 0 && alpha < 255 {
+        for channel in &mut pixel[..3] {
+          *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+        }
+      }
+    }
   }
+
+  let canvas = document()?
+    .create_element("canvas")?
+    .dyn_into::<web_sys::HtmlCanvasElement>()
+    .map_err(|_| GestaltError::ResourceCreation("capture canvas"))?;
+  canvas.set_width(width);
+  canvas.set_height(height);
+  let context2d = canvas
+    .get_context("2d")?
+    .and_then(|context| context.dyn_into::<web_sys::CanvasRenderingContext2d>().ok())
+    .ok_or(GestaltError::ResourceCreation("capture canvas"))?;
+  let image = web_sys::ImageData::new_with_u8_clamped_array_and_sh(wasm_bindgen::Clamped(&rows), width, height)?;
+  context2d.put_image_data(&image, 0.0, 0.0)?;
+  Ok(canvas)
+}
+
+pub(crate) fn document() -> Result<web_sys::Document> {
+  web_sys::window()
+    .ok_or(GestaltError::NoWindow)?
+    .document()
+    .ok_or(GestaltError::NoDocument)
 }
 
+// What a `WebGlCanvas` draws into: a canvas on the page, or one handed to a
+// worker.
+#[derive(Clone)]
+pub(crate) enum Surface {
+  Element(web_sys::HtmlCanvasElement),
+  Offscreen(web_sys::OffscreenCanvas),
+}
+
+impl Surface {
+  fn element(&self) -> Result<web_sys::HtmlCanvasElement> {
+    match self {
+      Surface::Element(canvas) => Ok(canvas.clone()),
+      Surface::Offscreen(_) => Err(GestaltError::InvalidArgument(String::from(
+        "this needs a canvas on the page, not an OffscreenCanvas",
+      ))),
+    }
+  }
+
+  fn event_target(&self) -> &web_sys::EventTarget {
+    match self {
+      Surface::Element(canvas) => canvas.as_ref(),
+      Surface::Offscreen(canvas) => canvas.as_ref(),
+    }
+  }
+
+  fn get_context(&self, kind: &str, attributes: &JsValue) -> Result<Option<js_sys::Object>> {
+    Ok(match self {
+      Surface::Element(canvas) => canvas.get_context_with_context_options(kind, attributes)?,
+      Surface::Offscreen(canvas) => canvas.get_context_with_context_options(kind, attributes)?,
+    })
+  }
+
+  fn width(&self) -> u32 {
+    match self {
+      Surface::Element(canvas) => canvas.width(),
+      Surface::Offscreen(canvas) => canvas.width(),
+    }
+  }
+
+  fn height(&self) -> u32 {
+    match self {
+      Surface::Element(canvas) => canvas.height(),
+      Surface::Offscreen(canvas) => canvas.height(),
+    }
+  }
+
+  fn set_width(&self, width: u32) {
+    match self {
+      Surface::Element(canvas) => canvas.set_width(width),
+      Surface::Offscreen(canvas) => canvas.set_width(width),
+    }
+  }
+
+  fn set_height(&self, height: u32) {
+    match self {
+      Surface::Element(canvas) => canvas.set_height(height),
+      Surface::Offscreen(canvas) => canvas.set_height(height),
+    }
+  }
+}
+
+// Keeps a `ResizeObserver` and its callback alive while observing.
+struct ResizeListener {
+  observer: web_sys::ResizeObserver,
+  _callback: Closure<dyn FnMut()>,
+}
+
+impl Drop for ResizeListener {
+  fn drop(&mut self) {
+    self.observer.disconnect();
+  }
+}
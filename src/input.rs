@@ -0,0 +1,753 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use web_sys::{Gamepad, GamepadButton, HtmlCanvasElement, KeyboardEvent, MouseEvent, PointerEvent};
+
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+use crate::graphics::WebGlCanvas;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct MouseState {
+  // Normalized canvas coordinates, (0, 0) bottom left to (1, 1) top right,
+  // matching GL texture coordinates.
+  pub(crate) x: f32,
+  pub(crate) y: f32,
+  // Bitmask as in `MouseEvent.buttons`: 1 primary, 2 secondary, 4 middle.
+  pub(crate) buttons: u16,
+  pub(crate) inside: bool,
+}
+
+// Tracks the mouse over a canvas. Pass it to `WebGlCanvas::bind_mouse` to
+// have `render` feed it to the shader.
+#[wasm_bindgen]
+pub struct Mouse {
+  state: Rc<RefCell<MouseState>>,
+  _listeners: Vec<EventListener>,
+}
+
+#[wasm_bindgen]
+impl Mouse {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<Mouse> {
+    let element = canvas.element()?;
+    let state = Rc::new(RefCell::new(MouseState::default()));
+
+    let mut listeners = Vec::new();
+    for kind in ["pointermove", "pointerdown", "pointerup"] {
+      let state = state.clone();
+      let target = element.clone();
+      listeners.push(EventListener::new(&element, kind, move |event| {
+        if let Some(event) = event.dyn_ref::<MouseEvent>() {
+          let (x, y) = normalized_position(&target, event);
+          let mut state = state.borrow_mut();
+          state.x = x;
+          state.y = y;
+          state.buttons = event.buttons();
+          state.inside = true;
+        }
+      })?);
+    }
+
+    let leave_state = state.clone();
+    listeners.push(EventListener::new(&element, "pointerleave", move |_| {
+      let mut state = leave_state.borrow_mut();
+      state.inside = false;
+      state.buttons = 0;
+    })?);
+
+    Ok(Mouse { state, _listeners: listeners })
+  }
+
+  pub fn x(&self) -> f32 {
+    self.state.borrow().x
+  }
+
+  pub fn y(&self) -> f32 {
+    self.state.borrow().y
+  }
+
+  pub fn buttons(&self) -> u16 {
+    self.state.borrow().buttons
+  }
+
+  // `button` as in `MouseEvent.button`: 0 primary, 1 middle, 2 secondary.
+  // Buttons past the 16 the mask holds are never pressed.
+  pub fn is_pressed(&self, button: u16) -> bool {
+    let mask = match button {
+      0 => Some(1),
+      1 => Some(4),
+      2 => Some(2),
+      button => 1u16.checked_shl(button.into()),
+    };
+    let buttons = self.state.borrow().buttons;
+    mask.is_some_and(|mask| buttons & mask != 0)
+  }
+
+  pub fn is_inside(&self) -> bool {
+    self.state.borrow().inside
+  }
+}
+
+impl Mouse {
+  pub(crate) fn state(&self) -> Rc<RefCell<MouseState>> {
+    self.state.clone()
+  }
+}
+
+// Maximum number of pointers passed to shaders by `WebGlCanvas::bind_pointers`.
+pub(crate) const MAX_SHADER_POINTERS: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PointerState {
+  pub(crate) id: i32,
+  // Normalized canvas coordinates, like `Mouse`.
+  pub(crate) x: f32,
+  pub(crate) y: f32,
+  // 0 to 1; 0.5 for pressed devices that do not report pressure.
+  pub(crate) pressure: f32,
+  // "mouse", "pen" or "touch".
+  pub(crate) pointer_type: String,
+  pub(crate) is_primary: bool,
+}
+
+impl PointerState {
+  fn to_js(&self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"id".into(), &self.id.into());
+    let _ = js_sys::Reflect::set(&object, &"x".into(), &self.x.into());
+    let _ = js_sys::Reflect::set(&object, &"y".into(), &self.y.into());
+    let _ = js_sys::Reflect::set(&object, &"pressure".into(), &self.pressure.into());
+    let _ = js_sys::Reflect::set(&object, &"pointerType".into(), &self.pointer_type.as_str().into());
+    let _ = js_sys::Reflect::set(&object, &"isPrimary".into(), &self.is_primary.into());
+    object.into()
+  }
+}
+
+// Tracks every pointer currently in contact with (or, for mice and pens,
+// pressed over) a canvas: fingers on a touch screen, pens, pressed mice.
+// Also disables browser panning and zooming on the canvas, as those would
+// swallow the touches.
+#[wasm_bindgen]
+pub struct Pointers {
+  state: Rc<RefCell<Vec<PointerState>>>,
+  _listeners: Vec<EventListener>,
+}
+
+#[wasm_bindgen]
+impl Pointers {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<Pointers> {
+    let element = canvas.element()?;
+    element.style().set_property("touch-action", "none")?;
+    let state = Rc::new(RefCell::new(Vec::<PointerState>::new()));
+
+    let mut listeners = Vec::new();
+    for kind in ["pointerdown", "pointermove"] {
+      let state = state.clone();
+      let target = element.clone();
+      listeners.push(EventListener::new(&element, kind, move |event| {
+        let Some(event) = event.dyn_ref::<PointerEvent>() else {
+          return;
+        };
+        let mut pointers = state.borrow_mut();
+        let index = pointers.iter().position(|pointer| pointer.id == event.pointer_id());
+        // Hovering mice and pens are not tracked, only contacts.
+        if index.is_none() && event.buttons() == 0 {
+          return;
+        }
+
+        let (x, y) = normalized_position(&target, event);
+        let pointer = PointerState {
+          id: event.pointer_id(),
+          x,
+          y,
+          pressure: event.pressure(),
+          pointer_type: event.pointer_type(),
+          is_primary: event.is_primary(),
+        };
+        match index {
+          Some(index) => pointers[index] = pointer,
+          None => pointers.push(pointer),
+        }
+      })?);
+    }
+
+    for kind in ["pointerup", "pointercancel", "pointerleave"] {
+      let state = state.clone();
+      listeners.push(EventListener::new(&element, kind, move |event| {
+        if let Some(event) = event.dyn_ref::<PointerEvent>() {
+          state.borrow_mut().retain(|pointer| pointer.id != event.pointer_id());
+        }
+      })?);
+    }
+
+    Ok(Pointers { state, _listeners: listeners })
+  }
+
+  pub fn count(&self) -> usize {
+    self.state.borrow().len()
+  }
+
+  // Active pointers as `{ id, x, y, pressure, pointerType, isPrimary }`
+  // objects, in the order they went down.
+  pub fn pointers(&self) -> js_sys::Array {
+    self.state.borrow().iter().map(PointerState::to_js).collect()
+  }
+}
+
+impl Pointers {
+  pub(crate) fn state(&self) -> Rc<RefCell<Vec<PointerState>>> {
+    self.state.clone()
+  }
+}
+
+#[derive(Default)]
+struct LockState {
+  locked: bool,
+  // Movement since the last `take_movement`, in CSS pixels, y down.
+  dx: f64,
+  dy: f64,
+  // Movement summed since locking or `set_position`, kept within `bounds`.
+  x: f64,
+  y: f64,
+  // Minimum x and y, maximum x and y.
+  bounds: Option<[f64; 4]>,
+  // Called with `(dx, dy, time)` for every movement.
+  callback: Option<js_sys::Function>,
+}
+
+impl LockState {
+  fn clamp(&mut self) {
+    if let Some([min_x, min_y, max_x, max_y]) = self.bounds {
+      self.x = self.x.clamp(min_x, max_x);
+      self.y = self.y.clamp(min_y, max_y);
+    }
+  }
+}
+
+// Hides the cursor and takes raw mouse movement over a canvas, which does not
+// stop at the edges of the screen, for tracking tasks or adjusting a value
+// by moving the mouse. Movement is reported as deltas in CSS pixels, x right
+// and y down as on screen, and summed into a position that can be bounded.
+#[wasm_bindgen]
+pub struct PointerLock {
+  element: HtmlCanvasElement,
+  state: Rc<RefCell<LockState>>,
+  _listeners: Vec<EventListener>,
+}
+
+#[wasm_bindgen]
+impl PointerLock {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<PointerLock> {
+    let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+    let document = window.document().ok_or(GestaltError::NoDocument)?;
+    let element = canvas.element()?;
+    let state = Rc::new(RefCell::new(LockState::default()));
+
+    let mut listeners = Vec::new();
+    let move_state = state.clone();
+    listeners.push(EventListener::new(&element, "mousemove", move |event| {
+      let Some(event) = event.dyn_ref::<MouseEvent>() else {
+        return;
+      };
+      let (dx, dy) = (event.movement_x() as f64, event.movement_y() as f64);
+      let callback = {
+        let mut state = move_state.borrow_mut();
+        if !state.locked {
+          return;
+        }
+        state.dx += dx;
+        state.dy += dy;
+        state.x += dx;
+        state.y += dy;
+        state.clamp();
+        state.callback.clone()
+      };
+      if let Some(callback) = callback {
+        let _ = callback.call3(&JsValue::NULL, &dx.into(), &dy.into(), &event.time_stamp().into());
+      }
+    })?);
+
+    let change_state = state.clone();
+    let target = element.clone();
+    let change_document = document.clone();
+    listeners.push(EventListener::new(&document, "pointerlockchange", move |_| {
+      let locked = change_document.pointer_lock_element().as_deref() == Some(target.as_ref());
+      let mut state = change_state.borrow_mut();
+      if locked && !state.locked {
+        state.dx = 0.0;
+        state.dy = 0.0;
+      }
+      state.locked = locked;
+    })?);
+
+    listeners.push(EventListener::new(&document, "pointerlockerror", |_| {
+      web_sys::console::error_1(&"Pointer lock was refused".into());
+    })?);
+
+    Ok(PointerLock {
+      element,
+      state,
+      _listeners: listeners,
+    })
+  }
+
+  // Browsers only grant the lock from a user gesture, so call this from a
+  // click or key handler. `is_locked` turns true once it is granted; Escape
+  // releases it.
+  pub fn request(&self) {
+    self.element.request_pointer_lock();
+  }
+
+  pub fn exit(&self) {
+    if self.is_locked() {
+      if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+        document.exit_pointer_lock();
+      }
+    }
+  }
+
+  pub fn is_locked(&self) -> bool {
+    self.state.borrow().locked
+  }
+
+  // `[dx, dy]` moved since the last call, or since locking.
+  pub fn take_movement(&self) -> Vec<f64> {
+    let mut state = self.state.borrow_mut();
+    let movement = vec![state.dx, state.dy];
+    state.dx = 0.0;
+    state.dy = 0.0;
+    movement
+  }
+
+  pub fn x(&self) -> f64 {
+    self.state.borrow().x
+  }
+
+  pub fn y(&self) -> f64 {
+    self.state.borrow().y
+  }
+
+  // Moves the summed position, e.g. to a random start value at the beginning
+  // of an adjustment trial.
+  pub fn set_position(&self, x: f64, y: f64) {
+    let mut state = self.state.borrow_mut();
+    state.x = x;
+    state.y = y;
+    state.clamp();
+  }
+
+  // Keeps the summed position within the rectangle, so that moving past an
+  // end and back does not have to undo the overshoot.
+  pub fn set_bounds(&self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Result<()> {
+    if !(min_x <= max_x && min_y <= max_y) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "pointer bounds ({}, {}) to ({}, {}) are empty",
+        min_x, min_y, max_x, max_y
+      )));
+    }
+    let mut state = self.state.borrow_mut();
+    state.bounds = Some([min_x, min_y, max_x, max_y]);
+    state.clamp();
+    Ok(())
+  }
+
+  pub fn clear_bounds(&self) {
+    self.state.borrow_mut().bounds = None;
+  }
+
+  // Calls `callback` with `(dx, dy, time)` for every movement while locked,
+  // `time` on the clock of frame timestamps; `undefined` removes it.
+  pub fn set_callback(&self, callback: Option<js_sys::Function>) {
+    self.state.borrow_mut().callback = callback;
+  }
+}
+
+impl Drop for PointerLock {
+  fn drop(&mut self) {
+    self.exit();
+  }
+}
+
+pub(crate) fn normalized_position(element: &HtmlCanvasElement, event: &MouseEvent) -> (f32, f32) {
+  let rect = element.get_bounding_client_rect();
+  if rect.width() <= 0.0 || rect.height() <= 0.0 {
+    return (0.0, 0.0);
+  }
+  let x = (event.client_x() as f64 - rect.left()) / rect.width();
+  let y = 1.0 - (event.client_y() as f64 - rect.top()) / rect.height();
+  (x as f32, y as f32)
+}
+
+// A key going down or up. `time` is the event's `timeStamp`, on the same
+// high-resolution clock as the `requestAnimationFrame` timestamps passed to
+// `render`, so reaction times can be computed against frame onsets.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct KeyEvent {
+  pub(crate) key: String,
+  pub(crate) code: String,
+  pub(crate) down: bool,
+  pub(crate) time: f64,
+}
+
+impl KeyEvent {
+  fn to_js(&self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"key".into(), &self.key.as_str().into());
+    let _ = js_sys::Reflect::set(&object, &"code".into(), &self.code.as_str().into());
+    let _ = js_sys::Reflect::set(&object, &"down".into(), &self.down.into());
+    let _ = js_sys::Reflect::set(&object, &"time".into(), &self.time.into());
+    object.into()
+  }
+}
+
+#[derive(Default)]
+struct KeyboardState {
+  // The `key` ("a", "ArrowLeft") of held keys by `code` ("KeyA"). `key`
+  // changes with modifiers, so releases are matched by `code` and remove
+  // the `key` the press had.
+  down: HashMap<String, String>,
+  events: Vec<KeyEvent>,
+  callback: Option<js_sys::Function>,
+}
+
+impl KeyboardState {
+  // Returns the record for the JS callback, which has to be called once the
+  // state is no longer borrowed.
+  fn handle(&mut self, event: &KeyboardEvent, down: bool) -> Option<JsValue> {
+    // Auto-repeat is not a new press.
+    if event.repeat() {
+      return None;
+    }
+
+    let record = KeyEvent {
+      key: event.key(),
+      code: event.code(),
+      down,
+      time: event.time_stamp(),
+    };
+    if down {
+      self.press(&record.code, &record.key);
+    } else {
+      self.release(&record.code, &record.key);
+    }
+
+    let js_record = self.callback.as_ref().map(|_| record.to_js());
+    self.events.push(record);
+    js_record
+  }
+
+  // Keys without a `code`, e.g. from some virtual keyboards, go by `key`.
+  fn press(&mut self, code: &str, key: &str) {
+    let id = if code.is_empty() { key } else { code };
+    self.down.insert(id.to_string(), key.to_string());
+  }
+
+  fn release(&mut self, code: &str, key: &str) {
+    self.down.remove(if code.is_empty() { key } else { code });
+  }
+
+  fn is_down(&self, key: &str) -> bool {
+    self.down.contains_key(key) || self.down.values().any(|held| held == key)
+  }
+}
+
+// Records key presses and releases on the whole page with their timestamps.
+#[wasm_bindgen]
+pub struct Keyboard {
+  state: Rc<RefCell<KeyboardState>>,
+  _listeners: Vec<EventListener>,
+}
+
+#[wasm_bindgen]
+impl Keyboard {
+
+  pub fn new() -> Result<Keyboard> {
+    let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+    let state = Rc::new(RefCell::new(KeyboardState::default()));
+
+    let mut listeners = Vec::new();
+    for (kind, down) in [("keydown", true), ("keyup", false)] {
+      let state = state.clone();
+      listeners.push(EventListener::new(&window, kind, move |event| {
+        if let Some(event) = event.dyn_ref::<KeyboardEvent>() {
+          let record = state.borrow_mut().handle(event, down);
+          let callback = state.borrow().callback.clone();
+          if let (Some(callback), Some(record)) = (callback, record) {
+            let _ = callback.call1(&JsValue::NULL, &record);
+          }
+        }
+      })?);
+    }
+
+    // Keys released while the page is in the background never send `keyup`.
+    let blur_state = state.clone();
+    listeners.push(EventListener::new(&window, "blur", move |_| {
+      blur_state.borrow_mut().down.clear();
+    })?);
+
+    Ok(Keyboard { state, _listeners: listeners })
+  }
+
+  // `key` may be a `KeyboardEvent.key` ("a", " ", "ArrowLeft") or a
+  // `KeyboardEvent.code` ("KeyA", "Space").
+  pub fn is_down(&self, key: &str) -> bool {
+    self.state.borrow().is_down(key)
+  }
+
+  // Calls `callback` with `{ key, code, down, time }` for every press and
+  // release; `undefined` removes it.
+  pub fn set_callback(&self, callback: Option<js_sys::Function>) {
+    self.state.borrow_mut().callback = callback;
+  }
+
+  // All recorded events as `{ key, code, down, time }` objects, oldest first.
+  pub fn events(&self) -> js_sys::Array {
+    self.state.borrow().events.iter().map(KeyEvent::to_js).collect()
+  }
+
+  pub fn clear_events(&self) {
+    self.state.borrow_mut().events.clear();
+  }
+}
+
+// A gamepad button going down or up. `time` is the gamepad's `timestamp`,
+// when the browser last got data from it, on the clock of frame timestamps.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ButtonEvent {
+  pub(crate) gamepad: u32,
+  pub(crate) button: u32,
+  pub(crate) down: bool,
+  // 0 to 1, for analogue triggers.
+  pub(crate) value: f64,
+  pub(crate) time: f64,
+}
+
+impl ButtonEvent {
+  fn to_js(&self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"gamepad".into(), &self.gamepad.into());
+    let _ = js_sys::Reflect::set(&object, &"button".into(), &self.button.into());
+    let _ = js_sys::Reflect::set(&object, &"down".into(), &self.down.into());
+    let _ = js_sys::Reflect::set(&object, &"value".into(), &self.value.into());
+    let _ = js_sys::Reflect::set(&object, &"time".into(), &self.time.into());
+    object.into()
+  }
+}
+
+// One connected gamepad as of the last poll.
+struct PadState {
+  index: u32,
+  id: String,
+  timestamp: f64,
+  // Pressed and value of each button.
+  buttons: Vec<(bool, f64)>,
+  axes: Vec<f64>,
+}
+
+#[derive(Default)]
+pub(crate) struct GamepadState {
+  pads: Vec<PadState>,
+  events: Vec<ButtonEvent>,
+}
+
+impl GamepadState {
+  fn pad(&self, index: u32) -> Option<&PadState> {
+    self.pads.iter().find(|pad| pad.index == index)
+  }
+
+  // Reads every gamepad, logging buttons that changed since the last poll.
+  pub(crate) fn poll(&mut self) {
+    let Some(navigator) = web_sys::window().map(|window| window.navigator()) else {
+      return;
+    };
+    let gamepads = match navigator.get_gamepads() {
+      Ok(gamepads) => gamepads,
+      Err(_) => return,
+    };
+
+    let mut pads = Vec::new();
+    for gamepad in gamepads.iter().filter_map(|gamepad| gamepad.dyn_into::<Gamepad>().ok()) {
+      if !gamepad.connected() {
+        continue;
+      }
+      let index = gamepad.index();
+      let timestamp = gamepad.timestamp();
+      let buttons: Vec<(bool, f64)> = gamepad
+        .buttons()
+        .iter()
+        .map(|button| {
+          let button: GamepadButton = button.unchecked_into();
+          (button.pressed(), button.value())
+        })
+        .collect();
+      let axes = gamepad.axes().iter().map(|axis| axis.as_f64().unwrap_or(0.0)).collect();
+
+      // Buttons held when a gamepad shows up count as pressed then.
+      let previous = self.pads.iter().find(|pad| pad.index == index).map_or(&[][..], |pad| &pad.buttons[..]);
+      for (button, &(pressed, value)) in buttons.iter().enumerate() {
+        let was_pressed = previous.get(button).is_some_and(|&(pressed, _)| pressed);
+        if pressed != was_pressed {
+          self.events.push(ButtonEvent {
+            gamepad: index,
+            button: button as u32,
+            down: pressed,
+            value,
+            time: timestamp,
+          });
+        }
+      }
+      pads.push(PadState {
+        index,
+        id: gamepad.id(),
+        timestamp,
+        buttons,
+        axes,
+      });
+    }
+    self.pads = pads;
+  }
+}
+
+// Reads gamepads, joysticks and USB response boxes through the Gamepad API,
+// which has no events for buttons and has to be polled: on `poll`, e.g.
+// from a faster timer, and every frame once attached to a `RenderLoop`
+// with `attach_gamepads`, before the frame's trial callbacks run. Presses
+// and releases are logged with the time the browser got them from the
+// device, on the clock of frame timestamps, so they can be timed against
+// stimulus onsets. Browsers only list a gamepad once one of its buttons has
+// been pressed on the page. Buttons and axes are numbered as in the
+// "standard" mapping where the device has one.
+#[wasm_bindgen]
+pub struct Gamepads {
+  state: Rc<RefCell<GamepadState>>,
+}
+
+#[wasm_bindgen]
+impl Gamepads {
+
+  pub fn new() -> Gamepads {
+    Gamepads {
+      state: Rc::new(RefCell::new(GamepadState::default())),
+    }
+  }
+
+  pub fn poll(&self) {
+    self.state.borrow_mut().poll();
+  }
+
+  // Indices of the gamepads connected at the last poll.
+  pub fn connected(&self) -> Vec<u32> {
+    self.state.borrow().pads.iter().map(|pad| pad.index).collect()
+  }
+
+  pub fn is_connected(&self, gamepad: u32) -> bool {
+    self.state.borrow().pad(gamepad).is_some()
+  }
+
+  // The device's name as the browser reports it, empty if not connected.
+  pub fn id(&self, gamepad: u32) -> String {
+    self.state.borrow().pad(gamepad).map(|pad| pad.id.clone()).unwrap_or_default()
+  }
+
+  // When the browser last got data from the gamepad, NaN if not connected.
+  pub fn timestamp(&self, gamepad: u32) -> f64 {
+    self.state.borrow().pad(gamepad).map_or(f64::NAN, |pad| pad.timestamp)
+  }
+
+  pub fn is_pressed(&self, gamepad: u32, button: u32) -> bool {
+    let state = self.state.borrow();
+    state
+      .pad(gamepad)
+      .and_then(|pad| pad.buttons.get(button as usize))
+      .is_some_and(|&(pressed, _)| pressed)
+  }
+
+  // 0 to 1, for analogue triggers; 0 or 1 for plain buttons.
+  pub fn button_value(&self, gamepad: u32, button: u32) -> f64 {
+    let state = self.state.borrow();
+    state
+      .pad(gamepad)
+      .and_then(|pad| pad.buttons.get(button as usize))
+      .map_or(0.0, |&(_, value)| value)
+  }
+
+  // -1 to 1; 0 for axes the gamepad does not have.
+  pub fn axis(&self, gamepad: u32, axis: u32) -> f64 {
+    let state = self.state.borrow();
+    state
+      .pad(gamepad)
+      .and_then(|pad| pad.axes.get(axis as usize).copied())
+      .unwrap_or(0.0)
+  }
+
+  pub fn axes(&self, gamepad: u32) -> Vec<f64> {
+    self.state.borrow().pad(gamepad).map(|pad| pad.axes.clone()).unwrap_or_default()
+  }
+
+  // All logged button changes as `{ gamepad, button, down, value, time }`
+  // objects, oldest first.
+  pub fn events(&self) -> js_sys::Array {
+    self.state.borrow().events.iter().map(ButtonEvent::to_js).collect()
+  }
+
+  pub fn clear_events(&self) {
+    self.state.borrow_mut().events.clear();
+  }
+}
+
+impl Gamepads {
+  pub(crate) fn shared(&self) -> Rc<RefCell<GamepadState>> {
+    self.state.clone()
+  }
+}
+
+impl Default for Gamepads {
+  fn default() -> Gamepads {
+    Gamepads::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::KeyboardState;
+
+  #[test]
+  fn keys_are_down_by_key_and_code() {
+    let mut keyboard = KeyboardState::default();
+    keyboard.press("KeyA", "a");
+    assert!(keyboard.is_down("a"));
+    assert!(keyboard.is_down("KeyA"));
+    keyboard.release("KeyA", "a");
+    assert!(!keyboard.is_down("a"));
+    assert!(!keyboard.is_down("KeyA"));
+  }
+
+  #[test]
+  fn a_modifier_between_press_and_release_leaves_nothing_held() {
+    let mut keyboard = KeyboardState::default();
+    keyboard.press("KeyA", "a");
+    keyboard.press("ShiftLeft", "Shift");
+    keyboard.release("KeyA", "A");
+    keyboard.release("ShiftLeft", "Shift");
+    assert!(!keyboard.is_down("a"));
+    assert!(!keyboard.is_down("A"));
+    assert!(!keyboard.is_down("KeyA"));
+    assert!(!keyboard.is_down("Shift"));
+  }
+
+  #[test]
+  fn keys_without_a_code_go_by_key() {
+    let mut keyboard = KeyboardState::default();
+    keyboard.press("", "Unidentified");
+    assert!(keyboard.is_down("Unidentified"));
+    keyboard.release("", "Unidentified");
+    assert!(!keyboard.is_down("Unidentified"));
+  }
+}
@@ -0,0 +1,49 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::gl_state::BlendMode;
+
+// Bands the renderers drawing on top of a canvas' scene are composited in,
+// bottom to top; within a layer they draw in the order they were made.
+// Each renderer starts out in the layer that suits it, e.g. `Draw2D` and
+// text in `Overlay` so fixation marks sit above any stimulus, and can be
+// moved with its `set_layer`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+  Background,
+  Stimulus,
+  Overlay,
+  // `FrameStats` and whatever else should never be covered.
+  Debug,
+}
+
+impl Layer {
+  pub(crate) const ALL: [Layer; 4] = [Layer::Background, Layer::Stimulus, Layer::Overlay, Layer::Debug];
+
+  pub(crate) fn index(self) -> usize {
+    self as usize
+  }
+}
+
+// The layer of one renderer, shared with the canvas drawing it.
+pub(crate) type LayerSlot = Rc<Cell<Layer>>;
+
+// What the canvas does with a whole layer.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LayerSettings {
+  pub(crate) visible: bool,
+  // Replaces the blend modes of the layer's renderers, if set.
+  pub(crate) blend_mode: Option<BlendMode>,
+}
+
+impl Default for LayerSettings {
+  fn default() -> LayerSettings {
+    LayerSettings {
+      visible: true,
+      blend_mode: None,
+    }
+  }
+}
@@ -1,4 +1,63 @@
+mod animation;
+mod assets;
+mod audio;
+mod batch;
+mod camera;
+mod color;
+mod color_vision;
+mod constant_stimuli;
+mod context_options;
+mod display_geometry;
+mod dither;
+mod draw2d;
+mod error;
+mod events;
+mod experiment;
+mod feedback;
+mod frame_export;
+mod gamma;
+mod gaze;
+mod geometry;
+mod gl_state;
+mod gpu_timer;
 mod graphics;
+mod input;
+mod layers;
+mod lines;
+mod masking;
+mod math;
+mod microphone;
+mod midi;
+mod particles;
+mod picking;
+mod post_process;
+mod preprocessor;
+mod random;
+mod recorder;
+mod remote;
+mod render_loop;
+mod render_target;
+mod resources;
+mod response;
+mod results;
+mod scene;
+mod sdf;
+mod session;
+mod shader;
+mod shadertoy;
+mod sprites;
+mod staircase;
+mod stats;
+mod stencil;
+mod stimuli;
+mod storage;
+mod svg;
+mod text;
+mod texture;
+mod trial_order;
+mod viewport;
+mod webgl1;
+mod worker;
 
 //use wasm_bindgen::prelude::*;
 //use wasm_bindgen::{JsCast, JsValue};
@@ -0,0 +1,352 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+
+// Each segment is one instance: its end points, the far ends of the
+// neighbouring segments (equal to the end point where there is none) and its
+// style. The vertex shader spans a quad around the segment, large enough for
+// joins and caps; the fragment shader cuts the exact outline out of it and
+// antialiases the edges. Neighbouring segments split each join along the
+// bisector, so nothing is drawn twice and translucent lines stay even.
+const LINE_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 previous;
+in vec2 start;
+in vec2 end;
+in vec2 next;
+in vec4 color;
+in float width;
+in vec2 style;
+
+uniform vec2 u_resolution;
+uniform float u_miter_limit;
+
+out vec2 v_position;
+flat out vec2 v_previous;
+flat out vec2 v_start;
+flat out vec2 v_end;
+flat out vec2 v_next;
+flat out vec4 v_color;
+flat out float v_width;
+flat out vec2 v_style;
+
+// Two triangles; x picks the end, y the side of the segment.
+const vec2 CORNERS[6] = vec2[6](
+  vec2(0.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(0.0, -1.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main()
+{
+  vec2 corner = CORNERS[gl_VertexID];
+  vec2 dir = normalize(end - start);
+  vec2 normal = vec2(-dir.y, dir.x);
+
+  // Thin lines are drawn one pixel wide and faded instead.
+  float half_width = max(width, 1.0) * 0.5;
+  float cap_extent = half_width + 1.0;
+  float join_extent = half_width * max(u_miter_limit, 1.0) + 1.0;
+  float start_extent = previous == start ? cap_extent : join_extent;
+  float end_extent = next == end ? cap_extent : join_extent;
+
+  vec2 position = corner.x == 0.0 ? start - dir * start_extent : end + dir * end_extent;
+  position += normal * corner.y * (half_width + 1.0);
+
+  v_position = position;
+  v_previous = previous;
+  v_start = start;
+  v_end = end;
+  v_next = next;
+  v_color = color;
+  v_width = width;
+  v_style = style;
+
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+const LINE_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform float u_miter_limit;
+
+in vec2 v_position;
+flat in vec2 v_previous;
+flat in vec2 v_start;
+flat in vec2 v_end;
+flat in vec2 v_next;
+flat in vec4 v_color;
+flat in float v_width;
+flat in vec2 v_style;
+
+out vec4 outColor;
+
+const float NONE = -1e6;
+
+// Signed distance to the outline at the segment end `joint`, where
+// `outward` points away from the segment and `neighbor` is the far end of
+// the adjacent segment. Discards what the adjacent segment draws.
+float end_distance(vec2 joint, vec2 outward, vec2 normal, vec2 neighbor, float half_width)
+{
+  vec2 rel = v_position - joint;
+  float beyond = dot(rel, outward);
+
+  if (neighbor == joint) {
+    int cap = int(v_style.y + 0.5);
+    if (cap == 0) return beyond;
+    if (cap == 1) return beyond - half_width;
+    return beyond > 0.0 ? length(rel) - half_width : NONE;
+  }
+
+  vec2 away = normalize(neighbor - joint);
+  vec2 bisector = outward + away;
+  if (dot(bisector, bisector) < 1e-8) bisector = outward;
+  if (dot(rel, bisector) > 0.0) discard;
+
+  vec2 miter = outward - away;
+  if (dot(miter, miter) < 1e-8) return NONE;
+  miter = normalize(miter);
+  vec2 outer = dot(normal, miter) > 0.0 ? normal : -normal;
+  if (beyond <= 0.0 || dot(rel, outer) <= 0.0) return NONE;
+
+  int join = int(v_style.x + 0.5);
+  float cos_half = dot(outer, miter);
+  if (join == 0 && u_miter_limit * cos_half >= 1.0) return NONE;
+  if (join == 1) return length(rel) - half_width;
+  return dot(rel, miter) - half_width * cos_half;
+}
+
+void main()
+{
+  float half_width = max(v_width, 1.0) * 0.5;
+  vec2 dir = normalize(v_end - v_start);
+  vec2 normal = vec2(-dir.y, dir.x);
+
+  float outline = abs(dot(v_position - v_start, normal)) - half_width;
+  outline = max(outline, end_distance(v_start, -dir, normal, v_previous, half_width));
+  outline = max(outline, end_distance(v_end, dir, normal, v_next, half_width));
+
+  float coverage = clamp(0.5 - outline, 0.0, 1.0) * min(v_width, 1.0);
+  outColor = vec4(v_color.rgb, v_color.a * coverage);
+}
+"##;
+
+// How two segments of a polyline meet. Miters longer than the miter limit
+// times the line width fall back to bevels, as in SVG.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineJoin {
+  Miter,
+  Round,
+  Bevel,
+}
+
+// How the ends of an open polyline are drawn. `Square` and `Round` extend
+// past the end points by half the width.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineCap {
+  Butt,
+  Square,
+  Round,
+}
+
+// Draws antialiased polylines of any width on top of a canvas' scene, in the
+// same pixel coordinates and with the same immediate-mode behaviour as
+// `Draw2D`. Native GL lines are limited to one pixel on most platforms.
+#[wasm_bindgen]
+pub struct LineRenderer {
+  lines: Rc<RefCell<Lines>>,
+  layer: LayerSlot,
+}
+
+struct Lines {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  color: [f32; 4],
+  width: f32,
+  join: LineJoin,
+  cap: LineCap,
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl LineRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<LineRenderer> {
+    let context = canvas.context();
+    let mut program = ShaderProgram::new(&context, LINE_VERT_SHADER, LINE_FRAG_SHADER)?;
+    let layout = attribute_layout(
+      &program,
+      &[("previous", 2), ("start", 2), ("end", 2), ("next", 2), ("color", 4), ("width", 1), ("style", 2)],
+    )?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_f32("u_miter_limit", 4.0);
+
+    let lines = Rc::new(RefCell::new(Lines {
+      context,
+      program,
+      batch,
+      color: [1.0, 1.0, 1.0, 1.0],
+      width: 1.0,
+      join: LineJoin::Miter,
+      cap: LineCap::Butt,
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(lines.clone(), Layer::Overlay);
+    Ok(LineRenderer { lines, layer })
+  }
+
+  // Layer the lines are drawn in, `Overlay` to begin with.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // `Alpha` by default; with `Additive`, crossing lines add up where they
+  // overlap.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.lines.borrow_mut().blend_mode = mode;
+  }
+
+  pub fn set_color(&self, r: f32, g: f32, b: f32, a: f32) {
+    self.lines.borrow_mut().color = [r, g, b, a];
+  }
+
+  // Line width in pixels, 1 to begin with.
+  pub fn set_width(&self, width: f32) {
+    self.lines.borrow_mut().width = width.max(0.0);
+  }
+
+  pub fn set_join(&self, join: LineJoin) {
+    self.lines.borrow_mut().join = join;
+  }
+
+  pub fn set_cap(&self, cap: LineCap) {
+    self.lines.borrow_mut().cap = cap;
+  }
+
+  // Applies to all lines drawn from the next frame on; 4 by default.
+  pub fn set_miter_limit(&self, limit: f32) {
+    self.lines.borrow_mut().program.set_f32("u_miter_limit", limit.max(1.0));
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.lines.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.lines.borrow_mut().batch.clear();
+  }
+
+  // Strokes the polyline through `points`, a flat list of x, y pairs. A
+  // closed polyline joins its last point back to the first and has no caps.
+  pub fn stroke_polyline(&self, points: &[f32], closed: bool) -> Result<()> {
+    if !points.len().is_multiple_of(2) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "polyline points are x, y pairs, got {} floats",
+        points.len()
+      )));
+    }
+
+    // Repeated points would make zero-length segments without a direction.
+    let mut path: Vec<(f32, f32)> = Vec::with_capacity(points.len() / 2);
+    for point in points.chunks(2).map(|point| (point[0], point[1])) {
+      if path.last() != Some(&point) {
+        path.push(point);
+      }
+    }
+    if closed && path.len() > 2 && path.first() == path.last() {
+      path.pop();
+    }
+    if path.len() < 2 {
+      return Ok(());
+    }
+
+    self.lines.borrow_mut().push_path(&path, closed && path.len() > 2);
+    Ok(())
+  }
+
+  pub fn stroke_line(&self, x0: f32, y0: f32, x1: f32, y1: f32) -> Result<()> {
+    self.stroke_polyline(&[x0, y0, x1, y1], false)
+  }
+}
+
+impl Lines {
+  fn push_path(&mut self, path: &[(f32, f32)], closed: bool) {
+    let count = path.len();
+    let segments = if closed { count } else { count - 1 };
+    let style = [join_index(self.join), cap_index(self.cap)];
+
+    for index in 0..segments {
+      let start = path[index];
+      let end = path[(index + 1) % count];
+      // Open ends have themselves as neighbours.
+      let previous = if !closed && index == 0 { start } else { path[(index + count - 1) % count] };
+      let next = if !closed && index + 2 >= count { end } else { path[(index + 2) % count] };
+
+      let [red, green, blue, alpha] = self.color;
+      self.batch.push(&[
+        previous.0, previous.1,
+        start.0, start.1,
+        end.0, end.1,
+        next.0, next.1,
+        red, green, blue, alpha,
+        self.width,
+        style[0], style[1],
+      ]);
+    }
+  }
+}
+
+impl Overlay for Lines {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
+
+// The encodings of `LineJoin` and `LineCap` in `LINE_FRAG_SHADER`.
+
+fn join_index(join: LineJoin) -> f32 {
+  match join {
+    LineJoin::Miter => 0.0,
+    LineJoin::Round => 1.0,
+    LineJoin::Bevel => 2.0,
+  }
+}
+
+fn cap_index(cap: LineCap) -> f32 {
+  match cap {
+    LineCap::Butt => 0.0,
+    LineCap::Square => 1.0,
+    LineCap::Round => 2.0,
+  }
+}
@@ -0,0 +1,353 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::{WebGl2RenderingContext, WebGlTexture};
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::{check_positive, Space, Units};
+use crate::texture::Texture;
+
+// A single textured quad, its centre and size in pixels.
+const QUAD_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in vec2 size;
+in float alpha;
+
+uniform vec2 u_resolution;
+
+out vec2 v_uv;
+flat out float v_alpha;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+  vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+);
+
+void main()
+{
+  vec2 corner = CORNERS[gl_VertexID];
+  // Screen y runs down, texture v up.
+  v_uv = vec2(corner.x + 0.5, 0.5 - corner.y);
+  v_alpha = alpha;
+
+  vec2 clip = (center + corner * size) / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+const QUAD_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_texture;
+
+in vec2 v_uv;
+flat in float v_alpha;
+
+out vec4 outColor;
+
+void main()
+{
+  vec4 color = texture(u_texture, v_uv);
+  outColor = vec4(color.rgb, color.a * v_alpha);
+}
+"##;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+  Idle,
+  Target,
+  Interval,
+  Mask,
+  // The mask is gone; its offset is logged on the next frame.
+  Ending,
+  Done,
+}
+
+impl Phase {
+  fn name(self) -> &'static str {
+    match self {
+      Phase::Idle => "idle",
+      Phase::Target => "target",
+      Phase::Interval => "interval",
+      Phase::Mask => "mask",
+      Phase::Ending | Phase::Done => "done",
+    }
+  }
+}
+
+struct Masking {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  target: WebGlTexture,
+  mask: WebGlTexture,
+  // Whether both textures have been drawn since they were set, so that the
+  // driver holds them on the GPU before they are first needed.
+  primed: bool,
+  // Centre and size in stimulus units; none covers the canvas.
+  rect: Option<[f32; 4]>,
+  // Frames of target, interval and mask.
+  durations: [u32; 3],
+  phase: Phase,
+  // Frames shown of the current phase.
+  frame: u32,
+  // Frame timestamps of the target's, interval's and mask's onset and the
+  // mask's offset.
+  onsets: [Option<f64>; 4],
+  clock: Rc<Cell<f64>>,
+}
+
+impl Masking {
+  fn duration(&self, phase: Phase) -> u32 {
+    match phase {
+      Phase::Target => self.durations[0],
+      Phase::Interval => self.durations[1],
+      Phase::Mask => self.durations[2],
+      _ => 0,
+    }
+  }
+
+  fn next(phase: Phase) -> Phase {
+    match phase {
+      Phase::Target => Phase::Interval,
+      Phase::Interval => Phase::Mask,
+      Phase::Mask => Phase::Ending,
+      phase => phase,
+    }
+  }
+
+  // Logs the onset of the current phase if it is its first frame, and
+  // returns the texture to show.
+  fn begin_frame(&mut self, time: f64) -> Option<WebGlTexture> {
+    let slot = match self.phase {
+      Phase::Target => 0,
+      Phase::Interval => 1,
+      Phase::Mask => 2,
+      Phase::Ending => {
+        self.onsets[3] = Some(time);
+        self.phase = Phase::Done;
+        return None;
+      }
+      Phase::Idle | Phase::Done => return None,
+    };
+    if self.frame == 0 {
+      self.onsets[slot] = Some(time);
+    }
+    match self.phase {
+      Phase::Target => Some(self.target.clone()),
+      Phase::Mask => Some(self.mask.clone()),
+      _ => None,
+    }
+  }
+
+  // Counts the frame just drawn, moving on to the next phase with any
+  // frames when the current one is over.
+  fn end_frame(&mut self) {
+    if !matches!(self.phase, Phase::Target | Phase::Interval | Phase::Mask) {
+      return;
+    }
+    self.frame += 1;
+    if self.frame >= self.duration(self.phase) {
+      self.frame = 0;
+      self.phase = Masking::next(self.phase);
+      while self.duration(self.phase) == 0 && self.phase != Phase::Ending {
+        self.phase = Masking::next(self.phase);
+      }
+    }
+  }
+
+  fn draw_texture(&mut self, texture: &WebGlTexture, quad: [f32; 4], alpha: f32) {
+    self.batch.clear();
+    self.batch.push(&[quad[0], quad[1], quad[2], quad[3], alpha]);
+    gl_state::bind_texture(&self.context, 0, texture);
+    self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+  }
+}
+
+// Backward masking: a target for some frames, then an inter-stimulus
+// interval showing only the scene, then a mask, all counted in frames.
+// Both images are drawn once, invisibly, on the first frame after they are
+// set, so that neither has to be moved to the GPU mid-sequence. The
+// timestamp of every phase's first frame is logged, to check the actual
+// durations against the intended ones. Drawn on top of a canvas' scene,
+// over the whole canvas unless `set_rect` says otherwise.
+#[wasm_bindgen]
+pub struct BackwardMasking {
+  masking: Rc<RefCell<Masking>>,
+  layer: LayerSlot,
+}
+
+#[wasm_bindgen]
+impl BackwardMasking {
+
+  // One frame of target, no interval and one frame of mask to begin with.
+  pub fn new(canvas: &WebGlCanvas, target: &Texture, mask: &Texture) -> Result<BackwardMasking> {
+    let context = canvas.context();
+    target.check_context(&context)?;
+    mask.check_context(&context)?;
+    let mut program = ShaderProgram::new(&context, QUAD_VERT_SHADER, QUAD_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("center", 2), ("size", 2), ("alpha", 1)])?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_i32("u_texture", 0);
+
+    let masking = Rc::new(RefCell::new(Masking {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      target: target.raw().clone(),
+      mask: mask.raw().clone(),
+      primed: false,
+      rect: None,
+      durations: [1, 0, 1],
+      phase: Phase::Idle,
+      frame: 0,
+      onsets: [None; 4],
+      clock: canvas.frame_clock(),
+    }));
+    let layer = canvas.add_overlay(masking.clone(), Layer::Stimulus);
+    Ok(BackwardMasking { masking, layer })
+  }
+
+  // Layer the target and mask are drawn in; `Stimulus` by default.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.masking.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  pub fn set_target(&self, target: &Texture) -> Result<()> {
+    let mut masking = self.masking.borrow_mut();
+    target.check_context(&masking.context)?;
+    masking.target = target.raw().clone();
+    masking.primed = false;
+    Ok(())
+  }
+
+  pub fn set_mask(&self, mask: &Texture) -> Result<()> {
+    let mut masking = self.masking.borrow_mut();
+    mask.check_context(&masking.context)?;
+    masking.mask = mask.raw().clone();
+    masking.primed = false;
+    Ok(())
+  }
+
+  // Centre and size of the images in stimulus units.
+  pub fn set_rect(&self, x: f32, y: f32, width: f32, height: f32) -> Result<()> {
+    check_positive("masking width", width)?;
+    check_positive("masking height", height)?;
+    self.masking.borrow_mut().rect = Some([x, y, width, height]);
+    Ok(())
+  }
+
+  // Back to covering the whole canvas.
+  pub fn clear_rect(&self) {
+    self.masking.borrow_mut().rect = None;
+  }
+
+  // Frames of target, interval and mask. Target and mask need at least
+  // one; an interval of 0 puts the mask right after the target.
+  pub fn set_durations(&self, target: u32, interval: u32, mask: u32) -> Result<()> {
+    if target == 0 || mask == 0 {
+      return Err(GestaltError::InvalidArgument("target and mask must last at least one frame".into()));
+    }
+    self.masking.borrow_mut().durations = [target, interval, mask];
+    Ok(())
+  }
+
+  // Shows the target from the next frame drawn, e.g. from a trial's setup
+  // callback, restarting a sequence under way.
+  pub fn start(&self) {
+    let mut masking = self.masking.borrow_mut();
+    masking.phase = Phase::Target;
+    masking.frame = 0;
+    masking.onsets = [None; 4];
+  }
+
+  // Hides whatever is shown, keeping the timestamps so far.
+  pub fn stop(&self) {
+    self.masking.borrow_mut().phase = Phase::Idle;
+  }
+
+  pub fn is_running(&self) -> bool {
+    matches!(self.masking.borrow().phase, Phase::Target | Phase::Interval | Phase::Mask)
+  }
+
+  // "idle", "target", "interval", "mask" or "done".
+  pub fn phase(&self) -> String {
+    self.masking.borrow().phase.name().to_string()
+  }
+
+  // `{ targetOnset, intervalOnset, maskOnset, maskOffset }`, the frame
+  // timestamps of the last sequence in milliseconds, null for phases not
+  // reached or skipped, plus `targetDuration`, `intervalDuration` and
+  // `maskDuration` as actually shown, the interval's measured up to the
+  // mask's onset.
+  pub fn timing(&self) -> JsValue {
+    let onsets = self.masking.borrow().onsets;
+    let optional = |value: Option<f64>| value.map_or(JsValue::NULL, JsValue::from);
+    let between = |start: Option<f64>, end: Option<f64>| optional(start.zip(end).map(|(start, end)| end - start));
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"targetOnset".into(), &optional(onsets[0]));
+    let _ = js_sys::Reflect::set(&object, &"intervalOnset".into(), &optional(onsets[1]));
+    let _ = js_sys::Reflect::set(&object, &"maskOnset".into(), &optional(onsets[2]));
+    let _ = js_sys::Reflect::set(&object, &"maskOffset".into(), &optional(onsets[3]));
+    let _ = js_sys::Reflect::set(&object, &"targetDuration".into(), &between(onsets[0], onsets[1].or(onsets[2])));
+    let _ = js_sys::Reflect::set(&object, &"intervalDuration".into(), &between(onsets[1], onsets[2]));
+    let _ = js_sys::Reflect::set(&object, &"maskDuration".into(), &between(onsets[2], onsets[3]));
+    object.into()
+  }
+}
+
+impl Overlay for Masking {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    let texture = self.begin_frame(self.clock.get());
+    let primed = self.primed;
+    if texture.is_none() && primed {
+      self.end_frame();
+      return;
+    }
+
+    let quad = match self.rect {
+      Some([x, y, rect_width, rect_height]) => {
+        let (origin_x, origin_y) = self.space.origin(width, height);
+        let scale = self.space.scale();
+        [origin_x + x * scale, origin_y + y * scale, rect_width * scale, rect_height * scale]
+      }
+      None => [width as f32 / 2.0, height as f32 / 2.0, width as f32, height as f32],
+    };
+    let context = self.context.clone();
+    self.program.set_vec2("u_resolution", width as f32, height as f32);
+    gl_state::set_blend_mode(&context, Some(BlendMode::Alpha));
+    if !primed {
+      // Fully transparent, so nothing shows.
+      for image in [self.target.clone(), self.mask.clone()] {
+        self.draw_texture(&image, quad, 0.0);
+      }
+      self.primed = true;
+    }
+    if let Some(texture) = texture {
+      self.draw_texture(&texture, quad, 1.0);
+    }
+    self.end_frame();
+  }
+
+  // The textures are gone with the old context and have to be set again.
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
@@ -0,0 +1,471 @@
+use std::convert::TryInto;
+use std::ops::{Add, Mul, Sub};
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+
+// Vectors and matrices for composing transforms, in Rust or JavaScript, and
+// passing them to shaders with `WebGlCanvas::set_uniform_matrix4` and the
+// like. Matrices are column-major as GLSL expects and transform column
+// vectors, so `a.multiply(b)` applies `b` first. Angles are in degrees.
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec2 {
+  pub x: f32,
+  pub y: f32,
+}
+
+#[wasm_bindgen]
+impl Vec2 {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new(x: f32, y: f32) -> Vec2 {
+    Vec2 { x, y }
+  }
+
+  pub fn add(&self, other: &Vec2) -> Vec2 {
+    *self + *other
+  }
+
+  pub fn sub(&self, other: &Vec2) -> Vec2 {
+    *self - *other
+  }
+
+  pub fn scale(&self, factor: f32) -> Vec2 {
+    *self * factor
+  }
+
+  pub fn dot(&self, other: &Vec2) -> f32 {
+    self.x * other.x + self.y * other.y
+  }
+
+  pub fn length(&self) -> f32 {
+    self.dot(self).sqrt()
+  }
+
+  pub fn normalize(&self) -> Result<Vec2> {
+    Ok(*self * (1.0 / nonzero_length(self.length())?))
+  }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec3 {
+  pub x: f32,
+  pub y: f32,
+  pub z: f32,
+}
+
+#[wasm_bindgen]
+impl Vec3 {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3 { x, y, z }
+  }
+
+  pub fn add(&self, other: &Vec3) -> Vec3 {
+    *self + *other
+  }
+
+  pub fn sub(&self, other: &Vec3) -> Vec3 {
+    *self - *other
+  }
+
+  pub fn scale(&self, factor: f32) -> Vec3 {
+    *self * factor
+  }
+
+  pub fn dot(&self, other: &Vec3) -> f32 {
+    self.x * other.x + self.y * other.y + self.z * other.z
+  }
+
+  pub fn cross(&self, other: &Vec3) -> Vec3 {
+    Vec3::new(
+      self.y * other.z - self.z * other.y,
+      self.z * other.x - self.x * other.z,
+      self.x * other.y - self.y * other.x,
+    )
+  }
+
+  pub fn length(&self) -> f32 {
+    self.dot(self).sqrt()
+  }
+
+  pub fn normalize(&self) -> Result<Vec3> {
+    Ok(*self * (1.0 / nonzero_length(self.length())?))
+  }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec4 {
+  pub x: f32,
+  pub y: f32,
+  pub z: f32,
+  pub w: f32,
+}
+
+#[wasm_bindgen]
+impl Vec4 {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new(x: f32, y: f32, z: f32, w: f32) -> Vec4 {
+    Vec4 { x, y, z, w }
+  }
+
+  pub fn add(&self, other: &Vec4) -> Vec4 {
+    *self + *other
+  }
+
+  pub fn sub(&self, other: &Vec4) -> Vec4 {
+    *self - *other
+  }
+
+  pub fn scale(&self, factor: f32) -> Vec4 {
+    *self * factor
+  }
+
+  pub fn dot(&self, other: &Vec4) -> f32 {
+    self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+  }
+
+  pub fn length(&self) -> f32 {
+    self.dot(self).sqrt()
+  }
+}
+
+macro_rules! vector_ops {
+  ($type:ident { $($field:ident),+ }) => {
+    impl Add for $type {
+      type Output = $type;
+
+      fn add(self, other: $type) -> $type {
+        $type { $($field: self.$field + other.$field),+ }
+      }
+    }
+
+    impl Sub for $type {
+      type Output = $type;
+
+      fn sub(self, other: $type) -> $type {
+        $type { $($field: self.$field - other.$field),+ }
+      }
+    }
+
+    impl Mul<f32> for $type {
+      type Output = $type;
+
+      fn mul(self, factor: f32) -> $type {
+        $type { $($field: self.$field * factor),+ }
+      }
+    }
+  };
+}
+
+vector_ops!(Vec2 { x, y });
+vector_ops!(Vec3 { x, y, z });
+vector_ops!(Vec4 { x, y, z, w });
+
+// A 3x3 matrix, for 2D transforms in homogeneous coordinates or normal
+// matrices.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+  elements: [f32; 9],
+}
+
+#[wasm_bindgen]
+impl Mat3 {
+
+  pub fn identity() -> Mat3 {
+    Mat3 { elements: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0] }
+  }
+
+  // From 9 floats in column-major order.
+  pub fn from_elements(elements: &[f32]) -> Result<Mat3> {
+    let elements = elements
+      .try_into()
+      .map_err(|_| GestaltError::InvalidArgument(format!("a mat3 needs 9 floats, got {}", elements.len())))?;
+    Ok(Mat3 { elements })
+  }
+
+  pub fn translation(x: f32, y: f32) -> Mat3 {
+    let mut matrix = Mat3::identity();
+    matrix.elements[6] = x;
+    matrix.elements[7] = y;
+    matrix
+  }
+
+  pub fn scaling(x: f32, y: f32) -> Mat3 {
+    let mut matrix = Mat3::identity();
+    matrix.elements[0] = x;
+    matrix.elements[4] = y;
+    matrix
+  }
+
+  // Counterclockwise with y up, clockwise on screen with y down.
+  pub fn rotation(angle: f32) -> Mat3 {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    Mat3 { elements: [cos, sin, 0.0, -sin, cos, 0.0, 0.0, 0.0, 1.0] }
+  }
+
+  pub fn multiply(&self, other: &Mat3) -> Mat3 {
+    Mat3 { elements: multiply::<3, 9>(&self.elements, &other.elements) }
+  }
+
+  pub fn transpose(&self) -> Mat3 {
+    Mat3 { elements: transpose::<3, 9>(&self.elements) }
+  }
+
+  // Transforms `point` with a homogeneous 1, so translations apply.
+  pub fn transform_point(&self, point: &Vec2) -> Vec2 {
+    let m = &self.elements;
+    Vec2::new(
+      m[0] * point.x + m[3] * point.y + m[6],
+      m[1] * point.x + m[4] * point.y + m[7],
+    )
+  }
+
+  // The 9 floats in column-major order.
+  pub fn elements(&self) -> Vec<f32> {
+    self.elements.to_vec()
+  }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+  elements: [f32; 16],
+}
+
+#[wasm_bindgen]
+impl Mat4 {
+
+  pub fn identity() -> Mat4 {
+    let mut elements = [0.0; 16];
+    for i in 0..4 {
+      elements[i * 5] = 1.0;
+    }
+    Mat4 { elements }
+  }
+
+  // From 16 floats in column-major order.
+  pub fn from_elements(elements: &[f32]) -> Result<Mat4> {
+    let elements = elements
+      .try_into()
+      .map_err(|_| GestaltError::InvalidArgument(format!("a mat4 needs 16 floats, got {}", elements.len())))?;
+    Ok(Mat4 { elements })
+  }
+
+  // A perspective projection looking down -z, `fov_y` degrees high, with
+  // `aspect` the viewport's width over its height. Depth runs from `near`
+  // to `far`, both positive.
+  pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Result<Mat4> {
+    if !(fov_y > 0.0 && fov_y < 180.0) {
+      return Err(GestaltError::InvalidArgument(format!("field of view must be within (0, 180) degrees, got {}", fov_y)));
+    }
+    if aspect.is_nan() || aspect <= 0.0 || near.is_nan() || near <= 0.0 || far.is_nan() || far <= near {
+      return Err(GestaltError::InvalidArgument(format!(
+        "perspective needs a positive aspect and 0 < near < far, got {}, {} and {}",
+        aspect, near, far
+      )));
+    }
+    let f = 1.0 / (fov_y.to_radians() / 2.0).tan();
+    let depth = 1.0 / (near - far);
+    Ok(Mat4 {
+      elements: [
+        f / aspect, 0.0, 0.0, 0.0,
+        0.0, f, 0.0, 0.0,
+        0.0, 0.0, (far + near) * depth, -1.0,
+        0.0, 0.0, 2.0 * far * near * depth, 0.0,
+      ],
+    })
+  }
+
+  // Maps the box from (left, bottom, -near) to (right, top, -far) onto clip
+  // space.
+  pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Result<Mat4> {
+    if left == right || bottom == top || near == far {
+      return Err(GestaltError::InvalidArgument(String::from("orthographic box must not be flat")));
+    }
+    let (width, height, depth) = (right - left, top - bottom, far - near);
+    Ok(Mat4 {
+      elements: [
+        2.0 / width, 0.0, 0.0, 0.0,
+        0.0, 2.0 / height, 0.0, 0.0,
+        0.0, 0.0, -2.0 / depth, 0.0,
+        -(right + left) / width, -(top + bottom) / height, -(far + near) / depth, 1.0,
+      ],
+    })
+  }
+
+  // A view from `eye` towards `target`, with `up` pointing up on screen.
+  pub fn look_at(eye: &Vec3, target: &Vec3, up: &Vec3) -> Result<Mat4> {
+    let degenerate = |_| {
+      GestaltError::InvalidArgument(String::from(
+        "look_at needs a target apart from the eye and an up direction not along the view",
+      ))
+    };
+    let forward = (*target - *eye).normalize().map_err(degenerate)?;
+    let side = forward.cross(up).normalize().map_err(degenerate)?;
+    let up = side.cross(&forward);
+    Ok(Mat4 {
+      elements: [
+        side.x, up.x, -forward.x, 0.0,
+        side.y, up.y, -forward.y, 0.0,
+        side.z, up.z, -forward.z, 0.0,
+        -side.dot(eye), -up.dot(eye), forward.dot(eye), 1.0,
+      ],
+    })
+  }
+
+  pub fn translation(x: f32, y: f32, z: f32) -> Mat4 {
+    let mut matrix = Mat4::identity();
+    matrix.elements[12] = x;
+    matrix.elements[13] = y;
+    matrix.elements[14] = z;
+    matrix
+  }
+
+  pub fn scaling(x: f32, y: f32, z: f32) -> Mat4 {
+    let mut matrix = Mat4::identity();
+    matrix.elements[0] = x;
+    matrix.elements[5] = y;
+    matrix.elements[10] = z;
+    matrix
+  }
+
+  // A rotation by `angle` degrees around `axis`, counterclockwise looking
+  // down the axis towards the origin.
+  pub fn rotation(angle: f32, axis: &Vec3) -> Result<Mat4> {
+    let Vec3 { x, y, z } = axis
+      .normalize()
+      .map_err(|_| GestaltError::InvalidArgument(String::from("rotation axis must not be zero")))?;
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let t = 1.0 - cos;
+    Ok(Mat4 {
+      elements: [
+        t * x * x + cos, t * x * y + sin * z, t * x * z - sin * y, 0.0,
+        t * x * y - sin * z, t * y * y + cos, t * y * z + sin * x, 0.0,
+        t * x * z + sin * y, t * y * z - sin * x, t * z * z + cos, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+      ],
+    })
+  }
+
+  // `self` times `other`, as in a model-view-projection matrix built as
+  // `projection.multiply(view).multiply(model)`.
+  pub fn multiply(&self, other: &Mat4) -> Mat4 {
+    Mat4 { elements: multiply::<4, 16>(&self.elements, &other.elements) }
+  }
+
+  pub fn transpose(&self) -> Mat4 {
+    Mat4 { elements: transpose::<4, 16>(&self.elements) }
+  }
+
+  // Transforms `point` with a homogeneous 1 and divides by the resulting w,
+  // e.g. to find where a vertex lands in clip space.
+  pub fn transform_point(&self, point: &Vec3) -> Vec3 {
+    let v = self.transform(&Vec4::new(point.x, point.y, point.z, 1.0));
+    Vec3::new(v.x / v.w, v.y / v.w, v.z / v.w)
+  }
+
+  pub fn transform(&self, vector: &Vec4) -> Vec4 {
+    let m = &self.elements;
+    let row = |i: usize| m[i] * vector.x + m[4 + i] * vector.y + m[8 + i] * vector.z + m[12 + i] * vector.w;
+    Vec4::new(row(0), row(1), row(2), row(3))
+  }
+
+  // The 16 floats in column-major order.
+  pub fn elements(&self) -> Vec<f32> {
+    self.elements.to_vec()
+  }
+}
+
+impl Mat3 {
+  pub(crate) fn as_slice(&self) -> &[f32] {
+    &self.elements
+  }
+}
+
+impl Mat4 {
+  pub(crate) fn as_slice(&self) -> &[f32] {
+    &self.elements
+  }
+}
+
+// The same for flat arrays, as taken by `set_uniform_mat4`.
+
+#[wasm_bindgen]
+pub fn mat4_identity() -> Vec<f32> {
+  Mat4::identity().elements()
+}
+
+#[wasm_bindgen]
+pub fn mat4_perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Result<Vec<f32>> {
+  Ok(Mat4::perspective(fov_y, aspect, near, far)?.elements())
+}
+
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn mat4_look_at(
+  eye_x: f32, eye_y: f32, eye_z: f32,
+  target_x: f32, target_y: f32, target_z: f32,
+  up_x: f32, up_y: f32, up_z: f32,
+) -> Result<Vec<f32>> {
+  let eye = Vec3::new(eye_x, eye_y, eye_z);
+  let target = Vec3::new(target_x, target_y, target_z);
+  Ok(Mat4::look_at(&eye, &target, &Vec3::new(up_x, up_y, up_z))?.elements())
+}
+
+#[wasm_bindgen]
+pub fn mat4_translation(x: f32, y: f32, z: f32) -> Vec<f32> {
+  Mat4::translation(x, y, z).elements()
+}
+
+#[wasm_bindgen]
+pub fn mat4_scaling(x: f32, y: f32, z: f32) -> Vec<f32> {
+  Mat4::scaling(x, y, z).elements()
+}
+
+#[wasm_bindgen]
+pub fn mat4_rotation(angle: f32, x: f32, y: f32, z: f32) -> Result<Vec<f32>> {
+  Ok(Mat4::rotation(angle, &Vec3::new(x, y, z))?.elements())
+}
+
+#[wasm_bindgen]
+pub fn mat4_multiply(a: &[f32], b: &[f32]) -> Result<Vec<f32>> {
+  Ok(Mat4::from_elements(a)?.multiply(&Mat4::from_elements(b)?).elements())
+}
+
+fn nonzero_length(length: f32) -> Result<f32> {
+  if length > f32::EPSILON {
+    Ok(length)
+  } else {
+    Err(GestaltError::InvalidArgument(String::from("cannot normalize a zero vector")))
+  }
+}
+
+// Column-major `a * b` of two `N` x `N` matrices with `L` = N * N elements.
+fn multiply<const N: usize, const L: usize>(a: &[f32; L], b: &[f32; L]) -> [f32; L] {
+  let mut product = [0.0; L];
+  for column in 0..N {
+    for row in 0..N {
+      product[column * N + row] = (0..N).map(|k| a[k * N + row] * b[column * N + k]).sum();
+    }
+  }
+  product
+}
+
+fn transpose<const N: usize, const L: usize>(m: &[f32; L]) -> [f32; L] {
+  let mut transposed = [0.0; L];
+  for column in 0..N {
+    for row in 0..N {
+      transposed[row * N + column] = m[column * N + row];
+    }
+  }
+  transposed
+}
@@ -0,0 +1,257 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::{AnalyserNode, AudioContext, MediaRecorderOptions, MediaStream, MediaStreamAudioSourceNode, MediaStreamConstraints, MediaStreamTrack, WebGl2RenderingContext};
+
+use crate::audio::{performance_now, AudioPlayer};
+use crate::error::{GestaltError, Result};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::Layer;
+use crate::recorder::{supported_mime_type, Recorder};
+
+// Samples kept by the analyser, about 40 ms at 48 kHz: a frame's worth of
+// sound with room for a slow one. Longer frames skip the oldest samples.
+const WINDOW: u32 = 2048;
+
+// Tried in order for `Microphone::recorder`.
+const MIME_TYPES: [&str; 4] = ["audio/webm;codecs=opus", "audio/webm", "audio/ogg;codecs=opus", "audio/mp4"];
+
+// The outcome of one trial, in milliseconds on the frame timestamp clock.
+struct VoiceRecord {
+  trial: usize,
+  // Timestamp of the first frame drawn after the trial started.
+  onset: Option<f64>,
+  voice_onset: Option<f64>,
+}
+
+impl VoiceRecord {
+  fn to_js(&self) -> JsValue {
+    let optional = |value: Option<f64>| value.map_or(JsValue::NULL, JsValue::from);
+    let rt = self.voice_onset.zip(self.onset).map(|(voice, onset)| voice - onset);
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"trial".into(), &self.trial.into());
+    let _ = js_sys::Reflect::set(&object, &"onset".into(), &optional(self.onset));
+    let _ = js_sys::Reflect::set(&object, &"voiceOnset".into(), &optional(self.voice_onset));
+    let _ = js_sys::Reflect::set(&object, &"rt".into(), &optional(rt));
+    object.into()
+  }
+}
+
+struct OpenTrial {
+  index: usize,
+  onset: Option<f64>,
+}
+
+struct MicrophoneState {
+  clock: Rc<Cell<f64>>,
+  context: AudioContext,
+  analyser: AnalyserNode,
+  samples: Vec<f32>,
+  // Context time of the newest sample looked at.
+  scanned: f64,
+  threshold: f32,
+  level: f32,
+  peak: f32,
+  trial: Option<OpenTrial>,
+  records: Vec<VoiceRecord>,
+}
+
+impl MicrophoneState {
+  fn close(&mut self, voice_onset: Option<f64>) {
+    if let Some(trial) = self.trial.take() {
+      self.records.push(VoiceRecord {
+        trial: trial.index,
+        onset: trial.onset,
+        voice_onset,
+      });
+    }
+  }
+
+  // Looks at the samples that arrived since the last frame, dating each by
+  // how long before the context's current time it was taken.
+  fn scan(&mut self) -> Result<()> {
+    let now = performance_now()?;
+    let context_time = self.context.current_time();
+    let rate = self.context.sample_rate() as f64;
+    self.analyser.get_float_time_domain_data(&mut self.samples);
+    let count = self.samples.len();
+    let fresh = (((context_time - self.scanned) * rate).round().max(0.0) as usize).min(count);
+    self.scanned = context_time;
+    if fresh == 0 {
+      return Ok(());
+    }
+
+    let samples = &self.samples[count - fresh..];
+    self.level = (samples.iter().map(|sample| sample * sample).sum::<f32>() / fresh as f32).sqrt();
+    self.peak = samples.iter().fold(0.0, |peak, sample| sample.abs().max(peak));
+
+    let onset = match &self.trial {
+      Some(OpenTrial { onset: Some(onset), .. }) => *onset,
+      _ => return Ok(()),
+    };
+    let threshold = self.threshold;
+    let voice_onset = samples
+      .iter()
+      .enumerate()
+      .filter(|(_, sample)| sample.abs() >= threshold)
+      .map(|(index, _)| now - (fresh - index) as f64 / rate * 1000.0)
+      .find(|&time| time >= onset);
+    if voice_onset.is_some() {
+      self.close(voice_onset);
+    }
+    Ok(())
+  }
+}
+
+// Onsets are taken, and the input scanned, in the frames the microphone is
+// drawn in.
+impl Overlay for MicrophoneState {
+  fn draw(&mut self, _width: u32, _height: u32, _time: f32) {
+    if let Some(trial) = &mut self.trial {
+      trial.onset.get_or_insert(self.clock.get());
+    }
+    if let Err(error) = self.scan() {
+      web_sys::console::error_1(&error.into());
+    }
+  }
+
+  fn restore(&mut self, _context: &WebGl2RenderingContext) -> Result<()> {
+    Ok(())
+  }
+}
+
+// A microphone for verbal responses: its level for a meter, and a voice
+// key timing the first sample at or above a threshold in each trial
+// against the stimulus onset, as `ResponseRecorder` does for keys. Voice
+// onsets are on the frame timestamp clock, dated from the audio context's
+// clock to within a few milliseconds; the input latency of the microphone
+// and the browser is not compensated and needs measuring for absolute
+// times. Browser echo cancellation, noise suppression and gain control
+// are turned off, as they delay and reshape onsets.
+#[wasm_bindgen]
+pub struct Microphone {
+  state: Rc<RefCell<MicrophoneState>>,
+  stream: MediaStream,
+  source: MediaStreamAudioSourceNode,
+}
+
+#[wasm_bindgen]
+impl Microphone {
+
+  // Asks for the microphone, resolving to a `Microphone` once allowed.
+  // Input goes through `player`'s context, which has to be running, and is
+  // scanned every frame `canvas` renders.
+  pub fn open(player: &AudioPlayer, canvas: &WebGlCanvas) -> Result<js_sys::Promise> {
+    let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+    let devices = window.navigator().media_devices()?;
+    let context = player.context();
+    let analyser = context.create_analyser()?;
+    analyser.set_fft_size(WINDOW);
+    let state = Rc::new(RefCell::new(MicrophoneState {
+      clock: canvas.frame_clock(),
+      scanned: context.current_time(),
+      context,
+      analyser,
+      samples: vec![0.0; WINDOW as usize],
+      threshold: 0.1,
+      level: 0.0,
+      peak: 0.0,
+      trial: None,
+      records: Vec::new(),
+    }));
+    canvas.add_overlay(state.clone(), Layer::Stimulus);
+
+    let audio = js_sys::Object::new();
+    for name in &["echoCancellation", "noiseSuppression", "autoGainControl"] {
+      js_sys::Reflect::set(&audio, &(*name).into(), &JsValue::FALSE)?;
+    }
+    let constraints = MediaStreamConstraints::new();
+    constraints.set_audio(&audio);
+    let request = devices.get_user_media_with_constraints(&constraints)?;
+    Ok(wasm_bindgen_futures::future_to_promise(async move {
+      let stream: MediaStream = JsFuture::from(request).await?.unchecked_into();
+      let source = state.borrow().context.create_media_stream_source(&stream)?;
+      source.connect_with_audio_node(&state.borrow().analyser)?;
+      Ok(Microphone { state, stream, source }.into())
+    }))
+  }
+
+  // Smallest absolute sample, 0 to 1, taken as the voice; 0.1 to begin
+  // with. Set it above the level of the room in silence.
+  pub fn set_threshold(&self, threshold: f32) -> Result<()> {
+    if !(threshold > 0.0 && threshold <= 1.0) {
+      return Err(GestaltError::InvalidArgument(format!("threshold must be above 0 and at most 1, got {}", threshold)));
+    }
+    self.state.borrow_mut().threshold = threshold;
+    Ok(())
+  }
+
+  // RMS of the samples taken during the last frame, 0 to 1.
+  pub fn level(&self) -> f32 {
+    self.state.borrow().level
+  }
+
+  // Largest absolute sample taken during the last frame, 0 to 1.
+  pub fn peak(&self) -> f32 {
+    self.state.borrow().peak
+  }
+
+  // Opens the next trial, closing an open one without a voice onset.
+  pub fn start_trial(&self) {
+    let mut state = self.state.borrow_mut();
+    state.close(None);
+    let index = state.records.len();
+    state.trial = Some(OpenTrial { index, onset: None });
+  }
+
+  // Closes the open trial without a voice onset, e.g. after a deadline.
+  pub fn end_trial(&self) {
+    self.state.borrow_mut().close(None);
+  }
+
+  // Whether a trial is open, i.e. no voice has been heard since its onset.
+  pub fn is_waiting(&self) -> bool {
+    self.state.borrow().trial.is_some()
+  }
+
+  // Closed trials as `{ trial, onset, voiceOnset, rt }` objects, oldest
+  // first; `voiceOnset` and `rt` are null without a voice onset.
+  pub fn records(&self) -> js_sys::Array {
+    self.state.borrow().records.iter().map(VoiceRecord::to_js).collect()
+  }
+
+  // Drops the records and any open trial; trial numbers start over.
+  pub fn clear(&self) {
+    let mut state = self.state.borrow_mut();
+    state.records.clear();
+    state.trial = None;
+  }
+
+  // The microphone's stream, e.g. for `AudioAnalyser::from_stream`.
+  pub fn stream(&self) -> MediaStream {
+    self.stream.clone()
+  }
+
+  // A recorder of the microphone, e.g. to code responses later.
+  // `bits_per_second` sets the audio bitrate, 0 leaving it to the browser.
+  pub fn recorder(&self, bits_per_second: u32) -> Result<Recorder> {
+    let options = MediaRecorderOptions::new();
+    options.set_mime_type(supported_mime_type(&MIME_TYPES, "audio")?);
+    if bits_per_second > 0 {
+      options.set_audio_bits_per_second(bits_per_second);
+    }
+    Recorder::from_stream(&self.stream, &options)
+  }
+
+  // Releases the microphone; level and voice onsets stop updating.
+  pub fn stop(&self) {
+    let _ = self.source.disconnect();
+    for track in self.stream.get_tracks().iter() {
+      track.unchecked_into::<MediaStreamTrack>().stop();
+    }
+  }
+}
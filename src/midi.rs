@@ -0,0 +1,231 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::{MidiAccess, MidiInput, MidiMessageEvent};
+
+use crate::animation::AnimationTarget;
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+  Control(u8),
+  Note(u8),
+}
+
+// A control or note driving a target between two sets of values.
+struct Mapping {
+  // 1 to 16, or any.
+  channel: Option<u8>,
+  source: Source,
+  target: AnimationTarget,
+  low: Vec<f32>,
+  high: Vec<f32>,
+}
+
+impl Mapping {
+  fn matches(&self, channel: u8, source: Source) -> bool {
+    self.source == source && self.channel.is_none_or(|mapped| mapped == channel)
+  }
+
+  fn apply(&self, amount: f32) {
+    let values: Vec<f32> = self.low.iter().zip(&self.high).map(|(low, high)| low + (high - low) * amount).collect();
+    self.target.set(&values);
+  }
+}
+
+struct MidiState {
+  mappings: Vec<Mapping>,
+  // Latest value of each control, by channel and number.
+  controls: HashMap<(u8, u8), u8>,
+  callback: Option<js_sys::Function>,
+}
+
+impl MidiState {
+  // Applies a message to the mapped targets. Returns it for the JS
+  // callback, which has to be called once the state is no longer borrowed.
+  fn handle(&mut self, data: &[u8], time: f64) -> Option<JsValue> {
+    let (status, number, value) = match *data {
+      [status, number, value, ..] => (status, number, value),
+      _ => return None,
+    };
+    let channel = (status & 0x0f) + 1;
+    let (kind, source, amount) = match status & 0xf0 {
+      0xb0 => {
+        self.controls.insert((channel, number), value);
+        ("control", Source::Control(number), value as f32 / 127.0)
+      }
+      // Note on with velocity 0 is a note off.
+      0x90 if value > 0 => ("noteon", Source::Note(number), value as f32 / 127.0),
+      0x80 | 0x90 => ("noteoff", Source::Note(number), 0.0),
+      _ => return None,
+    };
+    for mapping in self.mappings.iter().filter(|mapping| mapping.matches(channel, source)) {
+      mapping.apply(amount);
+    }
+
+    self.callback.as_ref()?;
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"type".into(), &kind.into());
+    let _ = js_sys::Reflect::set(&object, &"channel".into(), &channel.into());
+    let _ = js_sys::Reflect::set(&object, &"number".into(), &number.into());
+    let _ = js_sys::Reflect::set(&object, &"value".into(), &value.into());
+    let _ = js_sys::Reflect::set(&object, &"time".into(), &time.into());
+    Some(object.into())
+  }
+}
+
+// Hardware controllers through Web MIDI, for tweaking shaders and stimuli
+// live: control changes (knobs, faders) and notes (pads, keys) drive
+// uniforms and scene node properties as they arrive. Every connected input
+// is listened to, including ones plugged in later.
+#[wasm_bindgen]
+pub struct MidiController {
+  state: Rc<RefCell<MidiState>>,
+  access: MidiAccess,
+  inputs: Rc<RefCell<Vec<EventListener>>>,
+  _listener: EventListener,
+}
+
+#[wasm_bindgen]
+impl MidiController {
+
+  // Asks for MIDI access, resolving to a `MidiController` once allowed.
+  pub fn open() -> Result<js_sys::Promise> {
+    let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+    let request = window.navigator().request_midi_access()?;
+    Ok(wasm_bindgen_futures::future_to_promise(async move {
+      let access: MidiAccess = JsFuture::from(request).await?.unchecked_into();
+      let state = Rc::new(RefCell::new(MidiState {
+        mappings: Vec::new(),
+        controls: HashMap::new(),
+        callback: None,
+      }));
+      let inputs = Rc::new(RefCell::new(listen(&access, &state)?));
+
+      // Inputs come and go; listen to whichever are there now.
+      let weak_inputs: Weak<RefCell<Vec<EventListener>>> = Rc::downgrade(&inputs);
+      let change_access = access.clone();
+      let change_state = state.clone();
+      let listener = EventListener::new(&access, "statechange", move |_| {
+        if let (Some(inputs), Ok(listeners)) = (weak_inputs.upgrade(), listen(&change_access, &change_state)) {
+          *inputs.borrow_mut() = listeners;
+        }
+      })?;
+
+      Ok(MidiController { state, access, inputs, _listener: listener }.into())
+    }))
+  }
+
+  // Drives `target` with control change `controller` (0 to 127), from `low`
+  // at 0 to `high` at 127. `channel` is 1 to 16, `undefined` for any.
+  pub fn map_control(&self, channel: Option<u8>, controller: u8, target: &AnimationTarget, low: &[f32], high: &[f32]) -> Result<()> {
+    self.add_mapping(channel, Source::Control(controller), target, low, high)
+  }
+
+  // Drives `target` with `note` (0 to 127): towards `high` with the
+  // velocity it is struck with, back to `low` when it is released.
+  pub fn map_note(&self, channel: Option<u8>, note: u8, target: &AnimationTarget, low: &[f32], high: &[f32]) -> Result<()> {
+    self.add_mapping(channel, Source::Note(note), target, low, high)
+  }
+
+  // Removes the mappings of a control, from any channel.
+  pub fn unmap_control(&self, controller: u8) {
+    self.state.borrow_mut().mappings.retain(|mapping| mapping.source != Source::Control(controller));
+  }
+
+  pub fn unmap_note(&self, note: u8) {
+    self.state.borrow_mut().mappings.retain(|mapping| mapping.source != Source::Note(note));
+  }
+
+  pub fn clear_mappings(&self) {
+    self.state.borrow_mut().mappings.clear();
+  }
+
+  // Latest value of a control, 0 to 1, or `undefined` before it has moved.
+  pub fn control_value(&self, channel: u8, controller: u8) -> Option<f32> {
+    self.state.borrow().controls.get(&(channel, controller)).map(|&value| value as f32 / 127.0)
+  }
+
+  // Calls `callback` with each control change and note as
+  // `{ type, channel, number, value, time }`, `type` being "control",
+  // "noteon" or "noteoff" and `time` the event's timestamp, e.g. to find
+  // which knob to map; `undefined` removes it.
+  pub fn set_callback(&self, callback: Option<js_sys::Function>) {
+    self.state.borrow_mut().callback = callback;
+  }
+
+  // Names of the connected inputs.
+  pub fn input_names(&self) -> js_sys::Array {
+    inputs(&self.access)
+      .iter()
+      .map(|input| JsValue::from(input.name().unwrap_or_else(|| input.id())))
+      .collect()
+  }
+
+  pub fn input_count(&self) -> usize {
+    self.inputs.borrow().len()
+  }
+}
+
+impl MidiController {
+  fn add_mapping(&self, channel: Option<u8>, source: Source, target: &AnimationTarget, low: &[f32], high: &[f32]) -> Result<()> {
+    if let Some(channel) = channel.filter(|channel| !(1..=16).contains(channel)) {
+      return Err(GestaltError::InvalidArgument(format!("MIDI channels are 1 to 16, not {}", channel)));
+    }
+    let number = match source {
+      Source::Control(number) | Source::Note(number) => number,
+    };
+    if number > 127 {
+      return Err(GestaltError::InvalidArgument(format!("MIDI numbers are 0 to 127, not {}", number)));
+    }
+    if low.len() != target.components() || high.len() != target.components() {
+      return Err(GestaltError::InvalidArgument(format!(
+        "the target takes {} values, got {} to {}",
+        target.components(),
+        low.len(),
+        high.len()
+      )));
+    }
+    self.state.borrow_mut().mappings.push(Mapping {
+      channel,
+      source,
+      target: target.clone(),
+      low: low.to_vec(),
+      high: high.to_vec(),
+    });
+    Ok(())
+  }
+}
+
+fn inputs(access: &MidiAccess) -> Vec<MidiInput> {
+  access.inputs().values().into_iter().filter_map(|input| input.ok()?.dyn_into().ok()).collect()
+}
+
+fn listen(access: &MidiAccess, state: &Rc<RefCell<MidiState>>) -> Result<Vec<EventListener>> {
+  inputs(access)
+    .iter()
+    .map(|input| {
+      let state = state.clone();
+      EventListener::new(input, "midimessage", move |event| {
+        let event = match event.dyn_ref::<MidiMessageEvent>() {
+          Some(event) => event,
+          None => return,
+        };
+        let message = match event.data() {
+          Ok(data) => state.borrow_mut().handle(&data, event.time_stamp()),
+          Err(_) => return,
+        };
+        let callback = state.borrow().callback.clone();
+        if let (Some(callback), Some(message)) = (callback, message) {
+          let _ = callback.call1(&JsValue::NULL, &message);
+        }
+      })
+    })
+    .collect()
+}
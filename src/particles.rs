@@ -0,0 +1,389 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::random::Rng;
+use crate::shader::ShaderProgram;
+
+// One instance per particle. Size and colour follow `life`, 0 when the
+// particle is spawned and 1 when it dies.
+const PARTICLE_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in float life;
+
+uniform vec2 u_resolution;
+uniform vec2 u_size;
+uniform vec4 u_color_start;
+uniform vec4 u_color_end;
+
+out vec2 v_offset;
+flat out float v_radius;
+flat out vec4 v_color;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  v_radius = mix(u_size.x, u_size.y, life) * 0.5;
+  v_offset = CORNERS[gl_VertexID] * (v_radius + 0.5);
+  v_color = mix(u_color_start, u_color_end, life);
+
+  vec2 clip = (center + v_offset) / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+const PARTICLE_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+in vec2 v_offset;
+flat in float v_radius;
+flat in vec4 v_color;
+
+out vec4 outColor;
+
+void main()
+{
+  float coverage = clamp(v_radius - length(v_offset) + 0.5, 0.0, 1.0);
+  outColor = vec4(v_color.rgb, v_color.a * coverage);
+}
+"##;
+
+// Longer gaps between frames, e.g. while the page was hidden, are simulated
+// as this many seconds so the particles do not jump.
+const MAX_STEP: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+  x: f32,
+  y: f32,
+  vx: f32,
+  vy: f32,
+  // Seconds.
+  age: f32,
+  lifetime: f32,
+}
+
+// Round particles spawned continuously in an emitter rectangle and moved by
+// simple velocity fields, drawn in one instanced call on top of a canvas'
+// scene: generative visuals, or dynamic noise such as falling snow. The
+// simulation steps with the frame clock; size and colour change linearly
+// over each particle's life. Coordinates are drawing-buffer pixels from the
+// top left and velocities pixels per second.
+#[wasm_bindgen]
+pub struct ParticleSystem {
+  particles: Rc<RefCell<Particles>>,
+  layer: LayerSlot,
+}
+
+struct Particles {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  rng: Rng,
+  particles: Vec<Particle>,
+  max_particles: usize,
+  // x, y, width and height; the whole drawing buffer if unset.
+  emitter: Option<[f32; 4]>,
+  // Particles per second.
+  spawn_rate: f32,
+  // Fractional particles still to be spawned.
+  pending: f32,
+  // Shortest and longest, in seconds.
+  lifetime: (f32, f32),
+  velocity: (f32, f32),
+  // Standard deviation of each component of the initial velocity.
+  velocity_spread: f32,
+  gravity: (f32, f32),
+  // Moves the particles on top of their own velocity.
+  wind: (f32, f32),
+  // Centre and angular speed in radians per second, clockwise on screen.
+  vortex: Option<(f32, f32, f32)>,
+  // Standard deviation of the velocity kicks per square root second.
+  turbulence: f32,
+  // Fraction of velocity lost per second.
+  drag: f32,
+  size: (f32, f32),
+  color_start: [f32; 4],
+  color_end: [f32; 4],
+  blend_mode: BlendMode,
+  paused: bool,
+  last_time: Option<f32>,
+}
+
+#[wasm_bindgen]
+impl ParticleSystem {
+
+  // 100 white particles a second, each living 2 seconds and 4 pixels
+  // across, spawned anywhere on the canvas and standing still.
+  pub fn new(canvas: &WebGlCanvas) -> Result<ParticleSystem> {
+    let context = canvas.context();
+    let program = ShaderProgram::new(&context, PARTICLE_VERT_SHADER, PARTICLE_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("center", 2), ("life", 1)])?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+
+    let particles = Rc::new(RefCell::new(Particles {
+      context,
+      program,
+      batch,
+      rng: Rng::from_entropy(),
+      particles: Vec::new(),
+      max_particles: 10_000,
+      emitter: None,
+      spawn_rate: 100.0,
+      pending: 0.0,
+      lifetime: (2.0, 2.0),
+      velocity: (0.0, 0.0),
+      velocity_spread: 0.0,
+      gravity: (0.0, 0.0),
+      wind: (0.0, 0.0),
+      vortex: None,
+      turbulence: 0.0,
+      drag: 0.0,
+      size: (4.0, 4.0),
+      color_start: [1.0, 1.0, 1.0, 1.0],
+      color_end: [1.0, 1.0, 1.0, 1.0],
+      blend_mode: BlendMode::Alpha,
+      paused: false,
+      last_time: None,
+    }));
+    let layer = canvas.add_overlay(particles.clone(), Layer::Stimulus);
+    Ok(ParticleSystem { particles, layer })
+  }
+
+  // Layer the particles are drawn in; `Stimulus` by default.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Makes the spawning and motion repeat exactly for the same seed and
+  // frame timestamps, e.g. for `FrameExporter`.
+  pub fn set_seed(&self, seed: u32) {
+    self.particles.borrow_mut().rng = Rng::new(seed as u64);
+  }
+
+  // Where new particles appear, uniformly at random.
+  pub fn set_emitter(&self, x: f32, y: f32, width: f32, height: f32) -> Result<()> {
+    if width < 0.0 || height < 0.0 {
+      return Err(GestaltError::InvalidArgument(format!("an emitter cannot be {} x {} pixels", width, height)));
+    }
+    self.particles.borrow_mut().emitter = Some([x, y, width, height]);
+    Ok(())
+  }
+
+  // Spawns over the whole drawing buffer again.
+  pub fn clear_emitter(&self) {
+    self.particles.borrow_mut().emitter = None;
+  }
+
+  // Particles per second; 0 stops spawning, leaving the living ones.
+  pub fn set_spawn_rate(&self, rate: f32) {
+    self.particles.borrow_mut().spawn_rate = rate.max(0.0);
+  }
+
+  // Each particle lives a uniformly random time between `min` and `max`
+  // seconds.
+  pub fn set_lifetime(&self, min: f32, max: f32) -> Result<()> {
+    if min <= 0.0 || max < min {
+      return Err(GestaltError::InvalidArgument(format!("lifetimes cannot range from {} to {} seconds", min, max)));
+    }
+    self.particles.borrow_mut().lifetime = (min, max);
+    Ok(())
+  }
+
+  // Mean initial velocity, each component normally distributed around it
+  // with standard deviation `spread`.
+  pub fn set_velocity(&self, x: f32, y: f32, spread: f32) {
+    let mut particles = self.particles.borrow_mut();
+    particles.velocity = (x, y);
+    particles.velocity_spread = spread.max(0.0);
+  }
+
+  // Constant acceleration in pixels per second squared; positive y pulls
+  // downwards.
+  pub fn set_gravity(&self, x: f32, y: f32) {
+    self.particles.borrow_mut().gravity = (x, y);
+  }
+
+  // A uniform velocity field carrying every particle along, without
+  // changing the particles' own velocity.
+  pub fn set_wind(&self, x: f32, y: f32) {
+    self.particles.borrow_mut().wind = (x, y);
+  }
+
+  // A rotating velocity field around `x`, `y`, turning `speed` radians per
+  // second at any distance; negative turns anticlockwise.
+  pub fn set_vortex(&self, x: f32, y: f32, speed: f32) {
+    self.particles.borrow_mut().vortex = Some((x, y, speed));
+  }
+
+  pub fn clear_vortex(&self) {
+    self.particles.borrow_mut().vortex = None;
+  }
+
+  // Random velocity changes making the particles wander, e.g. snowflakes.
+  pub fn set_turbulence(&self, strength: f32) {
+    self.particles.borrow_mut().turbulence = strength.max(0.0);
+  }
+
+  // Fraction of their velocity the particles lose per second, 0 to 1.
+  pub fn set_drag(&self, drag: f32) {
+    self.particles.borrow_mut().drag = drag.clamp(0.0, 1.0);
+  }
+
+  // Diameters in pixels at spawn and at death.
+  pub fn set_size_over_life(&self, start: f32, end: f32) {
+    self.particles.borrow_mut().size = (start.max(0.0), end.max(0.0));
+  }
+
+  // Colours at spawn and at death, e.g. fading out to transparent.
+  pub fn set_color_over_life(&self, start: &Color, end: &Color) {
+    let mut particles = self.particles.borrow_mut();
+    particles.color_start = start.to_array();
+    particles.color_end = end.to_array();
+  }
+
+  // `Alpha` to begin with; `Additive` makes overlapping particles glow.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.particles.borrow_mut().blend_mode = mode;
+  }
+
+  // Beyond this many living particles no more are spawned. 10000 by
+  // default.
+  pub fn set_max_particles(&self, count: usize) {
+    let mut particles = self.particles.borrow_mut();
+    particles.max_particles = count;
+    particles.particles.truncate(count);
+  }
+
+  // Spawns `count` particles at once, within the limit, with the next
+  // frame drawn unpaused.
+  pub fn burst(&self, count: usize) {
+    self.particles.borrow_mut().pending += count as f32;
+  }
+
+  // Freezes the particles where they are, still drawing them.
+  pub fn set_paused(&self, paused: bool) {
+    let mut particles = self.particles.borrow_mut();
+    particles.paused = paused;
+    particles.last_time = None;
+  }
+
+  // Removes every living particle.
+  pub fn clear(&self) {
+    let mut particles = self.particles.borrow_mut();
+    particles.particles.clear();
+    particles.pending = 0.0;
+  }
+
+  pub fn particle_count(&self) -> usize {
+    self.particles.borrow().particles.len()
+  }
+}
+
+impl Particles {
+  fn spawn(&mut self, width: u32, height: u32) {
+    let [x, y, w, h] = self.emitter.unwrap_or([0.0, 0.0, width as f32, height as f32]);
+    let (min_life, max_life) = self.lifetime;
+    let spread = self.velocity_spread;
+    let rng = &mut self.rng;
+    let particle = Particle {
+      x: rng.range(x, x + w),
+      y: rng.range(y, y + h),
+      vx: self.velocity.0 + spread * rng.normal() as f32,
+      vy: self.velocity.1 + spread * rng.normal() as f32,
+      age: 0.0,
+      lifetime: rng.range(min_life, max_life).max(f32::EPSILON),
+    };
+    self.particles.push(particle);
+  }
+
+  // Advances the simulation by `dt` seconds.
+  fn step(&mut self, dt: f32, width: u32, height: u32) {
+    let decay = (1.0 - self.drag).powf(dt);
+    let kick = self.turbulence * dt.sqrt();
+    let (gx, gy) = self.gravity;
+    let (wx, wy) = self.wind;
+    let vortex = self.vortex;
+    let rng = &mut self.rng;
+    self.particles.retain_mut(|particle| {
+      particle.age += dt;
+      if particle.age >= particle.lifetime {
+        return false;
+      }
+      particle.vx = (particle.vx + gx * dt) * decay;
+      particle.vy = (particle.vy + gy * dt) * decay;
+      if kick > 0.0 {
+        particle.vx += kick * rng.normal() as f32;
+        particle.vy += kick * rng.normal() as f32;
+      }
+      let (mut fx, mut fy) = (wx, wy);
+      if let Some((cx, cy, speed)) = vortex {
+        // Perpendicular to the radius, y being down.
+        fx -= (particle.y - cy) * speed;
+        fy += (particle.x - cx) * speed;
+      }
+      particle.x += (particle.vx + fx) * dt;
+      particle.y += (particle.vy + fy) * dt;
+      true
+    });
+
+    self.pending += self.spawn_rate * dt;
+    let room = self.max_particles.saturating_sub(self.particles.len());
+    let count = (self.pending.floor() as usize).min(room);
+    for _ in 0..count {
+      self.spawn(width, height);
+    }
+    // Particles without room are not saved up for later.
+    self.pending = if count < room { self.pending.fract() } else { 0.0 };
+  }
+}
+
+impl Overlay for Particles {
+  fn draw(&mut self, width: u32, height: u32, time: f32) {
+    if !self.paused {
+      let dt = match self.last_time {
+        Some(last) => (time - last).clamp(0.0, MAX_STEP),
+        None => 0.0,
+      };
+      self.last_time = Some(time);
+      self.step(dt, width, height);
+    }
+    if self.particles.is_empty() {
+      return;
+    }
+
+    self.batch.clear();
+    for particle in &self.particles {
+      self.batch.push(&[particle.x, particle.y, particle.age / particle.lifetime]);
+    }
+    let (size_start, size_end) = self.size;
+    let [r0, g0, b0, a0] = self.color_start;
+    let [r1, g1, b1, a1] = self.color_end;
+    self.program.set_vec2("u_resolution", width as f32, height as f32);
+    self.program.set_vec2("u_size", size_start, size_end);
+    self.program.set_vec4("u_color_start", r0, g0, b0, a0);
+    self.program.set_vec4("u_color_end", r1, g1, b1, a1);
+    gl_state::set_blend_mode(&self.context, Some(self.blend_mode));
+    self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
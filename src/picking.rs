@@ -0,0 +1,153 @@
+use web_sys::{WebGl2RenderingContext, WebGlTexture};
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::error::Result;
+use crate::gl_state;
+use crate::math::Vec2;
+use crate::render_target::{unbind_render_target, RenderTarget};
+use crate::shader::ShaderProgram;
+
+// Values of the `kind` attribute.
+pub(crate) const SOLID: f32 = 0.0;
+// Covers where the texture is at least half opaque.
+pub(crate) const TEXTURED: f32 = 1.0;
+// Covers the unit circle in uv.
+pub(crate) const DISC: f32 = 2.0;
+
+// The one pixel being picked fills the whole 1 x 1 target.
+const PICK_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 position;
+in vec2 uv;
+in float kind;
+in vec4 id;
+
+uniform vec2 u_offset;
+
+out vec2 v_uv;
+flat out int v_kind;
+flat out vec4 v_id;
+
+void main()
+{
+  vec2 clip = (position - u_offset) * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+  v_uv = uv;
+  v_kind = int(kind + 0.5);
+  v_id = id;
+}
+"##;
+
+const PICK_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_texture;
+
+in vec2 v_uv;
+flat in int v_kind;
+flat in vec4 v_id;
+
+out vec4 outColor;
+
+void main()
+{
+  if (v_kind == 1 && texture(u_texture, v_uv).a < 0.5) {
+    discard;
+  }
+  if (v_kind == 2 && dot(v_uv, v_uv) > 1.0) {
+    discard;
+  }
+  outColor = v_id;
+}
+"##;
+
+// An ID buffer for one pixel: everything pushed is drawn with its id
+// encoded in its colour, and whatever ends up on top under the pixel is
+// read back. Ids are stored plus one in 24 bits, 0 meaning nothing.
+pub(crate) struct PickPass {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  target: Option<RenderTarget>,
+}
+
+impl PickPass {
+  pub(crate) fn new(context: &WebGl2RenderingContext) -> Result<PickPass> {
+    let mut program = ShaderProgram::new(context, PICK_VERT_SHADER, PICK_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("position", 2), ("uv", 2), ("kind", 1), ("id", 4)])?;
+    program.set_i32("u_texture", 0);
+    Ok(PickPass {
+      context: context.clone(),
+      batch: VertexBatch::new(context, &layout)?,
+      program,
+      target: None,
+    })
+  }
+
+  // Starts picking the drawing-buffer pixel at `x`, `y`, from the top left.
+  pub(crate) fn begin(&mut self, x: f32, y: f32) -> Result<()> {
+    if self.target.is_none() {
+      self.target = Some(RenderTarget::new(&self.context, 1, 1)?);
+    }
+    if let Some(target) = &self.target {
+      target.bind();
+    }
+    let context = &self.context;
+    gl_state::set_blend_mode(context, None);
+    gl_state::set_depth_test(context, false);
+    context.clear_color(0.0, 0.0, 0.0, 0.0);
+    context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+    self.program.set_vec2("u_offset", x.floor(), y.floor());
+    self.batch.clear();
+    Ok(())
+  }
+
+  // Adds one vertex of a triangle belonging to `id`.
+  pub(crate) fn push(&mut self, position: Vec2, uv: Vec2, kind: f32, id: usize) {
+    let code = id as u32 + 1;
+    let channel = |shift: u32| ((code >> shift) & 0xff) as f32 / 255.0;
+    self.batch.push(&[position.x, position.y, uv.x, uv.y, kind, channel(0), channel(8), channel(16), 1.0]);
+  }
+
+  // Draws what was pushed since the last flush, sampling `texture` for
+  // `TEXTURED` vertices.
+  pub(crate) fn flush(&mut self, texture: Option<&WebGlTexture>) {
+    if self.batch.is_empty() {
+      return;
+    }
+    self.program.use_program();
+    if let Some(texture) = texture {
+      gl_state::bind_texture(&self.context, 0, texture);
+    }
+    self.batch.draw(WebGl2RenderingContext::TRIANGLES);
+    self.batch.clear();
+  }
+
+  // Reads the id on top and goes back to drawing into the canvas.
+  pub(crate) fn finish(&mut self, texture: Option<&WebGlTexture>) -> Result<Option<usize>> {
+    self.flush(texture);
+    let mut pixel = [0; 4];
+    let read = self.context.read_pixels_with_opt_u8_array(
+      0,
+      0,
+      1,
+      1,
+      WebGl2RenderingContext::RGBA,
+      WebGl2RenderingContext::UNSIGNED_BYTE,
+      Some(&mut pixel),
+    );
+    unbind_render_target(&self.context);
+    read?;
+
+    let code = pixel[0] as u32 | (pixel[1] as u32) << 8 | (pixel[2] as u32) << 16;
+    Ok(code.checked_sub(1).map(|id| id as usize))
+  }
+
+  // The target is made again on the next pick.
+  pub(crate) fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.target = None;
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
@@ -0,0 +1,246 @@
+use web_sys::{WebGl2RenderingContext, WebGlFramebuffer};
+
+use crate::color_vision::{ColorVisionDeficiency, ColorVisionSimulation};
+use crate::dither::{DitherMode, Dithering};
+use crate::error::{GestaltError, Result};
+use crate::gamma::GammaCorrection;
+use crate::geometry::Geometry;
+use crate::gl_state;
+use crate::render_target::{supported_precision, unbind_render_target, RenderTarget, TargetPrecision};
+use crate::shader::{ShaderProgram, FULLSCREEN_VERT_SHADER};
+
+// A list of fullscreen fragment shader passes applied to the rendered scene.
+// The scene is drawn into an offscreen target, then each pass reads the
+// previous result and writes the next, ping-ponging between two targets; the
+// last pass draws to the canvas, or to the output target of an offscreen
+// render.
+//
+// Pass shaders receive:
+//   in vec2 v_uv;                 texture coordinate, (0, 0) bottom left
+//   uniform sampler2D u_input;    output of the scene or previous pass
+//   uniform vec2 u_resolution;    size of the drawing buffer in pixels
+//   uniform float u_time;         seconds, as passed to `render`
+//
+// A colour vision simulation, when set, runs after the user's passes, then
+// display correction and dithering. While dithering the targets are at
+// least half floats, so the scene and the passes keep finer steps than the
+// output has.
+pub(crate) struct PostProcessChain {
+  context: WebGl2RenderingContext,
+  quad: Geometry,
+  passes: Vec<ShaderProgram>,
+  simulation: Option<ColorVisionSimulation>,
+  correction: Option<GammaCorrection>,
+  dithering: Option<Dithering>,
+  // Of the targets, as far as the context supports it.
+  precision: TargetPrecision,
+  targets: Vec<RenderTarget>,
+  active: bool,
+}
+
+impl PostProcessChain {
+  pub(crate) fn new(context: &WebGl2RenderingContext) -> Result<PostProcessChain> {
+    Ok(PostProcessChain {
+      context: context.clone(),
+      quad: Geometry::fullscreen_quad(context)?,
+      passes: Vec::new(),
+      simulation: None,
+      correction: None,
+      dithering: None,
+      precision: TargetPrecision::Byte,
+      targets: Vec::new(),
+      active: false,
+    })
+  }
+
+  // Appends a pass and returns its index.
+  pub(crate) fn add_pass(&mut self, frag_src: &str) -> Result<usize> {
+    self.passes.push(ShaderProgram::new(&self.context, FULLSCREEN_VERT_SHADER, frag_src)?);
+    Ok(self.passes.len() - 1)
+  }
+
+  pub(crate) fn remove_pass(&mut self, index: usize) -> Result<()> {
+    self.check_index(index)?;
+    self.passes.remove(index);
+    Ok(())
+  }
+
+  pub(crate) fn clear(&mut self) {
+    self.passes.clear();
+    self.targets.clear();
+  }
+
+  // Deletes all GL objects, leaving a chain that cannot be used again.
+  pub(crate) fn delete(&mut self) {
+    self.clear();
+    self.simulation = None;
+    self.correction = None;
+    self.dithering = None;
+    self.quad.delete();
+  }
+
+  // The display correction pass, created on first use.
+  pub(crate) fn correction_mut(&mut self) -> Result<&mut GammaCorrection> {
+    if self.correction.is_none() {
+      self.correction = Some(GammaCorrection::new(&self.context)?);
+    }
+    Ok(self.correction.as_mut().unwrap())
+  }
+
+  pub(crate) fn clear_correction(&mut self) {
+    self.correction = None;
+  }
+
+  pub(crate) fn set_simulation(&mut self, deficiency: ColorVisionDeficiency, severity: f32) -> Result<()> {
+    self.simulation = Some(ColorVisionSimulation::new(&self.context, deficiency, severity)?);
+    Ok(())
+  }
+
+  pub(crate) fn clear_simulation(&mut self) {
+    self.simulation = None;
+  }
+
+  // Returns the precision granted, which falls back towards `Byte` where
+  // the context cannot render to `precision`.
+  pub(crate) fn set_precision(&mut self, precision: TargetPrecision) -> Result<TargetPrecision> {
+    self.precision = supported_precision(&self.context, precision)?;
+    self.targets.clear();
+    Ok(self.precision)
+  }
+
+  // The targets are made again at the precision dithering needs.
+  pub(crate) fn set_dithering(&mut self, mode: DitherMode, bits: u32) -> Result<()> {
+    self.dithering = Some(Dithering::new(&self.context, mode, bits)?);
+    self.targets.clear();
+    Ok(())
+  }
+
+  pub(crate) fn clear_dithering(&mut self) {
+    self.dithering = None;
+    self.targets.clear();
+  }
+
+  pub(crate) fn pass_mut(&mut self, index: usize) -> Result<&mut ShaderProgram> {
+    self.check_index(index)?;
+    Ok(&mut self.passes[index])
+  }
+
+  // Redirects drawing into the first offscreen target if there are any
+  // passes. Must be followed by `finish` once the scene is drawn.
+  pub(crate) fn begin(&mut self, width: u32, height: u32) -> Result<()> {
+    self.active = false;
+    if self.pass_count() == 0 {
+      return Ok(());
+    }
+
+    // A single pass reads the scene and writes the canvas, so one target
+    // suffices; longer chains alternate between two.
+    let needed = self.pass_count().min(2);
+    self.targets.truncate(needed);
+    for target in &mut self.targets {
+      target.resize(width, height)?;
+    }
+    while self.targets.len() < needed {
+      self.targets.push(RenderTarget::with_precision(&self.context, width, height, self.target_precision())?);
+    }
+
+    self.targets[0].bind();
+    self.active = true;
+    Ok(())
+  }
+
+  // Precision of what the scene is being drawn to between `begin` and
+  // `finish`; `None` is the canvas.
+  pub(crate) fn scene_precision(&self) -> Option<TargetPrecision> {
+    self.active.then(|| self.target_precision())
+  }
+
+  // Where the scene is being drawn between `begin` and `finish`; `None` is
+  // the canvas.
+  pub(crate) fn scene_framebuffer(&self) -> Option<&WebGlFramebuffer> {
+    if self.active {
+      Some(self.targets[0].framebuffer())
+    } else {
+      None
+    }
+  }
+
+  // Runs the passes, the last one drawing into `output`, the canvas for
+  // `None`.
+  pub(crate) fn finish(&mut self, time: f32, output: Option<&RenderTarget>) {
+    if !self.active {
+      return;
+    }
+    self.active = false;
+
+    gl_state::set_blend_mode(&self.context, None);
+    let last = self.pass_count() - 1;
+    let linear_input = self.correction.is_some();
+    let simulation = self.simulation.as_mut().map(|simulation| simulation.prepare(linear_input));
+    let correction = self.correction.as_mut().map(GammaCorrection::prepare);
+    let dithering = self.dithering.as_mut().map(Dithering::prepare);
+    for (index, pass) in self.passes.iter_mut().chain(simulation).chain(correction).chain(dithering).enumerate() {
+      let input = &self.targets[index % 2];
+      if index == last {
+        match output {
+          Some(output) => output.bind(),
+          None => unbind_render_target(&self.context),
+        }
+      } else {
+        self.targets[(index + 1) % 2].bind();
+      }
+
+      gl_state::bind_texture(&self.context, 0, input.texture().raw());
+      pass.set_i32("u_input", 0);
+      pass.set_vec2("u_resolution", input.width() as f32, input.height() as f32);
+      pass.set_f32("u_time", time / 1000.0);
+      self.quad.draw(WebGl2RenderingContext::TRIANGLES);
+    }
+  }
+
+  pub(crate) fn restore(&mut self) -> Result<()> {
+    self.quad.restore(&self.context)?;
+    self.precision = supported_precision(&self.context, self.precision)?;
+    for pass in &mut self.passes {
+      pass.restore()?;
+    }
+    if let Some(simulation) = &mut self.simulation {
+      simulation.restore()?;
+    }
+    if let Some(correction) = &mut self.correction {
+      correction.restore()?;
+    }
+    if let Some(dithering) = &mut self.dithering {
+      dithering.restore()?;
+    }
+    // Recreated on the next `begin`.
+    self.targets.clear();
+    Ok(())
+  }
+
+  fn target_precision(&self) -> TargetPrecision {
+    if self.dithering.is_some() && !self.precision.is_float() {
+      TargetPrecision::HalfFloat
+    } else {
+      self.precision
+    }
+  }
+
+  fn pass_count(&self) -> usize {
+    self.passes.len()
+      + self.simulation.is_some() as usize
+      + self.correction.is_some() as usize
+      + self.dithering.is_some() as usize
+  }
+
+  fn check_index(&self, index: usize) -> Result<()> {
+    if index >= self.passes.len() {
+      return Err(GestaltError::InvalidArgument(format!(
+        "post-process pass {} does not exist, there are {}",
+        index,
+        self.passes.len()
+      )));
+    }
+    Ok(())
+  }
+}
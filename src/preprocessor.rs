@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+
+// Expands `#include "name"` lines from a library of registered snippets and
+// injects `#define`s, so shaders can share helper code. Run sources through
+// `process` before handing them to `WebGlCanvas`.
+//
+// Each snippet is included at most once per shader. `#line` directives are
+// inserted around included code, using the snippet's inclusion order (from
+// 1) as the source string number, so compile errors still point at the
+// right line; the main source is string 0.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Preprocessor {
+  snippets: HashMap<String, String>,
+  defines: Vec<(String, String)>,
+}
+
+#[wasm_bindgen]
+impl Preprocessor {
+
+  pub fn new() -> Preprocessor {
+    Preprocessor::default()
+  }
+
+  // Registers `src` under `name`, replacing any snippet of that name.
+  pub fn add_snippet(&mut self, name: &str, src: &str) {
+    self.snippets.insert(name.to_string(), src.to_string());
+  }
+
+  pub fn remove_snippet(&mut self, name: &str) {
+    self.snippets.remove(name);
+  }
+
+  // Adds `#define name value` to every processed shader. An empty `value`
+  // just defines the name, for `#ifdef`.
+  pub fn define(&mut self, name: &str, value: &str) {
+    match self.defines.iter_mut().find(|(known, _)| known == name) {
+      Some((_, known_value)) => *known_value = value.to_string(),
+      None => self.defines.push((name.to_string(), value.to_string())),
+    }
+  }
+
+  pub fn undefine(&mut self, name: &str) {
+    self.defines.retain(|(known, _)| known != name);
+  }
+
+  // Returns `src` with includes expanded and the defines inserted right
+  // after the `#version` line, if there is one. Fails on unknown snippets.
+  pub fn process(&self, src: &str) -> Result<String> {
+    let mut out = String::with_capacity(src.len());
+    let mut body = src;
+    let mut first_line = 1;
+    let mut skipped_lines = 0;
+
+    // `#version` has to come first, so blank lines before it are dropped
+    // and made up for with `#line`.
+    let trimmed = src.trim_start();
+    if trimmed.starts_with("#version") {
+      skipped_lines = src[..src.len() - trimmed.len()].matches('\n').count();
+      let (version, rest) = trimmed.split_once('\n').unwrap_or((trimmed, ""));
+      out.push_str(version);
+      out.push('\n');
+      body = rest;
+      first_line = skipped_lines + 2;
+    }
+
+    for (name, value) in &self.defines {
+      out.push_str(&format!("#define {} {}\n", name, value));
+    }
+    if !self.defines.is_empty() || skipped_lines > 0 {
+      out.push_str(&format!("#line {} 0\n", first_line));
+    }
+
+    let mut included = Vec::new();
+    self.expand(body, 0, first_line, &mut included, &mut out)?;
+    Ok(out)
+  }
+}
+
+impl Preprocessor {
+  fn expand(&self, src: &str, source: usize, first_line: usize, included: &mut Vec<String>, out: &mut String) -> Result<()> {
+    for (index, line) in src.lines().enumerate() {
+      let name = match include_name(line) {
+        Some(name) => name,
+        None => {
+          out.push_str(line);
+          out.push('\n');
+          continue;
+        }
+      };
+
+      if !included.iter().any(|known| known == name) {
+        let snippet = self
+          .snippets
+          .get(name)
+          .ok_or_else(|| GestaltError::InvalidArgument(format!("no shader snippet named \"{}\"", name)))?;
+        // Registered before expanding, which also stops include cycles.
+        included.push(name.to_string());
+        let number = included.len();
+        out.push_str(&format!("#line 1 {}\n", number));
+        self.expand(snippet, number, 1, included, out)?;
+      }
+      out.push_str(&format!("#line {} {}\n", first_line + index + 1, source));
+    }
+    Ok(())
+  }
+}
+
+// The quoted name of an `#include "name"` line.
+fn include_name(line: &str) -> Option<&str> {
+  let rest = line.trim().strip_prefix('#')?.trim_start().strip_prefix("include")?;
+  let rest = rest.trim().strip_prefix('"')?;
+  rest.strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Preprocessor;
+
+  fn preprocessor(snippets: &[(&str, &str)]) -> Preprocessor {
+    let mut preprocessor = Preprocessor::new();
+    for (name, src) in snippets {
+      preprocessor.add_snippet(name, src);
+    }
+    preprocessor
+  }
+
+  #[test]
+  fn sources_without_directives_pass_through() {
+    let src = "#version 300 es\nvoid main() {}\n";
+    assert_eq!(Preprocessor::new().process(src).unwrap(), src);
+  }
+
+  #[test]
+  fn defines_go_after_the_version() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.define("COUNT", "4");
+    preprocessor.define("FLAG", "");
+    assert_eq!(
+      preprocessor.process("#version 300 es\nvoid main() {}\n").unwrap(),
+      "#version 300 es\n#define COUNT 4\n#define FLAG \n#line 2 0\nvoid main() {}\n"
+    );
+    assert_eq!(
+      preprocessor.process("void main() {}").unwrap(),
+      "#define COUNT 4\n#define FLAG \n#line 1 0\nvoid main() {}\n"
+    );
+  }
+
+  #[test]
+  fn blank_lines_before_the_version_are_dropped() {
+    let src = "\n\n  #version 300 es\nvoid main() {}\n";
+    assert_eq!(Preprocessor::new().process(src).unwrap(), "#version 300 es\n#line 4 0\nvoid main() {}\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.define("COUNT", "1");
+    assert_eq!(
+      preprocessor.process(src).unwrap(),
+      "#version 300 es\n#define COUNT 1\n#line 4 0\nvoid main() {}\n"
+    );
+  }
+
+  #[test]
+  fn lines_are_numbered_around_includes() {
+    let preprocessor = preprocessor(&[("common", "float c;\n")]);
+    assert_eq!(
+      preprocessor.process("#version 300 es\nvoid a();\n#include \"common\"\nvoid b();\n").unwrap(),
+      "#version 300 es\nvoid a();\n#line 1 1\nfloat c;\n#line 4 0\nvoid b();\n"
+    );
+  }
+
+  #[test]
+  fn lines_are_numbered_after_defines_and_includes() {
+    let mut preprocessor = preprocessor(&[("common", "float c;")]);
+    preprocessor.define("COUNT", "2");
+    assert_eq!(
+      preprocessor.process("#version 300 es\n#include \"common\"\nvoid main() {}").unwrap(),
+      "#version 300 es\n#define COUNT 2\n#line 2 0\n#line 1 1\nfloat c;\n#line 3 0\nvoid main() {}\n"
+    );
+  }
+
+  #[test]
+  fn nested_includes_number_their_sources_in_order() {
+    let preprocessor = preprocessor(&[("a", "#include \"b\"\nfloat a;"), ("b", "float b;")]);
+    assert_eq!(
+      preprocessor.process("#include \"a\"\nvoid main();").unwrap(),
+      "#line 1 1\n#line 1 2\nfloat b;\n#line 2 1\nfloat a;\n#line 2 0\nvoid main();\n"
+    );
+  }
+
+  #[test]
+  fn snippets_are_included_once() {
+    let preprocessor = preprocessor(&[("common", "float c;")]);
+    let out = preprocessor.process("#include \"common\"\n# include \"common\"\nvoid main();").unwrap();
+    assert_eq!(out.matches("float c;").count(), 1);
+    assert_eq!(out, "#line 1 1\nfloat c;\n#line 2 0\n#line 3 0\nvoid main();\n");
+  }
+
+  #[test]
+  fn include_cycles_stop() {
+    let preprocessor = preprocessor(&[("a", "#include \"b\"\nfloat a;"), ("b", "#include \"a\"\nfloat b;")]);
+    let out = preprocessor.process("#include \"a\"").unwrap();
+    assert_eq!(out.matches("float a;").count(), 1);
+    assert_eq!(out.matches("float b;").count(), 1);
+  }
+
+  #[test]
+  fn unknown_snippets_fail() {
+    let error = Preprocessor::new().process("#include \"missing\"").unwrap_err();
+    assert_eq!(error.to_string(), "no shader snippet named \"missing\"");
+  }
+}
@@ -0,0 +1,156 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+
+// xoshiro256** by Blackman and Vigna: fast, small state and good enough for
+// stimulus generation. Not for anything security related.
+#[derive(Clone, Debug)]
+pub(crate) struct Rng {
+  state: [u64; 4],
+}
+
+impl Rng {
+  // The state is expanded from `seed` with SplitMix64, as the authors
+  // recommend, so similar seeds still give unrelated sequences.
+  pub(crate) fn new(seed: u64) -> Rng {
+    let mut seed = seed;
+    let mut next = || {
+      seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+      let mut z = seed;
+      z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+      z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+      z ^ (z >> 31)
+    };
+    Rng {
+      state: [next(), next(), next(), next()],
+    }
+  }
+
+  // Seeded from `Math.random`, for when reproducibility does not matter.
+  pub(crate) fn from_entropy() -> Rng {
+    Rng::new((entropy_seed() as u64) << 32 | entropy_seed() as u64)
+  }
+
+  pub(crate) fn next_u64(&mut self) -> u64 {
+    let s = &mut self.state;
+    let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+    let t = s[1] << 17;
+    s[2] ^= s[0];
+    s[3] ^= s[1];
+    s[1] ^= s[2];
+    s[0] ^= s[3];
+    s[2] ^= t;
+    s[3] = s[3].rotate_left(45);
+    result
+  }
+
+  // Uniform in [0, 1), with all 53 bits of precision.
+  pub(crate) fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  // Uniform in [0, 1).
+  pub(crate) fn next_f32(&mut self) -> f32 {
+    (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+  }
+
+  // Uniform in [low, high).
+  pub(crate) fn range(&mut self, low: f32, high: f32) -> f32 {
+    low + (high - low) * self.next_f32()
+  }
+
+  // Uniform in 0..n, without modulo bias. `n` must not be 0.
+  pub(crate) fn below(&mut self, n: usize) -> usize {
+    let n = n as u64;
+    let zone = u64::MAX - u64::MAX % n;
+    loop {
+      let value = self.next_u64();
+      if value < zone {
+        return (value % n) as usize;
+      }
+    }
+  }
+  // Standard normal, by the Box-Muller transform.
+  pub(crate) fn normal(&mut self) -> f64 {
+    // 1 - u keeps the logarithm finite.
+    let radius = (-2.0 * (1.0 - self.next_f64()).ln()).sqrt();
+    radius * (std::f64::consts::TAU * self.next_f64()).cos()
+  }
+
+  // Fisher-Yates, from the back.
+  pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+    for index in (1..items.len()).rev() {
+      items.swap(index, self.below(index + 1));
+    }
+  }
+}
+
+// A seed from `Math.random`.
+pub(crate) fn entropy_seed() -> u32 {
+  (js_sys::Math::random() * 4_294_967_296.0) as u32
+}
+
+// A seedable random number generator for JavaScript, the same one the
+// stimuli use, so that trial orders, positions and noise drawn in JS are
+// reproducible from a recorded seed just like those drawn in Rust. The same
+// seed always gives the same sequence, in any browser.
+#[wasm_bindgen]
+pub struct Random {
+  seed: u32,
+  rng: Rng,
+}
+
+#[wasm_bindgen]
+impl Random {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new(seed: u32) -> Random {
+    Random { seed, rng: Rng::new(seed as u64) }
+  }
+
+  // Seeded from `Math.random`; read the seed with `seed` to record it.
+  pub fn from_entropy() -> Random {
+    Random::new(entropy_seed())
+  }
+
+  // The seed the sequence started from.
+  pub fn seed(&self) -> u32 {
+    self.seed
+  }
+
+  // Starts over from `seed`.
+  pub fn reseed(&mut self, seed: u32) {
+    *self = Random::new(seed);
+  }
+
+  // Uniform in [0, 1), like `Math.random`.
+  pub fn next(&mut self) -> f64 {
+    self.rng.next_f64()
+  }
+
+  // Uniform in [low, high).
+  pub fn range(&mut self, low: f64, high: f64) -> f64 {
+    low + (high - low) * self.rng.next_f64()
+  }
+
+  // Uniform in 0..n.
+  pub fn integer(&mut self, n: u32) -> Result<u32> {
+    if n == 0 {
+      return Err(GestaltError::InvalidArgument("cannot draw an integer below 0".into()));
+    }
+    Ok(self.rng.below(n as usize) as u32)
+  }
+
+  pub fn normal(&mut self, mean: f64, sd: f64) -> f64 {
+    mean + sd * self.rng.normal()
+  }
+
+  // Shuffles `array` in place.
+  pub fn shuffle(&mut self, array: &js_sys::Array) {
+    let mut items: Vec<JsValue> = array.iter().collect();
+    self.rng.shuffle(&mut items);
+    for (index, item) in items.iter().enumerate() {
+      array.set(index as u32, item.clone());
+    }
+  }
+}
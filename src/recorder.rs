@@ -0,0 +1,180 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use web_sys::{Blob, BlobEvent, MediaRecorder, MediaRecorderOptions, MediaStream, RecordingState};
+
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+use crate::graphics::WebGlCanvas;
+
+// Tried in order; the first the browser can encode is used.
+const MIME_TYPES: [&str; 3] = ["video/webm;codecs=vp9", "video/webm;codecs=vp8", "video/webm"];
+
+// Milliseconds of video handed over at a time while recording, so long
+// recordings do not sit in the encoder until the end.
+const TIME_SLICE: i32 = 1000;
+
+#[derive(Default)]
+struct RecordingParts {
+  chunks: Vec<Blob>,
+  // `resolve` and `reject` of the promises returned by `stop`.
+  pending: Vec<(js_sys::Function, js_sys::Function)>,
+}
+
+// Records what a canvas shows to a WebM video through `captureStream` and a
+// `MediaRecorder`, e.g. to show animated stimuli in a talk. Frames are
+// taken as they are displayed, so dropped frames are missing from the
+// video too; for an exact movie render the frames offline instead.
+// `Microphone::recorder` makes one recording sound instead.
+#[wasm_bindgen]
+pub struct Recorder {
+  recorder: MediaRecorder,
+  parts: Rc<RefCell<RecordingParts>>,
+  _listeners: [EventListener; 3],
+}
+
+#[wasm_bindgen]
+impl Recorder {
+
+  // `frame_rate` caps the frames taken per second, 0 taking one whenever
+  // the canvas changes. `bits_per_second` sets the video bitrate, 0 leaving
+  // it to the browser.
+  pub fn new(canvas: &WebGlCanvas, frame_rate: f32, bits_per_second: u32) -> Result<Recorder> {
+    if frame_rate.is_nan() || frame_rate < 0.0 {
+      return Err(GestaltError::InvalidArgument(format!("frame rate must not be negative, got {}", frame_rate)));
+    }
+    let element = canvas.element()?;
+    let stream = if frame_rate > 0.0 {
+      element.capture_stream_with_frame_request_rate(frame_rate as f64)?
+    } else {
+      element.capture_stream()?
+    };
+
+    let options = MediaRecorderOptions::new();
+    options.set_mime_type(supported_mime_type(&MIME_TYPES, "WebM video")?);
+    if bits_per_second > 0 {
+      options.set_video_bits_per_second(bits_per_second);
+    }
+    Recorder::from_stream(&stream, &options)
+  }
+
+  // Starts a new recording, dropping what is left of an earlier one.
+  pub fn start(&self) -> Result<()> {
+    if self.recorder.state() != RecordingState::Inactive {
+      return Err(GestaltError::InvalidArgument("already recording".into()));
+    }
+    self.parts.borrow_mut().chunks.clear();
+    Ok(self.recorder.start_with_time_slice(TIME_SLICE)?)
+  }
+
+  pub fn pause(&self) -> Result<()> {
+    Ok(self.recorder.pause()?)
+  }
+
+  pub fn resume(&self) -> Result<()> {
+    Ok(self.recorder.resume()?)
+  }
+
+  // Ends the recording. Resolves to it as a `Blob` of `mime_type` once the
+  // encoder has handed over the rest of it.
+  pub fn stop(&self) -> js_sys::Promise {
+    let recorder = self.recorder.clone();
+    let parts = self.parts.clone();
+    js_sys::Promise::new(&mut move |resolve, reject| {
+      if recorder.state() == RecordingState::Inactive {
+        let _ = reject.call1(&JsValue::NULL, &js_sys::Error::new("not recording"));
+        return;
+      }
+      parts.borrow_mut().pending.push((resolve, reject.clone()));
+      if let Err(error) = recorder.stop() {
+        parts.borrow_mut().pending.clear();
+        let _ = reject.call1(&JsValue::NULL, &error);
+      }
+    })
+  }
+
+  pub fn is_recording(&self) -> bool {
+    self.recorder.state() == RecordingState::Recording
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.recorder.state() == RecordingState::Paused
+  }
+
+  // The container and codecs used, e.g. "video/webm;codecs=vp9".
+  pub fn mime_type(&self) -> String {
+    self.recorder.mime_type()
+  }
+}
+
+impl Recorder {
+  pub(crate) fn from_stream(stream: &MediaStream, options: &MediaRecorderOptions) -> Result<Recorder> {
+    let recorder = MediaRecorder::new_with_media_stream_and_media_recorder_options(stream, options)?;
+    let parts = Rc::new(RefCell::new(RecordingParts::default()));
+
+    let data_parts = parts.clone();
+    let data = EventListener::new(&recorder, "dataavailable", move |event| {
+      if let Some(blob) = event.dyn_ref::<BlobEvent>().and_then(BlobEvent::data) {
+        data_parts.borrow_mut().chunks.push(blob);
+      }
+    })?;
+
+    // `stop` comes after the last `dataavailable`.
+    let stop_parts = parts.clone();
+    let stop_recorder = recorder.clone();
+    let stop = EventListener::new(&recorder, "stop", move |_| {
+      let (chunks, pending) = {
+        let mut parts = stop_parts.borrow_mut();
+        (std::mem::take(&mut parts.chunks), std::mem::take(&mut parts.pending))
+      };
+      let video = join_chunks(&chunks, &stop_recorder.mime_type());
+      for (resolve, reject) in pending {
+        let _ = match &video {
+          Ok(video) => resolve.call1(&JsValue::NULL, video),
+          Err(error) => reject.call1(&JsValue::NULL, error),
+        };
+      }
+    })?;
+
+    let error_parts = parts.clone();
+    let error = EventListener::new(&recorder, "error", move |event| {
+      web_sys::console::error_2(&"Recording failed:".into(), &event);
+      for (_, reject) in std::mem::take(&mut error_parts.borrow_mut().pending) {
+        let _ = reject.call1(&JsValue::NULL, &js_sys::Error::new("recording failed"));
+      }
+    })?;
+
+    Ok(Recorder {
+      recorder,
+      parts,
+      _listeners: [data, stop, error],
+    })
+  }
+}
+
+impl Drop for Recorder {
+  fn drop(&mut self) {
+    if self.recorder.state() != RecordingState::Inactive {
+      let _ = self.recorder.stop();
+    }
+  }
+}
+
+// The first of `mime_types` the browser can encode.
+pub(crate) fn supported_mime_type<'a>(mime_types: &[&'a str], what: &str) -> Result<&'a str> {
+  mime_types
+    .iter()
+    .copied()
+    .find(|mime_type| MediaRecorder::is_type_supported(mime_type))
+    .ok_or_else(|| GestaltError::InvalidArgument(format!("this browser cannot record {}", what)))
+}
+
+fn join_chunks(chunks: &[Blob], mime_type: &str) -> std::result::Result<JsValue, JsValue> {
+  let parts: js_sys::Array = chunks.iter().collect();
+  let options = web_sys::BlobPropertyBag::new();
+  options.set_type(mime_type);
+  Ok(Blob::new_with_blob_sequence_and_options(&parts, &options)?.into())
+}
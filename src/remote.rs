@@ -0,0 +1,233 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use web_sys::{MessageEvent, WebSocket};
+
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+use crate::experiment::{Observer, Runner, RunnerEvent, TrialRecord, TrialRunner};
+use crate::graphics::{CanvasState, WebGlCanvas};
+use crate::session::JsJson;
+
+// A message from the experimenter, `{ "command": ..., ... }`.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+enum Command {
+  LoadShader { source: String },
+  // A number sets a `float`, 2 to 4 numbers a vector and 16 a `mat4`.
+  SetUniform { name: String, value: UniformValue },
+  SetUniformInt { name: String, value: i32 },
+  Start,
+  Abort,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UniformValue {
+  Scalar(f32),
+  Vector(Vec<f32>),
+}
+
+// A message to the experimenter, `{ "event": ..., ... }`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+enum Event<'a> {
+  Phase { phase: &'static str, trial: usize },
+  Trial { record: &'a TrialRecord },
+  Error { command: Option<String>, message: String },
+}
+
+// An event from `RemoteControl::send_event`.
+#[derive(Serialize)]
+struct CustomEvent<'a> {
+  event: &'a str,
+  data: JsJson<'a>,
+}
+
+struct RemoteState {
+  socket: WebSocket,
+  canvas: Rc<RefCell<CanvasState>>,
+  runner: Option<Rc<RefCell<Runner>>>,
+  // Gets commands not handled here, parsed.
+  callback: Option<js_sys::Function>,
+}
+
+impl RemoteState {
+  // Sends `message` if the socket is open, dropping it otherwise.
+  fn send<T: Serialize>(&self, message: &T) {
+    if self.socket.ready_state() != WebSocket::OPEN {
+      return;
+    }
+    let sent = serde_json::to_string(message)
+      .map_err(|error| GestaltError::InvalidArgument(format!("remote event has no JSON form: {}", error)))
+      .and_then(|json| Ok(self.socket.send_with_str(&json)?));
+    if let Err(error) = sent {
+      web_sys::console::error_2(&"Failed to send remote event:".into(), &JsValue::from(error));
+    }
+  }
+
+  fn execute(&self, command: Command) -> Result<()> {
+    match command {
+      Command::LoadShader { source } => self.canvas.borrow_mut().replace_fragment_shader(&source)?,
+      Command::SetUniform { name, value } => {
+        let mut canvas = self.canvas.borrow_mut();
+        let program = canvas.program_mut();
+        match value {
+          UniformValue::Scalar(value) => program.set_f32(&name, value),
+          UniformValue::Vector(values) => match values[..] {
+            [x, y] => program.set_vec2(&name, x, y),
+            [x, y, z] => program.set_vec3(&name, x, y, z),
+            [x, y, z, w] => program.set_vec4(&name, x, y, z, w),
+            _ => program.set_mat4(&name, &values)?,
+          },
+        }
+      }
+      Command::SetUniformInt { name, value } => self.canvas.borrow_mut().program_mut().set_i32(&name, value),
+      Command::Start => self.runner()?.borrow_mut().start()?,
+      Command::Abort => self.runner()?.borrow_mut().stop(),
+    }
+    Ok(())
+  }
+
+  fn runner(&self) -> Result<&Rc<RefCell<Runner>>> {
+    self
+      .runner
+      .as_ref()
+      .ok_or_else(|| GestaltError::InvalidArgument("no trial runner is attached".into()))
+  }
+}
+
+// Runs `text` as a command, or hands it to the command callback if it is
+// not one of ours. Failures are sent back as error events.
+fn receive(state: &Rc<RefCell<RemoteState>>, text: &str) {
+  let name = serde_json::from_str::<serde_json::Value>(text)
+    .ok()
+    .and_then(|message| message.get("command")?.as_str().map(String::from));
+  let result = match name.as_deref() {
+    Some("loadShader" | "setUniform" | "setUniformInt" | "start" | "abort") => serde_json::from_str::<Command>(text)
+      .map_err(|error| GestaltError::InvalidArgument(format!("malformed command: {}", error)))
+      .and_then(|command| state.borrow().execute(command)),
+    _ => {
+      let callback = state.borrow().callback.clone();
+      match callback {
+        Some(callback) => js_sys::JSON::parse(text)
+          .and_then(|message| callback.call1(&JsValue::NULL, &message))
+          .map(|_| ())
+          .map_err(GestaltError::from),
+        None => Err(GestaltError::InvalidArgument(format!("unknown command in '{}'", text))),
+      }
+    }
+  };
+  if let Err(error) = result {
+    state.borrow().send(&Event::Error {
+      command: name,
+      message: error.to_string(),
+    });
+  }
+}
+
+// Lets an experimenter's machine drive the participant's display over a
+// WebSocket. Text messages are JSON commands: `{ command: "loadShader",
+// source }` swaps the canvas' fragment shader, `{ command: "setUniform",
+// name, value }` and `{ command: "setUniformInt", name, value }` set
+// uniforms, and `{ command: "start" }` and `{ command: "abort" }` start and
+// stop the attached `TrialRunner`. Other commands go to the command
+// callback. Back come `{ event: "phase", phase, trial }` and `{ event:
+// "trial", record }` from the runner, `{ event: "error", command, message }`
+// for failed commands, and whatever is passed to `send_event`.
+#[wasm_bindgen]
+pub struct RemoteControl {
+  state: Rc<RefCell<RemoteState>>,
+  _listeners: [EventListener; 2],
+}
+
+#[wasm_bindgen]
+impl RemoteControl {
+
+  // Opens a connection to the server at `url`, e.g. "ws://host:8080".
+  pub fn connect(url: &str, canvas: &WebGlCanvas) -> Result<RemoteControl> {
+    let socket = WebSocket::new(url)?;
+    let state = Rc::new(RefCell::new(RemoteState {
+      socket: socket.clone(),
+      canvas: canvas.state(),
+      runner: None,
+      callback: None,
+    }));
+
+    let message_state = Rc::downgrade(&state);
+    let message_listener = EventListener::new(&socket, "message", move |event| {
+      let Some(state) = message_state.upgrade() else {
+        return;
+      };
+      match event.unchecked_into::<MessageEvent>().data().as_string() {
+        Some(text) => receive(&state, &text),
+        None => web_sys::console::warn_1(&"Ignoring binary remote message".into()),
+      }
+    })?;
+
+    let error_listener = EventListener::new(&socket, "error", |_| {
+      web_sys::console::error_1(&"Remote control connection failed".into());
+    })?;
+
+    Ok(RemoteControl {
+      state,
+      _listeners: [message_listener, error_listener],
+    })
+  }
+
+  // True once connected and until closed.
+  pub fn is_open(&self) -> bool {
+    self.state.borrow().socket.ready_state() == WebSocket::OPEN
+  }
+
+  // Takes commands for `runner` and streams its phase changes and trial
+  // records back. Replaces an observer set on the runner before.
+  pub fn attach_trial_runner(&self, runner: &TrialRunner) {
+    self.detach_trial_runner();
+    let state = Rc::downgrade(&self.state);
+    let observer: Observer = Rc::new(move |event| {
+      if let Some(state) = state.upgrade() {
+        match event {
+          RunnerEvent::Phase(phase, trial) => state.borrow().send(&Event::Phase { phase, trial }),
+          RunnerEvent::Record(record) => state.borrow().send(&Event::Trial { record }),
+        }
+      }
+    });
+    let runner = runner.shared();
+    runner.borrow_mut().set_observer(Some(observer));
+    self.state.borrow_mut().runner = Some(runner);
+  }
+
+  pub fn detach_trial_runner(&self) {
+    if let Some(runner) = self.state.borrow_mut().runner.take() {
+      runner.borrow_mut().set_observer(None);
+    }
+  }
+
+  // Called with each message that is not a built-in command, parsed;
+  // `undefined` removes it.
+  pub fn set_command_callback(&self, callback: Option<js_sys::Function>) {
+    self.state.borrow_mut().callback = callback;
+  }
+
+  // Sends `{ event, data }`, `data` being anything `JSON.stringify` takes.
+  // Dropped while the connection is not open.
+  pub fn send_event(&self, event: &str, data: JsValue) {
+    self.state.borrow().send(&CustomEvent { event, data: JsJson(&data) });
+  }
+
+  pub fn close(&self) -> Result<()> {
+    Ok(self.state.borrow().socket.close()?)
+  }
+}
+
+impl Drop for RemoteControl {
+  fn drop(&mut self) {
+    self.detach_trial_runner();
+    let _ = self.state.borrow().socket.close();
+  }
+}
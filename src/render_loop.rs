@@ -0,0 +1,170 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::animation::{self, Timeline, TimelineState, TweenState, Tweens};
+use crate::error::Result;
+use crate::experiment::{self, Runner, TrialRunner};
+use crate::graphics::{CanvasState, WebGlCanvas};
+use crate::input::{GamepadState, Gamepads};
+use crate::worker;
+
+type FrameCallback = Closure<dyn FnMut(f64)>;
+
+// Drives `WebGlCanvas::render` from `requestAnimationFrame`, passing on the
+// frame timestamp.
+#[wasm_bindgen]
+pub struct RenderLoop {
+  canvas: Rc<RefCell<CanvasState>>,
+  running: Rc<Cell<bool>>,
+  request_id: Rc<Cell<Option<i32>>>,
+  // The callback re-requests itself, so it has to be reachable from inside.
+  frame: Rc<RefCell<Option<FrameCallback>>>,
+  gamepads: Rc<RefCell<Option<Rc<RefCell<GamepadState>>>>>,
+  runner: Rc<RefCell<Option<Rc<RefCell<Runner>>>>>,
+  timeline: Rc<RefCell<Option<Rc<RefCell<TimelineState>>>>>,
+  tweens: Rc<RefCell<Option<Rc<RefCell<TweenState>>>>>,
+}
+
+#[wasm_bindgen]
+impl RenderLoop {
+
+  pub fn new(canvas: &WebGlCanvas) -> RenderLoop {
+    RenderLoop {
+      canvas: canvas.state(),
+      running: Rc::new(Cell::new(false)),
+      request_id: Rc::new(Cell::new(None)),
+      frame: Rc::new(RefCell::new(None)),
+      gamepads: Rc::new(RefCell::new(None)),
+      runner: Rc::new(RefCell::new(None)),
+      timeline: Rc::new(RefCell::new(None)),
+      tweens: Rc::new(RefCell::new(None)),
+    }
+  }
+
+  pub fn start(&self) -> Result<()> {
+    if self.running.get() {
+      return Ok(());
+    }
+
+    if self.frame.borrow().is_none() {
+      let canvas = self.canvas.clone();
+      let running = self.running.clone();
+      let request_id = self.request_id.clone();
+      let frame = self.frame.clone();
+      let gamepads = self.gamepads.clone();
+      let runner = self.runner.clone();
+      let timeline = self.timeline.clone();
+      let tweens = self.tweens.clone();
+
+      *self.frame.borrow_mut() = Some(Closure::wrap(Box::new(move |time: f64| {
+        request_id.set(None);
+        if !running.get() {
+          return;
+        }
+
+        // First, so trial callbacks see this frame's buttons.
+        if let Some(pads) = gamepads.borrow().as_ref() {
+          pads.borrow_mut().poll();
+        }
+        // Before rendering, and with nothing borrowed, as trial callbacks
+        // usually change the scene.
+        let trials = runner.borrow().clone();
+        if let Some(trials) = trials {
+          experiment::advance(&trials, time);
+        }
+        // After the trials, which may start or seek it.
+        let animation = timeline.borrow().clone();
+        if let Some(animation) = animation {
+          animation::advance(&animation, time);
+        }
+        let transitions = tweens.borrow().clone();
+        if let Some(transitions) = transitions {
+          animation::advance_tweens(&transitions, time);
+        }
+        canvas.borrow_mut().render(time);
+
+        // A callback may have stopped the loop, with no request left to
+        // cancel, or stopped and restarted it, which queued a frame of its
+        // own; requesting another would run two chains of frames.
+        if !running.get() || request_id.get().is_some() {
+          return;
+        }
+        match request_frame(&frame) {
+          Ok(id) => request_id.set(Some(id)),
+          Err(_) => running.set(false),
+        }
+      }) as Box<dyn FnMut(f64)>));
+    }
+
+    let id = request_frame(&self.frame)?;
+    self.request_id.set(Some(id));
+    self.running.set(true);
+    Ok(())
+  }
+
+  pub fn stop(&self) {
+    self.running.set(false);
+    if let Some(id) = self.request_id.take() {
+      worker::cancel_animation_frame(id);
+    }
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.running.get()
+  }
+
+  // Polls `gamepads` once per frame, before anything else runs.
+  pub fn attach_gamepads(&self, gamepads: &Gamepads) {
+    *self.gamepads.borrow_mut() = Some(gamepads.shared());
+  }
+
+  pub fn detach_gamepads(&self) {
+    self.gamepads.borrow_mut().take();
+  }
+
+  // Advances `runner` once per frame, just before rendering it.
+  pub fn attach_trial_runner(&self, runner: &TrialRunner) {
+    *self.runner.borrow_mut() = Some(runner.shared());
+  }
+
+  pub fn detach_trial_runner(&self) {
+    self.runner.borrow_mut().take();
+  }
+
+  // Advances `timeline` once per frame, after the trial runner.
+  pub fn attach_timeline(&self, timeline: &Timeline) {
+    *self.timeline.borrow_mut() = Some(timeline.shared());
+  }
+
+  pub fn detach_timeline(&self) {
+    self.timeline.borrow_mut().take();
+  }
+
+  // Advances `tweens` once per frame, after the timeline, so a tween can
+  // take over from it.
+  pub fn attach_tweens(&self, tweens: &Tweens) {
+    *self.tweens.borrow_mut() = Some(tweens.shared());
+  }
+
+  pub fn detach_tweens(&self) {
+    self.tweens.borrow_mut().take();
+  }
+}
+
+impl Drop for RenderLoop {
+  fn drop(&mut self) {
+    self.stop();
+    // Break the reference cycle between the callback and itself.
+    self.frame.borrow_mut().take();
+  }
+}
+
+fn request_frame(frame: &Rc<RefCell<Option<FrameCallback>>>) -> Result<i32> {
+  let frame = frame.borrow();
+  // `start` always installs the callback before requesting a frame.
+  let callback = frame.as_ref().expect("render loop callback is installed");
+  worker::request_animation_frame(callback.as_ref().unchecked_ref())
+}
@@ -0,0 +1,193 @@
+use wasm_bindgen::prelude::*;
+
+use web_sys::{WebGl2RenderingContext, WebGlFramebuffer, WebGlRenderbuffer};
+
+use crate::error::{GestaltError, Result};
+use crate::texture::Texture;
+
+// Storage of an offscreen target's colour, finest last. `Byte` quantizes to
+// 1/255 like the canvas. `Srgb` has the same 8 bits spaced along the sRGB
+// curve, finer in the dark, and blends in linear light. `HalfFloat` keeps
+// about 11 bits and `Float` 24, both with values outside 0 to 1.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetPrecision {
+  Byte,
+  Srgb,
+  HalfFloat,
+  Float,
+}
+
+impl TargetPrecision {
+  // Internal format, format and type of the colour texture.
+  fn formats(self) -> (u32, u32, u32) {
+    match self {
+      TargetPrecision::Byte => (WebGl2RenderingContext::RGBA8, WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE),
+      TargetPrecision::Srgb => (WebGl2RenderingContext::SRGB8_ALPHA8, WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE),
+      TargetPrecision::HalfFloat => (WebGl2RenderingContext::RGBA16F, WebGl2RenderingContext::RGBA, WebGl2RenderingContext::HALF_FLOAT),
+      TargetPrecision::Float => (WebGl2RenderingContext::RGBA32F, WebGl2RenderingContext::RGBA, WebGl2RenderingContext::FLOAT),
+    }
+  }
+
+  pub(crate) fn is_float(self) -> bool {
+    matches!(self, TargetPrecision::HalfFloat | TargetPrecision::Float)
+  }
+}
+
+// The finest precision up to `wanted` the context can render to, enabling
+// the extensions it takes. Float targets also need blending and linear
+// filtering here, or they would fail where half floats work.
+pub(crate) fn supported_precision(context: &WebGl2RenderingContext, wanted: TargetPrecision) -> Result<TargetPrecision> {
+  let has = |name: &str| -> Result<bool> { Ok(context.get_extension(name)?.is_some()) };
+  if wanted == TargetPrecision::Float
+    && has("EXT_color_buffer_float")?
+    && has("EXT_float_blend")?
+    && has("OES_texture_float_linear")?
+  {
+    return Ok(TargetPrecision::Float);
+  }
+  if wanted.is_float() {
+    if has("EXT_color_buffer_float")? || has("EXT_color_buffer_half_float")? {
+      return Ok(TargetPrecision::HalfFloat);
+    }
+    return Ok(TargetPrecision::Byte);
+  }
+  Ok(wanted)
+}
+
+// An offscreen framebuffer with a single RGBA color texture and a combined
+// depth and stencil buffer. While bound, all drawing goes into the texture
+// instead of the canvas; afterwards the texture can be sampled like any
+// other, see `WebGlCanvas::set_uniform_render_target`.
+#[wasm_bindgen]
+pub struct RenderTarget {
+  context: WebGl2RenderingContext,
+  framebuffer: WebGlFramebuffer,
+  texture: Texture,
+  depth: WebGlRenderbuffer,
+  precision: TargetPrecision,
+}
+
+#[wasm_bindgen]
+impl RenderTarget {
+  pub fn width(&self) -> u32 {
+    self.texture.width()
+  }
+
+  pub fn height(&self) -> u32 {
+    self.texture.height()
+  }
+
+  pub fn precision(&self) -> TargetPrecision {
+    self.precision
+  }
+
+  // Directs drawing into this target and sets the viewport to cover it.
+  pub fn bind(&self) {
+    self.context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&self.framebuffer));
+    self.context.viewport(0, 0, self.width() as i32, self.height() as i32);
+  }
+
+  // Goes back to drawing into the canvas, with a full-canvas viewport.
+  pub fn unbind(&self) {
+    unbind_render_target(&self.context);
+  }
+
+  // Reallocates the color texture and depth-stencil buffer; their previous
+  // contents are lost.
+  pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+    if width == self.width() && height == self.height() {
+      return Ok(());
+    }
+    allocate_color(&mut self.texture, width, height, self.precision)?;
+    allocate_depth_stencil(&self.context, &self.depth, width, height);
+    Ok(())
+  }
+}
+
+impl RenderTarget {
+  pub(crate) fn new(context: &WebGl2RenderingContext, width: u32, height: u32) -> Result<RenderTarget> {
+    RenderTarget::with_precision(context, width, height, TargetPrecision::Byte)
+  }
+
+  // `precision` must be supported, see `supported_precision`.
+  pub(crate) fn with_precision(context: &WebGl2RenderingContext, width: u32, height: u32, precision: TargetPrecision) -> Result<RenderTarget> {
+    let mut texture = Texture::new(context)?;
+    allocate_color(&mut texture, width, height, precision)?;
+
+    let depth = context
+      .create_renderbuffer()
+      .ok_or(GestaltError::ResourceCreation("depth-stencil buffer"))?;
+    allocate_depth_stencil(context, &depth, width, height);
+
+    let framebuffer = context
+      .create_framebuffer()
+      .ok_or(GestaltError::ResourceCreation("framebuffer"))?;
+    context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&framebuffer));
+    context.framebuffer_texture_2d(
+      WebGl2RenderingContext::FRAMEBUFFER,
+      WebGl2RenderingContext::COLOR_ATTACHMENT0,
+      WebGl2RenderingContext::TEXTURE_2D,
+      Some(texture.raw()),
+      0,
+    );
+    context.framebuffer_renderbuffer(
+      WebGl2RenderingContext::FRAMEBUFFER,
+      WebGl2RenderingContext::DEPTH_STENCIL_ATTACHMENT,
+      WebGl2RenderingContext::RENDERBUFFER,
+      Some(&depth),
+    );
+    let status = context.check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER);
+    context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+
+    if status != WebGl2RenderingContext::FRAMEBUFFER_COMPLETE {
+      context.delete_framebuffer(Some(&framebuffer));
+      context.delete_renderbuffer(Some(&depth));
+      return Err(GestaltError::ResourceCreation("complete framebuffer"));
+    }
+
+    Ok(RenderTarget {
+      context: context.clone(),
+      framebuffer,
+      texture,
+      depth,
+      precision,
+    })
+  }
+
+  pub(crate) fn framebuffer(&self) -> &WebGlFramebuffer {
+    &self.framebuffer
+  }
+
+  pub(crate) fn texture(&self) -> &Texture {
+    &self.texture
+  }
+}
+
+impl Drop for RenderTarget {
+  fn drop(&mut self) {
+    self.context.delete_framebuffer(Some(&self.framebuffer));
+    self.context.delete_renderbuffer(Some(&self.depth));
+  }
+}
+
+fn allocate_color(texture: &mut Texture, width: u32, height: u32, precision: TargetPrecision) -> Result<()> {
+  let (internal_format, format, type_) = precision.formats();
+  texture.allocate(width, height, internal_format, format, type_)
+}
+
+fn allocate_depth_stencil(context: &WebGl2RenderingContext, depth: &WebGlRenderbuffer, width: u32, height: u32) {
+  context.bind_renderbuffer(WebGl2RenderingContext::RENDERBUFFER, Some(depth));
+  context.renderbuffer_storage(
+    WebGl2RenderingContext::RENDERBUFFER,
+    WebGl2RenderingContext::DEPTH24_STENCIL8,
+    width as i32,
+    height as i32,
+  );
+  context.bind_renderbuffer(WebGl2RenderingContext::RENDERBUFFER, None);
+}
+
+pub(crate) fn unbind_render_target(context: &WebGl2RenderingContext) {
+  context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+  context.viewport(0, 0, context.drawing_buffer_width(), context.drawing_buffer_height());
+}
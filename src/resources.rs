@@ -0,0 +1,92 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::error::Result;
+use crate::shader::LinkedProgram;
+
+// Programs are looked up by everything that goes into linking them.
+struct CachedProgram {
+  vert_src: String,
+  frag_src: String,
+  attributes: Vec<(String, u32)>,
+  program: Weak<LinkedProgram>,
+}
+
+// GL objects that can be shared by everything drawing with one context, so
+// e.g. several text renderers or a stimulus and its preview on the same
+// canvas compile and link their shaders once. GL objects belong to the
+// context that made them and mean nothing to any other, so every context,
+// i.e. every canvas element, has a cache of its own and each canvas on a
+// page compiles its shaders itself.
+#[derive(Default)]
+struct Resources {
+  programs: Vec<CachedProgram>,
+}
+
+thread_local! {
+  static RESOURCES: RefCell<Vec<(WebGl2RenderingContext, Resources)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn with_resources<R>(context: &WebGl2RenderingContext, f: impl FnOnce(&mut Resources) -> R) -> R {
+  RESOURCES.with(|resources| {
+    let mut resources = resources.borrow_mut();
+    let index = match resources.iter().position(|(known, _)| known == context) {
+      Some(index) => index,
+      None => {
+        resources.push((context.clone(), Resources::default()));
+        resources.len() - 1
+      }
+    };
+    f(&mut resources[index].1)
+  })
+}
+
+// The program linked from these sources and attribute bindings on
+// `context`, calling `link` only if there is none alive yet.
+pub(crate) fn program(
+  context: &WebGl2RenderingContext,
+  vert_src: &str,
+  frag_src: &str,
+  attributes: &[(String, u32)],
+  link: impl FnOnce() -> Result<LinkedProgram>,
+) -> Result<Rc<LinkedProgram>> {
+  let cached = with_resources(context, |resources| {
+    resources.programs.retain(|cached| cached.program.strong_count() > 0);
+    resources
+      .programs
+      .iter()
+      .find(|cached| cached.vert_src == vert_src && cached.frag_src == frag_src && cached.attributes == attributes)
+      .and_then(|cached| cached.program.upgrade())
+  });
+  if let Some(program) = cached {
+    return Ok(program);
+  }
+
+  // Linking may look up other resources, so the cache is not held.
+  let program = Rc::new(link()?);
+  with_resources(context, |resources| {
+    resources.programs.push(CachedProgram {
+      vert_src: vert_src.to_string(),
+      frag_src: frag_src.to_string(),
+      attributes: attributes.to_vec(),
+      program: Rc::downgrade(&program),
+    })
+  });
+  Ok(program)
+}
+
+// Stops handing out `program`, e.g. because it is being deleted.
+pub(crate) fn remove_program(context: &WebGl2RenderingContext, program: &Rc<LinkedProgram>) {
+  with_resources(context, |resources| {
+    resources.programs.retain(|cached| !std::ptr::eq(cached.program.as_ptr(), Rc::as_ptr(program)))
+  });
+}
+
+// Forgets everything cached for `context`, for a restored context, whose
+// objects all have to be made again, or one no longer drawn with. Objects
+// in use are deleted by their last user as usual.
+pub(crate) fn forget(context: &WebGl2RenderingContext) {
+  RESOURCES.with(|resources| resources.borrow_mut().retain(|(known, _)| known != context));
+}
@@ -0,0 +1,309 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use web_sys::{KeyboardEvent, PointerEvent, WebGl2RenderingContext};
+
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::input::normalized_position;
+use crate::layers::Layer;
+
+// A key or pointer press.
+#[derive(Clone, Debug, PartialEq)]
+struct Response {
+  // `KeyboardEvent.key`, or "pointer0", "pointer1"... by button.
+  value: String,
+  // The event's `timeStamp`.
+  time: f64,
+  // Normalized canvas coordinates of pointer presses, like `Mouse`.
+  position: Option<(f32, f32)>,
+}
+
+// The outcome of one trial. Times are in milliseconds on the clock of the
+// `requestAnimationFrame` timestamps passed to `render`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ResponseRecord {
+  trial: usize,
+  // Timestamp of the first frame drawn after the trial started.
+  onset: Option<f64>,
+  response: Option<Response>,
+  correct: Option<bool>,
+}
+
+impl ResponseRecord {
+  fn reaction_time(&self) -> Option<f64> {
+    self.response.as_ref().zip(self.onset).map(|(response, onset)| response.time - onset)
+  }
+
+  fn to_js(&self) -> JsValue {
+    let optional = |value: Option<f64>| value.map_or(JsValue::NULL, JsValue::from);
+    let response = self.response.as_ref();
+    let time = response.map(|response| response.time);
+    let position = response.and_then(|response| response.position);
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"trial".into(), &self.trial.into());
+    let _ = js_sys::Reflect::set(&object, &"onset".into(), &optional(self.onset));
+    let _ = js_sys::Reflect::set(&object, &"response".into(), &response.map_or(JsValue::NULL, |response| response.value.as_str().into()));
+    let _ = js_sys::Reflect::set(&object, &"time".into(), &optional(time));
+    let _ = js_sys::Reflect::set(&object, &"rt".into(), &optional(self.reaction_time()));
+    let _ = js_sys::Reflect::set(&object, &"x".into(), &optional(position.map(|(x, _)| x as f64)));
+    let _ = js_sys::Reflect::set(&object, &"y".into(), &optional(position.map(|(_, y)| y as f64)));
+    let _ = js_sys::Reflect::set(&object, &"correct".into(), &self.correct.map_or(JsValue::NULL, JsValue::from));
+    object.into()
+  }
+}
+
+// With the fields of `to_js`.
+impl Serialize for ResponseRecord {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let response = self.response.as_ref();
+    let position = response.and_then(|response| response.position);
+    let mut record = serializer.serialize_struct("ResponseRecord", 8)?;
+    record.serialize_field("trial", &self.trial)?;
+    record.serialize_field("onset", &self.onset)?;
+    record.serialize_field("response", &response.map(|response| &response.value))?;
+    record.serialize_field("time", &response.map(|response| response.time))?;
+    record.serialize_field("rt", &self.reaction_time())?;
+    record.serialize_field("x", &position.map(|(x, _)| x))?;
+    record.serialize_field("y", &position.map(|(_, y)| y))?;
+    record.serialize_field("correct", &self.correct)?;
+    record.end()
+  }
+}
+
+// The trial waiting for a response.
+struct OpenTrial {
+  index: usize,
+  expected: Option<String>,
+  onset: Option<f64>,
+}
+
+pub(crate) struct RecorderState {
+  clock: Rc<Cell<f64>>,
+  // Accepted keys, as `key` or `code`; empty accepts any.
+  keys: Vec<String>,
+  pointer: bool,
+  trial: Option<OpenTrial>,
+  records: Vec<ResponseRecord>,
+  callback: Option<js_sys::Function>,
+}
+
+impl RecorderState {
+  pub(crate) fn records(&self) -> &[ResponseRecord] {
+    &self.records
+  }
+
+  // Closes the open trial with `response`, right if the expected response is
+  // one of `codes`. Returns the record for the JS callback, which has to be
+  // called once the state is no longer borrowed.
+  fn close(&mut self, response: Option<Response>, codes: &[&str]) -> Option<JsValue> {
+    let trial = self.trial.take()?;
+    let correct = trial.expected.as_ref().map(|expected| {
+      response.is_some() && codes.iter().any(|code| code == expected)
+    });
+    let record = ResponseRecord {
+      trial: trial.index,
+      onset: trial.onset,
+      response,
+      correct,
+    };
+    let js_record = self.callback.as_ref().map(|_| record.to_js());
+    self.records.push(record);
+    js_record
+  }
+
+  // Whether a response at `time` counts: the stimulus must be on screen, so
+  // presses before the onset frame are ignored.
+  fn accepts(&self, time: f64) -> bool {
+    matches!(&self.trial, Some(OpenTrial { onset: Some(onset), .. }) if time >= *onset)
+  }
+
+  fn handle_key(&mut self, event: &KeyboardEvent) -> Option<JsValue> {
+    let (key, code) = (event.key(), event.code());
+    if event.repeat() || !self.accepts(event.time_stamp()) {
+      return None;
+    }
+    if !self.keys.is_empty() && !self.keys.iter().any(|accepted| *accepted == key || *accepted == code) {
+      return None;
+    }
+    let codes = [key.as_str(), code.as_str()];
+    let response = Response {
+      value: key.clone(),
+      time: event.time_stamp(),
+      position: None,
+    };
+    self.close(Some(response), &codes)
+  }
+
+  fn handle_pointer(&mut self, event: &PointerEvent, position: (f32, f32)) -> Option<JsValue> {
+    if !self.pointer || !self.accepts(event.time_stamp()) {
+      return None;
+    }
+    let value = format!("pointer{}", event.button());
+    let codes = [value.as_str(), "pointer"];
+    let response = Response {
+      value: value.clone(),
+      time: event.time_stamp(),
+      position: Some(position),
+    };
+    self.close(Some(response), &codes)
+  }
+}
+
+// Onsets are taken from the frames the recorder is drawn in.
+impl Overlay for RecorderState {
+  fn draw(&mut self, _width: u32, _height: u32, _time: f32) {
+    if let Some(trial) = &mut self.trial {
+      trial.onset.get_or_insert(self.clock.get());
+    }
+  }
+
+  fn restore(&mut self, _context: &WebGl2RenderingContext) -> Result<()> {
+    Ok(())
+  }
+}
+
+// Records one key or pointer response per trial, timed against the
+// stimulus onset: the `requestAnimationFrame` timestamp of the first frame
+// rendered after `start_trial`, on the same clock as event timestamps.
+// Presses before that frame, auto-repeats and keys not accepted are
+// ignored; the first accepted press ends the trial.
+#[wasm_bindgen]
+pub struct ResponseRecorder {
+  state: Rc<RefCell<RecorderState>>,
+  _listeners: Vec<EventListener>,
+}
+
+#[wasm_bindgen]
+impl ResponseRecorder {
+
+  // Listens for keys on the whole page and pointer presses on `canvas`,
+  // which times the onsets.
+  pub fn new(canvas: &WebGlCanvas) -> Result<ResponseRecorder> {
+    let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+    let element = canvas.element()?;
+    let state = Rc::new(RefCell::new(RecorderState {
+      clock: canvas.frame_clock(),
+      keys: Vec::new(),
+      pointer: true,
+      trial: None,
+      records: Vec::new(),
+      callback: None,
+    }));
+    canvas.add_overlay(state.clone(), Layer::Stimulus);
+
+    let key_state = state.clone();
+    let keys = EventListener::new(&window, "keydown", move |event| {
+      if let Some(event) = event.dyn_ref::<KeyboardEvent>() {
+        let record = key_state.borrow_mut().handle_key(event);
+        notify(&key_state, record);
+      }
+    })?;
+
+    let pointer_state = state.clone();
+    let target = element.clone();
+    let pointers = EventListener::new(&element, "pointerdown", move |event| {
+      if let Some(event) = event.dyn_ref::<PointerEvent>() {
+        let position = normalized_position(&target, event);
+        let record = pointer_state.borrow_mut().handle_pointer(event, position);
+        notify(&pointer_state, record);
+      }
+    })?;
+
+    Ok(ResponseRecorder { state, _listeners: vec![keys, pointers] })
+  }
+
+  // Accepted keys, as `KeyboardEvent.key` ("f", "ArrowLeft") or
+  // `KeyboardEvent.code` ("KeyF"); an empty array accepts any key.
+  pub fn set_keys(&self, keys: &js_sys::Array) {
+    self.state.borrow_mut().keys = keys.iter().filter_map(|key| key.as_string()).collect();
+  }
+
+  // Pointer presses count as responses to begin with.
+  pub fn set_pointer_enabled(&self, enabled: bool) {
+    self.state.borrow_mut().pointer = enabled;
+  }
+
+  // Opens the next trial, closing an open one without a response. `correct`
+  // is the right response, a key as in `set_keys`, "pointer" for any
+  // button or "pointer0" and so on for one; `undefined` records no accuracy.
+  pub fn start_trial(&self, correct: Option<String>) {
+    let record = self.state.borrow_mut().close(None, &[]);
+    notify(&self.state, record);
+    let mut state = self.state.borrow_mut();
+    let index = state.records.len();
+    state.trial = Some(OpenTrial {
+      index,
+      expected: correct,
+      onset: None,
+    });
+  }
+
+  // Closes the open trial without a response, e.g. after a deadline; a miss
+  // counts as wrong if a right response was given.
+  pub fn end_trial(&self) {
+    let record = self.state.borrow_mut().close(None, &[]);
+    notify(&self.state, record);
+  }
+
+  // Whether a trial is open.
+  pub fn is_waiting(&self) -> bool {
+    self.state.borrow().trial.is_some()
+  }
+
+  // Onset of the open trial, until its first frame is drawn `undefined`.
+  pub fn onset(&self) -> Option<f64> {
+    self.state.borrow().trial.as_ref().and_then(|trial| trial.onset)
+  }
+
+  // Calls `callback` with each trial's record as it closes; `undefined`
+  // removes it.
+  pub fn set_callback(&self, callback: Option<js_sys::Function>) {
+    self.state.borrow_mut().callback = callback;
+  }
+
+  // Closed trials as `{ trial, onset, response, time, rt, x, y, correct }`
+  // objects, oldest first. Missing values are null: `response`, `time` and
+  // `rt` without a response, `x` and `y` for keys, `correct` without a
+  // right response.
+  pub fn records(&self) -> js_sys::Array {
+    self.state.borrow().records.iter().map(ResponseRecord::to_js).collect()
+  }
+
+  pub fn record_count(&self) -> usize {
+    self.state.borrow().records.len()
+  }
+
+  // Proportion of right responses over trials with a right response given,
+  // NaN without any.
+  pub fn accuracy(&self) -> f64 {
+    let state = self.state.borrow();
+    let scored: Vec<bool> = state.records.iter().filter_map(|record| record.correct).collect();
+    scored.iter().filter(|&&correct| correct).count() as f64 / scored.len() as f64
+  }
+
+  // Drops the records and any open trial; trial numbers start over.
+  pub fn clear(&self) {
+    let mut state = self.state.borrow_mut();
+    state.records.clear();
+    state.trial = None;
+  }
+}
+
+impl ResponseRecorder {
+  pub(crate) fn shared(&self) -> Rc<RefCell<RecorderState>> {
+    self.state.clone()
+  }
+}
+
+fn notify(state: &Rc<RefCell<RecorderState>>, record: Option<JsValue>) {
+  let callback = state.borrow().callback.clone();
+  if let (Some(callback), Some(record)) = (callback, record) {
+    let _ = callback.call1(&JsValue::NULL, &record);
+  }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::error::{GestaltError, Result};
+use crate::graphics::document;
+
+// Writes RFC 4180 CSV: fields with commas, quotes, line breaks or outer
+// spaces are quoted, quotes doubled, and rows end in CRLF.
+pub(crate) struct CsvWriter {
+  out: String,
+}
+
+impl CsvWriter {
+  pub(crate) fn new() -> CsvWriter {
+    CsvWriter { out: String::new() }
+  }
+
+  pub(crate) fn write_row<S: AsRef<str>>(&mut self, fields: &[S]) {
+    for (index, field) in fields.iter().enumerate() {
+      if index > 0 {
+        self.out.push(',');
+      }
+      self.write_field(field.as_ref());
+    }
+    self.out.push_str("\r\n");
+  }
+
+  fn write_field(&mut self, field: &str) {
+    let quoted = field.contains([',', '"', '\r', '\n'])
+      || field.starts_with(char::is_whitespace)
+      || field.ends_with(char::is_whitespace);
+    if !quoted {
+      self.out.push_str(field);
+      return;
+    }
+    self.out.push('"');
+    for c in field.chars() {
+      if c == '"' {
+        self.out.push('"');
+      }
+      self.out.push(c);
+    }
+    self.out.push('"');
+  }
+
+  pub(crate) fn finish(self) -> String {
+    self.out
+  }
+}
+
+// Recorded trials as a table for saving, one row per record and one column
+// per field, such as the results of a `TrialRunner` or `ResponseRecorder`.
+// Nested objects are flattened into dotted columns ("params.intensity"),
+// arrays are written as JSON, and fields a record lacks are left empty.
+#[wasm_bindgen]
+pub struct Results {
+  // In the order first seen.
+  columns: Vec<String>,
+  rows: Vec<HashMap<String, String>>,
+}
+
+#[wasm_bindgen]
+impl Results {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Results {
+    Results {
+      columns: Vec::new(),
+      rows: Vec::new(),
+    }
+  }
+
+  // Appends a record, which must be an object.
+  pub fn add_record(&mut self, record: &JsValue) -> Result<()> {
+    let Some(record) = record.dyn_ref::<js_sys::Object>().filter(|_| !record.is_array()) else {
+      return Err(GestaltError::InvalidArgument("a result record must be an object".into()));
+    };
+    let mut row = HashMap::new();
+    self.flatten("", record, &mut row)?;
+    self.rows.push(row);
+    Ok(())
+  }
+
+  // Appends every record of an array, as returned by `results()` or
+  // `records()`.
+  pub fn add_records(&mut self, records: &js_sys::Array) -> Result<()> {
+    for record in records.iter() {
+      self.add_record(&record)?;
+    }
+    Ok(())
+  }
+
+  pub fn row_count(&self) -> usize {
+    self.rows.len()
+  }
+
+  pub fn columns(&self) -> js_sys::Array {
+    self.columns.iter().map(|column| JsValue::from(column.as_str())).collect()
+  }
+
+  pub fn clear(&mut self) {
+    self.columns.clear();
+    self.rows.clear();
+  }
+
+  // The table as CSV with a header row.
+  pub fn to_csv(&self) -> String {
+    let mut writer = CsvWriter::new();
+    writer.write_row(&self.columns);
+    for row in &self.rows {
+      let fields: Vec<&str> = self
+        .columns
+        .iter()
+        .map(|column| row.get(column).map_or("", String::as_str))
+        .collect();
+      writer.write_row(&fields);
+    }
+    writer.finish()
+  }
+
+  // Saves the CSV as `filename` through the browser's download, without a
+  // server.
+  pub fn download(&self, filename: &str) -> Result<()> {
+    download_text(&self.to_csv(), filename, "text/csv;charset=utf-8")
+  }
+}
+
+impl Default for Results {
+  fn default() -> Results {
+    Results::new()
+  }
+}
+
+impl Results {
+  fn flatten(&mut self, prefix: &str, object: &js_sys::Object, row: &mut HashMap<String, String>) -> Result<()> {
+    for entry in js_sys::Object::entries(object).iter() {
+      let entry: js_sys::Array = entry.unchecked_into();
+      let key = entry.get(0).as_string().unwrap_or_default();
+      let value = entry.get(1);
+      let column = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+
+      match value.dyn_ref::<js_sys::Object>() {
+        Some(nested) if !value.is_array() && !value.is_function() => self.flatten(&column, nested, row)?,
+        _ => {
+          if !self.columns.contains(&column) {
+            self.columns.push(column.clone());
+          }
+          row.insert(column, field_text(&value)?);
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+fn field_text(value: &JsValue) -> Result<String> {
+  if value.is_null() || value.is_undefined() {
+    return Ok(String::new());
+  }
+  if let Some(text) = value.as_string() {
+    return Ok(text);
+  }
+  if let Some(number) = value.as_f64() {
+    return Ok(number.to_string());
+  }
+  if let Some(flag) = value.as_bool() {
+    return Ok(flag.to_string());
+  }
+  // Arrays; functions and symbols have no JSON and stay empty.
+  Ok(js_sys::JSON::stringify(value)?.as_string().unwrap_or_default())
+}
+
+// How long a download's Blob URL is kept, time enough to start saving.
+const REVOKE_DELAY_MS: i32 = 40_000;
+
+// Hands `text` to the browser as a file download, through a temporary Blob
+// URL and link.
+pub(crate) fn download_text(text: &str, filename: &str, mime_type: &str) -> Result<()> {
+  let options = web_sys::BlobPropertyBag::new();
+  options.set_type(mime_type);
+  let parts = js_sys::Array::of1(&JsValue::from(text));
+  let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options)?;
+  let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+  let document = document()?;
+  let link = document
+    .create_element("a")?
+    .dyn_into::<web_sys::HtmlAnchorElement>()
+    .map_err(|_| GestaltError::ResourceCreation("download link"))?;
+  link.set_href(&url);
+  link.set_download(filename);
+  link.style().set_property("display", "none")?;
+  let body = document.body().ok_or(GestaltError::ResourceCreation("download link"))?;
+  body.append_child(&link)?;
+  link.click();
+  link.remove();
+
+  // Firefox and Safari cancel the download if the URL goes straight away.
+  let revoke = Closure::once_into_js(move || {
+    let _ = web_sys::Url::revoke_object_url(&url);
+  });
+  let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+  window.set_timeout_with_callback_and_timeout_and_arguments_0(revoke.unchecked_ref(), REVOKE_DELAY_MS)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CsvWriter;
+
+  fn csv(fields: &[&str]) -> String {
+    let mut writer = CsvWriter::new();
+    writer.write_row(fields);
+    writer.finish()
+  }
+
+  #[test]
+  fn plain_fields_are_left_alone() {
+    assert_eq!(csv(&["trial", "1", "0.25", ""]), "trial,1,0.25,\r\n");
+  }
+
+  #[test]
+  fn commas_are_quoted() {
+    assert_eq!(csv(&["a,b", "c"]), "\"a,b\",c\r\n");
+  }
+
+  #[test]
+  fn quotes_are_doubled() {
+    assert_eq!(csv(&["say \"yes\"", "\""]), "\"say \"\"yes\"\"\",\"\"\"\"\r\n");
+  }
+
+  #[test]
+  fn line_breaks_are_quoted() {
+    assert_eq!(csv(&["a\nb", "c\rd", "e\r\nf"]), "\"a\nb\",\"c\rd\",\"e\r\nf\"\r\n");
+  }
+
+  #[test]
+  fn outer_spaces_are_quoted() {
+    assert_eq!(csv(&[" left", "right ", "in side", "\ttab"]), "\" left\",\"right \",in side,\"\ttab\"\r\n");
+  }
+
+  #[test]
+  fn rows_end_in_crlf() {
+    let mut writer = CsvWriter::new();
+    writer.write_row(&["a", "b"]);
+    writer.write_row(&["c", "d"]);
+    assert_eq!(writer.finish(), "a,b\r\nc,d\r\n");
+  }
+}
@@ -0,0 +1,819 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::{WebGl2RenderingContext, WebGlTexture};
+
+use crate::animation::NodeProperty;
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::math::{Mat3, Vec2};
+use crate::picking::{self, PickPass};
+use crate::shader::ShaderProgram;
+use crate::stimuli::{DotLayer, Grating, Gratings, PlaidBlend};
+use crate::texture::Texture;
+use crate::viewport::{self, Viewport};
+
+const SCENE_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 position;
+in vec2 uv;
+in vec4 color;
+
+uniform vec2 u_resolution;
+
+out vec2 v_uv;
+out vec4 v_color;
+
+void main()
+{
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+  v_uv = uv;
+  v_color = color;
+}
+"##;
+
+const SCENE_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_texture;
+uniform bool u_textured;
+
+in vec2 v_uv;
+in vec4 v_color;
+
+out vec4 outColor;
+
+void main()
+{
+  outColor = u_textured ? texture(u_texture, v_uv) * v_color : v_color;
+}
+"##;
+
+// A retained-mode display: a tree of nodes, each with a transform relative
+// to its parent and optionally something to draw, kept from frame to frame
+// until changed or removed. Coordinates are drawing-buffer pixels from the
+// top left, as in `Draw2D`, unless confined to a viewport, and rotations are
+// clockwise in radians.
+//
+// Every frame the scene draws its visible nodes on top of the canvas'
+// scene, skipping those entirely outside the drawing buffer, lowest `z`
+// first; nodes of equal `z` are drawn parents before children, siblings in
+// the order they were added. Nodes are referred to by the ids the `add_*`
+// methods return, which stay unused once their node is removed.
+#[wasm_bindgen]
+pub struct Scene {
+  scene: Rc<RefCell<SceneState>>,
+  layer: LayerSlot,
+}
+
+pub(crate) struct SceneState {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  gratings: Gratings,
+  dots: DotLayer,
+  // Made on the first `pick`.
+  picker: Option<PickPass>,
+  nodes: Vec<Option<Node>>,
+  roots: Vec<usize>,
+  blend_mode: BlendMode,
+  // The part of the canvas the scene draws in, all of it unless set.
+  viewport: Option<Viewport>,
+  // Cleared to before the nodes are drawn, if set.
+  background: Option<[f32; 4]>,
+  culled: usize,
+}
+
+struct Node {
+  parent: Option<usize>,
+  children: Vec<usize>,
+  drawable: Drawable,
+  x: f32,
+  y: f32,
+  rotation: f32,
+  scale_x: f32,
+  scale_y: f32,
+  z: f32,
+  visible: bool,
+  pickable: bool,
+}
+
+enum Drawable {
+  // Only transforms its children.
+  Group,
+  // Triangles as x, y pairs in the node's coordinates.
+  Mesh { vertices: Vec<f32>, color: [f32; 4] },
+  // Centred on the node's origin.
+  Sprite { texture: WebGlTexture, width: f32, height: f32, tint: [f32; 4] },
+  Grating(Grating),
+  // Round dots at x, y pairs in the node's coordinates, `diameter` pixels
+  // across whatever the scale.
+  Dots { positions: Vec<f32>, diameter: f32, color: [f32; 4] },
+}
+
+// Consecutive nodes with the same key go into one draw call.
+#[derive(PartialEq)]
+enum BatchKey {
+  Untextured,
+  Textured(WebGlTexture),
+  Gratings,
+  Dots,
+}
+
+#[wasm_bindgen]
+impl Scene {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<Scene> {
+    let context = canvas.context();
+    let mut program = ShaderProgram::new(&context, SCENE_VERT_SHADER, SCENE_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("position", 2), ("uv", 2), ("color", 4)])?;
+    let batch = VertexBatch::new(&context, &layout)?;
+    program.set_i32("u_texture", 0);
+    let gratings = Gratings::new(&context)?;
+    let dots = DotLayer::new(&context)?;
+
+    let scene = Rc::new(RefCell::new(SceneState {
+      context,
+      program,
+      batch,
+      gratings,
+      dots,
+      picker: None,
+      nodes: Vec::new(),
+      roots: Vec::new(),
+      blend_mode: BlendMode::Alpha,
+      viewport: None,
+      background: None,
+      culled: 0,
+    }));
+    let layer = canvas.add_overlay(scene.clone(), Layer::Stimulus);
+    Ok(Scene { scene, layer })
+  }
+
+  // Layer of the canvas the scene is composited in, `Stimulus` to begin
+  // with.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Blending of meshes, sprites and dots, `Alpha` to begin with. Gratings
+  // always blend by alpha.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    let mut scene = self.scene.borrow_mut();
+    scene.blend_mode = mode;
+    scene.dots.blend_mode = mode;
+  }
+
+  // Confines the scene to `viewport`, from the next frame on, with node
+  // coordinates measured from its top left and nothing drawn outside it.
+  // Scenes with viewports side by side make independent panels, e.g. for
+  // left and right comparison displays.
+  pub fn set_viewport(&self, viewport: &Viewport) {
+    self.scene.borrow_mut().viewport = Some(*viewport);
+  }
+
+  pub fn clear_viewport(&self) {
+    self.scene.borrow_mut().viewport = None;
+  }
+
+  // Fills the scene's viewport, or the whole canvas without one, with
+  // `color` before drawing the nodes each frame, e.g. a mid-grey panel
+  // between black ones. Nothing is cleared unless set.
+  pub fn set_background(&self, color: &Color) {
+    self.scene.borrow_mut().background = Some(color.to_array());
+  }
+
+  pub fn clear_background(&self) {
+    self.scene.borrow_mut().background = None;
+  }
+
+  // A node that draws nothing itself, to move, turn or hide its children
+  // together. Without a parent it is added at the top level.
+  pub fn add_group(&self, parent: Option<usize>) -> Result<usize> {
+    self.scene.borrow_mut().add(parent, Drawable::Group)
+  }
+
+  // A node drawing `vertices`, a flat list of x, y pairs taken three at a
+  // time as triangles, filled with `color`.
+  pub fn add_mesh(&self, parent: Option<usize>, vertices: &[f32], color: &Color) -> Result<usize> {
+    if vertices.is_empty() || !vertices.len().is_multiple_of(6) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a mesh needs whole triangles of three x, y pairs, got {} floats",
+        vertices.len()
+      )));
+    }
+    let mesh = Drawable::Mesh {
+      vertices: vertices.to_vec(),
+      color: color.to_array(),
+    };
+    self.scene.borrow_mut().add(parent, mesh)
+  }
+
+  // A node drawing the whole of `texture` as a `width` x `height` rectangle
+  // around its origin. Textures do not survive a lost context, so sprites
+  // have to be added again after one.
+  pub fn add_sprite(&self, parent: Option<usize>, texture: &Texture, width: f32, height: f32) -> Result<usize> {
+    let mut scene = self.scene.borrow_mut();
+    texture.check_context(&scene.context)?;
+    let sprite = Drawable::Sprite {
+      texture: texture.raw().clone(),
+      width,
+      height,
+      tint: [1.0, 1.0, 1.0, 1.0],
+    };
+    scene.add(parent, sprite)
+  }
+
+  // A node drawing `grating`, whose position, radius and frequency are in
+  // the node's coordinates and orientation relative to the node's.
+  // Gratings drift from the first frame they are drawn in, see
+  // `restart_clock`.
+  pub fn add_grating(&self, parent: Option<usize>, grating: &Grating) -> Result<usize> {
+    self.scene.borrow_mut().add(parent, Drawable::Grating(*grating))
+  }
+
+  // A node drawing a dot cloud, `positions` being x, y pairs. Parented to a
+  // group, the whole cloud moves and turns with it, e.g. for common fate.
+  pub fn add_dots(&self, parent: Option<usize>, positions: &[f32], diameter: f32, color: &Color) -> Result<usize> {
+    check_dot_positions(positions)?;
+    let dots = Drawable::Dots {
+      positions: positions.to_vec(),
+      diameter: diameter.max(0.0),
+      color: color.to_array(),
+    };
+    self.scene.borrow_mut().add(parent, dots)
+  }
+
+  // Moves the dots of a node added with `add_dots`, e.g. for dots that also
+  // move on their own within the group.
+  pub fn set_dot_positions(&self, node: usize, positions: &[f32]) -> Result<()> {
+    check_dot_positions(positions)?;
+    match &mut self.scene.borrow_mut().node_mut(node)?.drawable {
+      Drawable::Dots { positions: current, .. } => {
+        current.clear();
+        current.extend_from_slice(positions);
+      }
+      _ => return Err(GestaltError::InvalidArgument(format!("scene node {} is not a dot cloud", node))),
+    }
+    Ok(())
+  }
+
+  // Replaces the grating of a node added with `add_grating`.
+  pub fn set_grating(&self, node: usize, grating: &Grating) -> Result<()> {
+    match &mut self.scene.borrow_mut().node_mut(node)?.drawable {
+      Drawable::Grating(current) => *current = *grating,
+      _ => return Err(GestaltError::InvalidArgument(format!("scene node {} is not a grating", node))),
+    }
+    Ok(())
+  }
+
+  // A mesh's fill, a sprite's tint or the colour of a dot cloud.
+  pub fn set_color(&self, node: usize, color: &Color) -> Result<()> {
+    match &mut self.scene.borrow_mut().node_mut(node)?.drawable {
+      Drawable::Mesh { color: current, .. }
+      | Drawable::Sprite { tint: current, .. }
+      | Drawable::Dots { color: current, .. } => *current = color.to_array(),
+      _ => return Err(GestaltError::InvalidArgument(format!("scene node {} has no colour", node))),
+    }
+    Ok(())
+  }
+
+  // Where the node's origin sits in its parent's coordinates.
+  pub fn set_position(&self, node: usize, x: f32, y: f32) -> Result<()> {
+    let mut scene = self.scene.borrow_mut();
+    let node = scene.node_mut(node)?;
+    node.x = x;
+    node.y = y;
+    Ok(())
+  }
+
+  pub fn set_rotation(&self, node: usize, rotation: f32) -> Result<()> {
+    self.scene.borrow_mut().node_mut(node)?.rotation = rotation;
+    Ok(())
+  }
+
+  pub fn set_scale(&self, node: usize, scale_x: f32, scale_y: f32) -> Result<()> {
+    let mut scene = self.scene.borrow_mut();
+    let node = scene.node_mut(node)?;
+    node.scale_x = scale_x;
+    node.scale_y = scale_y;
+    Ok(())
+  }
+
+  // Drawing order, 0 for new nodes. Higher is drawn later, i.e. on top.
+  // Not inherited: children keep their own `z`.
+  pub fn set_z(&self, node: usize, z: f32) -> Result<()> {
+    self.scene.borrow_mut().node_mut(node)?.z = z;
+    Ok(())
+  }
+
+  // Hides or shows the node and everything below it.
+  pub fn set_visible(&self, node: usize, visible: bool) -> Result<()> {
+    self.scene.borrow_mut().node_mut(node)?.visible = visible;
+    Ok(())
+  }
+
+  // Whether `pick` can return the node, true for new nodes. Unpickable
+  // nodes let picks through to what is below, e.g. for a background.
+  pub fn set_pickable(&self, node: usize, pickable: bool) -> Result<()> {
+    self.scene.borrow_mut().node_mut(node)?.pickable = pickable;
+    Ok(())
+  }
+
+  // Removes the node and everything below it.
+  pub fn remove(&self, node: usize) -> Result<()> {
+    let mut scene = self.scene.borrow_mut();
+    let parent = scene.node_mut(node)?.parent;
+    let siblings = match parent {
+      Some(parent) => &mut scene.node_mut(parent)?.children,
+      None => &mut scene.roots,
+    };
+    siblings.retain(|&sibling| sibling != node);
+
+    let mut pending = vec![node];
+    while let Some(index) = pending.pop() {
+      if let Some(removed) = scene.nodes[index].take() {
+        pending.extend(removed.children);
+      }
+    }
+    Ok(())
+  }
+
+  // Removes every node.
+  pub fn clear(&self) {
+    let mut scene = self.scene.borrow_mut();
+    scene.nodes.iter_mut().for_each(|node| *node = None);
+    scene.roots.clear();
+  }
+
+  // The transform from the node's coordinates to the scene's, i.e. pixels
+  // from the top left of the drawing buffer or the viewport, as of now.
+  pub fn world_transform(&self, node: usize) -> Result<Mat3> {
+    let scene = self.scene.borrow();
+    let mut transform = Mat3::identity();
+    let mut current = Some(node);
+    while let Some(index) = current {
+      let node = scene.node(index)?;
+      transform = node.local_transform().multiply(&transform);
+      current = node.parent;
+    }
+    Ok(transform)
+  }
+
+  // The node drawn on top at drawing-buffer pixel `x`, `y`, e.g. from
+  // `Mouse`, as the scene stands now; none if no pickable node covers it
+  // or it lies outside the scene's viewport.
+  // Goes by shape, not bounds: meshes count where their triangles are,
+  // sprites where their texture is at least half opaque, and dots and
+  // gratings within their circle. Groups are never returned; see
+  // `parent` to find which group a picked node belongs to.
+  pub fn pick(&self, x: f32, y: f32) -> Result<Option<usize>> {
+    self.scene.borrow_mut().pick(x, y)
+  }
+
+  pub fn parent(&self, node: usize) -> Result<Option<usize>> {
+    Ok(self.scene.borrow().node(node)?.parent)
+  }
+
+  // Makes the next frame drawn time 0 for the scene's gratings.
+  pub fn restart_clock(&self) {
+    self.scene.borrow_mut().gratings.restart_clock();
+  }
+
+  pub fn node_count(&self) -> usize {
+    self.scene.borrow().nodes.iter().filter(|node| node.is_some()).count()
+  }
+
+  // Nodes with something to draw that were skipped in the last frame for
+  // lying outside the drawing buffer.
+  pub fn culled_count(&self) -> usize {
+    self.scene.borrow().culled
+  }
+}
+
+impl Scene {
+  pub(crate) fn shared(&self) -> Rc<RefCell<SceneState>> {
+    self.scene.clone()
+  }
+}
+
+impl SceneState {
+  // Fails unless `property` of `node` can be animated.
+  pub(crate) fn check_animatable(&self, node: usize, property: NodeProperty) -> Result<()> {
+    let drawable = &self.node(node)?.drawable;
+    let fits = match property {
+      NodeProperty::Position | NodeProperty::Rotation | NodeProperty::Scale | NodeProperty::Z => true,
+      NodeProperty::Color => matches!(drawable, Drawable::Mesh { .. } | Drawable::Sprite { .. } | Drawable::Dots { .. }),
+      _ => matches!(drawable, Drawable::Grating(_)),
+    };
+    if !fits {
+      return Err(GestaltError::InvalidArgument(format!("scene node {} has no {:?} to animate", node, property)));
+    }
+    Ok(())
+  }
+
+  // Sets `property` of `node` to `values`, as many as the property takes.
+  pub(crate) fn animate(&mut self, node: usize, property: NodeProperty, values: &[f32]) -> Result<()> {
+    self.check_animatable(node, property)?;
+    let node = self.node_mut(node)?;
+    match (property, &mut node.drawable) {
+      (NodeProperty::Position, _) => {
+        node.x = values[0];
+        node.y = values[1];
+      }
+      (NodeProperty::Rotation, _) => node.rotation = values[0],
+      (NodeProperty::Scale, _) => {
+        node.scale_x = values[0];
+        node.scale_y = values[1];
+      }
+      (NodeProperty::Z, _) => node.z = values[0],
+      (NodeProperty::Color, Drawable::Mesh { color, .. })
+      | (NodeProperty::Color, Drawable::Sprite { tint: color, .. })
+      | (NodeProperty::Color, Drawable::Dots { color, .. }) => color.copy_from_slice(&values[..4]),
+      (NodeProperty::GratingPhase, Drawable::Grating(grating)) => grating.phase = values[0],
+      (NodeProperty::GratingContrast, Drawable::Grating(grating)) => grating.contrast = values[0],
+      (NodeProperty::GratingOrientation, Drawable::Grating(grating)) => grating.orientation = values[0],
+      (NodeProperty::GratingFrequency, Drawable::Grating(grating)) => grating.frequency = values[0],
+      (NodeProperty::GratingRadius, Drawable::Grating(grating)) => grating.radius = values[0],
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn add(&mut self, parent: Option<usize>, drawable: Drawable) -> Result<usize> {
+    let index = self.nodes.len();
+    match parent {
+      Some(parent) => self.node_mut(parent)?.children.push(index),
+      None => self.roots.push(index),
+    }
+    self.nodes.push(Some(Node {
+      parent,
+      children: Vec::new(),
+      drawable,
+      x: 0.0,
+      y: 0.0,
+      rotation: 0.0,
+      scale_x: 1.0,
+      scale_y: 1.0,
+      z: 0.0,
+      visible: true,
+      pickable: true,
+    }));
+    Ok(index)
+  }
+
+  fn node(&self, index: usize) -> Result<&Node> {
+    self
+      .nodes
+      .get(index)
+      .and_then(Option::as_ref)
+      .ok_or_else(|| GestaltError::InvalidArgument(format!("no scene node {}", index)))
+  }
+
+  fn node_mut(&mut self, index: usize) -> Result<&mut Node> {
+    self
+      .nodes
+      .get_mut(index)
+      .and_then(Option::as_mut)
+      .ok_or_else(|| GestaltError::InvalidArgument(format!("no scene node {}", index)))
+  }
+
+  // Draws the scene into a `width` x `height` pixel viewport.
+  fn draw_nodes(&mut self, width: u32, height: u32, time: f32) {
+    let mut visible = self.visible_nodes();
+    let before = visible.len();
+    visible.retain(|&(index, world)| {
+      let Some(drawable) = self.nodes[index].as_ref().map(|node| &node.drawable) else {
+        return false;
+      };
+      let corners = drawable.bounds().map(|corner| world.transform_point(&corner));
+      let margin = drawable.margin();
+      let outside = |coordinate: fn(&Vec2) -> f32, limit: f32| {
+        corners.iter().all(|corner| coordinate(corner) < -margin)
+          || corners.iter().all(|corner| coordinate(corner) > limit + margin)
+      };
+      !outside(|corner| corner.x, width as f32) && !outside(|corner| corner.y, height as f32)
+    });
+    self.culled = before - visible.len();
+
+    let mut current: Option<BatchKey> = None;
+    for (index, world) in visible {
+      let Some(node) = self.nodes[index].take() else {
+        continue;
+      };
+      let key = node.drawable.batch_key();
+      if let Some(previous) = current.as_ref().filter(|&previous| *previous != key) {
+        self.flush(previous, width, height, time);
+      }
+      self.push(&node.drawable, &world);
+      self.nodes[index] = Some(node);
+      current = Some(key);
+    }
+    if let Some(key) = current {
+      self.flush(&key, width, height, time);
+    }
+  }
+
+  // The visible nodes with something to draw and their world transforms,
+  // in drawing order.
+  fn visible_nodes(&self) -> Vec<(usize, Mat3)> {
+    let mut visible = Vec::new();
+    for &root in &self.roots {
+      self.collect(root, &Mat3::identity(), &mut visible);
+    }
+    // A stable sort, so ties stay in tree order.
+    visible.sort_by(|&(a, _), &(b, _)| {
+      let z = |index: usize| self.nodes[index].as_ref().map_or(0.0, |node| node.z);
+      z(a).total_cmp(&z(b))
+    });
+    visible
+  }
+
+  fn pick(&mut self, x: f32, y: f32) -> Result<Option<usize>> {
+    let (x, y) = match self.viewport {
+      Some(viewport) if !viewport.contains(x, y) => return Ok(None),
+      Some(viewport) => (x - viewport.x as f32, y - viewport.y as f32),
+      None => (x, y),
+    };
+    let mut picker = match self.picker.take() {
+      Some(picker) => picker,
+      None => PickPass::new(&self.context)?,
+    };
+    let picked = self.pick_with(&mut picker, x, y);
+    self.picker = Some(picker);
+    picked
+  }
+
+  fn pick_with(&self, picker: &mut PickPass, x: f32, y: f32) -> Result<Option<usize>> {
+    picker.begin(x, y)?;
+    // The texture the pending sprites sample.
+    let mut texture: Option<&WebGlTexture> = None;
+    for (index, world) in self.visible_nodes() {
+      let Some(node) = self.nodes[index].as_ref().filter(|node| node.pickable) else {
+        continue;
+      };
+      let vertex = |picker: &mut PickPass, x: f32, y: f32, uv: Vec2, kind: f32| {
+        picker.push(world.transform_point(&Vec2::new(x, y)), uv, kind, index);
+      };
+      match &node.drawable {
+        Drawable::Group => {}
+        Drawable::Mesh { vertices, .. } => {
+          for point in vertices.chunks(2) {
+            vertex(picker, point[0], point[1], Vec2::default(), picking::SOLID);
+          }
+        }
+        Drawable::Sprite { texture: sprite_texture, width, height, .. } => {
+          if texture.is_some_and(|texture| texture != sprite_texture) {
+            picker.flush(texture);
+          }
+          texture = Some(sprite_texture);
+          let (half_width, half_height) = (width / 2.0, height / 2.0);
+          for (x, y) in quad_corners(half_width, half_height) {
+            let uv = Vec2::new(0.5 + x / (2.0 * half_width), 0.5 - y / (2.0 * half_height));
+            vertex(picker, x, y, uv, picking::TEXTURED);
+          }
+        }
+        Drawable::Dots { positions, diameter, .. } => {
+          let radius = diameter / 2.0;
+          for point in positions.chunks(2) {
+            let center = world.transform_point(&Vec2::new(point[0], point[1]));
+            for (x, y) in quad_corners(radius, radius) {
+              picker.push(Vec2::new(center.x + x, center.y + y), Vec2::new(x / radius, y / radius), picking::DISC, index);
+            }
+          }
+        }
+        Drawable::Grating(grating) => {
+          if let Some(placed) = place_grating(grating, &world) {
+            for (x, y) in quad_corners(placed.radius, placed.radius) {
+              let uv = Vec2::new(x / placed.radius, y / placed.radius);
+              picker.push(Vec2::new(placed.x + x, placed.y + y), uv, picking::DISC, index);
+            }
+          }
+        }
+      }
+    }
+    picker.finish(texture)
+  }
+
+  // Appends the visible nodes below `index` that have something to draw,
+  // with their world transforms, in tree order.
+  fn collect(&self, index: usize, parent: &Mat3, visible: &mut Vec<(usize, Mat3)>) {
+    let Some(node) = self.nodes[index].as_ref().filter(|node| node.visible) else {
+      return;
+    };
+    let world = parent.multiply(&node.local_transform());
+    if !matches!(node.drawable, Drawable::Group) {
+      visible.push((index, world));
+    }
+    for &child in &node.children {
+      self.collect(child, &world, visible);
+    }
+  }
+
+  // Adds a drawable, transformed by `world`, to the pending batch.
+  fn push(&mut self, drawable: &Drawable, world: &Mat3) {
+    match drawable {
+      Drawable::Group => {}
+      Drawable::Mesh { vertices, color } => {
+        let [red, green, blue, alpha] = *color;
+        for point in vertices.chunks(2) {
+          let position = world.transform_point(&Vec2::new(point[0], point[1]));
+          self.batch.push(&[position.x, position.y, 0.0, 0.0, red, green, blue, alpha]);
+        }
+      }
+      Drawable::Sprite { width, height, tint, .. } => {
+        let [red, green, blue, alpha] = *tint;
+        let (half_width, half_height) = (width / 2.0, height / 2.0);
+        // Screen y runs down, texture v up.
+        let corner = |x: f32, y: f32, u: f32, v: f32| {
+          let position = world.transform_point(&Vec2::new(x, y));
+          [position.x, position.y, u, v, red, green, blue, alpha]
+        };
+        let top_left = corner(-half_width, -half_height, 0.0, 1.0);
+        let top_right = corner(half_width, -half_height, 1.0, 1.0);
+        let bottom_right = corner(half_width, half_height, 1.0, 0.0);
+        let bottom_left = corner(-half_width, half_height, 0.0, 0.0);
+        for vertex in [top_left, top_right, bottom_right, top_left, bottom_right, bottom_left] {
+          self.batch.push(&vertex);
+        }
+      }
+      Drawable::Grating(grating) => {
+        let Some(placed) = place_grating(grating, world) else {
+          return;
+        };
+        let none = Grating {
+          contrast: 0.0,
+          ..placed
+        };
+        self.gratings.push(&placed, &none, PlaidBlend::Coherent);
+      }
+      Drawable::Dots { positions, diameter, color } => {
+        for point in positions.chunks(2) {
+          let position = world.transform_point(&Vec2::new(point[0], point[1]));
+          self.dots.push(position.x, position.y, *diameter, *color);
+        }
+      }
+    }
+  }
+
+  fn flush(&mut self, key: &BatchKey, width: u32, height: u32, time: f32) {
+    match key {
+      BatchKey::Gratings => self.gratings.draw(width, height, time),
+      BatchKey::Dots => {
+        self.dots.draw(width, height);
+        self.dots.clear();
+      }
+      BatchKey::Untextured | BatchKey::Textured(_) => {
+        let context = &self.context;
+        self.program.set_vec2("u_resolution", width as f32, height as f32);
+        self.program.set_i32("u_textured", matches!(key, BatchKey::Textured(_)) as i32);
+        if let BatchKey::Textured(texture) = key {
+          gl_state::bind_texture(context, 0, texture);
+        }
+        gl_state::set_blend_mode(context, Some(self.blend_mode));
+        self.batch.draw(WebGl2RenderingContext::TRIANGLES);
+        self.batch.clear();
+      }
+    }
+  }
+}
+
+impl Node {
+  fn local_transform(&self) -> Mat3 {
+    Mat3::translation(self.x, self.y)
+      .multiply(&Mat3::rotation(self.rotation.to_degrees()))
+      .multiply(&Mat3::scaling(self.scale_x, self.scale_y))
+  }
+}
+
+impl Drawable {
+  // Corners of a box around everything drawn, in the node's coordinates.
+  fn bounds(&self) -> [Vec2; 4] {
+    let (min_x, min_y, max_x, max_y) = match self {
+      Drawable::Group => (0.0, 0.0, 0.0, 0.0),
+      Drawable::Mesh { vertices, .. } => extent(vertices),
+      Drawable::Sprite { width, height, .. } => (-width / 2.0, -height / 2.0, width / 2.0, height / 2.0),
+      Drawable::Grating(grating) => (
+        grating.x - grating.radius,
+        grating.y - grating.radius,
+        grating.x + grating.radius,
+        grating.y + grating.radius,
+      ),
+      // Without their size, which does not scale, see `margin`.
+      Drawable::Dots { positions, .. } => extent(positions),
+    };
+    [
+      Vec2::new(min_x, min_y),
+      Vec2::new(max_x, min_y),
+      Vec2::new(max_x, max_y),
+      Vec2::new(min_x, max_y),
+    ]
+  }
+
+  // Pixels drawn beyond the transformed bounds.
+  fn margin(&self) -> f32 {
+    match self {
+      Drawable::Dots { diameter, .. } => diameter / 2.0 + 0.5,
+      _ => 0.0,
+    }
+  }
+
+  fn batch_key(&self) -> BatchKey {
+    match self {
+      Drawable::Sprite { texture, .. } => BatchKey::Textured(texture.clone()),
+      Drawable::Grating(_) => BatchKey::Gratings,
+      Drawable::Dots { .. } => BatchKey::Dots,
+      Drawable::Group | Drawable::Mesh { .. } => BatchKey::Untextured,
+    }
+  }
+}
+
+impl Overlay for SceneState {
+  fn draw(&mut self, width: u32, height: u32, time: f32) {
+    if self.viewport.is_none() && self.background.is_none() {
+      self.draw_nodes(width, height, time);
+      return;
+    }
+    let region = self.viewport.unwrap_or(Viewport::new(0, 0, width, height));
+    let context = self.context.clone();
+    let outer = gl_state::scissor(&context);
+    let [x, y, region_width, region_height] = region.gl_rect(height);
+    context.viewport(x, y, region_width, region_height);
+    gl_state::set_scissor(&context, Some(viewport::intersect(region.gl_rect(height), outer)));
+    if let Some([red, green, blue, alpha]) = self.background {
+      context.clear_color(red, green, blue, alpha);
+      context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+    }
+    self.draw_nodes(region.width, region.height, time);
+    context.viewport(0, 0, width as i32, height as i32);
+    gl_state::set_scissor(&context, outer);
+  }
+
+  // Sprites' textures are gone with the old context, see `add_sprite`.
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)?;
+    self.gratings.restore(context)?;
+    self.dots.restore(context)?;
+    match &mut self.picker {
+      Some(picker) => picker.restore(context),
+      None => Ok(()),
+    }
+  }
+}
+
+// `grating` in drawing-buffer pixels, none if `world` shrinks it away.
+fn place_grating(grating: &Grating, world: &Mat3) -> Option<Grating> {
+  let m = world.as_slice();
+  // Gratings stay round, so a non-uniform scale is averaged out.
+  let scale = (m[0] * m[4] - m[1] * m[3]).abs().sqrt();
+  if scale <= f32::EPSILON {
+    return None;
+  }
+  let center = world.transform_point(&Vec2::new(grating.x, grating.y));
+  Some(Grating {
+    x: center.x,
+    y: center.y,
+    radius: grating.radius * scale,
+    frequency: grating.frequency / scale,
+    orientation: grating.orientation + m[1].atan2(m[0]),
+    sigma: grating.sigma * scale,
+    ramp: grating.ramp * scale,
+    ..*grating
+  })
+}
+
+// Two triangles covering the box from -`half_width`, -`half_height` to
+// `half_width`, `half_height`.
+fn quad_corners(half_width: f32, half_height: f32) -> [(f32, f32); 6] {
+  let (left, top, right, bottom) = (-half_width, -half_height, half_width, half_height);
+  [(left, top), (right, top), (right, bottom), (left, top), (right, bottom), (left, bottom)]
+}
+
+fn check_dot_positions(positions: &[f32]) -> Result<()> {
+  if positions.is_empty() || !positions.len().is_multiple_of(2) {
+    return Err(GestaltError::InvalidArgument(format!(
+      "dot positions are x, y pairs, got {} floats",
+      positions.len()
+    )));
+  }
+  Ok(())
+}
+
+// The smallest and largest x and y of a flat list of x, y pairs.
+fn extent(points: &[f32]) -> (f32, f32, f32, f32) {
+  points.chunks(2).fold(
+    (f32::INFINITY, f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+    |(min_x, min_y, max_x, max_y), point| {
+      (min_x.min(point[0]), min_y.min(point[1]), max_x.max(point[0]), max_y.max(point[1]))
+    },
+  )
+}
@@ -0,0 +1,96 @@
+// Signed distance fields for scalable glyphs and shapes.
+
+// Stands in for an infinite squared distance; large enough for any bitmap,
+// small enough to keep the arithmetic below finite.
+const FAR: f32 = 1e20;
+
+// Turns an 8-bit coverage bitmap into a distance field of the same size.
+// Each output byte encodes the distance from the pixel centre to the shape's
+// edge, 128 on the edge and rising inside: 0.5 + distance / (2 * spread),
+// scaled to 0..255 and clamped, so `spread` pixels either side are covered.
+pub(crate) fn signed_distance_field(coverage: &[u8], width: usize, height: usize, spread: f32) -> Vec<u8> {
+  let inside: Vec<bool> = coverage.iter().map(|&value| value >= 128).collect();
+
+  // Squared distances to the nearest pixel of the other kind.
+  let mut to_inside: Vec<f32> = inside.iter().map(|&inside| if inside { 0.0 } else { FAR }).collect();
+  let mut to_outside: Vec<f32> = inside.iter().map(|&inside| if inside { FAR } else { 0.0 }).collect();
+  distance_transform(&mut to_inside, width, height);
+  distance_transform(&mut to_outside, width, height);
+
+  inside
+    .iter()
+    .zip(to_inside.iter().zip(&to_outside))
+    .map(|(&inside, (&to_inside, &to_outside))| {
+      // The edge runs half a pixel from the centres on either side of it.
+      let distance = if inside { to_outside.sqrt() - 0.5 } else { 0.5 - to_inside.sqrt() };
+      let value = 0.5 + distance / (2.0 * spread);
+      (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    })
+    .collect()
+}
+
+// Exact squared Euclidean distance transform in place, after Felzenszwalb
+// and Huttenlocher: one pass over the columns, then one over the rows.
+fn distance_transform(grid: &mut [f32], width: usize, height: usize) {
+  let longest = width.max(height);
+  let mut line = vec![0.0; longest];
+  let mut out = vec![0.0; longest];
+  let mut vertices = vec![0; longest];
+  let mut bounds = vec![0.0; longest + 1];
+
+  for x in 0..width {
+    for y in 0..height {
+      line[y] = grid[y * width + x];
+    }
+    transform_line(&line[..height], &mut out[..height], &mut vertices, &mut bounds);
+    for y in 0..height {
+      grid[y * width + x] = out[y];
+    }
+  }
+
+  for y in 0..height {
+    let row = &mut grid[y * width..(y + 1) * width];
+    line[..width].copy_from_slice(row);
+    transform_line(&line[..width], row, &mut vertices, &mut bounds);
+  }
+}
+
+// The lower envelope of the parabolas rooted at each sample of `f`.
+fn transform_line(f: &[f32], out: &mut [f32], vertices: &mut [usize], bounds: &mut [f32]) {
+  if f.is_empty() {
+    return;
+  }
+
+  let mut k = 0;
+  vertices[0] = 0;
+  bounds[0] = f32::NEG_INFINITY;
+  bounds[1] = f32::INFINITY;
+
+  // `bounds[0]` is minus infinity, so `k` never drops below zero.
+  let intersection = |q: usize, v: usize| {
+    let (qf, vf) = (q as f32, v as f32);
+    ((f[q] + qf * qf) - (f[v] + vf * vf)) / (2.0 * qf - 2.0 * vf)
+  };
+  for q in 1..f.len() {
+    let mut s = intersection(q, vertices[k]);
+    while s <= bounds[k] {
+      k -= 1;
+      s = intersection(q, vertices[k]);
+    }
+    k += 1;
+    vertices[k] = q;
+    bounds[k] = s;
+    bounds[k + 1] = f32::INFINITY;
+  }
+
+  k = 0;
+  for (q, value) in out.iter_mut().enumerate() {
+    let qf = q as f32;
+    while bounds[k + 1] < qf {
+      k += 1;
+    }
+    let v = vertices[k];
+    let offset = qf - v as f32;
+    *value = offset * offset + f[v];
+  }
+}
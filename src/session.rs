@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::ser::Error as _;
+use serde::{Serialize, Serializer};
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+use crate::experiment::{Runner, TrialRecord, TrialRunner};
+use crate::response::{RecorderState, ResponseRecord, ResponseRecorder};
+use crate::results::download_text;
+
+// A JS value as the JSON `JSON.stringify` makes of it, null for values it
+// has none for, such as `undefined`.
+pub(crate) struct JsJson<'a>(pub(crate) &'a JsValue);
+
+impl Serialize for JsJson<'_> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    let json = js_sys::JSON::stringify(self.0).map_err(|_| S::Error::custom("value has no JSON form"))?;
+    match json.as_string() {
+      Some(json) => serde_json::from_str::<serde_json::Value>(&json).map_err(S::Error::custom)?.serialize(serializer),
+      None => serializer.serialize_none(),
+    }
+  }
+}
+
+// The setup a session ran on.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Metadata {
+  // ISO 8601, UTC.
+  created: String,
+  user_agent: String,
+  language: Option<String>,
+  // In CSS pixels.
+  screen_width: i32,
+  screen_height: i32,
+  device_pixel_ratio: f64,
+  // In Hz, as given to the trial runner or estimated by it.
+  refresh_rate: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionData<'a> {
+  metadata: Metadata,
+  info: JsJson<'a>,
+  trials: &'a [TrialRecord],
+  responses: &'a [ResponseRecord],
+  dropped_frames: u32,
+}
+
+// Everything recorded in a session, exported as one JSON document for
+// analysis pipelines: metadata on the browser and display, free-form info
+// such as a participant id, and the records of a `TrialRunner` and a
+// `ResponseRecorder`, with the same fields as their `results()` and
+// `records()`.
+#[wasm_bindgen]
+pub struct Session {
+  created: String,
+  info: JsValue,
+  runner: Option<Rc<RefCell<Runner>>>,
+  recorder: Option<Rc<RefCell<RecorderState>>>,
+}
+
+#[wasm_bindgen]
+impl Session {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Session {
+    Session {
+      created: String::from(js_sys::Date::new_0().to_iso_string()),
+      info: JsValue::NULL,
+      runner: None,
+      recorder: None,
+    }
+  }
+
+  // Anything `JSON.stringify` takes, written out as is.
+  pub fn set_info(&mut self, info: JsValue) {
+    self.info = info;
+  }
+
+  pub fn set_trial_runner(&mut self, runner: &TrialRunner) {
+    self.runner = Some(runner.shared());
+  }
+
+  pub fn set_response_recorder(&mut self, recorder: &ResponseRecorder) {
+    self.recorder = Some(recorder.shared());
+  }
+
+  // `{ metadata, info, trials, responses, droppedFrames }`, the metadata
+  // being `{ created, userAgent, language, screenWidth, screenHeight,
+  // devicePixelRatio, refreshRate }`.
+  pub fn session_to_json(&self) -> Result<String> {
+    let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+    let navigator = window.navigator();
+    let screen = window.screen()?;
+    let runner = self.runner.as_ref().map(|runner| runner.borrow());
+    let recorder = self.recorder.as_ref().map(|recorder| recorder.borrow());
+
+    let data = SessionData {
+      metadata: Metadata {
+        created: self.created.clone(),
+        user_agent: navigator.user_agent()?,
+        language: navigator.language(),
+        screen_width: screen.width()?,
+        screen_height: screen.height()?,
+        device_pixel_ratio: window.device_pixel_ratio(),
+        refresh_rate: runner.as_ref().and_then(|runner| runner.frame_interval()).map(|interval| 1000.0 / interval),
+      },
+      info: JsJson(&self.info),
+      trials: runner.as_ref().map_or(&[], |runner| runner.records()),
+      responses: recorder.as_ref().map_or(&[], |recorder| recorder.records()),
+      dropped_frames: runner.as_ref().map_or(0, |runner| runner.dropped_frames()),
+    };
+    serde_json::to_string(&data)
+      .map_err(|error| GestaltError::InvalidArgument(format!("session data has no JSON form: {}", error)))
+  }
+
+  // Saves the JSON as `filename` through the browser's download.
+  pub fn download(&self, filename: &str) -> Result<()> {
+    download_text(&self.session_to_json()?, filename, "application/json")
+  }
+}
+
+impl Default for Session {
+  fn default() -> Session {
+    Session::new()
+  }
+}
@@ -0,0 +1,719 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlShader, WebGlUniformLocation};
+
+use crate::error::{GestaltError, Result};
+use crate::gl_state;
+use crate::resources;
+
+// Attribute location the `position` input is bound to in every program, so a
+// single `Geometry` can be drawn with any of them.
+pub(crate) const POSITION_LOCATION: u32 = 0;
+
+// The `mat4` uniform 3D vertex shaders transform `position` by, set with
+// `WebGlCanvas::set_model_view_projection`.
+pub(crate) const MODEL_VIEW_PROJECTION: &str = "u_model_view_projection";
+
+// Vertex shader for `Geometry::fullscreen_quad`, passing texture coordinates
+// running from (0, 0) at the bottom left to (1, 1) at the top right.
+pub(crate) const FULLSCREEN_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 position;
+
+out vec2 v_uv;
+
+void main()
+{
+  v_uv = position * 0.5 + 0.5;
+  gl_Position = vec4(position, 0.0, 1.0);
+}
+"##;
+
+// The last value set for a uniform, re-applied when the program is relinked.
+#[derive(Clone, Debug, PartialEq)]
+enum UniformValue {
+  F32(f32),
+  I32(i32),
+  Vec2(f32, f32),
+  Vec3(f32, f32, f32),
+  Vec4(f32, f32, f32, f32),
+  Vec3Array(Vec<f32>),
+  Vec4Array(Vec<f32>),
+  Mat3(Vec<f32>),
+  Mat4(Vec<f32>),
+}
+
+impl UniformValue {
+  // What the uniform holds before it is first set.
+  fn zero(&self) -> UniformValue {
+    match self {
+      UniformValue::F32(_) => UniformValue::F32(0.0),
+      UniformValue::I32(_) => UniformValue::I32(0),
+      UniformValue::Vec2(..) => UniformValue::Vec2(0.0, 0.0),
+      UniformValue::Vec3(..) => UniformValue::Vec3(0.0, 0.0, 0.0),
+      UniformValue::Vec4(..) => UniformValue::Vec4(0.0, 0.0, 0.0, 0.0),
+      UniformValue::Vec3Array(values) => UniformValue::Vec3Array(vec![0.0; values.len()]),
+      UniformValue::Vec4Array(values) => UniformValue::Vec4Array(vec![0.0; values.len()]),
+      UniformValue::Mat3(matrix) => UniformValue::Mat3(vec![0.0; matrix.len()]),
+      UniformValue::Mat4(matrix) => UniformValue::Mat4(vec![0.0; matrix.len()]),
+    }
+  }
+}
+
+thread_local! {
+  static NEXT_PROGRAM_ID: Cell<u64> = const { Cell::new(1) };
+}
+
+// A linked program, shared through `resources` by every `ShaderProgram`
+// built from the same sources on one context. Uniforms live in the program,
+// so it knows which `ShaderProgram` set them last and to what.
+pub(crate) struct LinkedProgram {
+  context: WebGl2RenderingContext,
+  program: WebGlProgram,
+  // Id of the `ShaderProgram` whose values the uniforms hold, 0 for none.
+  user: Cell<u64>,
+  // Every uniform set so far, with the value it holds.
+  uniforms: RefCell<HashMap<String, UniformValue>>,
+}
+
+impl LinkedProgram {
+  fn link(context: &WebGl2RenderingContext, vert_src: &str, frag_src: &str, attributes: &[(String, u32)]) -> Result<LinkedProgram> {
+    let vert_shader = compile_shader(context, WebGl2RenderingContext::VERTEX_SHADER, vert_src)?;
+    let frag_shader = match compile_shader(context, WebGl2RenderingContext::FRAGMENT_SHADER, frag_src) {
+      Ok(shader) => shader,
+      Err(error) => {
+        context.delete_shader(Some(&vert_shader));
+        return Err(error);
+      }
+    };
+    let program = link_program(context, &vert_shader, &frag_shader, attributes);
+    // A linked program keeps what it needs of its shaders.
+    context.delete_shader(Some(&vert_shader));
+    context.delete_shader(Some(&frag_shader));
+
+    Ok(LinkedProgram {
+      context: context.clone(),
+      program: program?,
+      user: Cell::new(0),
+      uniforms: RefCell::new(HashMap::new()),
+    })
+  }
+}
+
+impl Drop for LinkedProgram {
+  fn drop(&mut self) {
+    self.context.delete_program(Some(&self.program));
+  }
+}
+
+// A linked vertex + fragment shader pair, with the uniform setters used all
+// over the crate. Sources, attribute locations and uniform values are kept,
+// so the program can be rebuilt after a context loss or relinked with a new
+// fragment shader without the caller noticing. Programs with the same
+// sources on one context share their GL program, each seeing only the
+// uniform values it set itself.
+pub(crate) struct ShaderProgram {
+  context: WebGl2RenderingContext,
+  id: u64,
+  vert_src: String,
+  frag_src: String,
+  linked: Rc<LinkedProgram>,
+  // Locations of the active attributes, including those of earlier
+  // programs, bound explicitly on relinking.
+  attributes: RefCell<Vec<(String, u32)>>,
+  // Locations of the active uniforms, filled in whenever the program is
+  // linked so that setting a uniform never has to ask the GL. Array
+  // elements other than the first are looked up on first use, misses
+  // included.
+  uniforms: HashMap<String, Option<WebGlUniformLocation>>,
+  values: HashMap<String, UniformValue>,
+}
+
+impl ShaderProgram {
+  pub(crate) fn new(context: &WebGl2RenderingContext, vert_src: &str, frag_src: &str) -> Result<ShaderProgram> {
+    let linked = resources::program(context, vert_src, frag_src, &[], || {
+      LinkedProgram::link(context, vert_src, frag_src, &[])
+    })?;
+
+    let mut shader_program = ShaderProgram {
+      context: context.clone(),
+      id: NEXT_PROGRAM_ID.with(|id| id.replace(id.get() + 1)),
+      vert_src: vert_src.to_string(),
+      frag_src: frag_src.to_string(),
+      linked,
+      attributes: RefCell::new(Vec::new()),
+      uniforms: HashMap::new(),
+      values: HashMap::new(),
+    };
+    shader_program.cache_locations();
+    Ok(shader_program)
+  }
+
+  // Recompiles from the kept sources, for use on a restored context.
+  pub(crate) fn restore(&mut self) -> Result<()> {
+    self.linked = self.link(&self.frag_src)?;
+    self.reapply_uniforms();
+    Ok(())
+  }
+
+  // Compiles `frag_src` and relinks it with the current vertex shader. Only
+  // if both succeed is the new program swapped in, with all attribute
+  // locations and uniform values carried over; otherwise the old one stays.
+  pub(crate) fn replace_fragment_shader(&mut self, frag_src: &str) -> Result<()> {
+    self.linked = self.link(frag_src)?;
+    self.frag_src = frag_src.to_string();
+    self.reapply_uniforms();
+    Ok(())
+  }
+
+  // Deletes the GL program now unless others share it, rather than when the
+  // last of them is dropped. This program cannot be used afterwards.
+  pub(crate) fn delete(&self) {
+    if Rc::strong_count(&self.linked) == 1 {
+      resources::remove_program(&self.context, &self.linked);
+      self.context.delete_program(Some(&self.linked.program));
+    }
+  }
+
+  // Makes the program current, with this program's uniform values.
+  pub(crate) fn use_program(&self) {
+    gl_state::use_program(&self.context, &self.linked.program);
+    if self.linked.user.replace(self.id) != self.id {
+      self.apply_values();
+    }
+  }
+
+  // The program for the vertex shader and `frag_src` with the attribute
+  // locations bound so far, shared if it already exists.
+  fn link(&self, frag_src: &str) -> Result<Rc<LinkedProgram>> {
+    let attributes = self.attributes.borrow().clone();
+    resources::program(&self.context, &self.vert_src, frag_src, &attributes, || {
+      LinkedProgram::link(&self.context, &self.vert_src, frag_src, &attributes)
+    })
+  }
+
+  pub(crate) fn has_attribute(&self, name: &str) -> bool {
+    self.attribute_location(name).is_some()
+  }
+
+  pub(crate) fn attribute_location(&self, name: &str) -> Option<u32> {
+    self.attributes.borrow().iter().find(|(known, _)| known == name).map(|(_, location)| *location)
+  }
+
+  // Active uniforms of the linked program, i.e. those the compiler kept.
+  // Arrays are listed once, as `name[0]`, with their length as `size`.
+  pub(crate) fn active_uniforms(&self) -> Vec<ActiveVariable> {
+    let context = &self.context;
+    let count = context
+      .get_program_parameter(&self.linked.program, WebGl2RenderingContext::ACTIVE_UNIFORMS)
+      .as_f64()
+      .unwrap_or(0.0) as u32;
+
+    (0..count)
+      .filter_map(|index| context.get_active_uniform(&self.linked.program, index))
+      .map(|info| ActiveVariable {
+        location: context
+          .get_uniform_location(&self.linked.program, &info.name())
+          .map_or(JsValue::NULL, JsValue::from),
+        name: info.name(),
+        gl_type: info.type_(),
+        size: info.size(),
+      })
+      .collect()
+  }
+
+  // Active vertex attributes of the linked program, with their locations.
+  pub(crate) fn active_attributes(&self) -> Vec<ActiveVariable> {
+    let context = &self.context;
+    let count = context
+      .get_program_parameter(&self.linked.program, WebGl2RenderingContext::ACTIVE_ATTRIBUTES)
+      .as_f64()
+      .unwrap_or(0.0) as u32;
+
+    (0..count)
+      .filter_map(|index| context.get_active_attrib(&self.linked.program, index))
+      .map(|info| ActiveVariable {
+        location: context.get_attrib_location(&self.linked.program, &info.name()).into(),
+        name: info.name(),
+        gl_type: info.type_(),
+        size: info.size(),
+      })
+      .collect()
+  }
+
+  // The setters make the program current, so they can be called at any time.
+  // Names the program does not use are ignored, like in plain WebGL.
+
+  pub(crate) fn set_f32(&mut self, name: &str, value: f32) {
+    self.set(name, UniformValue::F32(value));
+  }
+
+  pub(crate) fn set_i32(&mut self, name: &str, value: i32) {
+    self.set(name, UniformValue::I32(value));
+  }
+
+  pub(crate) fn set_vec2(&mut self, name: &str, x: f32, y: f32) {
+    self.set(name, UniformValue::Vec2(x, y));
+  }
+
+  pub(crate) fn set_vec3(&mut self, name: &str, x: f32, y: f32, z: f32) {
+    self.set(name, UniformValue::Vec3(x, y, z));
+  }
+
+  pub(crate) fn set_vec4(&mut self, name: &str, x: f32, y: f32, z: f32, w: f32) {
+    self.set(name, UniformValue::Vec4(x, y, z, w));
+  }
+
+  // Sets a `vec3` array uniform from consecutive groups of three floats.
+  pub(crate) fn set_vec3_array(&mut self, name: &str, values: &[f32]) {
+    self.set(name, UniformValue::Vec3Array(values.to_vec()));
+  }
+
+  // Sets a `vec4` array uniform from consecutive groups of four floats.
+  pub(crate) fn set_vec4_array(&mut self, name: &str, values: &[f32]) {
+    self.set(name, UniformValue::Vec4Array(values.to_vec()));
+  }
+
+  pub(crate) fn set_mat3(&mut self, name: &str, matrix: &[f32]) -> Result<()> {
+    if matrix.len() != 9 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "mat3 uniform `{}` needs 9 values, got {}",
+        name,
+        matrix.len()
+      )));
+    }
+    self.set(name, UniformValue::Mat3(matrix.to_vec()));
+    Ok(())
+  }
+
+  pub(crate) fn set_mat4(&mut self, name: &str, matrix: &[f32]) -> Result<()> {
+    if matrix.len() != 16 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "mat4 uniform `{}` needs 16 values, got {}",
+        name,
+        matrix.len()
+      )));
+    }
+    self.set(name, UniformValue::Mat4(matrix.to_vec()));
+    Ok(())
+  }
+
+  fn set(&mut self, name: &str, value: UniformValue) {
+    self.use_program();
+    let location = self.uniform_location(name);
+    {
+      let mut applied = self.linked.uniforms.borrow_mut();
+      if applied.get(name) != Some(&value) {
+        apply_uniform(&self.context, location.as_ref(), &value);
+        applied.insert(name.to_string(), value.clone());
+      }
+    }
+
+    match self.values.get_mut(name) {
+      Some(stored) => *stored = value,
+      None => {
+        self.values.insert(name.to_string(), value);
+      }
+    }
+  }
+
+  // Looks up every active uniform and attribute of the linked program.
+  fn cache_locations(&mut self) {
+    self.uniforms.clear();
+    for uniform in self.active_uniforms() {
+      let location = uniform.location.dyn_into::<WebGlUniformLocation>().ok();
+      // Arrays are set by their plain name, as in WebGL.
+      if let Some(name) = uniform.name.strip_suffix("[0]") {
+        self.uniforms.insert(name.to_string(), location.clone());
+      }
+      self.uniforms.insert(uniform.name, location);
+    }
+    for attribute in self.active_attributes() {
+      let Some(location) = attribute.location.as_f64().filter(|&location| location >= 0.0) else {
+        continue;
+      };
+      let mut attributes = self.attributes.borrow_mut();
+      if !attributes.iter().any(|(known, _)| *known == attribute.name) {
+        attributes.push((attribute.name, location as u32));
+      }
+    }
+  }
+
+  fn reapply_uniforms(&mut self) {
+    self.cache_locations();
+    let names: Vec<String> = self.values.keys().cloned().collect();
+    for name in names {
+      self.uniform_location(&name);
+    }
+    self.linked.user.set(0);
+    self.use_program();
+  }
+
+  // Sets the uniforms of the shared program to this program's values, and
+  // those it never set back to zero.
+  fn apply_values(&self) {
+    let mut applied = self.linked.uniforms.borrow_mut();
+    for (name, current) in applied.iter_mut() {
+      let wanted = self.values.get(name).cloned().unwrap_or_else(|| current.zero());
+      if *current != wanted {
+        apply_uniform(&self.context, self.cached_location(name).as_ref(), &wanted);
+        *current = wanted;
+      }
+    }
+    for (name, value) in &self.values {
+      if !applied.contains_key(name) {
+        apply_uniform(&self.context, self.cached_location(name).as_ref(), value);
+        applied.insert(name.clone(), value.clone());
+      }
+    }
+  }
+
+  // Like `uniform_location` without filling in the cache.
+  fn cached_location(&self, name: &str) -> Option<WebGlUniformLocation> {
+    if let Some(location) = self.uniforms.get(name) {
+      return location.clone();
+    }
+    if !name.contains('[') {
+      return None;
+    }
+    self.context.get_uniform_location(&self.linked.program, name)
+  }
+
+  fn uniform_location(&mut self, name: &str) -> Option<WebGlUniformLocation> {
+    if let Some(location) = self.uniforms.get(name) {
+      return location.clone();
+    }
+    // Not active, unless it is an element of an active array.
+    if !name.contains('[') {
+      return None;
+    }
+    let location = self.context.get_uniform_location(&self.linked.program, name);
+    self.uniforms.insert(name.to_string(), location.clone());
+    location
+  }
+}
+
+// A uniform or attribute as reported by `getActiveUniform` /
+// `getActiveAttrib`. `location` is a `WebGLUniformLocation` for uniforms and
+// a number for attributes.
+pub(crate) struct ActiveVariable {
+  name: String,
+  gl_type: u32,
+  size: i32,
+  location: JsValue,
+}
+
+impl ActiveVariable {
+  pub(crate) fn to_js(&self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"name".into(), &self.name.as_str().into());
+    let _ = js_sys::Reflect::set(&object, &"type".into(), &glsl_type_name(self.gl_type).into());
+    let _ = js_sys::Reflect::set(&object, &"glType".into(), &self.gl_type.into());
+    let _ = js_sys::Reflect::set(&object, &"size".into(), &self.size.into());
+    let _ = js_sys::Reflect::set(&object, &"location".into(), &self.location);
+    object.into()
+  }
+}
+
+// The GLSL spelling of a type enum from `getActiveUniform`.
+fn glsl_type_name(gl_type: u32) -> &'static str {
+  match gl_type {
+    WebGl2RenderingContext::FLOAT => "float",
+    WebGl2RenderingContext::FLOAT_VEC2 => "vec2",
+    WebGl2RenderingContext::FLOAT_VEC3 => "vec3",
+    WebGl2RenderingContext::FLOAT_VEC4 => "vec4",
+    WebGl2RenderingContext::INT => "int",
+    WebGl2RenderingContext::INT_VEC2 => "ivec2",
+    WebGl2RenderingContext::INT_VEC3 => "ivec3",
+    WebGl2RenderingContext::INT_VEC4 => "ivec4",
+    WebGl2RenderingContext::UNSIGNED_INT => "uint",
+    WebGl2RenderingContext::UNSIGNED_INT_VEC2 => "uvec2",
+    WebGl2RenderingContext::UNSIGNED_INT_VEC3 => "uvec3",
+    WebGl2RenderingContext::UNSIGNED_INT_VEC4 => "uvec4",
+    WebGl2RenderingContext::BOOL => "bool",
+    WebGl2RenderingContext::BOOL_VEC2 => "bvec2",
+    WebGl2RenderingContext::BOOL_VEC3 => "bvec3",
+    WebGl2RenderingContext::BOOL_VEC4 => "bvec4",
+    WebGl2RenderingContext::FLOAT_MAT2 => "mat2",
+    WebGl2RenderingContext::FLOAT_MAT3 => "mat3",
+    WebGl2RenderingContext::FLOAT_MAT4 => "mat4",
+    WebGl2RenderingContext::FLOAT_MAT2X3 => "mat2x3",
+    WebGl2RenderingContext::FLOAT_MAT2X4 => "mat2x4",
+    WebGl2RenderingContext::FLOAT_MAT3X2 => "mat3x2",
+    WebGl2RenderingContext::FLOAT_MAT3X4 => "mat3x4",
+    WebGl2RenderingContext::FLOAT_MAT4X2 => "mat4x2",
+    WebGl2RenderingContext::FLOAT_MAT4X3 => "mat4x3",
+    WebGl2RenderingContext::SAMPLER_2D => "sampler2D",
+    WebGl2RenderingContext::SAMPLER_3D => "sampler3D",
+    WebGl2RenderingContext::SAMPLER_CUBE => "samplerCube",
+    WebGl2RenderingContext::SAMPLER_2D_SHADOW => "sampler2DShadow",
+    WebGl2RenderingContext::SAMPLER_2D_ARRAY => "sampler2DArray",
+    WebGl2RenderingContext::SAMPLER_2D_ARRAY_SHADOW => "sampler2DArrayShadow",
+    WebGl2RenderingContext::SAMPLER_CUBE_SHADOW => "samplerCubeShadow",
+    WebGl2RenderingContext::INT_SAMPLER_2D => "isampler2D",
+    WebGl2RenderingContext::INT_SAMPLER_3D => "isampler3D",
+    WebGl2RenderingContext::INT_SAMPLER_CUBE => "isamplerCube",
+    WebGl2RenderingContext::INT_SAMPLER_2D_ARRAY => "isampler2DArray",
+    WebGl2RenderingContext::UNSIGNED_INT_SAMPLER_2D => "usampler2D",
+    WebGl2RenderingContext::UNSIGNED_INT_SAMPLER_3D => "usampler3D",
+    WebGl2RenderingContext::UNSIGNED_INT_SAMPLER_CUBE => "usamplerCube",
+    WebGl2RenderingContext::UNSIGNED_INT_SAMPLER_2D_ARRAY => "usampler2DArray",
+    _ => "unknown",
+  }
+}
+
+fn apply_uniform(context: &WebGl2RenderingContext, location: Option<&WebGlUniformLocation>, value: &UniformValue) {
+  match value {
+    UniformValue::F32(x) => context.uniform1f(location, *x),
+    UniformValue::I32(x) => context.uniform1i(location, *x),
+    UniformValue::Vec2(x, y) => context.uniform2f(location, *x, *y),
+    UniformValue::Vec3(x, y, z) => context.uniform3f(location, *x, *y, *z),
+    UniformValue::Vec4(x, y, z, w) => context.uniform4f(location, *x, *y, *z, *w),
+    UniformValue::Vec3Array(values) => context.uniform3fv_with_f32_array(location, values),
+    UniformValue::Vec4Array(values) => context.uniform4fv_with_f32_array(location, values),
+    UniformValue::Mat3(matrix) => context.uniform_matrix3fv_with_f32_array(location, false, matrix),
+    UniformValue::Mat4(matrix) => context.uniform_matrix4fv_with_f32_array(location, false, matrix),
+  }
+}
+
+fn compile_shader(
+    context: &WebGl2RenderingContext,
+    shader_type: u32,
+    source: &str,
+) -> Result<WebGlShader> {
+  let shader = context
+    .create_shader(shader_type)
+    .ok_or(GestaltError::ResourceCreation("shader object"))?;
+  context.shader_source(&shader, source);
+  context.compile_shader(&shader);
+
+  if context
+    .get_shader_parameter(&shader, WebGl2RenderingContext::COMPILE_STATUS)
+    .as_bool()
+    .unwrap_or(false)
+  {
+    Ok(shader)
+  } else {
+    let stage = if shader_type == WebGl2RenderingContext::VERTEX_SHADER { "vertex" } else { "fragment" };
+    let log = context
+      .get_shader_info_log(&shader)
+      .unwrap_or_else(|| String::from("Unknown error creating shader"));
+    context.delete_shader(Some(&shader));
+    Err(GestaltError::ShaderCompile {
+      stage,
+      diagnostics: parse_info_log(&log),
+      log,
+    })
+  }
+}
+
+// One message from a shader info log. `line` is 1-based and refers to the
+// user's source; either may be missing when the driver does not say.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderDiagnostic {
+  pub severity: &'static str,
+  pub line: Option<u32>,
+  pub column: Option<u32>,
+  pub message: String,
+}
+
+impl ShaderDiagnostic {
+  pub(crate) fn to_js(&self) -> JsValue {
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"severity".into(), &self.severity.into());
+    let _ = js_sys::Reflect::set(&object, &"line".into(), &self.line.map_or(JsValue::NULL, JsValue::from));
+    let _ = js_sys::Reflect::set(&object, &"column".into(), &self.column.map_or(JsValue::NULL, JsValue::from));
+    let _ = js_sys::Reflect::set(&object, &"message".into(), &self.message.as_str().into());
+    object.into()
+  }
+}
+
+// Splits an info log into diagnostics. Understands the ANGLE / Firefox
+// format `ERROR: 0:12: message`, Mesa's `0:12(5): error: message` and
+// NVIDIA's `0(12) : error C0000: message`. Lines in any other format are
+// kept as diagnostics without a location.
+pub(crate) fn parse_info_log(log: &str) -> Vec<ShaderDiagnostic> {
+  log
+    .lines()
+    .map(|line| line.trim_matches(|c: char| c.is_whitespace() || c == '\0'))
+    .filter(|line| !line.is_empty())
+    .map(parse_info_log_line)
+    .collect()
+}
+
+fn parse_info_log_line(line: &str) -> ShaderDiagnostic {
+  let (mut severity, rest) = strip_severity(line).unwrap_or(("error", line));
+  let (line, column, mut message) = match parse_location(rest) {
+    Some((line, column, message)) => (Some(line), column, message),
+    None => (None, None, rest),
+  };
+
+  // Mesa and NVIDIA put the severity after the location.
+  if let Some((inner_severity, inner_message)) = strip_severity(message) {
+    severity = inner_severity;
+    message = inner_message;
+  }
+
+  ShaderDiagnostic {
+    severity,
+    line,
+    column,
+    message: message.to_string(),
+  }
+}
+
+// Strips `ERROR:`, `warning:` or NVIDIA's `error C1008:` off the front.
+fn strip_severity(text: &str) -> Option<(&'static str, &str)> {
+  let lower = text.to_ascii_lowercase();
+  let (severity, len) = if lower.starts_with("error") {
+    ("error", "error".len())
+  } else if lower.starts_with("warning") {
+    ("warning", "warning".len())
+  } else {
+    return None;
+  };
+
+  let mut rest = &text[len..];
+  if let Some(code) = rest.strip_prefix(" C") {
+    rest = code.trim_start_matches(|c: char| c.is_ascii_digit());
+  }
+  Some((severity, rest.strip_prefix(':')?.trim_start()))
+}
+
+// Parses `<string>:<line>:` or `<string>:<line>(<column>):` or
+// `<string>(<line>) :`, returning the line, column and what follows.
+fn parse_location(text: &str) -> Option<(u32, Option<u32>, &str)> {
+  let digits = |text: &str| text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+
+  let string_len = digits(text);
+  if string_len == 0 {
+    return None;
+  }
+  let rest = &text[string_len..];
+
+  if let Some(rest) = rest.strip_prefix('(') {
+    let line_len = digits(rest);
+    let line = rest[..line_len].parse().ok()?;
+    let rest = rest[line_len..].strip_prefix(')')?.trim_start().strip_prefix(':')?;
+    return Some((line, None, rest.trim_start()));
+  }
+
+  let rest = rest.strip_prefix(':')?;
+  let line_len = digits(rest);
+  let line = rest[..line_len].parse().ok()?;
+  let mut rest = &rest[line_len..];
+  let mut column = None;
+  if let Some(after) = rest.strip_prefix('(') {
+    let column_len = digits(after);
+    column = Some(after[..column_len].parse().ok()?);
+    rest = after[column_len..].strip_prefix(')')?;
+  }
+  let rest = rest.strip_prefix(':')?;
+  Some((line, column, rest.trim_start()))
+}
+
+// Links the shaders into a new program, leaving it in use. `position` and
+// the given `attributes` are bound to fixed locations.
+fn link_program(
+  context: &WebGl2RenderingContext,
+  vert_shader: &WebGlShader,
+  frag_shader: &WebGlShader,
+  attributes: &[(String, u32)],
+) -> Result<WebGlProgram> {
+  let program = context
+    .create_program()
+    .ok_or(GestaltError::ResourceCreation("program object"))?;
+
+  context.attach_shader(&program, vert_shader);
+  context.attach_shader(&program, frag_shader);
+  context.bind_attrib_location(&program, POSITION_LOCATION, "position");
+  for (name, location) in attributes {
+    context.bind_attrib_location(&program, *location, name);
+  }
+  context.link_program(&program);
+
+  if context
+    .get_program_parameter(&program, WebGl2RenderingContext::LINK_STATUS)
+    .as_bool()
+    .unwrap_or(false)
+  {
+    gl_state::use_program(context, &program);
+    Ok(program)
+  } else {
+    let log = context
+      .get_program_info_log(&program)
+      .unwrap_or_else(|| String::from("Unknown error creating program object"));
+    context.delete_program(Some(&program));
+    Err(GestaltError::ProgramLink(log))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_info_log, ShaderDiagnostic};
+
+  fn diagnostic(severity: &'static str, line: Option<u32>, column: Option<u32>, message: &str) -> ShaderDiagnostic {
+    ShaderDiagnostic {
+      severity,
+      line,
+      column,
+      message: message.to_string(),
+    }
+  }
+
+  #[test]
+  fn angle_errors_and_warnings() {
+    let log = "ERROR: 0:12: 'foo' : undeclared identifier\nWARNING: 0:3: 'bar' : unused\n";
+    assert_eq!(
+      parse_info_log(log),
+      vec![
+        diagnostic("error", Some(12), None, "'foo' : undeclared identifier"),
+        diagnostic("warning", Some(3), None, "'bar' : unused"),
+      ]
+    );
+  }
+
+  #[test]
+  fn mesa_locations_with_columns() {
+    assert_eq!(
+      parse_info_log("0:7(15): error: syntax error, unexpected '}'"),
+      vec![diagnostic("error", Some(7), Some(15), "syntax error, unexpected '}'")]
+    );
+    assert_eq!(
+      parse_info_log("0:2(1): warning: extension not supported"),
+      vec![diagnostic("warning", Some(2), Some(1), "extension not supported")]
+    );
+  }
+
+  #[test]
+  fn nvidia_locations_and_codes() {
+    assert_eq!(
+      parse_info_log("0(24) : error C1008: undefined variable \"x\""),
+      vec![diagnostic("error", Some(24), None, "undefined variable \"x\"")]
+    );
+  }
+
+  #[test]
+  fn lines_without_a_location() {
+    assert_eq!(
+      parse_info_log("ERROR: 2 compilation errors.  No code generated.\nsomething unexpected"),
+      vec![
+        diagnostic("error", None, None, "2 compilation errors.  No code generated."),
+        diagnostic("error", None, None, "something unexpected"),
+      ]
+    );
+  }
+
+  #[test]
+  fn blank_lines_and_nul_padding_are_skipped() {
+    assert_eq!(
+      parse_info_log("\n  ERROR: 0:1: bad\0\n\n\0"),
+      vec![diagnostic("error", Some(1), None, "bad")]
+    );
+    assert!(parse_info_log("").is_empty());
+  }
+}
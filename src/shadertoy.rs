@@ -0,0 +1,119 @@
+use crate::input::Mouse;
+use crate::shader::ShaderProgram;
+
+// Declarations Shadertoy provides implicitly, prepended to pasted shaders.
+const SHADERTOY_PREFIX: &str = r##"#version 300 es
+precision highp float;
+precision highp int;
+
+uniform vec3 iResolution;
+uniform float iTime;
+uniform float iTimeDelta;
+uniform int iFrame;
+uniform vec4 iMouse;
+uniform vec4 iDate;
+uniform vec3 iChannelResolution[4];
+uniform sampler2D iChannel0;
+uniform sampler2D iChannel1;
+uniform sampler2D iChannel2;
+uniform sampler2D iChannel3;
+
+out vec4 outColor;
+
+"##;
+
+const SHADERTOY_SUFFIX: &str = r##"
+
+void main()
+{
+  mainImage(outColor, gl_FragCoord.xy);
+}
+"##;
+
+// Turns a Shadertoy `mainImage` shader into a complete GLSL ES 3.00 one.
+pub(crate) fn wrap_source(src: &str) -> String {
+  let mut wrapped = String::with_capacity(SHADERTOY_PREFIX.len() + src.len() + SHADERTOY_SUFFIX.len());
+  wrapped.push_str(SHADERTOY_PREFIX);
+  // Line numbers in compile errors point one line past the prefix.
+  wrapped.push_str("#line 1\n");
+  wrapped.push_str(src);
+  wrapped.push_str(SHADERTOY_SUFFIX);
+  wrapped
+}
+
+// Per-frame state behind the Shadertoy uniforms.
+pub(crate) struct Shadertoy {
+  mouse: Mouse,
+  frame: i32,
+  last_time: Option<f32>,
+  // iMouse: current position while pressed, and where the press started.
+  drag: (f32, f32),
+  click: (f32, f32),
+  was_down: bool,
+  channel_resolutions: [f32; 12],
+}
+
+impl Shadertoy {
+  pub(crate) fn new(mouse: Mouse) -> Shadertoy {
+    Shadertoy {
+      mouse,
+      frame: 0,
+      last_time: None,
+      drag: (0.0, 0.0),
+      click: (0.0, 0.0),
+      was_down: false,
+      channel_resolutions: [0.0; 12],
+    }
+  }
+
+  pub(crate) fn set_channel_resolution(&mut self, channel: usize, width: u32, height: u32) {
+    self.channel_resolutions[channel * 3..channel * 3 + 3].copy_from_slice(&[width as f32, height as f32, 1.0]);
+  }
+
+  // `time` in milliseconds as passed to `render`; sizes in pixels.
+  pub(crate) fn update(&mut self, program: &mut ShaderProgram, time: f32, width: u32, height: u32) {
+    let seconds = time / 1000.0;
+    let delta = self.last_time.map(|last| seconds - last).unwrap_or(0.0);
+    self.last_time = Some(seconds);
+
+    let (width, height) = (width as f32, height as f32);
+    let down = self.mouse.buttons() & 1 != 0;
+    if down {
+      self.drag = (self.mouse.x() * width, self.mouse.y() * height);
+      if !self.was_down {
+        self.click = self.drag;
+      }
+    }
+
+    // Shadertoy's convention: z and w are the click position, z negative once
+    // released, w negative after the first pressed frame.
+    let click_x = if down { self.click.0 } else { -self.click.0 };
+    let click_y = if down && !self.was_down { self.click.1 } else { -self.click.1 };
+    self.was_down = down;
+
+    let date = js_sys::Date::new_0();
+    let day_seconds = date.get_hours() as f32 * 3600.0
+      + date.get_minutes() as f32 * 60.0
+      + date.get_seconds() as f32
+      + date.get_milliseconds() as f32 / 1000.0;
+
+    program.set_vec3("iResolution", width, height, 1.0);
+    program.set_f32("iTime", seconds);
+    program.set_f32("iTimeDelta", delta);
+    program.set_i32("iFrame", self.frame);
+    program.set_vec4("iMouse", self.drag.0, self.drag.1, click_x, click_y);
+    program.set_vec4(
+      "iDate",
+      date.get_full_year() as f32,
+      date.get_month() as f32,
+      date.get_date() as f32,
+      day_seconds,
+    );
+    program.set_vec3_array("iChannelResolution", &self.channel_resolutions);
+    for channel in 0..4 {
+      program.set_i32(&format!("iChannel{}", channel), channel);
+    }
+
+    self.frame += 1;
+  }
+}
@@ -0,0 +1,231 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::{WebGl2RenderingContext, WebGlTexture};
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::texture::Texture;
+
+const SPRITE_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in vec2 size;
+in float rotation;
+in vec4 uv_rect;
+in vec4 tint;
+
+uniform vec2 u_resolution;
+
+out vec2 v_uv;
+out vec4 v_tint;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+  vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+);
+
+void main()
+{
+  vec2 corner = CORNERS[gl_VertexID];
+  float c = cos(rotation);
+  float s = sin(rotation);
+  vec2 offset = corner * size;
+  vec2 position = center + vec2(c * offset.x - s * offset.y, s * offset.x + c * offset.y);
+
+  // Screen y runs down, texture v up.
+  v_uv = uv_rect.xy + vec2(corner.x + 0.5, 0.5 - corner.y) * uv_rect.zw;
+  v_tint = tint;
+
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+const SPRITE_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_texture;
+
+in vec2 v_uv;
+in vec4 v_tint;
+
+out vec4 outColor;
+
+void main()
+{
+  outColor = texture(u_texture, v_uv) * v_tint;
+}
+"##;
+
+// Floats per sprite passed to `SpriteBatch::draw_sprites`.
+const PACKED_SPRITE_LEN: usize = 5;
+
+// Draws textured quads from one texture, typically an atlas, on top of a
+// canvas' scene, all in a single instanced draw call. Coordinates and
+// immediate-mode behaviour are as in `Draw2D`. Sprites are positioned by
+// their centre and rotated clockwise by `rotation` radians; the texture's
+// colours are multiplied by the current tint.
+#[wasm_bindgen]
+pub struct SpriteBatch {
+  sprites: Rc<RefCell<Sprites>>,
+  layer: LayerSlot,
+}
+
+struct Sprites {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  texture: WebGlTexture,
+  tint: [f32; 4],
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl SpriteBatch {
+
+  pub fn new(canvas: &WebGlCanvas, texture: &Texture) -> Result<SpriteBatch> {
+    let context = canvas.context();
+    texture.check_context(&context)?;
+    let mut program = ShaderProgram::new(&context, SPRITE_VERT_SHADER, SPRITE_FRAG_SHADER)?;
+    let layout = attribute_layout(
+      &program,
+      &[("center", 2), ("size", 2), ("rotation", 1), ("uv_rect", 4), ("tint", 4)],
+    )?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_i32("u_texture", 0);
+
+    let sprites = Rc::new(RefCell::new(Sprites {
+      context,
+      program,
+      batch,
+      texture: texture.raw().clone(),
+      tint: [1.0, 1.0, 1.0, 1.0],
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(sprites.clone(), Layer::Stimulus);
+    Ok(SpriteBatch { sprites, layer })
+  }
+
+  // Layer the sprites are drawn in. Starts out as `Stimulus`.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // `Alpha` by default. `Premultiplied` suits images whose colours were
+  // multiplied by their alpha before upload.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.sprites.borrow_mut().blend_mode = mode;
+  }
+
+  // Switches to another texture. Sprites already drawn this frame use it too.
+  pub fn set_texture(&self, texture: &Texture) -> Result<()> {
+    let mut sprites = self.sprites.borrow_mut();
+    texture.check_context(&sprites.context)?;
+    sprites.texture = texture.raw().clone();
+    Ok(())
+  }
+
+  // Colour multiplied with the texture, opaque white to begin with.
+  pub fn set_tint(&self, r: f32, g: f32, b: f32, a: f32) {
+    self.sprites.borrow_mut().tint = [r, g, b, a];
+  }
+
+  pub fn set_tint_color(&self, color: &Color) {
+    self.sprites.borrow_mut().tint = color.to_array();
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.sprites.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.sprites.borrow_mut().batch.clear();
+  }
+
+  // Draws the whole texture as a `width` x `height` pixel sprite.
+  pub fn draw(&self, x: f32, y: f32, width: f32, height: f32, rotation: f32) {
+    self.sprites.borrow_mut().push(x, y, width, height, rotation, [0.0, 0.0, 1.0, 1.0]);
+  }
+
+  // Draws the part of the texture from `(u, v)`, its bottom left corner in
+  // texture coordinates, spanning `uv_width` x `uv_height`.
+  #[allow(clippy::too_many_arguments)]
+  pub fn draw_region(
+    &self,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    rotation: f32,
+    u: f32,
+    v: f32,
+    uv_width: f32,
+    uv_height: f32,
+  ) {
+    self.sprites.borrow_mut().push(x, y, width, height, rotation, [u, v, uv_width, uv_height]);
+  }
+
+  // Draws many whole-texture sprites at once from a flat list of
+  // `x, y, width, height, rotation` groups, for dot and symbol displays.
+  pub fn draw_sprites(&self, sprites: &[f32]) -> Result<()> {
+    if !sprites.len().is_multiple_of(PACKED_SPRITE_LEN) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "sprites take {} floats each, got {}",
+        PACKED_SPRITE_LEN,
+        sprites.len()
+      )));
+    }
+
+    let mut batch = self.sprites.borrow_mut();
+    for sprite in sprites.chunks(PACKED_SPRITE_LEN) {
+      batch.push(sprite[0], sprite[1], sprite[2], sprite[3], sprite[4], [0.0, 0.0, 1.0, 1.0]);
+    }
+    Ok(())
+  }
+}
+
+impl Sprites {
+  fn push(&mut self, x: f32, y: f32, width: f32, height: f32, rotation: f32, uv_rect: [f32; 4]) {
+    let [red, green, blue, alpha] = self.tint;
+    self.batch.push(&[
+      x, y,
+      width, height,
+      rotation,
+      uv_rect[0], uv_rect[1], uv_rect[2], uv_rect[3],
+      red, green, blue, alpha,
+    ]);
+  }
+}
+
+impl Overlay for Sprites {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      gl_state::bind_texture(context, 0, &self.texture);
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  // The texture itself is gone with the old context and has to be set again.
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
@@ -0,0 +1,341 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+
+// How steps change the intensity: `Linear` adds them, `Log` adds them to
+// the intensity's base 10 logarithm, so a step of 0.1 is a factor of about
+// 1.26.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepScale {
+  Linear,
+  Log,
+}
+
+pub(crate) struct StaircaseState {
+  start: f32,
+  intensity: f32,
+  // Consecutive wrong and right responses before stepping up and down.
+  up: u32,
+  down: u32,
+  up_steps: Vec<f32>,
+  down_steps: Vec<f32>,
+  scale: StepScale,
+  min: f32,
+  max: f32,
+  max_reversals: u32,
+  max_trials: u32,
+  wrong_run: u32,
+  right_run: u32,
+  // -1 after a step down, 1 after a step up, 0 before the first step.
+  direction: i32,
+  intensities: Vec<f32>,
+  reversals: Vec<f32>,
+}
+
+impl StaircaseState {
+  pub(crate) fn intensity(&self) -> f32 {
+    self.intensity
+  }
+
+  pub(crate) fn is_finished(&self) -> bool {
+    (self.max_reversals > 0 && self.reversals.len() as u32 >= self.max_reversals)
+      || (self.max_trials > 0 && self.intensities.len() as u32 >= self.max_trials)
+  }
+
+  pub(crate) fn update(&mut self, correct: bool) {
+    if self.is_finished() {
+      return;
+    }
+    self.intensities.push(self.intensity);
+
+    let direction = if correct {
+      self.wrong_run = 0;
+      self.right_run += 1;
+      if self.right_run < self.down {
+        return;
+      }
+      self.right_run = 0;
+      -1
+    } else {
+      self.right_run = 0;
+      self.wrong_run += 1;
+      if self.wrong_run < self.up {
+        return;
+      }
+      self.wrong_run = 0;
+      1
+    };
+
+    if self.direction != 0 && direction != self.direction {
+      self.reversals.push(self.intensity);
+    }
+    self.direction = direction;
+
+    // Sizes move on with every reversal, the last one staying.
+    let steps = if direction > 0 { &self.up_steps } else { &self.down_steps };
+    let step = steps[self.reversals.len().min(steps.len() - 1)] * direction as f32;
+    let intensity = match self.scale {
+      StepScale::Linear => self.intensity + step,
+      StepScale::Log => self.intensity * 10f32.powf(step),
+    };
+    self.intensity = intensity.clamp(self.min, self.max);
+  }
+}
+
+// An adaptive up/down staircase. After `up` wrong responses in a row the
+// intensity steps up, after `down` right ones it steps down: 1-up/1-down
+// converges on 50% correct, 1-up/2-down on 70.7% and 1-up/3-down on 79.4%.
+// Unequal up and down steps give weighted staircases. It ends after a
+// number of reversals or trials, whichever comes first; run it by hand
+// with `update`, or with `TrialRunner::run_staircase`.
+#[wasm_bindgen]
+pub struct Staircase {
+  state: Rc<RefCell<StaircaseState>>,
+}
+
+#[wasm_bindgen]
+impl Staircase {
+
+  // Starts at `start` with linear steps of `step` both ways, ending after
+  // 10 reversals.
+  #[wasm_bindgen(constructor)]
+  pub fn new(start: f32, up: u32, down: u32, step: f32) -> Result<Staircase> {
+    if up == 0 || down == 0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a staircase needs at least one response per step, got {}-up/{}-down",
+        up, down
+      )));
+    }
+    Ok(Staircase {
+      state: Rc::new(RefCell::new(StaircaseState {
+        start,
+        intensity: start,
+        up,
+        down,
+        up_steps: vec![step],
+        down_steps: vec![step],
+        scale: StepScale::Linear,
+        min: f32::NEG_INFINITY,
+        max: f32::INFINITY,
+        max_reversals: 10,
+        max_trials: 0,
+        wrong_run: 0,
+        right_run: 0,
+        direction: 0,
+        intensities: Vec::new(),
+        reversals: Vec::new(),
+      })),
+    })
+  }
+
+  // Step sizes up and down, by number of reversals so far: the first
+  // applies before the first reversal, the second after it and so on, the
+  // last one staying. Sizes are in intensity units, or log units with
+  // `StepScale::Log`.
+  pub fn set_steps(&self, up: &[f32], down: &[f32]) -> Result<()> {
+    if up.is_empty() || down.is_empty() {
+      return Err(GestaltError::InvalidArgument("staircase steps must not be empty".into()));
+    }
+    let mut state = self.state.borrow_mut();
+    state.up_steps = up.to_vec();
+    state.down_steps = down.to_vec();
+    Ok(())
+  }
+
+  pub fn set_scale(&self, scale: StepScale) {
+    self.state.borrow_mut().scale = scale;
+  }
+
+  // Limits the intensity, which is unbounded to begin with.
+  pub fn set_bounds(&self, min: f32, max: f32) -> Result<()> {
+    if min.is_nan() || max.is_nan() || min > max {
+      return Err(GestaltError::InvalidArgument(format!("invalid staircase bounds {} to {}", min, max)));
+    }
+    let mut state = self.state.borrow_mut();
+    state.min = min;
+    state.max = max;
+    state.intensity = state.intensity.clamp(min, max);
+    Ok(())
+  }
+
+  // Ends the staircase after `reversals` reversals or `trials` trials; 0
+  // removes a limit.
+  pub fn set_termination(&self, reversals: u32, trials: u32) -> Result<()> {
+    if reversals == 0 && trials == 0 {
+      return Err(GestaltError::InvalidArgument("a staircase needs a reversal or trial limit".into()));
+    }
+    let mut state = self.state.borrow_mut();
+    state.max_reversals = reversals;
+    state.max_trials = trials;
+    Ok(())
+  }
+
+  // The intensity for the next trial.
+  pub fn intensity(&self) -> f32 {
+    self.state.borrow().intensity
+  }
+
+  // Records the response to a trial at the current intensity and steps as
+  // the rule says. Ignored once finished.
+  pub fn update(&self, correct: bool) {
+    self.state.borrow_mut().update(correct);
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.state.borrow().is_finished()
+  }
+
+  pub fn trial_count(&self) -> usize {
+    self.state.borrow().intensities.len()
+  }
+
+  // The intensity of every trial so far.
+  pub fn intensities(&self) -> Vec<f32> {
+    self.state.borrow().intensities.clone()
+  }
+
+  // The intensities at which the direction reversed.
+  pub fn reversals(&self) -> Vec<f32> {
+    self.state.borrow().reversals.clone()
+  }
+
+  // The mean of the reversal intensities after the first `discard`, the
+  // geometric mean on a log scale; NaN without any.
+  pub fn threshold(&self, discard: usize) -> f32 {
+    let state = self.state.borrow();
+    let reversals = state.reversals.get(discard..).unwrap_or(&[]);
+    let count = reversals.len() as f32;
+    match state.scale {
+      StepScale::Linear => reversals.iter().sum::<f32>() / count,
+      StepScale::Log => 10f32.powf(reversals.iter().map(|value| value.log10()).sum::<f32>() / count),
+    }
+  }
+
+  // Back to the start, keeping the settings.
+  pub fn reset(&self) {
+    let mut state = self.state.borrow_mut();
+    state.intensity = state.start.clamp(state.min, state.max);
+    state.wrong_run = 0;
+    state.right_run = 0;
+    state.direction = 0;
+    state.intensities.clear();
+    state.reversals.clear();
+  }
+}
+
+impl Staircase {
+  pub(crate) fn shared(&self) -> Rc<RefCell<StaircaseState>> {
+    self.state.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Staircase, StepScale};
+
+  fn run(staircase: &Staircase, responses: &str) {
+    for response in responses.chars() {
+      staircase.update(response == 'R');
+    }
+  }
+
+  #[test]
+  fn one_up_two_down() {
+    let staircase = Staircase::new(10.0, 1, 2, 2.0).unwrap();
+    run(&staircase, "RRRRWWRR");
+    assert_eq!(staircase.intensities(), vec![10.0, 10.0, 8.0, 8.0, 6.0, 8.0, 10.0, 10.0]);
+    assert_eq!(staircase.reversals(), vec![6.0, 10.0]);
+    assert_eq!(staircase.intensity(), 8.0);
+    assert_eq!(staircase.threshold(0), 8.0);
+    assert_eq!(staircase.threshold(1), 10.0);
+  }
+
+  #[test]
+  fn a_wrong_response_resets_the_run_of_right_ones() {
+    let staircase = Staircase::new(10.0, 1, 3, 1.0).unwrap();
+    run(&staircase, "RRW");
+    assert_eq!(staircase.intensity(), 11.0);
+    assert!(staircase.reversals().is_empty());
+    run(&staircase, "RRR");
+    assert_eq!(staircase.intensity(), 10.0);
+    assert_eq!(staircase.reversals(), vec![11.0]);
+  }
+
+  #[test]
+  fn steps_move_on_with_reversals() {
+    let staircase = Staircase::new(20.0, 1, 1, 4.0).unwrap();
+    staircase.set_steps(&[4.0, 2.0, 1.0], &[4.0, 2.0, 1.0]).unwrap();
+    run(&staircase, "RWRRW");
+    assert_eq!(staircase.intensities(), vec![20.0, 16.0, 18.0, 17.0, 16.0]);
+    assert_eq!(staircase.reversals(), vec![16.0, 18.0, 16.0]);
+    assert_eq!(staircase.intensity(), 17.0);
+  }
+
+  #[test]
+  fn log_steps_and_geometric_threshold() {
+    let staircase = Staircase::new(100.0, 1, 1, 1.0).unwrap();
+    staircase.set_scale(StepScale::Log);
+    run(&staircase, "RWR");
+    assert_eq!(staircase.reversals(), vec![10.0, 100.0]);
+    assert!((staircase.intensity() - 10.0).abs() < 1e-4);
+    assert!((staircase.threshold(0) - 10f32.powf(1.5)).abs() < 1e-3);
+  }
+
+  #[test]
+  fn bounds_clamp_the_intensity() {
+    let staircase = Staircase::new(3.0, 1, 1, 2.0).unwrap();
+    staircase.set_bounds(0.0, 4.0).unwrap();
+    run(&staircase, "RR");
+    assert_eq!(staircase.intensity(), 0.0);
+    run(&staircase, "WW");
+    assert_eq!(staircase.intensity(), 4.0);
+  }
+
+  #[test]
+  fn ends_after_the_reversal_limit() {
+    let staircase = Staircase::new(10.0, 1, 1, 1.0).unwrap();
+    staircase.set_termination(2, 0).unwrap();
+    run(&staircase, "RWR");
+    assert!(staircase.is_finished());
+    run(&staircase, "RRRR");
+    assert_eq!(staircase.trial_count(), 3);
+    assert_eq!(staircase.intensity(), 9.0);
+  }
+
+  #[test]
+  fn ends_after_the_trial_limit() {
+    let staircase = Staircase::new(10.0, 1, 1, 1.0).unwrap();
+    staircase.set_termination(0, 4).unwrap();
+    run(&staircase, "RRRRRR");
+    assert!(staircase.is_finished());
+    assert_eq!(staircase.trial_count(), 4);
+  }
+
+  #[test]
+  fn no_threshold_without_reversals() {
+    let staircase = Staircase::new(10.0, 1, 1, 1.0).unwrap();
+    run(&staircase, "RRR");
+    assert!(staircase.threshold(0).is_nan());
+  }
+
+  #[test]
+  fn reset_keeps_the_settings() {
+    let staircase = Staircase::new(10.0, 1, 1, 1.0).unwrap();
+    staircase.set_bounds(0.0, 8.0).unwrap();
+    run(&staircase, "RWR");
+    staircase.reset();
+    assert_eq!(staircase.intensity(), 8.0);
+    assert_eq!(staircase.trial_count(), 0);
+    assert!(staircase.reversals().is_empty());
+  }
+
+  #[test]
+  fn rejects_zero_responses_per_step() {
+    assert!(Staircase::new(10.0, 0, 1, 1.0).is_err());
+    assert!(Staircase::new(10.0, 1, 0, 1.0).is_err());
+  }
+}
@@ -0,0 +1,230 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::draw2d::Draw2D;
+use crate::error::Result;
+use crate::experiment::FrameMonitor;
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::text::TextRenderer;
+
+thread_local! {
+  // Draw calls made since the stats overlay last looked.
+  static DRAW_CALLS: Cell<u32> = const { Cell::new(0) };
+}
+
+// Called by everything that issues a draw call.
+pub(crate) fn count_draw_call() {
+  DRAW_CALLS.with(|calls| calls.set(calls.get() + 1));
+}
+
+// Frame intervals kept for the frame rate and histogram.
+const HISTORY: usize = 240;
+// Histogram bins, each `BIN_WIDTH` milliseconds wide; the last also takes
+// everything longer.
+const BINS: usize = 25;
+const BIN_WIDTH: f64 = 2.0;
+
+// Panel layout in CSS pixels, scaled by `devicePixelRatio`.
+const PANEL_WIDTH: f32 = 200.0;
+const PANEL_HEIGHT: f32 = 116.0;
+const PADDING: f32 = 6.0;
+const FONT_SIZE: f32 = 12.0;
+const LINE_HEIGHT: f32 = 15.0;
+const CHART_HEIGHT: f32 = 48.0;
+
+struct Stats {
+  clock: Rc<Cell<f64>>,
+  monitor: FrameMonitor,
+  last: Option<f64>,
+  intervals: VecDeque<f64>,
+  // In the last frame.
+  draw_calls: u32,
+  visible: bool,
+  // Top left corner of the panel in drawing-buffer pixels.
+  position: (f32, f32),
+  scale: f32,
+  shapes: Draw2D,
+  text: TextRenderer,
+}
+
+impl Stats {
+  fn frame_rate(&self) -> f64 {
+    let total: f64 = self.intervals.iter().sum();
+    if total > 0.0 {
+      1000.0 * self.intervals.len() as f64 / total
+    } else {
+      0.0
+    }
+  }
+
+  fn histogram(&self) -> [u32; BINS] {
+    let mut bins = [0; BINS];
+    for interval in &self.intervals {
+      bins[((interval / BIN_WIDTH) as usize).min(BINS - 1)] += 1;
+    }
+    bins
+  }
+
+  fn draw_panel(&self) -> Result<()> {
+    let (x, y) = self.position;
+    let scale = self.scale;
+    let shapes = &self.shapes;
+    shapes.set_color(0.0, 0.0, 0.0, 0.7);
+    shapes.fill_rect(x, y, PANEL_WIDTH * scale, PANEL_HEIGHT * scale);
+
+    let frame_time = self.intervals.back().copied().unwrap_or(0.0);
+    let lines = format!(
+      "{:.1} fps  {:.1} ms\ndropped {}\ndraw calls {}",
+      self.frame_rate(),
+      frame_time,
+      self.monitor.dropped(),
+      self.draw_calls
+    );
+    self.text.draw_text(&lines, x + PADDING * scale, y + PADDING * scale)?;
+
+    // Frame times from 0 to `BINS * BIN_WIDTH` ms left to right, bars
+    // scaled to the fullest bin.
+    let bins = self.histogram();
+    let fullest = bins.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let chart_left = x + PADDING * scale;
+    let chart_bottom = y + (PANEL_HEIGHT - PADDING) * scale;
+    let bar_width = (PANEL_WIDTH - 2.0 * PADDING) * scale / BINS as f32;
+    shapes.set_color(0.3, 0.9, 0.4, 1.0);
+    for (index, &count) in bins.iter().enumerate() {
+      let height = count as f32 / fullest * CHART_HEIGHT * scale;
+      shapes.fill_rect(chart_left + index as f32 * bar_width, chart_bottom - height, bar_width * 0.8, height);
+    }
+    // The refresh period, frames right of it are late.
+    if let Some(period) = self.monitor.period() {
+      let marker = chart_left + (period / BIN_WIDTH) as f32 * bar_width;
+      shapes.set_color(1.0, 0.3, 0.3, 1.0);
+      shapes.fill_rect(marker, chart_bottom - CHART_HEIGHT * scale, scale, CHART_HEIGHT * scale);
+    }
+    Ok(())
+  }
+}
+
+// Times every frame, shown or not, and lays out the panel for the next.
+impl Overlay for Stats {
+  fn draw(&mut self, _width: u32, _height: u32, _time: f32) {
+    let time = self.clock.get();
+    if let Some(last) = self.last.replace(time) {
+      self.intervals.push_back(time - last);
+      if self.intervals.len() > HISTORY {
+        self.intervals.pop_front();
+      }
+    }
+    self.monitor.tick(time);
+    // Everything since the last frame's panel: one frame's worth.
+    self.draw_calls = DRAW_CALLS.with(|calls| calls.replace(0));
+
+    self.shapes.clear();
+    self.text.clear();
+    if self.visible {
+      if let Err(error) = self.draw_panel() {
+        web_sys::console::error_1(&format!("Failed to draw frame stats: {}", error).into());
+      }
+    }
+  }
+
+  // The panel's renderers restore themselves.
+  fn restore(&mut self, _context: &WebGl2RenderingContext) -> Result<()> {
+    Ok(())
+  }
+}
+
+// A debug panel in the corner of a canvas, for piloting: frame rate and the
+// last frame's time, frames dropped, draw calls in the last frame and a
+// histogram of recent frame times with the refresh period marked. Drawn
+// inside the canvas, so it shows in fullscreen and in screenshots, and
+// under any overlays made after it. Measures even while hidden.
+#[wasm_bindgen]
+pub struct FrameStats {
+  stats: Rc<RefCell<Stats>>,
+  layer: LayerSlot,
+}
+
+#[wasm_bindgen]
+impl FrameStats {
+
+  // Hidden to begin with.
+  pub fn new(canvas: &WebGlCanvas) -> Result<FrameStats> {
+    let scale = web_sys::window().map_or(1.0, |window| window.device_pixel_ratio()) as f32;
+    // The renderers draw before this overlay, so they keep what it drew and
+    // show it a frame late.
+    let shapes = Draw2D::new(canvas)?;
+    shapes.set_auto_clear(false);
+    shapes.set_layer(Layer::Debug);
+    let text = TextRenderer::new(canvas, "monospace", FONT_SIZE * scale)?;
+    text.set_auto_clear(false);
+    text.set_layer(Layer::Debug);
+    text.set_line_spacing(LINE_HEIGHT / FONT_SIZE);
+    let stats = Rc::new(RefCell::new(Stats {
+      clock: canvas.frame_clock(),
+      monitor: FrameMonitor::default(),
+      last: None,
+      intervals: VecDeque::new(),
+      draw_calls: 0,
+      visible: false,
+      position: (PADDING * scale, PADDING * scale),
+      scale,
+      shapes,
+      text,
+    }));
+    let layer = canvas.add_overlay(stats.clone(), Layer::Debug);
+    Ok(FrameStats { stats, layer })
+  }
+
+  // Layer the stats are drawn in, `Debug` to begin with.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+    let stats = self.stats.borrow();
+    stats.shapes.set_layer(layer);
+    stats.text.set_layer(layer);
+  }
+
+  pub fn set_visible(&self, visible: bool) {
+    self.stats.borrow_mut().visible = visible;
+  }
+
+  pub fn is_visible(&self) -> bool {
+    self.stats.borrow().visible
+  }
+
+  pub fn toggle(&self) {
+    let mut stats = self.stats.borrow_mut();
+    stats.visible = !stats.visible;
+  }
+
+  // Top left corner of the panel in drawing-buffer pixels.
+  pub fn set_position(&self, x: f32, y: f32) {
+    self.stats.borrow_mut().position = (x, y);
+  }
+
+  // Over the last few seconds of frames.
+  pub fn frame_rate(&self) -> f64 {
+    self.stats.borrow().frame_rate()
+  }
+
+  pub fn dropped_frames(&self) -> u32 {
+    self.stats.borrow().monitor.dropped()
+  }
+
+  pub fn draw_calls(&self) -> u32 {
+    self.stats.borrow().draw_calls
+  }
+
+  // Forgets the frames so far.
+  pub fn reset(&self) {
+    let mut stats = self.stats.borrow_mut();
+    stats.monitor.reset();
+    stats.last = None;
+    stats.intervals.clear();
+  }
+}
@@ -0,0 +1,97 @@
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::error::{GestaltError, Result};
+use crate::gl_state;
+use crate::shader::ShaderProgram;
+
+const STENCIL_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 position;
+
+uniform vec2 u_resolution;
+
+void main()
+{
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// Only the stencil buffer is written.
+const STENCIL_FRAG_SHADER: &str = r##"#version 300 es
+precision mediump float;
+
+out vec4 outColor;
+
+void main()
+{
+  outColor = vec4(1.0);
+}
+"##;
+
+// Where a stencil mask lets drawing through.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StencilMode {
+  // Only within the mask's triangles, e.g. a non-rectangular aperture.
+  Inside,
+  // Everywhere but the mask's triangles, e.g. an occluder the stimulus
+  // passes behind.
+  Outside,
+}
+
+// A mask written into the stencil buffer at the start of every frame,
+// clipping everything drawn after it.
+pub(crate) struct StencilMask {
+  program: ShaderProgram,
+  batch: VertexBatch,
+  mode: StencilMode,
+}
+
+impl StencilMask {
+  // `vertices` are x, y pairs in drawing-buffer pixels from the top left,
+  // three to a triangle. Overlapping triangles are simply both inside.
+  pub(crate) fn new(context: &WebGl2RenderingContext, vertices: &[f32], mode: StencilMode) -> Result<StencilMask> {
+    if vertices.is_empty() || !vertices.len().is_multiple_of(6) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a stencil mask needs whole triangles of three x, y pairs, got {} floats",
+        vertices.len()
+      )));
+    }
+    let program = ShaderProgram::new(context, STENCIL_VERT_SHADER, STENCIL_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("position", 2)])?;
+    let mut batch = VertexBatch::new(context, &layout)?;
+    for point in vertices.chunks(2) {
+      batch.push(point);
+    }
+    Ok(StencilMask { program, batch, mode })
+  }
+
+  // Writes the mask into the cleared stencil buffer of the bound
+  // framebuffer and leaves stencil testing on for what follows.
+  pub(crate) fn apply(&mut self, context: &WebGl2RenderingContext, width: u32, height: u32) {
+    self.program.set_vec2("u_resolution", width as f32, height as f32);
+    gl_state::set_depth_test(context, false);
+    gl_state::set_stencil_test(context, true);
+    context.color_mask(false, false, false, false);
+    context.stencil_func(WebGl2RenderingContext::ALWAYS, 1, 0xff);
+    context.stencil_op(WebGl2RenderingContext::KEEP, WebGl2RenderingContext::KEEP, WebGl2RenderingContext::REPLACE);
+    self.batch.draw(WebGl2RenderingContext::TRIANGLES);
+
+    context.color_mask(true, true, true, true);
+    let test = match self.mode {
+      StencilMode::Inside => WebGl2RenderingContext::EQUAL,
+      StencilMode::Outside => WebGl2RenderingContext::NOTEQUAL,
+    };
+    context.stencil_func(test, 1, 0xff);
+    context.stencil_op(WebGl2RenderingContext::KEEP, WebGl2RenderingContext::KEEP, WebGl2RenderingContext::KEEP);
+  }
+
+  pub(crate) fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
@@ -0,0 +1,330 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::Result;
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::{check_positive, Space, Units};
+
+// One instance per board, in stimulus units. `extent` is the half width and
+// height of a grid or the outer and inner radius of a dartboard, `checks`
+// the check size of a grid or the ring and sector counts of a dartboard,
+// `style` 0 for a grid and 1 for a dartboard.
+const CHECKERBOARD_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in vec2 extent;
+in vec2 checks;
+in float contrast;
+in float reversal_frequency;
+in float style;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+out vec2 v_local;
+flat out vec2 v_extent;
+flat out vec2 v_checks;
+flat out float v_contrast;
+flat out float v_reversal_frequency;
+flat out int v_layout;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  v_layout = int(style + 0.5);
+  vec2 half_size = v_layout == 0 ? extent : vec2(extent.x);
+  // A pixel of room for the antialiased edge.
+  v_local = CORNERS[gl_VertexID] * (half_size + 1.0 / u_scale);
+  v_extent = extent;
+  v_checks = checks;
+  v_contrast = contrast;
+  v_reversal_frequency = reversal_frequency;
+
+  vec2 position = u_origin + (center + v_local) * u_scale;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// Modulates the background colour like the Gabor shader. The checks' signs
+// come from the product of two sines, each antialiased on its own.
+const CHECKERBOARD_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform vec4 u_background;
+uniform float u_scale;
+// Frames drawn since the renderer's clock started, and the refresh rate.
+uniform int u_frame;
+uniform float u_frame_rate;
+
+in vec2 v_local;
+flat in vec2 v_extent;
+flat in vec2 v_checks;
+flat in float v_contrast;
+flat in float v_reversal_frequency;
+flat in int v_layout;
+
+out vec4 outColor;
+
+const float PI = 3.14159265359;
+
+float square(float wave)
+{
+  return clamp(wave / max(fwidth(wave), 1e-4), -1.0, 1.0);
+}
+
+void main()
+{
+  float mask;
+  vec2 coordinates;
+  if (v_layout == 0) {
+    vec2 inside = (v_extent - abs(v_local)) * u_scale + 0.5;
+    mask = clamp(min(inside.x, inside.y), 0.0, 1.0);
+    // A check corner at the centre.
+    coordinates = v_local / v_checks.x;
+  } else {
+    float distance = length(v_local);
+    float outer = (v_extent.x - distance) * u_scale + 0.5;
+    float inner = (distance - v_extent.y) * u_scale + 0.5;
+    mask = clamp(min(outer, inner), 0.0, 1.0);
+    // Rings widen in proportion to eccentricity, or evenly from the centre.
+    float ring = v_extent.y > 0.0
+      ? log(max(distance, 1e-6) / v_extent.y) / log(v_extent.x / v_extent.y)
+      : distance / v_extent.x;
+    float angle = atan(v_local.y, v_local.x);
+    coordinates = vec2(ring * v_checks.x, angle / (2.0 * PI) * v_checks.y);
+  }
+  float checks = square(sin(PI * coordinates.x)) * square(sin(PI * coordinates.y));
+
+  // Reversals fall on whole frames.
+  int reversals = int(floor(float(u_frame) * v_reversal_frequency / u_frame_rate + 1e-3));
+  float polarity = (reversals & 1) == 0 ? 1.0 : -1.0;
+  outColor = vec4(u_background.rgb * (1.0 + v_contrast * polarity * checks), mask);
+}
+"##;
+
+// `Grid` is a rectangle of square checks; `Dartboard` an annulus divided
+// into rings and sectors, for stimulating the visual field evenly.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckerLayout {
+  Grid,
+  Dartboard,
+}
+
+// Parameters of one checkerboard. Lengths are in the renderer's units.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkerboard {
+  pub x: f32,
+  pub y: f32,
+  pub layout: CheckerLayout,
+  // Size of a grid.
+  pub width: f32,
+  pub height: f32,
+  // Side of a grid's checks, with a check corner at `x`, `y`.
+  pub check_size: f32,
+  // Outer radius of a dartboard, and inner radius of its central hole. With
+  // a hole the ring widths grow in proportion to eccentricity, without one
+  // they are even.
+  pub radius: f32,
+  pub inner_radius: f32,
+  pub rings: u32,
+  // Rounded up to an even count, so checks alternate all the way round.
+  pub sectors: u32,
+  // Michelson contrast, 0 to 1.
+  pub contrast: f32,
+  // Contrast reversals per second; 0 for a static board.
+  pub reversal_frequency: f32,
+}
+
+#[wasm_bindgen]
+impl Checkerboard {
+
+  // A static 320 unit square grid of 40 unit checks.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Checkerboard {
+    Checkerboard {
+      x: 0.0,
+      y: 0.0,
+      layout: CheckerLayout::Grid,
+      width: 320.0,
+      height: 320.0,
+      check_size: 40.0,
+      radius: 160.0,
+      inner_radius: 10.0,
+      rings: 6,
+      sectors: 16,
+      contrast: 1.0,
+      reversal_frequency: 0.0,
+    }
+  }
+}
+
+impl Default for Checkerboard {
+  fn default() -> Checkerboard {
+    Checkerboard::new()
+  }
+}
+
+// Draws contrast-reversing checkerboards on top of a canvas' scene, with the
+// same immediate-mode behaviour as `Draw2D`. Reversals are counted in frames
+// drawn since the renderer was created or `restart_clock` was called, at the
+// refresh rate given by `set_frame_rate`, so each happens on a frame
+// boundary; rates that do not divide the refresh rate alternate between the
+// nearest whole numbers of frames.
+#[wasm_bindgen]
+pub struct CheckerboardRenderer {
+  boards: Rc<RefCell<Boards>>,
+  layer: LayerSlot,
+}
+
+struct Boards {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  frame: i32,
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl CheckerboardRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<CheckerboardRenderer> {
+    let context = canvas.context();
+    let mut program = ShaderProgram::new(&context, CHECKERBOARD_VERT_SHADER, CHECKERBOARD_FRAG_SHADER)?;
+    let layout = attribute_layout(
+      &program,
+      &[
+        ("center", 2),
+        ("extent", 2),
+        ("checks", 2),
+        ("contrast", 1),
+        ("reversal_frequency", 1),
+        ("style", 1),
+      ],
+    )?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_vec4("u_background", 0.5, 0.5, 0.5, 1.0);
+    program.set_f32("u_frame_rate", 60.0);
+
+    let boards = Rc::new(RefCell::new(Boards {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      frame: 0,
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(boards.clone(), Layer::Stimulus);
+    Ok(CheckerboardRenderer { boards, layer })
+  }
+
+  // Moves the boards to another layer of the canvas; `Stimulus` by default.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // How the boards are blended over the scene; `Alpha` by default.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.boards.borrow_mut().blend_mode = mode;
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.boards.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // The mean colour of the boards, mid grey by default.
+  pub fn set_background(&self, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.boards.borrow_mut().program.set_vec4("u_background", r, g, b, a);
+  }
+
+  // The display's refresh rate in Hz, 60 to begin with.
+  pub fn set_frame_rate(&self, frame_rate: f32) -> Result<()> {
+    check_positive("frame rate", frame_rate)?;
+    self.boards.borrow_mut().program.set_f32("u_frame_rate", frame_rate);
+    Ok(())
+  }
+
+  // Makes the next frame drawn frame 0, e.g. at stimulus onset.
+  pub fn restart_clock(&self) {
+    self.boards.borrow_mut().frame = 0;
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.boards.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.boards.borrow_mut().batch.clear();
+  }
+
+  pub fn draw(&self, board: &Checkerboard) -> Result<()> {
+    let (extent, checks, layout) = match board.layout {
+      CheckerLayout::Grid => {
+        check_positive("check size", board.check_size)?;
+        ([board.width / 2.0, board.height / 2.0], [board.check_size, 0.0], 0.0)
+      }
+      CheckerLayout::Dartboard => {
+        check_positive("dartboard radius", board.radius)?;
+        let inner_radius = board.inner_radius.clamp(0.0, board.radius / 2.0);
+        let sectors = board.sectors.max(2).next_multiple_of(2);
+        ([board.radius, inner_radius], [board.rings.max(1) as f32, sectors as f32], 1.0)
+      }
+    };
+    self.boards.borrow_mut().batch.push(&[
+      board.x, board.y,
+      extent[0], extent[1],
+      checks[0], checks[1],
+      board.contrast.clamp(0.0, 1.0),
+      board.reversal_frequency.max(0.0),
+      layout,
+    ]);
+    Ok(())
+  }
+}
+
+impl Overlay for Boards {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      self.program.set_i32("u_frame", self.frame);
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+      self.frame += 1;
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
@@ -0,0 +1,205 @@
+use std::f32::consts::{PI, TAU};
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+use crate::random::Rng;
+use crate::stimuli::check_positive;
+use crate::stimuli::gabor::Gabor;
+
+// Attempts at placing the path before giving up, and background candidates
+// in a row that may fail before the field counts as full.
+const PATH_ATTEMPTS: usize = 100;
+const BACKGROUND_ATTEMPTS: usize = 1000;
+
+// A contour integration display after Field, Hayes and Hess (1993): a field
+// of randomly oriented Gabors hiding a path of elements aligned with it. The
+// path has `path_length` segments of length `spacing`, each turning by
+// `path_angle` from the one before, to the left or right at random. An
+// element sits at the middle of every segment, oriented along it give or
+// take up to `orientation_jitter`. Background elements fill the rest of the
+// field, no closer than `min_distance` to any other element, so density
+// does not give the path away when it is close to `spacing`. Lengths are in
+// the renderer's units, angles in radians.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContourField {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+  pub path_length: u32,
+  pub spacing: f32,
+  pub path_angle: f32,
+  pub orientation_jitter: f32,
+  pub min_distance: f32,
+  // Of every element, as in `Gabor`. Phases are random.
+  pub frequency: f32,
+  pub sigma: f32,
+  pub contrast: f32,
+  pub seed: u32,
+}
+
+#[wasm_bindgen]
+impl ContourField {
+
+  // A 10 element straight path in a 600 unit square field.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> ContourField {
+    ContourField {
+      x: 0.0,
+      y: 0.0,
+      width: 600.0,
+      height: 600.0,
+      path_length: 10,
+      spacing: 40.0,
+      path_angle: 0.0,
+      orientation_jitter: 0.0,
+      min_distance: 32.0,
+      frequency: 0.1,
+      sigma: 5.0,
+      contrast: 1.0,
+      seed: 0,
+    }
+  }
+
+  // Lays out a display. The same parameters and seed always give the same
+  // one.
+  pub fn generate(&self) -> Result<ContourDisplay> {
+    check_positive("contour field width", self.width)?;
+    check_positive("contour field height", self.height)?;
+    check_positive("path element spacing", self.spacing)?;
+
+    let mut rng = Rng::new(self.seed as u64);
+    let mut elements = self.path(&mut rng)?;
+    let path_count = elements.len();
+
+    // Dart throwing: a candidate is kept if it is far enough from all
+    // elements so far.
+    let mut failures = 0;
+    let min_distance = self.min_distance.max(0.0);
+    while failures < BACKGROUND_ATTEMPTS {
+      let x = self.x + rng.range(-0.5, 0.5) * self.width;
+      let y = self.y + rng.range(-0.5, 0.5) * self.height;
+      let crowded = elements
+        .iter()
+        .any(|element: &Gabor| (element.x - x).powi(2) + (element.y - y).powi(2) < min_distance * min_distance);
+      if crowded {
+        failures += 1;
+      } else {
+        failures = 0;
+        let orientation = rng.range(0.0, PI);
+        elements.push(self.element(&mut rng, x, y, orientation));
+      }
+    }
+
+    Ok(ContourDisplay { elements, path_count })
+  }
+}
+
+impl ContourField {
+  // The path elements, at a random place and heading where the path fits
+  // in the field. A path length of 0 gives a display without a path.
+  fn path(&self, rng: &mut Rng) -> Result<Vec<Gabor>> {
+    if self.path_length == 0 {
+      return Ok(Vec::new());
+    }
+    for _ in 0..PATH_ATTEMPTS {
+      let mut heading = rng.range(0.0, TAU);
+      let (mut x, mut y) = (0.0f32, 0.0f32);
+      // Segment midpoints and headings, from a path starting at the origin.
+      let mut midpoints = Vec::with_capacity(self.path_length as usize);
+      for segment in 0..self.path_length {
+        if segment > 0 {
+          heading += if rng.next_f32() < 0.5 { self.path_angle } else { -self.path_angle };
+        }
+        let (dx, dy) = (heading.cos() * self.spacing, heading.sin() * self.spacing);
+        midpoints.push((x + dx / 2.0, y + dy / 2.0, heading));
+        x += dx;
+        y += dy;
+      }
+
+      let (min_x, max_x) = bounds(midpoints.iter().map(|point| point.0));
+      let (min_y, max_y) = bounds(midpoints.iter().map(|point| point.1));
+      let (room_x, room_y) = (self.width - (max_x - min_x), self.height - (max_y - min_y));
+      if room_x < 0.0 || room_y < 0.0 {
+        continue;
+      }
+      let offset_x = self.x - self.width / 2.0 - min_x + rng.range(0.0, room_x);
+      let offset_y = self.y - self.height / 2.0 - min_y + rng.range(0.0, room_y);
+
+      return Ok(
+        midpoints
+          .into_iter()
+          .map(|(x, y, heading)| {
+            let jitter = rng.range(-1.0, 1.0) * self.orientation_jitter;
+            // Orientation 0 has vertical bars, along a heading of PI / 2.
+            self.element(rng, x + offset_x, y + offset_y, heading - PI / 2.0 + jitter)
+          })
+          .collect(),
+      );
+    }
+    Err(GestaltError::InvalidArgument(format!(
+      "a path of {} elements {} apart does not fit in a {} x {} field",
+      self.path_length, self.spacing, self.width, self.height
+    )))
+  }
+
+  fn element(&self, rng: &mut Rng, x: f32, y: f32, orientation: f32) -> Gabor {
+    Gabor {
+      x,
+      y,
+      frequency: self.frequency,
+      orientation,
+      phase: rng.range(0.0, TAU),
+      contrast: self.contrast,
+      sigma: self.sigma,
+    }
+  }
+}
+
+impl Default for ContourField {
+  fn default() -> ContourField {
+    ContourField::new()
+  }
+}
+
+fn bounds(values: impl Iterator<Item = f32>) -> (f32, f32) {
+  values.fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), value| (low.min(value), high.max(value)))
+}
+
+// A laid out contour display, drawn with `GaborRenderer::draw_contour`. The
+// path elements come first.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct ContourDisplay {
+  elements: Vec<Gabor>,
+  path_count: usize,
+}
+
+#[wasm_bindgen]
+impl ContourDisplay {
+
+  pub fn element_count(&self) -> usize {
+    self.elements.len()
+  }
+
+  pub fn path_count(&self) -> usize {
+    self.path_count
+  }
+
+  // Element positions as a flat list of x, y pairs.
+  pub fn positions(&self) -> Vec<f32> {
+    self.elements.iter().flat_map(|element| [element.x, element.y]).collect()
+  }
+
+  pub fn orientations(&self) -> Vec<f32> {
+    self.elements.iter().map(|element| element.orientation).collect()
+  }
+}
+
+impl ContourDisplay {
+  pub(crate) fn elements(&self) -> &[Gabor] {
+    &self.elements
+  }
+}
@@ -0,0 +1,242 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::lattice::DotLattice;
+use crate::stimuli::{Space, Units};
+
+// Round antialiased dots, one instance each, positioned in stimulus units
+// with their diameter in pixels.
+const DOT_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in float diameter;
+in vec4 color;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+out vec2 v_offset;
+flat out float v_radius;
+flat out vec4 v_color;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  // Half a pixel of room for the antialiased edge.
+  v_radius = diameter * 0.5;
+  v_offset = CORNERS[gl_VertexID] * (v_radius + 0.5);
+  v_color = color;
+
+  vec2 position = u_origin + center * u_scale + v_offset;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+const DOT_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+in vec2 v_offset;
+flat in float v_radius;
+flat in vec4 v_color;
+
+out vec4 outColor;
+
+void main()
+{
+  float coverage = clamp(v_radius - length(v_offset) + 0.5, 0.0, 1.0);
+  outColor = vec4(v_color.rgb, v_color.a * coverage);
+}
+"##;
+
+// The GL side of the dot stimuli: a batch of dots drawn in one instanced
+// call.
+pub(crate) struct DotLayer {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  pub(crate) space: Space,
+  pub(crate) blend_mode: BlendMode,
+}
+
+impl DotLayer {
+  pub(crate) fn new(context: &WebGl2RenderingContext) -> Result<DotLayer> {
+    let program = ShaderProgram::new(context, DOT_VERT_SHADER, DOT_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("center", 2), ("diameter", 1), ("color", 4)])?;
+    Ok(DotLayer {
+      context: context.clone(),
+      batch: VertexBatch::instanced(context, &layout)?,
+      program,
+      space: Space::new(),
+      blend_mode: BlendMode::Alpha,
+    })
+  }
+
+  pub(crate) fn push(&mut self, x: f32, y: f32, diameter: f32, color: [f32; 4]) {
+    let [red, green, blue, alpha] = color;
+    self.batch.push(&[x, y, diameter, red, green, blue, alpha]);
+  }
+
+  pub(crate) fn clear(&mut self) {
+    self.batch.clear();
+  }
+
+  pub(crate) fn draw(&mut self, width: u32, height: u32) {
+    if self.batch.is_empty() {
+      return;
+    }
+    let context = &self.context;
+    let (origin_x, origin_y) = self.space.origin(width, height);
+    self.program.set_vec2("u_resolution", width as f32, height as f32);
+    self.program.set_vec2("u_origin", origin_x, origin_y);
+    self.program.set_f32("u_scale", self.space.scale());
+    gl_state::set_blend_mode(context, Some(self.blend_mode));
+    self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+  }
+
+  pub(crate) fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
+
+// Draws round dots on top of a canvas' scene, with the same immediate-mode
+// behaviour as `Draw2D`: dot lattices, or any dot display computed in
+// JavaScript with a size and colour per dot.
+#[wasm_bindgen]
+pub struct DotRenderer {
+  dots: Rc<RefCell<DotDisplay>>,
+  layer: LayerSlot,
+}
+
+struct DotDisplay {
+  layer: DotLayer,
+  dot_size: f32,
+  color: [f32; 4],
+  auto_clear: bool,
+}
+
+#[wasm_bindgen]
+impl DotRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<DotRenderer> {
+    let dots = Rc::new(RefCell::new(DotDisplay {
+      layer: DotLayer::new(&canvas.context())?,
+      dot_size: 4.0,
+      color: [1.0, 1.0, 1.0, 1.0],
+      auto_clear: true,
+    }));
+    let layer = canvas.add_overlay(dots.clone(), Layer::Stimulus);
+    Ok(DotRenderer { dots, layer })
+  }
+
+  // Layer the dots are composited in. Starts out as `Stimulus`.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.dots.borrow_mut().layer.space.set(units, pixels_per_degree);
+  }
+
+  // Diameter in pixels of dots without a size of their own, 4 to begin
+  // with.
+  pub fn set_dot_size(&self, size: f32) {
+    self.dots.borrow_mut().dot_size = size.max(0.0);
+  }
+
+  // Colour of dots without a colour of their own, white to begin with.
+  pub fn set_color(&self, color: &Color) {
+    self.dots.borrow_mut().color = color.to_array();
+  }
+
+  // `Alpha` to begin with, for transparent dots; `Additive` makes
+  // overlapping dots brighter.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.dots.borrow_mut().layer.blend_mode = mode;
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.dots.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.dots.borrow_mut().layer.clear();
+  }
+
+  // Draws dots at `positions`, a flat list of x, y pairs. `sizes` holds a
+  // diameter per dot and `colors` r, g, b, a per dot; either may be empty
+  // to use the current size or colour for all dots.
+  pub fn draw_dots(&self, positions: &[f32], sizes: &[f32], colors: &[f32]) -> Result<()> {
+    let count = positions.len() / 2;
+    if !positions.len().is_multiple_of(2) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "dot positions are x, y pairs, got {} floats",
+        positions.len()
+      )));
+    }
+    if !sizes.is_empty() && sizes.len() != count {
+      return Err(GestaltError::InvalidArgument(format!("{} dots but {} sizes", count, sizes.len())));
+    }
+    if !colors.is_empty() && colors.len() != count * 4 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "{} dots need {} colour components, got {}",
+        count,
+        count * 4,
+        colors.len()
+      )));
+    }
+
+    let mut dots = self.dots.borrow_mut();
+    for index in 0..count {
+      let size = sizes.get(index).copied().unwrap_or(dots.dot_size);
+      let color = match colors.get(index * 4..index * 4 + 4) {
+        Some(color) => [color[0], color[1], color[2], color[3]],
+        None => dots.color,
+      };
+      dots.layer.push(positions[index * 2], positions[index * 2 + 1], size, color);
+    }
+    Ok(())
+  }
+
+  // Draws the dots of `lattice` in the current size and colour. Jitter is
+  // drawn anew on every call.
+  pub fn draw_lattice(&self, lattice: &DotLattice) {
+    let mut dots = self.dots.borrow_mut();
+    let (size, color) = (dots.dot_size, dots.color);
+    for point in lattice.positions().chunks(2) {
+      dots.layer.push(point[0], point[1], size, color);
+    }
+  }
+}
+
+impl Overlay for DotDisplay {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    self.layer.draw(width, height);
+    if self.auto_clear {
+      self.layer.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.layer.restore(context)
+  }
+}
@@ -0,0 +1,363 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::{linear_to_srgb, Color};
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::random::Rng;
+use crate::shader::ShaderProgram;
+use crate::stimuli::{check_positive, Space, Units};
+
+// A single disc, in stimulus units.
+const FLICKER_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in float diameter;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+out vec2 v_local;
+flat out float v_radius;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  v_radius = diameter * 0.5 * u_scale;
+  v_local = CORNERS[gl_VertexID] * (v_radius + 1.0);
+  vec2 position = u_origin + center * u_scale + v_local;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// Half cycles fall on whole frames, like checkerboard reversals.
+const FLICKER_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform vec3 u_reference;
+uniform vec3 u_test;
+uniform int u_frame;
+uniform float u_frame_rate;
+uniform float u_frequency;
+
+in vec2 v_local;
+flat in float v_radius;
+
+out vec4 outColor;
+
+void main()
+{
+  float mask = clamp(v_radius - length(v_local) + 0.5, 0.0, 1.0);
+  int half_cycles = int(floor(float(u_frame) * 2.0 * u_frequency / u_frame_rate + 1e-3));
+  outColor = vec4((half_cycles & 1) == 0 ? u_reference : u_test, mask);
+}
+"##;
+
+// Heterochromatic flicker photometry: a disc alternates between a reference
+// and a test colour, and the participant adjusts the test's intensity until
+// the flicker is least visible. Colour differences cannot be followed at
+// 15 Hz or so, luminance differences can, so that setting is where the
+// two are isoluminant for this observer and display.
+//
+// Each setting starts from a random offset around the CIE isoluminant
+// point; `accept` records it and starts the next, until `repeats` settings
+// are made. Their geometric mean, `luminance_ratio`, is kept as the
+// isoluminant point, for chromatic stimuli to take their colours from with
+// `isoluminant_color`. The test's intensity is a factor on its linear-light
+// r, g, b, as far as the display allows without changing its chromaticity.
+#[wasm_bindgen]
+pub struct FlickerPhotometry {
+  photometry: Rc<RefCell<Photometry>>,
+  layer: LayerSlot,
+}
+
+struct Photometry {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  frame: i32,
+  reference: Color,
+  test: Color,
+  // Factor on the test's linear r, g, b, and its upper limit where a
+  // channel reaches 1.
+  gain: f32,
+  max_gain: f32,
+  // Log units per step of `adjust`, and furthest start offset.
+  step: f32,
+  start_range: f32,
+  repeats: u32,
+  settings: Vec<f32>,
+  running: bool,
+  rng: Rng,
+}
+
+impl Photometry {
+  // The gain at which the test has the reference's CIE luminance.
+  fn nominal_gain(&self) -> f32 {
+    self.reference.luminance() / self.test.luminance()
+  }
+
+  fn set_gain(&mut self, gain: f32) {
+    self.gain = gain.min(self.max_gain);
+    let [r, g, b] = self.test.linear_rgb().map(|c| linear_to_srgb(c * self.gain));
+    self.program.set_vec3("u_test", r, g, b);
+  }
+
+  fn begin_setting(&mut self) {
+    let offset = self.rng.range(-self.start_range, self.start_range);
+    let gain = self.nominal_gain() * 10f32.powf(offset);
+    self.set_gain(gain);
+  }
+
+  fn luminance_ratio(&self) -> f32 {
+    let count = self.settings.len() as f32;
+    10f32.powf(self.settings.iter().map(|gain| gain.log10()).sum::<f32>() / count)
+  }
+}
+
+#[wasm_bindgen]
+impl FlickerPhotometry {
+
+  // Flickers `test` against `reference`, both needing some luminance, at
+  // 15 Hz with 6 settings of 0.02 log unit steps, starting up to 0.3 log
+  // units from the CIE isoluminant point.
+  pub fn new(canvas: &WebGlCanvas, reference: &Color, test: &Color) -> Result<FlickerPhotometry> {
+    check_positive("reference luminance", reference.luminance())?;
+    check_positive("test luminance", test.luminance())?;
+    let context = canvas.context();
+    let mut program = ShaderProgram::new(&context, FLICKER_VERT_SHADER, FLICKER_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("center", 2), ("diameter", 1)])?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_vec3("u_reference", reference.r, reference.g, reference.b);
+    program.set_f32("u_frame_rate", 60.0);
+    program.set_f32("u_frequency", 15.0);
+
+    let max_gain = 1.0 / test.linear_rgb().iter().fold(0.0f32, |max, &c| max.max(c));
+    let mut photometry = Photometry {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      frame: 0,
+      reference: *reference,
+      test: *test,
+      gain: 1.0,
+      max_gain,
+      step: 0.02,
+      start_range: 0.3,
+      repeats: 6,
+      settings: Vec::new(),
+      running: false,
+      rng: Rng::from_entropy(),
+    };
+    let gain = photometry.nominal_gain();
+    photometry.set_gain(gain);
+
+    let photometry = Rc::new(RefCell::new(photometry));
+    let layer = canvas.add_overlay(photometry.clone(), Layer::Stimulus);
+    Ok(FlickerPhotometry { photometry, layer })
+  }
+
+  // Moves the disc to another layer of the canvas; `Stimulus` by default.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.photometry.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // Places the disc, in the renderer's units; nothing shows before. Small
+  // central fields, e.g. 2 degrees, keep rods and macular pigment out of
+  // the match.
+  pub fn set_patch(&self, x: f32, y: f32, diameter: f32) -> Result<()> {
+    check_positive("patch diameter", diameter)?;
+    let mut photometry = self.photometry.borrow_mut();
+    photometry.batch.clear();
+    photometry.batch.push(&[x, y, diameter]);
+    Ok(())
+  }
+
+  // Full reference-test cycles per second. Half a cycle should be a whole
+  // number of frames at the display's refresh rate, e.g. 15 or 20 Hz at
+  // 60 Hz.
+  pub fn set_frequency(&self, frequency: f32) -> Result<()> {
+    check_positive("flicker frequency", frequency)?;
+    self.photometry.borrow_mut().program.set_f32("u_frequency", frequency);
+    Ok(())
+  }
+
+  // The display's refresh rate in Hz, 60 to begin with.
+  pub fn set_frame_rate(&self, frame_rate: f32) -> Result<()> {
+    check_positive("frame rate", frame_rate)?;
+    self.photometry.borrow_mut().program.set_f32("u_frame_rate", frame_rate);
+    Ok(())
+  }
+
+  // Log units per step of `adjust`.
+  pub fn set_step(&self, step: f32) -> Result<()> {
+    check_positive("step", step)?;
+    self.photometry.borrow_mut().step = step;
+    Ok(())
+  }
+
+  // Settings start up to `range` log units either side of the CIE
+  // isoluminant point; 0 starts them all on it.
+  pub fn set_start_range(&self, range: f32) -> Result<()> {
+    if range.is_nan() || range < 0.0 {
+      return Err(GestaltError::InvalidArgument(format!("start range must not be negative, got {}", range)));
+    }
+    self.photometry.borrow_mut().start_range = range;
+    Ok(())
+  }
+
+  pub fn set_repeats(&self, repeats: u32) -> Result<()> {
+    if repeats == 0 {
+      return Err(GestaltError::InvalidArgument("flicker photometry needs at least 1 setting".into()));
+    }
+    self.photometry.borrow_mut().repeats = repeats;
+    Ok(())
+  }
+
+  // Seeds the start offsets, from `Math.random` to begin with.
+  pub fn set_seed(&self, seed: u32) {
+    self.photometry.borrow_mut().rng = Rng::new(seed as u64);
+  }
+
+  // Shows the flicker and begins the first setting, discarding earlier
+  // ones.
+  pub fn start(&self) {
+    let mut photometry = self.photometry.borrow_mut();
+    photometry.settings.clear();
+    photometry.frame = 0;
+    photometry.running = true;
+    photometry.begin_setting();
+  }
+
+  // Hides the flicker, keeping the settings made.
+  pub fn stop(&self) {
+    self.photometry.borrow_mut().running = false;
+  }
+
+  // Makes the test brighter by `steps` steps, or dimmer for negative ones,
+  // e.g. from arrow keys.
+  pub fn adjust(&self, steps: i32) {
+    let mut photometry = self.photometry.borrow_mut();
+    if photometry.running {
+      let gain = photometry.gain * 10f32.powf(steps as f32 * photometry.step);
+      photometry.set_gain(gain);
+    }
+  }
+
+  // Records the current setting as one of minimal flicker and begins the
+  // next, or hides the flicker after the last.
+  pub fn accept(&self) {
+    let mut photometry = self.photometry.borrow_mut();
+    if !photometry.running {
+      return;
+    }
+    let gain = photometry.gain;
+    photometry.settings.push(gain);
+    if photometry.settings.len() as u32 >= photometry.repeats {
+      photometry.running = false;
+    } else {
+      photometry.begin_setting();
+    }
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.photometry.borrow().running
+  }
+
+  pub fn is_finished(&self) -> bool {
+    let photometry = self.photometry.borrow();
+    photometry.settings.len() as u32 >= photometry.repeats
+  }
+
+  // The factor on the test's linear r, g, b being shown.
+  pub fn gain(&self) -> f32 {
+    self.photometry.borrow().gain
+  }
+
+  // The gain of every setting so far.
+  pub fn settings(&self) -> Vec<f32> {
+    self.photometry.borrow().settings.clone()
+  }
+
+  // The isoluminant point, the geometric mean gain of the settings; NaN
+  // without any.
+  pub fn luminance_ratio(&self) -> f32 {
+    self.photometry.borrow().luminance_ratio()
+  }
+
+  // CIE luminance of the test at the isoluminant point over that of the
+  // reference: 1 for an observer matching the CIE standard observer,
+  // above 1 where the test looks darker to this one.
+  pub fn cie_ratio(&self) -> f32 {
+    let photometry = self.photometry.borrow();
+    photometry.luminance_ratio() / photometry.nominal_gain()
+  }
+
+  // The test colour at the isoluminant point, to draw chromatic stimuli
+  // against the reference with; `undefined` before any setting.
+  pub fn isoluminant_color(&self) -> Option<Color> {
+    let photometry = self.photometry.borrow();
+    if photometry.settings.is_empty() {
+      return None;
+    }
+    let ratio = photometry.luminance_ratio();
+    let [r, g, b] = photometry.test.linear_rgb().map(|c| c * ratio);
+    Some(Color::from_linear(r, g, b, photometry.test.a))
+  }
+
+  // `{reference, test, ratio, settings}`, the colours as sRGB `[r, g, b]`,
+  // to store with the session's data.
+  pub fn record(&self) -> JsValue {
+    let photometry = self.photometry.borrow();
+    let numbers = |values: &[f32]| values.iter().map(|&value| JsValue::from(value)).collect::<js_sys::Array>();
+    let (reference, test) = (photometry.reference, photometry.test);
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &"reference".into(), &numbers(&[reference.r, reference.g, reference.b]));
+    let _ = js_sys::Reflect::set(&object, &"test".into(), &numbers(&[test.r, test.g, test.b]));
+    let _ = js_sys::Reflect::set(&object, &"ratio".into(), &photometry.luminance_ratio().into());
+    let _ = js_sys::Reflect::set(&object, &"settings".into(), &numbers(&photometry.settings));
+    object.into()
+  }
+}
+
+impl Overlay for Photometry {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.running || self.batch.is_empty() {
+      return;
+    }
+    let (origin_x, origin_y) = self.space.origin(width, height);
+    self.program.set_vec2("u_resolution", width as f32, height as f32);
+    self.program.set_vec2("u_origin", origin_x, origin_y);
+    self.program.set_f32("u_scale", self.space.scale());
+    self.program.set_i32("u_frame", self.frame);
+    gl_state::set_blend_mode(&self.context, Some(BlendMode::Alpha));
+    self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    self.frame += 1;
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
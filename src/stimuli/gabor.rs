@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::Result;
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::contour::ContourDisplay;
+use crate::stimuli::{Space, Units};
+
+// One instance per patch, in stimulus units. The quad reaches out to
+// `ENVELOPE_EXTENT` sigmas, where the envelope is below a thousandth.
+const GABOR_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in float frequency;
+in float orientation;
+in float phase;
+in float contrast;
+in float sigma;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+out vec2 v_local;
+flat out vec2 v_direction;
+flat out float v_frequency;
+flat out float v_phase;
+flat out float v_contrast;
+flat out float v_sigma;
+
+const float ENVELOPE_EXTENT = 4.0;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  v_local = CORNERS[gl_VertexID] * sigma * ENVELOPE_EXTENT;
+  v_direction = vec2(cos(orientation), sin(orientation));
+  v_frequency = frequency;
+  v_phase = phase;
+  v_contrast = contrast;
+  v_sigma = sigma;
+
+  vec2 position = u_origin + (center + v_local) * u_scale;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// The carrier modulates the background colour around itself; blending by
+// the envelope then gives background * (1 + contrast * envelope * carrier)
+// on a uniform background of that colour.
+const GABOR_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform vec4 u_background;
+
+in vec2 v_local;
+flat in vec2 v_direction;
+flat in float v_frequency;
+flat in float v_phase;
+flat in float v_contrast;
+flat in float v_sigma;
+
+out vec4 outColor;
+
+const float TAU = 6.28318530718;
+
+void main()
+{
+  float envelope = exp(-dot(v_local, v_local) / (2.0 * v_sigma * v_sigma));
+  float carrier = cos(TAU * v_frequency * dot(v_local, v_direction) + v_phase);
+  outColor = vec4(u_background.rgb * (1.0 + v_contrast * carrier), envelope);
+}
+"##;
+
+// Parameters of one Gabor patch. Positions and sizes are in the renderer's
+// units, see `GaborRenderer::set_units`; angles are in radians.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gabor {
+  pub x: f32,
+  pub y: f32,
+  // Cycles per unit.
+  pub frequency: f32,
+  // 0 gives vertical bars; positive angles turn them clockwise.
+  pub orientation: f32,
+  // Of the carrier at the centre, 0 being a peak of a cosine.
+  pub phase: f32,
+  // Michelson contrast, 0 to 1.
+  pub contrast: f32,
+  // Standard deviation of the Gaussian envelope.
+  pub sigma: f32,
+}
+
+#[wasm_bindgen]
+impl Gabor {
+
+  // A vertical, full-contrast cosine patch at the origin.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Gabor {
+    Gabor {
+      x: 0.0,
+      y: 0.0,
+      frequency: 0.05,
+      orientation: 0.0,
+      phase: 0.0,
+      contrast: 1.0,
+      sigma: 20.0,
+    }
+  }
+}
+
+impl Default for Gabor {
+  fn default() -> Gabor {
+    Gabor::new()
+  }
+}
+
+// Draws Gabor patches, sinusoidal gratings in a Gaussian envelope, on top of
+// a canvas' scene, all in one instanced draw call and with the same
+// immediate-mode behaviour as `Draw2D`. Patches modulate the background
+// colour, so the scene behind them should be cleared to that colour.
+#[wasm_bindgen]
+pub struct GaborRenderer {
+  gabors: Rc<RefCell<Gabors>>,
+  layer: LayerSlot,
+}
+
+struct Gabors {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl GaborRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<GaborRenderer> {
+    let context = canvas.context();
+    let mut program = ShaderProgram::new(&context, GABOR_VERT_SHADER, GABOR_FRAG_SHADER)?;
+    let layout = attribute_layout(
+      &program,
+      &[("center", 2), ("frequency", 1), ("orientation", 1), ("phase", 1), ("contrast", 1), ("sigma", 1)],
+    )?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_vec4("u_background", 0.5, 0.5, 0.5, 1.0);
+
+    let gabors = Rc::new(RefCell::new(Gabors {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(gabors.clone(), Layer::Stimulus);
+    Ok(GaborRenderer { gabors, layer })
+  }
+
+  // Layer of the canvas the patches are drawn in, `Stimulus` unless moved.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // How the patches are blended with what is behind them, `Alpha` by default.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.gabors.borrow_mut().blend_mode = mode;
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  // Applies to all patches drawn from the next frame on.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.gabors.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // The mean colour of the patches, mid grey by default.
+  pub fn set_background(&self, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.gabors.borrow_mut().program.set_vec4("u_background", r, g, b, a);
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.gabors.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.gabors.borrow_mut().batch.clear();
+  }
+
+  pub fn draw(&self, gabor: &Gabor) {
+    self.gabors.borrow_mut().push(gabor);
+  }
+
+  // Draws every element of a contour integration display.
+  pub fn draw_contour(&self, display: &ContourDisplay) {
+    let mut gabors = self.gabors.borrow_mut();
+    for gabor in display.elements() {
+      gabors.push(gabor);
+    }
+  }
+}
+
+impl Gabors {
+  fn push(&mut self, gabor: &Gabor) {
+    self.batch.push(&[
+      gabor.x, gabor.y,
+      gabor.frequency,
+      gabor.orientation,
+      gabor.phase,
+      gabor.contrast.clamp(0.0, 1.0),
+      gabor.sigma,
+    ]);
+  }
+}
+
+impl Overlay for Gabors {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
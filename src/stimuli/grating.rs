@@ -0,0 +1,424 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::Result;
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::{Space, Units};
+
+// One instance per grating or plaid, in stimulus units. A plaid has two
+// components, a single grating a second one of zero contrast. Each
+// component's `carrier` is orientation, frequency, phase and contrast, and
+// its `timing` temporal frequency, waveform and motion; `style` holds the
+// envelope and how the components combine. Codes are as in `style_codes`
+// and `component_codes`.
+const GRATING_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in float radius;
+in vec2 envelope;
+in vec2 style;
+in vec4 carrier_a;
+in vec3 timing_a;
+in vec4 carrier_b;
+in vec3 timing_b;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+out vec2 v_local;
+flat out float v_radius;
+flat out vec2 v_envelope;
+flat out ivec2 v_style;
+flat out vec4 v_carriers[2];
+flat out vec3 v_timings[2];
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  // A pixel of room for the antialiased edge.
+  v_local = CORNERS[gl_VertexID] * (radius + 1.0 / u_scale);
+  v_radius = radius;
+  v_envelope = envelope;
+  v_style = ivec2(style + 0.5);
+  v_carriers[0] = carrier_a;
+  v_carriers[1] = carrier_b;
+  v_timings[0] = timing_a;
+  v_timings[1] = timing_b;
+
+  vec2 position = u_origin + (center + v_local) * u_scale;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// Modulates the background colour like the Gabor shader, with the envelope
+// as alpha.
+const GRATING_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform vec4 u_background;
+uniform float u_scale;
+// Seconds since the renderer's clock started.
+uniform float u_time;
+
+in vec2 v_local;
+flat in float v_radius;
+flat in vec2 v_envelope;
+flat in ivec2 v_style;
+flat in vec4 v_carriers[2];
+flat in vec3 v_timings[2];
+
+out vec4 outColor;
+
+const float TAU = 6.28318530718;
+
+// Contrast times carrier of one component at this fragment and time.
+float modulation(vec4 carrier, vec3 timing)
+{
+  vec2 direction = vec2(cos(carrier.x), sin(carrier.x));
+  float phase = carrier.z;
+  float contrast = carrier.w;
+  float cycles = timing.x * u_time;
+  if (int(timing.z + 0.5) == 0) {
+    phase -= TAU * cycles;
+  } else {
+    contrast *= cos(TAU * cycles);
+  }
+
+  float wave = cos(TAU * carrier.y * dot(v_local, direction) + phase);
+  if (int(timing.y + 0.5) == 1) {
+    wave = clamp(wave / max(fwidth(wave), 1e-4), -1.0, 1.0);
+  }
+  return contrast * wave;
+}
+
+void main()
+{
+  float distance = length(v_local);
+  // The hard edge is antialiased in pixels.
+  float envelope = clamp((v_radius - distance) * u_scale + 0.5, 0.0, 1.0);
+  if (v_style.x == 0) {
+    envelope *= exp(-distance * distance / (2.0 * v_envelope.x * v_envelope.x));
+  } else if (v_style.x == 1) {
+    float ramp = clamp((v_radius - distance) / max(v_envelope.y, 1e-6), 0.0, 1.0);
+    envelope *= 0.5 - 0.5 * cos(ramp * TAU * 0.5);
+  }
+
+  float a = modulation(v_carriers[0], v_timings[0]);
+  float b = modulation(v_carriers[1], v_timings[1]);
+  float luminance = v_style.y == 0 ? 1.0 + a + b : (1.0 + a) * (1.0 + b);
+  outColor = vec4(u_background.rgb * luminance, envelope);
+}
+"##;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+  Sine,
+  Square,
+}
+
+// The window a grating is seen through. All are cut off at the grating's
+// radius: `Gaussian` fades with the standard deviation `sigma`,
+// `RaisedCosine` is flat and falls off over the last `ramp` units, `Hard`
+// is a plain disc.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Envelope {
+  Gaussian,
+  RaisedCosine,
+  Hard,
+}
+
+// `Drift` moves the carrier across the envelope, perpendicular to the bars,
+// by `temporal_frequency` cycles per second. `Counterphase` keeps it in
+// place and reverses its contrast sinusoidally at that rate.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motion {
+  Drift,
+  Counterphase,
+}
+
+// How the two gratings of a plaid combine. `Coherent` adds their
+// modulations, the usual plaid that tends to be seen moving as one pattern;
+// summed contrasts above 1 clip. `Transparent` multiplies them, as if one
+// grating were a transparent layer over the other, which favours seeing
+// two surfaces sliding across each other.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaidBlend {
+  Coherent,
+  Transparent,
+}
+
+// Parameters of one grating. Lengths are in the renderer's units, angles in
+// radians.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grating {
+  pub x: f32,
+  pub y: f32,
+  pub radius: f32,
+  // Cycles per unit.
+  pub frequency: f32,
+  // 0 gives vertical bars, drifting rightwards; positive angles turn them
+  // clockwise.
+  pub orientation: f32,
+  // At the centre when the clock starts, 0 being a peak of a cosine.
+  pub phase: f32,
+  // Michelson contrast, 0 to 1.
+  pub contrast: f32,
+  // Cycles per second; 0 for a static grating.
+  pub temporal_frequency: f32,
+  pub waveform: Waveform,
+  pub envelope: Envelope,
+  pub motion: Motion,
+  pub sigma: f32,
+  pub ramp: f32,
+}
+
+#[wasm_bindgen]
+impl Grating {
+
+  // A static sine grating in a hard 100 unit aperture.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Grating {
+    Grating {
+      x: 0.0,
+      y: 0.0,
+      radius: 100.0,
+      frequency: 0.05,
+      orientation: 0.0,
+      phase: 0.0,
+      contrast: 1.0,
+      temporal_frequency: 0.0,
+      waveform: Waveform::Sine,
+      envelope: Envelope::Hard,
+      motion: Motion::Drift,
+      sigma: 25.0,
+      ramp: 10.0,
+    }
+  }
+}
+
+impl Default for Grating {
+  fn default() -> Grating {
+    Grating::new()
+  }
+}
+
+// Draws sine and square wave gratings, and plaids of two of them, on top of
+// a canvas' scene, with the same immediate-mode behaviour as `Draw2D`.
+// Drift and counterphase follow the time passed to `WebGlCanvas::render`,
+// counted from the first frame after the renderer was created or
+// `restart_clock` was called, so rates are in cycles per second whatever
+// the frame rate.
+#[wasm_bindgen]
+pub struct GratingRenderer {
+  gratings: Rc<RefCell<Gratings>>,
+  layer: LayerSlot,
+}
+
+pub(crate) struct Gratings {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  // Render time of the first frame drawn, in seconds.
+  start: Option<f32>,
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl GratingRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<GratingRenderer> {
+    let gratings = Rc::new(RefCell::new(Gratings::new(&canvas.context())?));
+    let layer = canvas.add_overlay(gratings.clone(), Layer::Stimulus);
+    Ok(GratingRenderer { gratings, layer })
+  }
+
+  // Moves the gratings to `layer`, e.g. `Background` behind other stimuli.
+  // `Stimulus` to begin with.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // How the gratings combine with what is drawn behind them, `Alpha`
+  // unless set.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.gratings.borrow_mut().blend_mode = mode;
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.gratings.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // The mean colour of the gratings, mid grey by default.
+  pub fn set_background(&self, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.gratings.borrow_mut().program.set_vec4("u_background", r, g, b, a);
+  }
+
+  // Makes the next frame drawn time 0, e.g. at stimulus onset.
+  pub fn restart_clock(&self) {
+    self.gratings.borrow_mut().restart_clock();
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.gratings.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.gratings.borrow_mut().batch.clear();
+  }
+
+  pub fn draw(&self, grating: &Grating) {
+    let none = Grating {
+      contrast: 0.0,
+      ..*grating
+    };
+    self.gratings.borrow_mut().push(grating, &none, PlaidBlend::Coherent);
+  }
+
+  // Draws `first` and `second` superimposed as a plaid. Position, radius
+  // and envelope are those of `first`.
+  pub fn draw_plaid(&self, first: &Grating, second: &Grating, blend: PlaidBlend) {
+    self.gratings.borrow_mut().push(first, second, blend);
+  }
+}
+
+impl Gratings {
+  // Also used by `Scene`, which draws gratings in between its other nodes.
+  pub(crate) fn new(context: &WebGl2RenderingContext) -> Result<Gratings> {
+    let mut program = ShaderProgram::new(context, GRATING_VERT_SHADER, GRATING_FRAG_SHADER)?;
+    let layout = attribute_layout(
+      &program,
+      &[
+        ("center", 2),
+        ("radius", 1),
+        ("envelope", 2),
+        ("style", 2),
+        ("carrier_a", 4),
+        ("timing_a", 3),
+        ("carrier_b", 4),
+        ("timing_b", 3),
+      ],
+    )?;
+    let batch = VertexBatch::instanced(context, &layout)?;
+    program.set_vec4("u_background", 0.5, 0.5, 0.5, 1.0);
+
+    Ok(Gratings {
+      context: context.clone(),
+      program,
+      batch,
+      space: Space::new(),
+      start: None,
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    })
+  }
+
+  pub(crate) fn restart_clock(&mut self) {
+    self.start = None;
+  }
+
+  pub(crate) fn push(&mut self, first: &Grating, second: &Grating, blend: PlaidBlend) {
+    let component = |grating: &Grating| {
+      let [waveform, motion] = component_codes(grating);
+      [
+        grating.orientation,
+        grating.frequency,
+        grating.phase,
+        grating.contrast.clamp(0.0, 1.0),
+        grating.temporal_frequency,
+        waveform,
+        motion,
+      ]
+    };
+    let [envelope, blend] = style_codes(first, blend);
+    let a = component(first);
+    let b = component(second);
+    self.batch.push(&[
+      first.x, first.y,
+      first.radius,
+      first.sigma, first.ramp,
+      envelope, blend,
+      a[0], a[1], a[2], a[3],
+      a[4], a[5], a[6],
+      b[0], b[1], b[2], b[3],
+      b[4], b[5], b[6],
+    ]);
+  }
+}
+
+impl Overlay for Gratings {
+  fn draw(&mut self, width: u32, height: u32, time: f32) {
+    if !self.batch.is_empty() {
+      let start = *self.start.get_or_insert(time);
+      let context = &self.context;
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      self.program.set_f32("u_time", time - start);
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
+
+// The encodings of a grating's envelope and a plaid's blend, and of each
+// component's waveform and motion, in `GRATING_FRAG_SHADER`.
+
+fn style_codes(grating: &Grating, blend: PlaidBlend) -> [f32; 2] {
+  let envelope = match grating.envelope {
+    Envelope::Gaussian => 0.0,
+    Envelope::RaisedCosine => 1.0,
+    Envelope::Hard => 2.0,
+  };
+  let blend = match blend {
+    PlaidBlend::Coherent => 0.0,
+    PlaidBlend::Transparent => 1.0,
+  };
+  [envelope, blend]
+}
+
+fn component_codes(grating: &Grating) -> [f32; 2] {
+  let waveform = match grating.waveform {
+    Waveform::Sine => 0.0,
+    Waveform::Square => 1.0,
+  };
+  let motion = match grating.motion {
+    Motion::Drift => 0.0,
+    Motion::Counterphase => 1.0,
+  };
+  [waveform, motion]
+}
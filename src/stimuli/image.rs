@@ -0,0 +1,277 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::{WebGl2RenderingContext, WebGlTexture};
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::error::Result;
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::grating::Envelope;
+use crate::stimuli::{check_positive, Space, Units};
+use crate::texture::Texture;
+
+// One instance per image, in stimulus units. With snapping the top left
+// corner lands on a pixel boundary, so an image drawn at its own size maps
+// texels one to one onto pixels.
+const IMAGE_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in vec2 size;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+uniform int u_snap;
+
+out vec2 v_uv;
+out vec2 v_local;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+  vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+);
+
+void main()
+{
+  vec2 corner = CORNERS[gl_VertexID];
+  vec2 extent = size * u_scale;
+  vec2 top_left = u_origin + center * u_scale - extent * 0.5;
+  if (u_snap == 1) {
+    top_left = floor(top_left + 0.5);
+  }
+  vec2 position = top_left + (corner + 0.5) * extent;
+
+  // Screen y runs down, texture v up.
+  v_uv = vec2(corner.x + 0.5, 0.5 - corner.y);
+  v_local = corner * 2.0;
+
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// Envelope codes as in the grating shader, -1 for none; distances are
+// relative to the ellipse inscribed in the image.
+const IMAGE_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_texture;
+uniform int u_nearest;
+uniform int u_envelope;
+uniform float u_sigma;
+uniform float u_ramp;
+
+in vec2 v_uv;
+in vec2 v_local;
+
+out vec4 outColor;
+
+const float PI = 3.14159265359;
+
+void main()
+{
+  vec4 color;
+  if (u_nearest == 1) {
+    ivec2 size = textureSize(u_texture, 0);
+    color = texelFetch(u_texture, clamp(ivec2(v_uv * vec2(size)), ivec2(0), size - 1), 0);
+  } else {
+    color = texture(u_texture, v_uv);
+  }
+
+  float window = 1.0;
+  if (u_envelope >= 0) {
+    float distance = length(v_local);
+    window = distance <= 1.0 ? 1.0 : 0.0;
+    if (u_envelope == 0) {
+      window *= exp(-distance * distance / (2.0 * u_sigma * u_sigma));
+    } else if (u_envelope == 1) {
+      float ramp = clamp((1.0 - distance) / max(u_ramp, 1e-6), 0.0, 1.0);
+      window *= 0.5 - 0.5 * cos(ramp * PI);
+    }
+  }
+  outColor = vec4(color.rgb, color.a * window);
+}
+"##;
+
+// How texels are sampled. `Nearest` keeps pixel art and noise textures
+// crisp when scaled up; `Linear` interpolates for smooth scaling and
+// sub-pixel motion.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFilter {
+  Nearest,
+  Linear,
+}
+
+// Draws a texture, e.g. a photograph from `AssetLoader`, as a stimulus
+// centred on a position and sized in pixels or degrees of visual angle,
+// with the same immediate-mode behaviour as `Draw2D`. Optionally windowed by
+// an envelope as gratings are, fading it into the background.
+#[wasm_bindgen]
+pub struct ImageStimulus {
+  images: Rc<RefCell<Images>>,
+  layer: LayerSlot,
+}
+
+struct Images {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  texture: WebGlTexture,
+  // Of the texture, in texels.
+  texture_size: (u32, u32),
+  space: Space,
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl ImageStimulus {
+
+  pub fn new(canvas: &WebGlCanvas, texture: &Texture) -> Result<ImageStimulus> {
+    let context = canvas.context();
+    texture.check_context(&context)?;
+    let mut program = ShaderProgram::new(&context, IMAGE_VERT_SHADER, IMAGE_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("center", 2), ("size", 2)])?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_i32("u_texture", 0);
+    program.set_i32("u_nearest", 0);
+    program.set_i32("u_snap", 1);
+    program.set_i32("u_envelope", -1);
+
+    let images = Rc::new(RefCell::new(Images {
+      context,
+      program,
+      batch,
+      texture: texture.raw().clone(),
+      texture_size: (texture.width(), texture.height()),
+      space: Space::new(),
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(images.clone(), Layer::Stimulus);
+    Ok(ImageStimulus { images, layer })
+  }
+
+  // `Stimulus` unless moved.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // `Alpha` to begin with.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.images.borrow_mut().blend_mode = mode;
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.images.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // Switches to another texture. Images already drawn this frame use it too.
+  pub fn set_texture(&self, texture: &Texture) -> Result<()> {
+    let mut images = self.images.borrow_mut();
+    texture.check_context(&images.context)?;
+    images.texture = texture.raw().clone();
+    images.texture_size = (texture.width(), texture.height());
+    Ok(())
+  }
+
+  // `Linear` to begin with. Leaves the texture's own filtering alone.
+  pub fn set_filter(&self, filter: ImageFilter) {
+    self.images.borrow_mut().program.set_i32("u_nearest", (filter == ImageFilter::Nearest) as i32);
+  }
+
+  // Whether images are moved by up to half a pixel so their corners fall on
+  // pixel boundaries, which keeps images at their own size sharp. On to
+  // begin with; turn it off for smooth sub-pixel motion.
+  pub fn set_pixel_snap(&self, snap: bool) {
+    self.images.borrow_mut().program.set_i32("u_snap", snap as i32);
+  }
+
+  // Windows the images within the ellipse inscribed in them, `undefined`
+  // showing them whole. `sigma` and `ramp` are as for gratings, but as
+  // fractions of the half width and height.
+  pub fn set_envelope(&self, envelope: Option<Envelope>, sigma: f32, ramp: f32) -> Result<()> {
+    if envelope == Some(Envelope::Gaussian) {
+      check_positive("sigma", sigma)?;
+    }
+    if envelope == Some(Envelope::RaisedCosine) {
+      check_positive("ramp", ramp)?;
+    }
+    let code = match envelope {
+      None => -1,
+      Some(Envelope::Gaussian) => 0,
+      Some(Envelope::RaisedCosine) => 1,
+      Some(Envelope::Hard) => 2,
+    };
+    let mut images = self.images.borrow_mut();
+    images.program.set_i32("u_envelope", code);
+    images.program.set_f32("u_sigma", sigma);
+    images.program.set_f32("u_ramp", ramp);
+    Ok(())
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.images.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.images.borrow_mut().batch.clear();
+  }
+
+  // Draws the image centred on `(x, y)`, `width` x `height` units large.
+  pub fn draw(&self, x: f32, y: f32, width: f32, height: f32) -> Result<()> {
+    check_positive("width", width)?;
+    check_positive("height", height)?;
+    self.images.borrow_mut().batch.push(&[x, y, width, height]);
+    Ok(())
+  }
+
+  // Draws the image centred on `(x, y)`, as wide as `width` units and as
+  // high as its aspect ratio makes it.
+  pub fn draw_width(&self, x: f32, y: f32, width: f32) -> Result<()> {
+    let (texture_width, texture_height) = self.images.borrow().texture_size;
+    self.draw(x, y, width, width * texture_height as f32 / texture_width.max(1) as f32)
+  }
+
+  // Draws the image centred on `(x, y)` at its own size, one texel to a
+  // drawing buffer pixel, whatever the units.
+  pub fn draw_native(&self, x: f32, y: f32) {
+    let mut images = self.images.borrow_mut();
+    let (width, height) = images.texture_size;
+    let scale = images.space.scale();
+    images.batch.push(&[x, y, width as f32 / scale, height as f32 / scale]);
+  }
+}
+
+impl Overlay for Images {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      gl_state::bind_texture(context, 0, &self.texture);
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  // The texture itself is gone with the old context and has to be set again.
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
@@ -0,0 +1,263 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::{read_config, Space, Units};
+
+// One inducer per instance: a disc with a wedge cut out, the wedge given by
+// the direction of its bisector and its half angle.
+const INDUCER_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in float radius;
+in float wedge_direction;
+in float wedge_half_angle;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+out vec2 v_offset;
+flat out float v_radius;
+flat out vec2 v_edge_normals[2];
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  v_radius = radius * u_scale;
+  v_offset = CORNERS[gl_VertexID] * (v_radius + 1.0);
+  // Normals of the wedge's sides, pointing into the wedge.
+  float first = wedge_direction - wedge_half_angle;
+  float second = wedge_direction + wedge_half_angle;
+  v_edge_normals[0] = vec2(-sin(first), cos(first));
+  v_edge_normals[1] = vec2(sin(second), -cos(second));
+
+  vec2 position = u_origin + center * u_scale + v_offset;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+const INDUCER_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform vec4 u_color;
+
+in vec2 v_offset;
+flat in float v_radius;
+flat in vec2 v_edge_normals[2];
+
+out vec4 outColor;
+
+void main()
+{
+  float disc = v_radius - length(v_offset);
+  // Wedges are narrower than a half plane, so the distance to one is the
+  // larger distance to the half planes of its sides.
+  float wedge = max(-dot(v_offset, v_edge_normals[0]), -dot(v_offset, v_edge_normals[1]));
+  float coverage = clamp(min(disc, wedge) + 0.5, 0.0, 1.0);
+  outColor = vec4(u_color.rgb, u_color.a * coverage);
+}
+"##;
+
+// A Kanizsa figure: an illusory regular polygon induced by "pac-man" discs
+// at its corners, each missing the wedge that the polygon would cover.
+// Lengths are in the renderer's units, angles in radians.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Kanizsa {
+  // Centre of the polygon.
+  pub x: f32,
+  pub y: f32,
+  // 3 for the Kanizsa triangle, 4 for the square.
+  pub sides: u32,
+  // Distance between neighbouring inducer centres, the polygon's side.
+  pub spacing: f32,
+  // Fraction of each side covered by the inducers, 0 to 1.
+  pub support_ratio: f32,
+  // Of the whole figure, clockwise; at 0 the bottom side is horizontal.
+  pub rotation: f32,
+  // Of each inducer about its centre; 0 aligns the wedges with the polygon,
+  // PI turns them outwards, the usual control without illusory contours.
+  pub inducer_rotation: f32,
+}
+
+#[wasm_bindgen]
+impl Kanizsa {
+
+  // A square with 100 unit sides and a support ratio of 0.5.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Kanizsa {
+    Kanizsa {
+      x: 0.0,
+      y: 0.0,
+      sides: 4,
+      spacing: 100.0,
+      support_ratio: 0.5,
+      rotation: 0.0,
+      inducer_rotation: 0.0,
+    }
+  }
+
+  // Reads a figure from a JSON object with any of the fields `x`, `y`,
+  // `sides`, `spacing`, `supportRatio`, `rotation` and `inducerRotation`;
+  // missing fields keep the defaults of `new`.
+  pub fn from_json(json: &str) -> Result<Kanizsa> {
+    let config = js_sys::JSON::parse(json)?;
+    let mut figure = Kanizsa::new();
+    read_config(&config, "x", &mut figure.x)?;
+    read_config(&config, "y", &mut figure.y)?;
+    let mut sides = figure.sides as f32;
+    read_config(&config, "sides", &mut sides)?;
+    figure.sides = sides as u32;
+    read_config(&config, "spacing", &mut figure.spacing)?;
+    read_config(&config, "supportRatio", &mut figure.support_ratio)?;
+    read_config(&config, "rotation", &mut figure.rotation)?;
+    read_config(&config, "inducerRotation", &mut figure.inducer_rotation)?;
+    Ok(figure)
+  }
+}
+
+impl Default for Kanizsa {
+  fn default() -> Kanizsa {
+    Kanizsa::new()
+  }
+}
+
+// Draws Kanizsa figures on top of a canvas' scene, with the same
+// immediate-mode behaviour as `Draw2D`.
+#[wasm_bindgen]
+pub struct KanizsaRenderer {
+  figures: Rc<RefCell<Figures>>,
+  layer: LayerSlot,
+}
+
+struct Figures {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl KanizsaRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<KanizsaRenderer> {
+    let context = canvas.context();
+    let mut program = ShaderProgram::new(&context, INDUCER_VERT_SHADER, INDUCER_FRAG_SHADER)?;
+    let layout = attribute_layout(
+      &program,
+      &[("center", 2), ("radius", 1), ("wedge_direction", 1), ("wedge_half_angle", 1)],
+    )?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_vec4("u_color", 0.0, 0.0, 0.0, 1.0);
+
+    let figures = Rc::new(RefCell::new(Figures {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(figures.clone(), Layer::Stimulus);
+    Ok(KanizsaRenderer { figures, layer })
+  }
+
+  // Layer the figures are drawn in; `Stimulus` by default.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Blend mode of the figures, `Alpha` unless set.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.figures.borrow_mut().blend_mode = mode;
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.figures.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // Colour of the inducers, black to begin with.
+  pub fn set_color(&self, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.figures.borrow_mut().program.set_vec4("u_color", r, g, b, a);
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.figures.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.figures.borrow_mut().batch.clear();
+  }
+
+  pub fn draw(&self, figure: &Kanizsa) -> Result<()> {
+    if figure.sides < 3 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "a Kanizsa figure needs at least 3 sides, got {}",
+        figure.sides
+      )));
+    }
+
+    let sides = figure.sides as f32;
+    let circumradius = figure.spacing / (2.0 * (PI / sides).sin());
+    let radius = figure.support_ratio.clamp(0.0, 1.0) * figure.spacing / 2.0;
+    // Half the polygon's interior angle.
+    let half_angle = PI / 2.0 - PI / sides;
+
+    let mut figures = self.figures.borrow_mut();
+    for corner in 0..figure.sides {
+      // The first two corners span the bottom side.
+      let angle = figure.rotation + PI / 2.0 + PI / sides + 2.0 * PI * corner as f32 / sides;
+      let x = figure.x + circumradius * angle.cos();
+      let y = figure.y + circumradius * angle.sin();
+      // The wedge points back at the centre.
+      let direction = angle + PI + figure.inducer_rotation;
+      figures.batch.push(&[x, y, radius, direction, half_angle]);
+    }
+    Ok(())
+  }
+}
+
+impl Overlay for Figures {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
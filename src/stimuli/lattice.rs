@@ -0,0 +1,88 @@
+use std::f32::consts::PI;
+
+use wasm_bindgen::prelude::*;
+
+use crate::random::Rng;
+
+// A lattice of dots for grouping by proximity: `columns` dots along the
+// first axis, `spacing_a` apart, and `rows` along the second, `spacing_b`
+// apart, centred on `x`, `y`. The second axis is `angle` radians clockwise
+// from the first, so PI / 2 gives a rectangular lattice and other angles
+// oblique ones. Lengths are in the renderer's units.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DotLattice {
+  pub x: f32,
+  pub y: f32,
+  pub columns: u32,
+  pub rows: u32,
+  pub spacing_a: f32,
+  pub spacing_b: f32,
+  // Between the axes.
+  pub angle: f32,
+  // Of the first axis, clockwise from rightwards.
+  pub orientation: f32,
+  // Largest random displacement of a dot along each axis; displacements
+  // are uniform and independent per dot.
+  pub jitter_a: f32,
+  pub jitter_b: f32,
+}
+
+#[wasm_bindgen]
+impl DotLattice {
+
+  // A square 7 x 7 lattice with 20 unit spacing and no jitter.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> DotLattice {
+    DotLattice {
+      x: 0.0,
+      y: 0.0,
+      columns: 7,
+      rows: 7,
+      spacing_a: 20.0,
+      spacing_b: 20.0,
+      angle: PI / 2.0,
+      orientation: 0.0,
+      jitter_a: 0.0,
+      jitter_b: 0.0,
+    }
+  }
+
+  // Dot positions as a flat list of x, y pairs, row by row, with fresh
+  // jitter on every call.
+  pub fn positions(&self) -> Vec<f32> {
+    self.positions_with(&mut Rng::from_entropy())
+  }
+
+  // Like `positions`, with the jitter drawn from `seed`, so the same seed
+  // gives the same lattice.
+  pub fn seeded_positions(&self, seed: u32) -> Vec<f32> {
+    self.positions_with(&mut Rng::new(seed as u64))
+  }
+}
+
+impl DotLattice {
+  pub(crate) fn positions_with(&self, rng: &mut Rng) -> Vec<f32> {
+    let axis_a = (self.orientation.cos(), self.orientation.sin());
+    let axis_b = ((self.orientation + self.angle).cos(), (self.orientation + self.angle).sin());
+    let first_column = (self.columns as f32 - 1.0) / 2.0;
+    let first_row = (self.rows as f32 - 1.0) / 2.0;
+
+    let mut positions = Vec::with_capacity(self.columns as usize * self.rows as usize * 2);
+    for row in 0..self.rows {
+      for column in 0..self.columns {
+        let a = (column as f32 - first_column) * self.spacing_a + rng.range(-1.0, 1.0) * self.jitter_a;
+        let b = (row as f32 - first_row) * self.spacing_b + rng.range(-1.0, 1.0) * self.jitter_b;
+        positions.push(self.x + a * axis_a.0 + b * axis_b.0);
+        positions.push(self.y + a * axis_a.1 + b * axis_b.1);
+      }
+    }
+    positions
+  }
+}
+
+impl Default for DotLattice {
+  fn default() -> DotLattice {
+    DotLattice::new()
+  }
+}
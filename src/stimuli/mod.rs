@@ -0,0 +1,152 @@
+// Parametric stimuli for psychophysics, each drawn on top of a canvas' scene
+// like the other overlays.
+
+mod checkerboard;
+mod contour;
+mod dots;
+mod flicker_photometry;
+mod gabor;
+mod grating;
+mod image;
+mod kanizsa;
+mod lattice;
+mod mondrian;
+mod noise;
+mod primitives;
+mod rdk;
+mod vernier;
+mod walker;
+
+pub(crate) use dots::DotLayer;
+pub(crate) use grating::{Grating, Gratings, PlaidBlend};
+
+use std::f32::consts::PI;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+use crate::random::Rng;
+
+// What stimulus positions and sizes are measured in. Pixels are drawing
+// buffer pixels from the top left, as in `Draw2D`; degrees of visual angle
+// are measured from the centre of the canvas, y also running down, at the
+// pixels per degree a `DisplayGeometry` gives. Frequencies are in cycles
+// per unit.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Units {
+  Pixels,
+  Degrees,
+}
+
+// Maps stimulus units to drawing buffer pixels.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Space {
+  units: Units,
+  pixels_per_degree: f32,
+}
+
+impl Space {
+  pub(crate) fn new() -> Space {
+    Space {
+      units: Units::Pixels,
+      pixels_per_degree: 1.0,
+    }
+  }
+
+  pub(crate) fn set(&mut self, units: Units, pixels_per_degree: f32) {
+    self.units = units;
+    self.pixels_per_degree = pixels_per_degree;
+  }
+
+  // Pixel position of the unit origin in a `width` x `height` canvas.
+  pub(crate) fn origin(&self, width: u32, height: u32) -> (f32, f32) {
+    match self.units {
+      Units::Pixels => (0.0, 0.0),
+      Units::Degrees => (width as f32 / 2.0, height as f32 / 2.0),
+    }
+  }
+
+  // Pixels per unit.
+  pub(crate) fn scale(&self) -> f32 {
+    match self.units {
+      Units::Pixels => 1.0,
+      Units::Degrees => self.pixels_per_degree,
+    }
+  }
+}
+
+// The outline of a stimulus region, e.g. the field of a random-dot display.
+// Its size is the width and height of the bounding box.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApertureShape {
+  Ellipse,
+  Rectangle,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Aperture {
+  pub(crate) shape: ApertureShape,
+  pub(crate) x: f32,
+  pub(crate) y: f32,
+  pub(crate) width: f32,
+  pub(crate) height: f32,
+}
+
+impl Aperture {
+  pub(crate) fn new(shape: ApertureShape, x: f32, y: f32, width: f32, height: f32) -> Result<Aperture> {
+    check_positive("aperture width", width)?;
+    check_positive("aperture height", height)?;
+    Ok(Aperture { shape, x, y, width, height })
+  }
+
+  // Whether the point `(dx, dy)` from the centre lies inside.
+  pub(crate) fn contains(&self, dx: f32, dy: f32) -> bool {
+    let (u, v) = (2.0 * dx / self.width, 2.0 * dy / self.height);
+    match self.shape {
+      ApertureShape::Ellipse => u * u + v * v <= 1.0,
+      ApertureShape::Rectangle => u.abs() <= 1.0 && v.abs() <= 1.0,
+    }
+  }
+
+  pub(crate) fn area(&self) -> f32 {
+    match self.shape {
+      ApertureShape::Ellipse => PI * self.width * self.height / 4.0,
+      ApertureShape::Rectangle => self.width * self.height,
+    }
+  }
+
+  // A uniformly distributed point inside, relative to the centre.
+  pub(crate) fn sample(&self, rng: &mut Rng) -> (f32, f32) {
+    loop {
+      let dx = rng.range(-0.5, 0.5) * self.width;
+      let dy = rng.range(-0.5, 0.5) * self.height;
+      if self.contains(dx, dy) {
+        return (dx, dy);
+      }
+    }
+  }
+}
+
+// Rejects zero, negative and NaN values of a parameter.
+pub(crate) fn check_positive(name: &str, value: f32) -> Result<()> {
+  if value > 0.0 {
+    Ok(())
+  } else {
+    Err(GestaltError::InvalidArgument(format!("{} must be positive, got {}", name, value)))
+  }
+}
+
+// Overwrites `value` with the number at `key` of a parsed JSON config, if
+// there is one.
+pub(crate) fn read_config(config: &JsValue, key: &str, value: &mut f32) -> Result<()> {
+  let field = js_sys::Reflect::get(config, &key.into())?;
+  if field.is_undefined() {
+    return Ok(());
+  }
+  *value = field
+    .as_f64()
+    .ok_or_else(|| GestaltError::InvalidArgument(format!("config field '{}' must be a number", key)))? as f32;
+  Ok(())
+}
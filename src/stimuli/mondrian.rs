@@ -0,0 +1,281 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::{linear_to_srgb, RGB_TO_XYZ};
+use crate::error::Result;
+use crate::gl_state;
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::random::Rng;
+use crate::shader::ShaderProgram;
+use crate::stimuli::{check_positive, Space, Units};
+
+// One opaque rectangle per instance, its corners in stimulus units.
+const RECT_VERT_SHADER: &str = r##"#version 300 es
+
+in vec4 bounds;
+in vec3 color;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+flat out vec3 v_color;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+  vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main()
+{
+  v_color = color;
+  vec2 corner = mix(bounds.xy, bounds.zw, CORNERS[gl_VertexID]);
+  vec2 position = u_origin + corner * u_scale;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+const RECT_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+flat in vec3 v_color;
+
+out vec4 outColor;
+
+void main()
+{
+  outColor = vec4(v_color, 1.0);
+}
+"##;
+
+// A Mondrian mask: a field of `count` overlapping random rectangles on a
+// base of the mean luminance, e.g. for continuous flash suppression. Sides
+// are uniform between `min_size` and `max_size`, and rectangles are cut off
+// at the field's edges. Each has a relative luminance drawn uniformly around
+// `mean_luminance` with an RMS contrast of `contrast`, clipped to 0 to 1;
+// chromatic masks give each a random hue of that luminance, as saturated as
+// `saturation` and the display allow. Lengths are in the renderer's units.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mondrian {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+  pub count: u32,
+  pub min_size: f32,
+  pub max_size: f32,
+  pub mean_luminance: f32,
+  pub contrast: f32,
+  pub chromatic: bool,
+  // 0 to 1.
+  pub saturation: f32,
+  // New patterns per second, each from the next seed; 0 for a static mask.
+  pub refresh_rate: f32,
+  pub seed: u32,
+}
+
+#[wasm_bindgen]
+impl Mondrian {
+
+  // A static, chromatic 400 unit square of 200 rectangles.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Mondrian {
+    Mondrian {
+      x: 0.0,
+      y: 0.0,
+      width: 400.0,
+      height: 400.0,
+      count: 200,
+      min_size: 20.0,
+      max_size: 100.0,
+      mean_luminance: 0.2,
+      contrast: 0.5,
+      chromatic: true,
+      saturation: 1.0,
+      refresh_rate: 0.0,
+      seed: 0,
+    }
+  }
+}
+
+impl Mondrian {
+  // Field bounds and sRGB colour of each rectangle of pattern `index`,
+  // base first.
+  fn rectangles(&self, index: u32) -> Vec<[f32; 7]> {
+    let (left, top) = (self.x - self.width / 2.0, self.y - self.height / 2.0);
+    let (right, bottom) = (left + self.width, top + self.height);
+    let mut rng = Rng::new(self.seed.wrapping_add(index) as u64);
+    let mean = self.mean_luminance.clamp(0.0, 1.0);
+    let grey = linear_to_srgb(mean);
+
+    let mut rectangles = Vec::with_capacity(self.count as usize + 1);
+    rectangles.push([left, top, right, bottom, grey, grey, grey]);
+    // A uniform distribution's half width is sqrt(3) standard deviations.
+    let spread = 3f32.sqrt() * self.contrast.max(0.0) * mean;
+    for _ in 0..self.count {
+      let (x, y) = (rng.range(left, right), rng.range(top, bottom));
+      let width = rng.range(self.min_size, self.max_size).max(0.0);
+      let height = rng.range(self.min_size, self.max_size).max(0.0);
+      let luminance = rng.range(mean - spread, mean + spread).clamp(0.0, 1.0);
+      let [r, g, b] = if self.chromatic {
+        hue_of_luminance(&mut rng, luminance, self.saturation)
+      } else {
+        [luminance; 3]
+      }
+      .map(linear_to_srgb);
+      rectangles.push([
+        (x - width / 2.0).max(left),
+        (y - height / 2.0).max(top),
+        (x + width / 2.0).min(right),
+        (y + height / 2.0).min(bottom),
+        r, g, b,
+      ]);
+    }
+    rectangles
+  }
+}
+
+impl Default for Mondrian {
+  fn default() -> Mondrian {
+    Mondrian::new()
+  }
+}
+
+// Linear RGB of a random hue with relative luminance `luminance`: a fully
+// saturated hue scaled to that luminance, then mixed with grey of the same
+// luminance until the display can show it.
+fn hue_of_luminance(rng: &mut Rng, luminance: f32, saturation: f32) -> [f32; 3] {
+  let h = rng.range(0.0, 6.0);
+  let hue = [(h - 3.0).abs() - 1.0, 2.0 - (h - 2.0).abs(), 2.0 - (h - 4.0).abs()].map(|c| c.clamp(0.0, 1.0));
+  let hue_luminance: f32 = (0..3).map(|i| hue[i] * RGB_TO_XYZ[1][i]).sum();
+  let scaled = hue.map(|c| c * luminance / hue_luminance);
+  let limit = scaled
+    .iter()
+    .filter(|&&c| c > 1.0)
+    .map(|&c| (1.0 - luminance) / (c - luminance))
+    .fold(1.0f32, f32::min);
+  let mix = saturation.clamp(0.0, 1.0) * limit;
+  scaled.map(|c| luminance + mix * (c - luminance))
+}
+
+// Draws Mondrian masks on top of a canvas' scene, with the same
+// immediate-mode behaviour as `Draw2D`. Patterns are laid out when the
+// frame is drawn, from the frames drawn since the renderer was created or
+// `restart_clock` was called and the refresh rate given by
+// `set_frame_rate`, so a refreshing mask changes on frame boundaries only.
+#[wasm_bindgen]
+pub struct MondrianRenderer {
+  masks: Rc<RefCell<Masks>>,
+  layer: LayerSlot,
+}
+
+struct Masks {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  masks: Vec<Mondrian>,
+  frame: u32,
+  frame_rate: f32,
+  auto_clear: bool,
+}
+
+#[wasm_bindgen]
+impl MondrianRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<MondrianRenderer> {
+    let context = canvas.context();
+    let program = ShaderProgram::new(&context, RECT_VERT_SHADER, RECT_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("bounds", 4), ("color", 3)])?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+
+    let masks = Rc::new(RefCell::new(Masks {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      masks: Vec::new(),
+      frame: 0,
+      frame_rate: 60.0,
+      auto_clear: true,
+    }));
+    let layer = canvas.add_overlay(masks.clone(), Layer::Stimulus);
+    Ok(MondrianRenderer { masks, layer })
+  }
+
+  // Layer the masks are drawn in, `Stimulus` to begin with. `Overlay` puts
+  // them over everything but debug info.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.masks.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // The display's refresh rate in Hz, 60 to begin with.
+  pub fn set_frame_rate(&self, frame_rate: f32) -> Result<()> {
+    check_positive("frame rate", frame_rate)?;
+    self.masks.borrow_mut().frame_rate = frame_rate;
+    Ok(())
+  }
+
+  // Makes the next frame drawn frame 0, e.g. at mask onset.
+  pub fn restart_clock(&self) {
+    self.masks.borrow_mut().frame = 0;
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.masks.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.masks.borrow_mut().masks.clear();
+  }
+
+  pub fn draw(&self, mask: &Mondrian) {
+    self.masks.borrow_mut().masks.push(*mask);
+  }
+}
+
+impl Overlay for Masks {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.masks.is_empty() {
+      for mask in &self.masks {
+        // Small enough not to skip a pattern through rounding.
+        let index = (self.frame as f32 * mask.refresh_rate.max(0.0) / self.frame_rate + 1e-3).floor() as u32;
+        for rectangle in mask.rectangles(index) {
+          self.batch.push(&rectangle);
+        }
+      }
+
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      gl_state::set_blend_mode(&self.context, None);
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+      self.batch.clear();
+      self.frame += 1;
+    }
+
+    if self.auto_clear {
+      self.masks.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
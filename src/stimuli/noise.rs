@@ -0,0 +1,272 @@
+use std::f32::consts::TAU;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::error::{GestaltError, Result};
+use crate::graphics::WebGlCanvas;
+use crate::random::Rng;
+use crate::stimuli::check_positive;
+use crate::texture::{Texture, TextureFormat};
+
+// `White` is uniform and `Gaussian` normally distributed, both independent
+// per pixel. `Pink` has an amplitude spectrum falling as 1 / f^slope, 1 for
+// pink noise proper, 0 for white and 2 for brown. `Perlin` is smooth
+// gradient noise on a lattice of `scale` pixels, with finer octaves added at
+// half the amplitude each.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+  White,
+  Gaussian,
+  Pink,
+  Perlin,
+}
+
+// A noise field of `width` x `height` pixels, generated on the CPU. The same
+// parameters and seed always give the same field. Luminance is normalised
+// to `mean` with an RMS contrast, standard deviation over mean, of
+// `rms_contrast`; values outside 0 to 1 are clipped.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Noise {
+  pub width: u32,
+  pub height: u32,
+  pub kind: NoiseKind,
+  pub seed: u32,
+  // Spectral slope of `Pink` noise.
+  pub slope: f32,
+  // Lattice spacing of `Perlin` noise in pixels, and its number of octaves.
+  pub scale: f32,
+  pub octaves: u32,
+  pub mean: f32,
+  pub rms_contrast: f32,
+}
+
+#[wasm_bindgen]
+impl Noise {
+
+  // A 256 x 256 field of white noise around mid grey with an RMS contrast
+  // of 0.2.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Noise {
+    Noise {
+      width: 256,
+      height: 256,
+      kind: NoiseKind::White,
+      seed: 0,
+      slope: 1.0,
+      scale: 32.0,
+      octaves: 4,
+      mean: 0.5,
+      rms_contrast: 0.2,
+    }
+  }
+
+  // Luminance values row by row from the top.
+  pub fn generate(&self) -> Result<Vec<f32>> {
+    if self.width == 0 || self.height == 0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "noise needs a size, got {} x {}",
+        self.width, self.height
+      )));
+    }
+    if self.rms_contrast.is_nan() || self.rms_contrast < 0.0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "RMS contrast must not be negative, got {}",
+        self.rms_contrast
+      )));
+    }
+
+    let mut rng = Rng::new(self.seed as u64);
+    let (width, height) = (self.width as usize, self.height as usize);
+    let mut field = match self.kind {
+      NoiseKind::White => (0..width * height).map(|_| rng.range(-1.0, 1.0)).collect(),
+      NoiseKind::Gaussian => gaussian(&mut rng, width * height),
+      NoiseKind::Pink => spectral(&mut rng, width, height, self.slope),
+      NoiseKind::Perlin => {
+        check_positive("Perlin noise scale", self.scale)?;
+        perlin(&mut rng, width, height, self.scale, self.octaves.max(1))
+      }
+    };
+    normalise(&mut field, self.mean, self.rms_contrast);
+    Ok(field)
+  }
+
+  // Generates the field into a grey, opaque float texture of the same size,
+  // sampled nearest-neighbour so every noise pixel stays sharp. Draw it as a
+  // background with a `SpriteBatch` or bind it to a shader.
+  pub fn texture(&self, canvas: &WebGlCanvas) -> Result<Texture> {
+    let field = self.generate()?;
+    let width = self.width as usize;
+    // Texture rows go bottom to top.
+    let mut pixels = Vec::with_capacity(field.len() * 4);
+    for row in field.chunks(width).rev() {
+      for &value in row {
+        pixels.extend_from_slice(&[value, value, value, 1.0]);
+      }
+    }
+    let texture = canvas.create_texture_from_f32_data(self.width, self.height, TextureFormat::Rgba, &pixels)?;
+    texture.set_filter(WebGl2RenderingContext::NEAREST);
+    Ok(texture)
+  }
+}
+
+impl Default for Noise {
+  fn default() -> Noise {
+    Noise::new()
+  }
+}
+
+// Scales `field` to zero mean and unit standard deviation, then to
+// `mean * (1 + rms_contrast * value)` within 0 to 1.
+fn normalise(field: &mut [f32], mean: f32, rms_contrast: f32) {
+  let count = field.len() as f32;
+  let average = field.iter().sum::<f32>() / count;
+  let deviation = (field.iter().map(|value| (value - average).powi(2)).sum::<f32>() / count).sqrt();
+  let gain = if deviation > 0.0 { rms_contrast / deviation } else { 0.0 };
+  for value in field.iter_mut() {
+    *value = (mean * (1.0 + gain * (*value - average))).clamp(0.0, 1.0);
+  }
+}
+
+// Standard normal values by the Box-Muller transform.
+fn gaussian(rng: &mut Rng, count: usize) -> Vec<f32> {
+  let mut values = Vec::with_capacity(count + 1);
+  while values.len() < count {
+    // 1 - u keeps the logarithm finite.
+    let radius = (-2.0 * (1.0 - rng.next_f32()).ln()).sqrt();
+    let angle = TAU * rng.next_f32();
+    values.push(radius * angle.cos());
+    values.push(radius * angle.sin());
+  }
+  values.truncate(count);
+  values
+}
+
+// Gaussian white noise filtered to an amplitude spectrum of 1 / f^slope.
+// The filtering is done at the next power of two sizes, so the field only
+// wraps around when its sizes are powers of two.
+fn spectral(rng: &mut Rng, width: usize, height: usize, slope: f32) -> Vec<f32> {
+  let (columns, rows) = (width.next_power_of_two(), height.next_power_of_two());
+  let mut real = gaussian(rng, columns * rows);
+  let mut imaginary = vec![0.0; columns * rows];
+  fft_2d(&mut real, &mut imaginary, columns, rows, false);
+
+  for row in 0..rows {
+    // Frequencies above Nyquist are the negative ones.
+    let fy = row.min(rows - row) as f32 / rows as f32;
+    for column in 0..columns {
+      let fx = column.min(columns - column) as f32 / columns as f32;
+      let frequency = (fx * fx + fy * fy).sqrt();
+      // No DC, which normalising would remove anyway.
+      let gain = if frequency > 0.0 { frequency.powf(-slope) } else { 0.0 };
+      real[row * columns + column] *= gain;
+      imaginary[row * columns + column] *= gain;
+    }
+  }
+
+  fft_2d(&mut real, &mut imaginary, columns, rows, true);
+  let mut field = Vec::with_capacity(width * height);
+  for row in real.chunks(columns).take(height) {
+    field.extend_from_slice(&row[..width]);
+  }
+  field
+}
+
+// Row then column FFTs of a `columns` x `rows` complex field, both powers
+// of two. The inverse is unscaled.
+fn fft_2d(real: &mut [f32], imaginary: &mut [f32], columns: usize, rows: usize, inverse: bool) {
+  for row in 0..rows {
+    let span = row * columns..(row + 1) * columns;
+    fft(&mut real[span.clone()], &mut imaginary[span], inverse);
+  }
+  let mut column_real = vec![0.0; rows];
+  let mut column_imaginary = vec![0.0; rows];
+  for column in 0..columns {
+    for row in 0..rows {
+      column_real[row] = real[row * columns + column];
+      column_imaginary[row] = imaginary[row * columns + column];
+    }
+    fft(&mut column_real, &mut column_imaginary, inverse);
+    for row in 0..rows {
+      real[row * columns + column] = column_real[row];
+      imaginary[row * columns + column] = column_imaginary[row];
+    }
+  }
+}
+
+// Iterative radix-2 Cooley-Tukey FFT in place.
+fn fft(real: &mut [f32], imaginary: &mut [f32], inverse: bool) {
+  let count = real.len();
+  let bits = count.trailing_zeros();
+  if bits == 0 {
+    return;
+  }
+  for index in 0..count {
+    let reversed = index.reverse_bits() >> (usize::BITS - bits);
+    if index < reversed {
+      real.swap(index, reversed);
+      imaginary.swap(index, reversed);
+    }
+  }
+
+  let sign = if inverse { 1.0 } else { -1.0 };
+  let mut size = 2;
+  while size <= count {
+    let step = sign * TAU / size as f32;
+    for start in (0..count).step_by(size) {
+      for offset in 0..size / 2 {
+        let (sin, cos) = (step * offset as f32).sin_cos();
+        let (even, odd) = (start + offset, start + offset + size / 2);
+        let odd_real = real[odd] * cos - imaginary[odd] * sin;
+        let odd_imaginary = real[odd] * sin + imaginary[odd] * cos;
+        real[odd] = real[even] - odd_real;
+        imaginary[odd] = imaginary[even] - odd_imaginary;
+        real[even] += odd_real;
+        imaginary[even] += odd_imaginary;
+      }
+    }
+    size *= 2;
+  }
+}
+
+// Fractal Perlin noise: octaves of gradient noise, each at twice the
+// frequency and half the amplitude of the one before.
+fn perlin(rng: &mut Rng, width: usize, height: usize, scale: f32, octaves: u32) -> Vec<f32> {
+  // A shuffled permutation, repeated so lookups need no wrapping.
+  let mut permutation: Vec<usize> = (0..256).collect();
+  for index in (1..256).rev() {
+    permutation.swap(index, rng.below(index + 1));
+  }
+  let permutation: Vec<usize> = permutation.iter().chain(permutation.iter()).copied().collect();
+
+  let gradient = |x: usize, y: usize, dx: f32, dy: f32| {
+    let angle = permutation[permutation[x & 255] + (y & 255)] as f32 * TAU / 256.0;
+    dx * angle.cos() + dy * angle.sin()
+  };
+  let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+
+  let mut field = vec![0.0; width * height];
+  for octave in 0..octaves {
+    let frequency = (1u32 << octave) as f32 / scale;
+    let amplitude = 0.5f32.powi(octave as i32);
+    for y in 0..height {
+      for x in 0..width {
+        let (u, v) = (x as f32 * frequency, y as f32 * frequency);
+        let (cell_x, cell_y) = (u.floor(), v.floor());
+        let (dx, dy) = (u - cell_x, v - cell_y);
+        let (cx, cy) = (cell_x as usize, cell_y as usize);
+        let top = lerp(gradient(cx, cy, dx, dy), gradient(cx + 1, cy, dx - 1.0, dy), fade(dx));
+        let bottom = lerp(gradient(cx, cy + 1, dx, dy - 1.0), gradient(cx + 1, cy + 1, dx - 1.0, dy - 1.0), fade(dx));
+        field[y * width + x] += amplitude * lerp(top, bottom, fade(dy));
+      }
+    }
+  }
+  field
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+  a + (b - a) * t
+}
@@ -0,0 +1,325 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::Result;
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::{check_positive, ApertureShape, Space, Units};
+
+// Values of the `kind` attribute.
+const CROSS: f32 = 0.0;
+const BULLSEYE: f32 = 1.0;
+const ARROW: f32 = 2.0;
+const ELLIPSE_MASK: f32 = 3.0;
+const RECTANGLE_MASK: f32 = 4.0;
+
+// One primitive per instance: its centre, half sizes along and across its
+// axis and shape parameters in stimulus units, the axis' orientation and
+// the kind of shape. Masks cover the whole canvas instead of their box.
+const PRIMITIVE_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in vec2 half_size;
+in vec2 params;
+in float orientation;
+in float kind;
+in vec4 color;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+// In pixels, along and across the axis.
+out vec2 v_local;
+flat out vec2 v_half_size;
+flat out vec2 v_params;
+flat out int v_kind;
+flat out vec4 v_color;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  v_half_size = half_size * u_scale;
+  v_params = params * u_scale;
+  v_kind = int(kind + 0.5);
+  v_color = color;
+
+  // Orientation 0 points right, turning clockwise.
+  vec2 along = vec2(cos(orientation), sin(orientation));
+  vec2 across = vec2(-along.y, along.x);
+  vec2 origin = u_origin + center * u_scale;
+  vec2 corner = CORNERS[gl_VertexID];
+
+  if (v_kind >= 3) {
+    gl_Position = vec4(corner, 0.0, 1.0);
+    vec2 offset = (corner * vec2(0.5, -0.5) + 0.5) * u_resolution - origin;
+    v_local = vec2(dot(offset, along), dot(offset, across));
+    return;
+  }
+
+  // One pixel to spare for the antialiased edges.
+  v_local = corner * (v_half_size + 1.0);
+  vec2 position = origin + along * v_local.x + across * v_local.y;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// Shapes are signed distance functions in pixels, negative inside, turned
+// into coverage with a one pixel wide edge. Masks are opaque outside, fading
+// in over `v_params.x` pixels inside their outline along a raised cosine.
+const PRIMITIVE_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+in vec2 v_local;
+flat in vec2 v_half_size;
+flat in vec2 v_params;
+flat in int v_kind;
+flat in vec4 v_color;
+
+out vec4 outColor;
+
+const float PI = 3.14159265358979;
+
+float box(vec2 p, vec2 half_size)
+{
+  vec2 d = abs(p) - half_size;
+  return length(max(d, 0.0)) + min(max(d.x, d.y), 0.0);
+}
+
+// An isosceles triangle pointing right with its tip at `tip`, `depth`
+// long and `half_width` wide at the base, after Inigo Quilez.
+float head(vec2 p, float tip, float depth, float half_width)
+{
+  vec2 q = vec2(half_width, depth);
+  p = vec2(abs(p.y), tip - p.x);
+  vec2 a = p - q * clamp(dot(p, q) / dot(q, q), 0.0, 1.0);
+  vec2 b = p - q * vec2(clamp(p.x / q.x, 0.0, 1.0), 1.0);
+  vec2 d = min(vec2(dot(a, a), p.y * q.x - p.x * q.y), vec2(dot(b, b), q.y - p.y));
+  return -sqrt(d.x) * sign(d.y);
+}
+
+void main()
+{
+  vec2 p = v_local;
+  float alpha;
+  if (v_kind == 0) {
+    float half_width = v_params.x * 0.5;
+    float d = min(box(p, vec2(v_half_size.x, half_width)), box(p, vec2(half_width, v_half_size.y)));
+    alpha = clamp(0.5 - d, 0.0, 1.0);
+  } else if (v_kind == 1) {
+    // Missing parts stay far away, rather than leaving a hairline.
+    float r = length(p);
+    float ring = v_params.x > 0.0 ? abs(r - v_half_size.x + v_params.x * 0.5) - v_params.x * 0.5 : 1e6;
+    float d = min(ring, v_params.y > 0.0 ? r - v_params.y * 0.5 : 1e6);
+    alpha = clamp(0.5 - d, 0.0, 1.0);
+  } else if (v_kind == 2) {
+    float head_length = min(v_params.y, 2.0 * v_half_size.x);
+    float shaft_length = v_half_size.x - head_length * 0.5;
+    float shaft = box(p + vec2(head_length * 0.5, 0.0), vec2(shaft_length, v_params.x * 0.5));
+    float d = min(shaft, head(p, v_half_size.x, head_length, v_half_size.y));
+    alpha = clamp(0.5 - d, 0.0, 1.0);
+  } else {
+    float d = v_kind == 3
+      ? (length(p / v_half_size) - 1.0) * min(v_half_size.x, v_half_size.y)
+      : box(p, v_half_size);
+    if (v_params.x > 0.0) {
+      float t = clamp((d + v_params.x) / v_params.x, 0.0, 1.0);
+      alpha = 0.5 - 0.5 * cos(PI * t);
+    } else {
+      alpha = clamp(d + 0.5, 0.0, 1.0);
+    }
+  }
+  if (alpha <= 0.0) {
+    discard;
+  }
+  outColor = vec4(v_color.rgb, v_color.a * alpha);
+}
+"##;
+
+// Draws the small shapes most displays need, fixation marks, arrow cues and
+// apertures, on top of a canvas' scene with the same immediate-mode
+// behaviour as `Draw2D`. Positions and sizes are in stimulus units, so in
+// degrees of visual angle they hold across displays. Shapes are drawn in the
+// order they were queued, so a fixation mark queued after an aperture lies
+// on top of it.
+#[wasm_bindgen]
+pub struct PrimitiveRenderer {
+  primitives: Rc<RefCell<Primitives>>,
+  layer: LayerSlot,
+}
+
+struct Primitives {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  color: [f32; 4],
+  background: [f32; 4],
+  auto_clear: bool,
+  blend_mode: BlendMode,
+}
+
+#[wasm_bindgen]
+impl PrimitiveRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<PrimitiveRenderer> {
+    let context = canvas.context();
+    let program = ShaderProgram::new(&context, PRIMITIVE_VERT_SHADER, PRIMITIVE_FRAG_SHADER)?;
+    let layout = attribute_layout(
+      &program,
+      &[("center", 2), ("half_size", 2), ("params", 2), ("orientation", 1), ("kind", 1), ("color", 4)],
+    )?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+
+    let primitives = Rc::new(RefCell::new(Primitives {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      color: [1.0, 1.0, 1.0, 1.0],
+      background: [0.5, 0.5, 0.5, 1.0],
+      auto_clear: true,
+      blend_mode: BlendMode::Alpha,
+    }));
+    let layer = canvas.add_overlay(primitives.clone(), Layer::Stimulus);
+    Ok(PrimitiveRenderer { primitives, layer })
+  }
+
+  // Layer of the canvas the shapes go in. `Stimulus` unless moved.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // How primitives combine with the scene behind them. Defaults to `Alpha`.
+  pub fn set_blend_mode(&self, mode: BlendMode) {
+    self.primitives.borrow_mut().blend_mode = mode;
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.primitives.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // Colour of the fixation marks and cues drawn from now on, white to
+  // begin with.
+  pub fn set_color(&self, color: &Color) {
+    self.primitives.borrow_mut().color = color.to_array();
+  }
+
+  // Colour apertures mask the rest of the canvas with, mid grey to begin
+  // with.
+  pub fn set_background(&self, color: &Color) {
+    self.primitives.borrow_mut().background = color.to_array();
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.primitives.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.primitives.borrow_mut().batch.clear();
+  }
+
+  // A plus sign `size` wide and high, with bars `thickness` wide.
+  pub fn draw_fixation_cross(&self, x: f32, y: f32, size: f32, thickness: f32) -> Result<()> {
+    check_positive("fixation cross size", size)?;
+    check_positive("fixation cross thickness", thickness)?;
+    let mut primitives = self.primitives.borrow_mut();
+    let color = primitives.color;
+    primitives.push(CROSS, x, y, (size / 2.0, size / 2.0), (thickness, 0.0), 0.0, color);
+    Ok(())
+  }
+
+  // A ring of `diameter` and `ring_width` around a dot of `dot_diameter`;
+  // a `ring_width` of 0 leaves the dot alone.
+  pub fn draw_bullseye(&self, x: f32, y: f32, diameter: f32, ring_width: f32, dot_diameter: f32) -> Result<()> {
+    check_positive("bullseye diameter", diameter)?;
+    let mut primitives = self.primitives.borrow_mut();
+    let color = primitives.color;
+    let radius = diameter / 2.0;
+    primitives.push(BULLSEYE, x, y, (radius, radius), (ring_width.max(0.0), dot_diameter.max(0.0)), 0.0, color);
+    Ok(())
+  }
+
+  // An arrow `length` long centred on `x`, `y`, pointing `direction`
+  // radians clockwise from rightwards. Its shaft is `thickness` wide and its
+  // head, four times that, as long as it is wide.
+  pub fn draw_arrow(&self, x: f32, y: f32, length: f32, thickness: f32, direction: f32) -> Result<()> {
+    check_positive("arrow length", length)?;
+    check_positive("arrow thickness", thickness)?;
+    let mut primitives = self.primitives.borrow_mut();
+    let color = primitives.color;
+    let head = 4.0 * thickness;
+    primitives.push(ARROW, x, y, (length / 2.0, head / 2.0), (thickness, head), direction, color);
+    Ok(())
+  }
+
+  // Covers the canvas outside the given region in the background colour,
+  // so that only what lies inside shows. With an `edge` above 0 the mask
+  // fades in over that width inside the outline along a raised cosine,
+  // instead of cutting off sharply.
+  pub fn draw_aperture(&self, shape: ApertureShape, x: f32, y: f32, width: f32, height: f32, edge: f32) -> Result<()> {
+    check_positive("aperture width", width)?;
+    check_positive("aperture height", height)?;
+    let kind = match shape {
+      ApertureShape::Ellipse => ELLIPSE_MASK,
+      ApertureShape::Rectangle => RECTANGLE_MASK,
+    };
+    let mut primitives = self.primitives.borrow_mut();
+    let background = primitives.background;
+    primitives.push(kind, x, y, (width / 2.0, height / 2.0), (edge.max(0.0), 0.0), 0.0, background);
+    Ok(())
+  }
+}
+
+impl Primitives {
+  #[allow(clippy::too_many_arguments)]
+  fn push(&mut self, kind: f32, x: f32, y: f32, half_size: (f32, f32), params: (f32, f32), orientation: f32, color: [f32; 4]) {
+    let [red, green, blue, alpha] = color;
+    self.batch.push(&[
+      x, y,
+      half_size.0, half_size.1,
+      params.0, params.1,
+      orientation,
+      kind,
+      red, green, blue, alpha,
+    ]);
+  }
+}
+
+impl Overlay for Primitives {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let context = &self.context;
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      gl_state::set_blend_mode(context, Some(self.blend_mode));
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
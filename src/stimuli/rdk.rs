@@ -0,0 +1,245 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::color::Color;
+use crate::error::Result;
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::random::Rng;
+use crate::stimuli::dots::DotLayer;
+use crate::stimuli::{check_positive, Aperture, ApertureShape, Units};
+
+#[derive(Clone, Copy, Debug)]
+struct Dot {
+  // Relative to the aperture centre.
+  x: f32,
+  y: f32,
+  // Frames since the dot was placed.
+  age: u32,
+}
+
+// A random-dot kinematogram: dots in an aperture of which a `coherence`
+// fraction moves in one direction while the rest move randomly. Every
+// `draw` advances the display by exactly one frame, so motion is locked to
+// the display's refresh: dots step `speed / frame_rate` units each time.
+// Each frame a new random subset of the dots carries the signal, and every
+// other dot steps in a random direction of its own. Dots leaving the
+// aperture or outliving their lifetime are replotted at random.
+#[wasm_bindgen]
+pub struct RandomDotKinematogram {
+  dots: Rc<RefCell<Dots>>,
+  layer: LayerSlot,
+}
+
+struct Dots {
+  layer: DotLayer,
+  rng: Rng,
+  aperture: Aperture,
+  dots: Vec<Dot>,
+  dot_size: f32,
+  color: [f32; 4],
+  direction: f32,
+  speed: f32,
+  frame_rate: f32,
+  coherence: f32,
+  // In frames, 0 for unlimited.
+  lifetime: u32,
+}
+
+#[wasm_bindgen]
+impl RandomDotKinematogram {
+
+  // 100 white dots of 3 pixels in a 200 pixel circle at the origin, fully
+  // coherent and moving right at 100 units per second.
+  pub fn new(canvas: &WebGlCanvas) -> Result<RandomDotKinematogram> {
+    let mut dots = Dots {
+      layer: DotLayer::new(&canvas.context())?,
+      rng: Rng::from_entropy(),
+      aperture: Aperture::new(ApertureShape::Ellipse, 0.0, 0.0, 200.0, 200.0)?,
+      dots: Vec::new(),
+      dot_size: 3.0,
+      color: [1.0, 1.0, 1.0, 1.0],
+      direction: 0.0,
+      speed: 100.0,
+      frame_rate: 60.0,
+      coherence: 1.0,
+      lifetime: 0,
+    };
+    dots.set_count(100);
+
+    let dots = Rc::new(RefCell::new(dots));
+    let layer = canvas.add_overlay(dots.clone(), Layer::Stimulus);
+    Ok(RandomDotKinematogram { dots, layer })
+  }
+
+  // Layer the kinematogram is drawn in, `Stimulus` to begin with.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.dots.borrow_mut().layer.space.set(units, pixels_per_degree);
+  }
+
+  // Centre and bounding box size of the aperture, in stimulus units. All
+  // dots are replotted; the dot count stays.
+  pub fn set_aperture(&self, shape: ApertureShape, x: f32, y: f32, width: f32, height: f32) -> Result<()> {
+    let mut dots = self.dots.borrow_mut();
+    dots.aperture = Aperture::new(shape, x, y, width, height)?;
+    let count = dots.dots.len();
+    dots.set_count(count);
+    Ok(())
+  }
+
+  pub fn set_dot_count(&self, count: usize) {
+    self.dots.borrow_mut().set_count(count);
+  }
+
+  // Sets the dot count from a density in dots per square unit, for the
+  // current aperture.
+  pub fn set_density(&self, density: f32) {
+    let mut dots = self.dots.borrow_mut();
+    let count = (density.max(0.0) * dots.aperture.area()).round() as usize;
+    dots.set_count(count);
+  }
+
+  pub fn dot_count(&self) -> usize {
+    self.dots.borrow().dots.len()
+  }
+
+  // Dot diameter in pixels.
+  pub fn set_dot_size(&self, size: f32) {
+    self.dots.borrow_mut().dot_size = size.max(0.0);
+  }
+
+  pub fn set_color(&self, color: &Color) {
+    self.dots.borrow_mut().color = color.to_array();
+  }
+
+  // Signal direction in radians, 0 being rightwards and positive angles
+  // turning clockwise on screen.
+  pub fn set_direction(&self, direction: f32) {
+    self.dots.borrow_mut().direction = direction;
+  }
+
+  // In units per second at the refresh rate given by `set_frame_rate`.
+  pub fn set_speed(&self, speed: f32) {
+    self.dots.borrow_mut().speed = speed;
+  }
+
+  // The display's refresh rate in Hz, 60 to begin with.
+  pub fn set_frame_rate(&self, frame_rate: f32) -> Result<()> {
+    check_positive("frame rate", frame_rate)?;
+    self.dots.borrow_mut().frame_rate = frame_rate;
+    Ok(())
+  }
+
+  // Fraction of dots moving in the signal direction each frame, 0 to 1.
+  pub fn set_coherence(&self, coherence: f32) {
+    self.dots.borrow_mut().coherence = coherence.clamp(0.0, 1.0);
+  }
+
+  // Frames a dot lives before it is replotted, 0 for as long as it stays
+  // in the aperture. Ages start out staggered so dots do not all expire at
+  // once.
+  pub fn set_lifetime(&self, frames: u32) {
+    let mut dots = self.dots.borrow_mut();
+    dots.lifetime = frames;
+    let count = dots.dots.len();
+    dots.set_count(count);
+  }
+
+  // Restarts the random sequence from `seed` and replots all dots, so the
+  // same seed and settings give the same dots frame by frame. Seeded from
+  // `Math.random` to begin with.
+  pub fn set_seed(&self, seed: u32) {
+    let mut dots = self.dots.borrow_mut();
+    dots.rng = Rng::new(seed as u64);
+    let count = dots.dots.len();
+    dots.set_count(count);
+  }
+
+  // Replots all dots.
+  pub fn reset(&self) {
+    let mut dots = self.dots.borrow_mut();
+    let count = dots.dots.len();
+    dots.set_count(count);
+  }
+
+  // Advances the dots by one frame and draws them in this frame.
+  pub fn draw(&self) {
+    let mut dots = self.dots.borrow_mut();
+    dots.step();
+    dots.queue();
+  }
+}
+
+impl Dots {
+  // Replaces all dots with `count` newly placed ones.
+  fn set_count(&mut self, count: usize) {
+    self.dots.clear();
+    for _ in 0..count {
+      let mut dot = self.place();
+      if self.lifetime > 0 {
+        dot.age = self.rng.below(self.lifetime as usize) as u32;
+      }
+      self.dots.push(dot);
+    }
+  }
+
+  fn place(&mut self) -> Dot {
+    let (x, y) = self.aperture.sample(&mut self.rng);
+    Dot { x, y, age: 0 }
+  }
+
+  fn step(&mut self) {
+    let count = self.dots.len();
+    if count == 0 {
+      return;
+    }
+
+    // Move the signal dots to the front with a partial Fisher-Yates shuffle.
+    let signal = (self.coherence * count as f32).round() as usize;
+    for index in 0..signal {
+      let other = index + self.rng.below(count - index);
+      self.dots.swap(index, other);
+    }
+
+    let step = self.speed / self.frame_rate;
+    for index in 0..count {
+      let direction = if index < signal { self.direction } else { self.rng.range(0.0, 2.0 * PI) };
+      let dot = &mut self.dots[index];
+      dot.x += step * direction.cos();
+      dot.y += step * direction.sin();
+      dot.age += 1;
+
+      let expired = self.lifetime > 0 && dot.age >= self.lifetime;
+      if expired || !self.aperture.contains(dot.x, dot.y) {
+        self.dots[index] = self.place();
+      }
+    }
+  }
+
+  fn queue(&mut self) {
+    for dot in &self.dots {
+      self.layer.push(self.aperture.x + dot.x, self.aperture.y + dot.y, self.dot_size, self.color);
+    }
+  }
+}
+
+impl Overlay for Dots {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    self.layer.draw(width, height);
+    self.layer.clear();
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.layer.restore(context)
+  }
+}
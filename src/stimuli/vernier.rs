@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::color::Color;
+use crate::error::Result;
+use crate::gl_state;
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::shader::ShaderProgram;
+use crate::stimuli::{Space, Units};
+
+// One bar per instance, with its centre and half sizes across and along
+// its axis in stimulus units.
+const BAR_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 center;
+in vec2 half_size;
+in float orientation;
+
+uniform vec2 u_resolution;
+uniform vec2 u_origin;
+uniform float u_scale;
+
+out vec2 v_local;
+flat out vec2 v_half_size;
+
+const vec2 CORNERS[6] = vec2[6](
+  vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+  vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main()
+{
+  // In pixels, with one to spare for the antialiased edges.
+  v_half_size = half_size * u_scale;
+  v_local = CORNERS[gl_VertexID] * (v_half_size + 1.0);
+
+  // Orientation 0 is vertical, turning clockwise.
+  vec2 along = vec2(-sin(orientation), cos(orientation));
+  vec2 across = vec2(along.y, -along.x);
+  vec2 position = u_origin + center * u_scale + across * v_local.x + along * v_local.y;
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+}
+"##;
+
+// Pixel coverage by a box filter: exact for edges along the pixel grid, so
+// positions hold to a fraction of a pixel. Bar and background are mixed in
+// linear light, as the display adds light; pixels the bar does not touch
+// are left alone.
+const BAR_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform vec4 u_color;
+uniform vec4 u_background;
+
+in vec2 v_local;
+flat in vec2 v_half_size;
+
+out vec4 outColor;
+
+vec3 to_linear(vec3 c)
+{
+  return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+}
+
+vec3 to_srgb(vec3 c)
+{
+  return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+void main()
+{
+  vec2 coverage = clamp(v_half_size - abs(v_local) + 0.5, 0.0, 1.0);
+  float alpha = coverage.x * coverage.y * u_color.a;
+  if (alpha <= 0.0) {
+    discard;
+  }
+  vec3 color = mix(to_linear(u_background.rgb), to_linear(u_color.rgb), alpha);
+  outColor = vec4(to_srgb(color), 1.0);
+}
+"##;
+
+// A vernier target: two collinear bars of `length` and `width`, end to end
+// with `gap` between them, displaced sideways from each other by `offset`.
+// Positive offsets move the second bar, below the gap at orientation 0,
+// to the right and the first to the left, half each. Lengths are in the
+// renderer's units and need not be whole pixels.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vernier {
+  // Centre of the gap.
+  pub x: f32,
+  pub y: f32,
+  pub length: f32,
+  pub width: f32,
+  pub gap: f32,
+  pub offset: f32,
+  // Of the bars' axis in radians; 0 is vertical, positive turns clockwise.
+  pub orientation: f32,
+}
+
+#[wasm_bindgen]
+impl Vernier {
+
+  // Aligned vertical bars 40 units long and 2 wide with a gap of 4.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Vernier {
+    Vernier {
+      x: 0.0,
+      y: 0.0,
+      length: 40.0,
+      width: 2.0,
+      gap: 4.0,
+      offset: 0.0,
+      orientation: 0.0,
+    }
+  }
+}
+
+impl Default for Vernier {
+  fn default() -> Vernier {
+    Vernier::new()
+  }
+}
+
+// Draws vernier targets on top of a canvas' scene, with the same
+// immediate-mode behaviour as `Draw2D`. The bars are antialiased against
+// the background colour, so the scene behind them should be cleared to it.
+#[wasm_bindgen]
+pub struct VernierRenderer {
+  verniers: Rc<RefCell<Verniers>>,
+  layer: LayerSlot,
+}
+
+struct Verniers {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  space: Space,
+  auto_clear: bool,
+}
+
+#[wasm_bindgen]
+impl VernierRenderer {
+
+  pub fn new(canvas: &WebGlCanvas) -> Result<VernierRenderer> {
+    let context = canvas.context();
+    let mut program = ShaderProgram::new(&context, BAR_VERT_SHADER, BAR_FRAG_SHADER)?;
+    let layout = attribute_layout(&program, &[("center", 2), ("half_size", 2), ("orientation", 1)])?;
+    let batch = VertexBatch::instanced(&context, &layout)?;
+    program.set_vec4("u_color", 0.0, 0.0, 0.0, 1.0);
+    program.set_vec4("u_background", 0.5, 0.5, 0.5, 1.0);
+
+    let verniers = Rc::new(RefCell::new(Verniers {
+      context,
+      program,
+      batch,
+      space: Space::new(),
+      auto_clear: true,
+    }));
+    let layer = canvas.add_overlay(verniers.clone(), Layer::Stimulus);
+    Ok(VernierRenderer { verniers, layer })
+  }
+
+  // Moves the verniers to another layer; they start in `Stimulus`.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.verniers.borrow_mut().space.set(units, pixels_per_degree);
+  }
+
+  // Colour of the bars, black to begin with.
+  pub fn set_color(&self, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.verniers.borrow_mut().program.set_vec4("u_color", r, g, b, a);
+  }
+
+  // Mid grey to begin with.
+  pub fn set_background(&self, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.verniers.borrow_mut().program.set_vec4("u_background", r, g, b, a);
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.verniers.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.verniers.borrow_mut().batch.clear();
+  }
+
+  pub fn draw(&self, vernier: &Vernier) {
+    let (sin, cos) = vernier.orientation.sin_cos();
+    let (along, across) = ((-sin, cos), (cos, sin));
+    let distance = (vernier.gap + vernier.length) / 2.0;
+
+    let mut verniers = self.verniers.borrow_mut();
+    for side in [-1.0, 1.0] {
+      let x = vernier.x + side * (along.0 * distance + across.0 * vernier.offset / 2.0);
+      let y = vernier.y + side * (along.1 * distance + across.1 * vernier.offset / 2.0);
+      verniers.batch.push(&[x, y, vernier.width / 2.0, vernier.length / 2.0, vernier.orientation]);
+    }
+  }
+}
+
+impl Overlay for Verniers {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      let (origin_x, origin_y) = self.space.origin(width, height);
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_origin", origin_x, origin_y);
+      self.program.set_f32("u_scale", self.space.scale());
+      gl_state::set_blend_mode(&self.context, None);
+      self.batch.draw_instanced(WebGl2RenderingContext::TRIANGLES, 6);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)
+  }
+}
@@ -0,0 +1,359 @@
+use std::cell::RefCell;
+use std::f32::consts::{PI, TAU};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::color::Color;
+use crate::error::{GestaltError, Result};
+use crate::graphics::{Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::random::Rng;
+use crate::stimuli::check_positive;
+use crate::stimuli::dots::DotLayer;
+use crate::stimuli::Units;
+
+// Head, then shoulder, elbow, wrist, hip, knee and ankle of the left and the
+// right side.
+const POINTS: usize = 13;
+
+// Frames of the built-in gait cycle.
+const BUILT_IN_FRAMES: usize = 60;
+
+// A point-light walker: the 13 joints of a figure walking on the spot,
+// seen from the side and shown as dots. Playback loops over one gait
+// cycle, either the built-in one or motion-capture data given to
+// `load_frames`, following the time passed to `WebGlCanvas::render` from
+// the first frame after the walker was created or `restart_clock` was
+// called. Every `draw` shows the walker in that frame.
+//
+// Scrambling moves every dot's trajectory to a random place within the
+// figure's bounds, keeping its local motion but destroying the form.
+// Noise dots each copy the motion of a random joint, at a random phase and
+// place within the noise area, for masking the walker.
+#[wasm_bindgen]
+pub struct PointLightWalker {
+  walker: Rc<RefCell<Walker>>,
+  layer: LayerSlot,
+}
+
+struct Walker {
+  layer: DotLayer,
+  rng: Rng,
+  // x, y of every point per frame, in heights with y up and the figure's
+  // bounds centred on 0.
+  frames: Vec<[(f32, f32); POINTS]>,
+  // Each point's mean position over the cycle.
+  means: [(f32, f32); POINTS],
+  // Where each point's trajectory is moved to when scrambled.
+  scramble: [(f32, f32); POINTS],
+  scrambled: bool,
+  noise: Vec<NoiseDot>,
+  noise_area: (f32, f32),
+  x: f32,
+  y: f32,
+  height: f32,
+  // Gait cycles per second.
+  speed: f32,
+  facing_left: bool,
+  inverted: bool,
+  dot_size: f32,
+  color: [f32; 4],
+  start: Option<f32>,
+  queued: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct NoiseDot {
+  point: usize,
+  // Fraction of a cycle.
+  phase: f32,
+  // Mean position, in heights.
+  x: f32,
+  y: f32,
+}
+
+#[wasm_bindgen]
+impl PointLightWalker {
+
+  // The built-in walker, 200 units tall at the origin, facing right at one
+  // gait cycle per second, in white 5 pixel dots.
+  pub fn new(canvas: &WebGlCanvas) -> Result<PointLightWalker> {
+    let mut walker = Walker {
+      layer: DotLayer::new(&canvas.context())?,
+      rng: Rng::from_entropy(),
+      frames: Vec::new(),
+      means: [(0.0, 0.0); POINTS],
+      scramble: [(0.0, 0.0); POINTS],
+      scrambled: false,
+      noise: Vec::new(),
+      noise_area: (1.5, 1.5),
+      x: 0.0,
+      y: 0.0,
+      height: 200.0,
+      speed: 1.0,
+      facing_left: false,
+      inverted: false,
+      dot_size: 5.0,
+      color: [1.0, 1.0, 1.0, 1.0],
+      start: None,
+      queued: false,
+    };
+    walker.set_frames((0..BUILT_IN_FRAMES).map(|frame| gait(frame as f32 / BUILT_IN_FRAMES as f32)).collect())?;
+
+    let walker = Rc::new(RefCell::new(walker));
+    let layer = canvas.add_overlay(walker.clone(), Layer::Stimulus);
+    Ok(PointLightWalker { walker, layer })
+  }
+
+  // Layer the walker is drawn in, `Stimulus` by default.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+
+  // Pixels to begin with. `pixels_per_degree` is only used for degrees.
+  pub fn set_units(&self, units: Units, pixels_per_degree: f32) {
+    self.walker.borrow_mut().layer.space.set(units, pixels_per_degree);
+  }
+
+  // Replaces the gait cycle with motion-capture data: x, y of the 13 points
+  // in the order head, then shoulder, elbow, wrist, hip, knee and ankle of
+  // the left and right side, frame after frame over exactly one cycle. y
+  // runs up; the data is rescaled to the walker's height and centred.
+  pub fn load_frames(&self, coordinates: &[f32]) -> Result<()> {
+    let values = POINTS * 2;
+    if coordinates.is_empty() || !coordinates.len().is_multiple_of(values) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "walker frames are {} floats each, got {}",
+        values,
+        coordinates.len()
+      )));
+    }
+
+    let frames = coordinates
+      .chunks(values)
+      .map(|frame| std::array::from_fn(|point| (frame[point * 2], frame[point * 2 + 1])))
+      .collect();
+    self.walker.borrow_mut().set_frames(frames)
+  }
+
+  // Of the figure's centre.
+  pub fn set_position(&self, x: f32, y: f32) {
+    let mut walker = self.walker.borrow_mut();
+    walker.x = x;
+    walker.y = y;
+  }
+
+  // Of the figure from ankles to head, in units.
+  pub fn set_height(&self, height: f32) -> Result<()> {
+    check_positive("walker height", height)?;
+    self.walker.borrow_mut().height = height;
+    Ok(())
+  }
+
+  // Gait cycles per second, two steps each; negative speeds walk
+  // backwards.
+  pub fn set_speed(&self, speed: f32) {
+    self.walker.borrow_mut().speed = speed;
+  }
+
+  pub fn set_facing_left(&self, facing_left: bool) {
+    self.walker.borrow_mut().facing_left = facing_left;
+  }
+
+  // Upside down, about the figure's centre.
+  pub fn set_inverted(&self, inverted: bool) {
+    self.walker.borrow_mut().inverted = inverted;
+  }
+
+  // Scrambles the walker, with new random places each time it is turned
+  // on.
+  pub fn set_scrambled(&self, scrambled: bool) {
+    let mut walker = self.walker.borrow_mut();
+    if scrambled && !walker.scrambled {
+      walker.scramble_points();
+    }
+    walker.scrambled = scrambled;
+  }
+
+  // Restarts the random sequence from `seed`, which the scrambled places
+  // and noise dots made from then on are drawn from. Seeded from
+  // `Math.random` to begin with.
+  pub fn set_seed(&self, seed: u32) {
+    self.walker.borrow_mut().rng = Rng::new(seed as u64);
+  }
+
+  // Replaces the noise dots with `count` new ones.
+  pub fn set_noise_dots(&self, count: u32) {
+    self.walker.borrow_mut().set_noise(count as usize);
+  }
+
+  // Size of the area noise dots are placed in, centred on the figure, in
+  // walker heights; 1.5 x 1.5 to begin with. Applies to new noise dots.
+  pub fn set_noise_area(&self, width: f32, height: f32) -> Result<()> {
+    check_positive("noise area width", width)?;
+    check_positive("noise area height", height)?;
+    self.walker.borrow_mut().noise_area = (width, height);
+    Ok(())
+  }
+
+  // Diameter in pixels.
+  pub fn set_dot_size(&self, size: f32) {
+    self.walker.borrow_mut().dot_size = size.max(0.0);
+  }
+
+  pub fn set_color(&self, color: &Color) {
+    self.walker.borrow_mut().color = color.to_array();
+  }
+
+  // Makes the next frame drawn the start of the cycle.
+  pub fn restart_clock(&self) {
+    self.walker.borrow_mut().start = None;
+  }
+
+  pub fn draw(&self) {
+    self.walker.borrow_mut().queued = true;
+  }
+}
+
+impl Walker {
+  // Rescales `frames` to a height of 1 and centres their bounds on 0.
+  fn set_frames(&mut self, mut frames: Vec<[(f32, f32); POINTS]>) -> Result<()> {
+    let points = || frames.iter().flatten();
+    let (min_x, max_x) = points().fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), p| (low.min(p.0), high.max(p.0)));
+    let (min_y, max_y) = points().fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), p| (low.min(p.1), high.max(p.1)));
+    check_positive("walker data height", max_y - min_y)?;
+    let (center_x, center_y, scale) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0, 1.0 / (max_y - min_y));
+    for point in frames.iter_mut().flatten() {
+      *point = ((point.0 - center_x) * scale, (point.1 - center_y) * scale);
+    }
+
+    let count = frames.len() as f32;
+    self.means = std::array::from_fn(|point| {
+      let (x, y) = frames.iter().fold((0.0, 0.0), |sum, frame| (sum.0 + frame[point].0, sum.1 + frame[point].1));
+      (x / count, y / count)
+    });
+    self.frames = frames;
+    if self.scrambled {
+      self.scramble_points();
+    }
+    Ok(())
+  }
+
+  fn scramble_points(&mut self) {
+    let points = || self.frames.iter().flatten();
+    let width = points().fold(0.0f32, |width, point| width.max(point.0.abs())) * 2.0;
+    for place in self.scramble.iter_mut() {
+      *place = (self.rng.range(-0.5, 0.5) * width, self.rng.range(-0.5, 0.5));
+    }
+  }
+
+  fn set_noise(&mut self, count: usize) {
+    let (width, height) = self.noise_area;
+    self.noise = (0..count)
+      .map(|_| NoiseDot {
+        point: self.rng.below(POINTS),
+        phase: self.rng.next_f32(),
+        x: self.rng.range(-0.5, 0.5) * width,
+        y: self.rng.range(-0.5, 0.5) * height,
+      })
+      .collect();
+  }
+
+  // Position of `point` at `cycle`, a fraction of a cycle, interpolated
+  // between frames.
+  fn position(&self, point: usize, cycle: f32) -> (f32, f32) {
+    let position = cycle.rem_euclid(1.0) * self.frames.len() as f32;
+    let frame = position.floor() as usize % self.frames.len();
+    let next = (frame + 1) % self.frames.len();
+    let t = position.fract();
+    let (a, b) = (self.frames[frame][point], self.frames[next][point]);
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+  }
+
+  // From heights with y up to units.
+  fn place(&self, x: f32, y: f32) -> (f32, f32) {
+    let x = if self.facing_left { -x } else { x };
+    let y = if self.inverted { -y } else { y };
+    (self.x + x * self.height, self.y - y * self.height)
+  }
+}
+
+impl Overlay for Walker {
+  fn draw(&mut self, width: u32, height: u32, time: f32) {
+    if !self.queued {
+      return;
+    }
+    self.queued = false;
+
+    let start = *self.start.get_or_insert(time);
+    let cycle = (time - start) * self.speed;
+    for point in 0..POINTS {
+      let (mut x, mut y) = self.position(point, cycle);
+      if self.scrambled {
+        x += self.scramble[point].0 - self.means[point].0;
+        y += self.scramble[point].1 - self.means[point].1;
+      }
+      let (x, y) = self.place(x, y);
+      self.layer.push(x, y, self.dot_size, self.color);
+    }
+    for dot in &self.noise {
+      let (x, y) = self.position(dot.point, cycle + dot.phase);
+      let mean = self.means[dot.point];
+      let (x, y) = self.place(dot.x + x - mean.0, dot.y + y - mean.1);
+      self.layer.push(x, y, self.dot_size, self.color);
+    }
+    self.layer.draw(width, height);
+    self.layer.clear();
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.layer.restore(context)
+  }
+}
+
+// The built-in gait at `cycle`, 0 to 1, facing right: sinusoidal joint
+// angles on a figure about 1 high, the left leg swinging forward in the first
+// half. The right side is drawn slightly behind, as if seen a little from
+// the front, so the two sides do not coincide.
+fn gait(cycle: f32) -> [(f32, f32); POINTS] {
+  // Segment lengths and joint heights, in heights.
+  const HEAD: f32 = 0.93;
+  const SHOULDER: f32 = 0.81;
+  const HIP: f32 = 0.52;
+  const UPPER_ARM: f32 = 0.18;
+  const FOREARM: f32 = 0.15;
+  const THIGH: f32 = 0.245;
+  const SHANK: f32 = 0.245;
+  const SIDE_OFFSET: f32 = 0.02;
+
+  let phase = cycle * TAU;
+  // The body is highest in mid stance, twice a cycle.
+  let bob = 0.01 * (2.0 * phase).cos();
+  let joint = |origin: (f32, f32), angle: f32, length: f32| (origin.0 + length * angle.sin(), origin.1 - length * angle.cos());
+
+  let mut points = [(0.0, 0.0); POINTS];
+  points[0] = (0.02, HEAD + bob);
+  for side in 0..2 {
+    let phase = phase + side as f32 * PI;
+    let offset = -(side as f32) * SIDE_OFFSET;
+    // Angles forward from hanging straight down. The knee bends most
+    // while the leg swings forward, the arms swing against the legs.
+    let hip_angle = 0.4 * phase.sin();
+    let knee_angle = hip_angle - 0.1 - 0.6 * phase.cos().max(0.0);
+    let shoulder_angle = -0.3 * phase.sin();
+    let elbow_angle = shoulder_angle + 0.35 - 0.2 * phase.sin();
+
+    let shoulder = (offset, SHOULDER + bob);
+    let elbow = joint(shoulder, shoulder_angle, UPPER_ARM);
+    let wrist = joint(elbow, elbow_angle, FOREARM);
+    let hip = (offset, HIP + bob);
+    let knee = joint(hip, hip_angle, THIGH);
+    let ankle = joint(knee, knee_angle, SHANK);
+    let first = 1 + side * 6;
+    points[first..first + 6].copy_from_slice(&[shoulder, elbow, wrist, hip, knee, ankle]);
+  }
+  points
+}
@@ -0,0 +1,207 @@
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::{EventTarget, IdbDatabase, IdbKeyRange, IdbObjectStore, IdbRequest, IdbTransactionMode};
+
+use crate::error::{GestaltError, Result};
+use crate::events::EventListener;
+use crate::experiment::TrialRunner;
+
+// One object store of trial records keyed by `[session, index]`, so a
+// session's records sort by trial.
+const TRIALS: &str = "trials";
+const VERSION: u32 = 1;
+
+// Waits for `target` to fire `done` or one of `failed`, resolving with
+// `result` or rejecting with `error`, read when the event comes.
+async fn settle<R, E>(target: &EventTarget, done: &'static str, failed: &[&'static str], result: R, error: E) -> Result<JsValue>
+where
+  R: Fn() -> JsValue + Clone + 'static,
+  E: Fn() -> JsValue + Clone + 'static,
+{
+  let mut listeners = Vec::new();
+  let promise = js_sys::Promise::new(&mut |resolve, reject| {
+    let result = result.clone();
+    listeners.push(EventListener::new(target, done, move |_| {
+      let _ = resolve.call1(&JsValue::NULL, &result());
+    }));
+    for &kind in failed {
+      let (reject, error) = (reject.clone(), error.clone());
+      listeners.push(EventListener::new(target, kind, move |_| {
+        let _ = reject.call1(&JsValue::NULL, &error());
+      }));
+    }
+  });
+  // Kept registered until the event.
+  let _listeners = listeners.into_iter().collect::<Result<Vec<_>>>()?;
+  Ok(JsFuture::from(promise).await?)
+}
+
+async fn request_result(request: &IdbRequest) -> Result<JsValue> {
+  let (success, failure) = (request.clone(), request.clone());
+  settle(
+    request,
+    "success",
+    &["error"],
+    move || success.result().unwrap_or(JsValue::UNDEFINED),
+    move || failure.error().ok().flatten().map_or(JsValue::UNDEFINED, JsValue::from),
+  )
+  .await
+}
+
+async fn open_database(name: &str) -> Result<IdbDatabase> {
+  let window = web_sys::window().ok_or(GestaltError::NoWindow)?;
+  let factory = window.indexed_db()?.ok_or(GestaltError::ResourceCreation("IndexedDB storage"))?;
+  let request = factory.open_with_u32(name, VERSION)?;
+  let upgrade = request.clone();
+  let _upgrade = EventListener::new(&request, "upgradeneeded", move |_| {
+    if let Ok(database) = upgrade.result() {
+      let database: IdbDatabase = database.unchecked_into();
+      if let Err(error) = database.create_object_store(TRIALS) {
+        web_sys::console::error_2(&"Failed to set up session storage:".into(), &error);
+      }
+    }
+  })?;
+  Ok(request_result(&request).await?.unchecked_into())
+}
+
+fn trials_store(database: &IdbDatabase, mode: IdbTransactionMode) -> Result<IdbObjectStore> {
+  Ok(database.transaction_with_str_and_mode(TRIALS, mode)?.object_store(TRIALS)?)
+}
+
+// Every key of `session`, whatever the trial index.
+fn session_range(session: &str) -> Result<IdbKeyRange> {
+  let lower = js_sys::Array::of2(&session.into(), &f64::NEG_INFINITY.into());
+  let upper = js_sys::Array::of2(&session.into(), &f64::INFINITY.into());
+  Ok(IdbKeyRange::bound(&lower, &upper)?)
+}
+
+async fn save_trial(database: IdbDatabase, session: String, record: JsValue) -> Result<()> {
+  let index = js_sys::Reflect::get(&record, &"index".into())?;
+  if index.as_f64().is_none() {
+    return Err(GestaltError::InvalidArgument("trial record has no `index`".into()));
+  }
+  let store = trials_store(&database, IdbTransactionMode::Readwrite)?;
+  let key = js_sys::Array::of2(&session.into(), &index);
+  store.put_with_key(&record, &key)?;
+
+  // Only a committed transaction is safe from a closing tab.
+  let transaction = store.transaction();
+  let failure = transaction.clone();
+  settle(
+    &transaction,
+    "complete",
+    &["error", "abort"],
+    || JsValue::UNDEFINED,
+    move || failure.error().map_or(JsValue::UNDEFINED, JsValue::from),
+  )
+  .await?;
+  Ok(())
+}
+
+async fn load_trials(database: IdbDatabase, session: String) -> Result<js_sys::Array> {
+  let store = trials_store(&database, IdbTransactionMode::Readonly)?;
+  let request = store.get_all_with_key(session_range(&session)?.as_ref())?;
+  Ok(request_result(&request).await?.unchecked_into())
+}
+
+// Trial records stored in the browser's IndexedDB as they come in, so that a
+// crashed tab or an accidental reload loses at most the trial under way.
+// Records are kept per session id until deleted; `resume` picks a
+// `TrialRunner` up where a stored session stopped. All methods that touch
+// the database return promises.
+#[wasm_bindgen]
+pub struct SessionStore {
+  database: IdbDatabase,
+}
+
+#[wasm_bindgen]
+impl SessionStore {
+
+  // Opens, or creates, the database `name`. Resolves to a `SessionStore`.
+  pub fn open(name: String) -> js_sys::Promise {
+    wasm_bindgen_futures::future_to_promise(async move {
+      let database = open_database(&name).await?;
+      Ok(SessionStore { database }.into())
+    })
+  }
+
+  // Stores a record from `TrialRunner::results()` under `session`, replacing
+  // one with the same index. Resolves once it is committed.
+  pub fn save_trial(&self, session: String, record: JsValue) -> js_sys::Promise {
+    let database = self.database.clone();
+    wasm_bindgen_futures::future_to_promise(async move {
+      save_trial(database, session, record).await?;
+      Ok(JsValue::UNDEFINED)
+    })
+  }
+
+  // Resolves to the records stored under `session`, in trial order.
+  pub fn load_trials(&self, session: String) -> js_sys::Promise {
+    let database = self.database.clone();
+    wasm_bindgen_futures::future_to_promise(async move { Ok(load_trials(database, session).await?.into()) })
+  }
+
+  // Resolves to the ids of all sessions with stored records.
+  pub fn sessions(&self) -> js_sys::Promise {
+    let database = self.database.clone();
+    wasm_bindgen_futures::future_to_promise(async move {
+      let store = trials_store(&database, IdbTransactionMode::Readonly)?;
+      let keys: js_sys::Array = request_result(&store.get_all_keys()?).await?.unchecked_into();
+      let sessions = js_sys::Array::new();
+      for key in keys.iter() {
+        let session = js_sys::Array::from(&key).get(0);
+        if !sessions.includes(&session, 0) {
+          sessions.push(&session);
+        }
+      }
+      Ok(sessions.into())
+    })
+  }
+
+  pub fn delete_session(&self, session: String) -> js_sys::Promise {
+    let database = self.database.clone();
+    wasm_bindgen_futures::future_to_promise(async move {
+      let store = trials_store(&database, IdbTransactionMode::Readwrite)?;
+      request_result(&store.delete(session_range(&session)?.as_ref())?).await?;
+      Ok(JsValue::UNDEFINED)
+    })
+  }
+
+  // Saves each trial of `runner` under `session` as it completes. Failures
+  // are logged to the console, as there is no caller to hand them to.
+  pub fn checkpoint(&self, runner: &TrialRunner, session: String) {
+    let database = self.database.clone();
+    let checkpoint: Rc<dyn Fn(JsValue)> = Rc::new(move |record| {
+      let (database, session) = (database.clone(), session.clone());
+      wasm_bindgen_futures::spawn_local(async move {
+        if let Err(error) = save_trial(database, session, record).await {
+          web_sys::console::error_2(&"Failed to store trial:".into(), &JsValue::from(error));
+        }
+      });
+    });
+    runner.shared().borrow_mut().set_checkpoint(Some(checkpoint));
+  }
+
+  // Stops saving the trials of `runner`.
+  pub fn stop_checkpoint(&self, runner: &TrialRunner) {
+    runner.shared().borrow_mut().set_checkpoint(None);
+  }
+
+  // Loads `session`, resumes `runner` after its stored trials, which must be
+  // set up as they were, and checkpoints it from there. Resolves to the
+  // number of trials restored.
+  pub fn resume(&self, runner: &TrialRunner, session: String) -> js_sys::Promise {
+    self.checkpoint(runner, session.clone());
+    let database = self.database.clone();
+    let runner = runner.shared();
+    wasm_bindgen_futures::future_to_promise(async move {
+      let records = load_trials(database, session).await?;
+      runner.borrow_mut().resume(&records)?;
+      Ok(records.length().into())
+    })
+  }
+}
@@ -0,0 +1,637 @@
+use std::f64::consts::PI;
+
+use wasm_bindgen::prelude::*;
+
+use crate::draw2d::triangulate;
+use crate::error::{GestaltError, Result};
+
+type Point = (f32, f32);
+
+// Path data split into tokens: commands, numbers and arc flags, with the
+// separators between them optional wherever the SVG grammar allows.
+struct Lexer<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Lexer<'a> {
+  fn new(data: &'a str) -> Lexer<'a> {
+    Lexer { data: data.as_bytes(), position: 0 }
+  }
+
+  fn skip_separators(&mut self) {
+    while matches!(self.data.get(self.position), Some(b' ' | b'\t' | b'\r' | b'\n' | b',')) {
+      self.position += 1;
+    }
+  }
+
+  fn peek(&mut self) -> Option<u8> {
+    self.skip_separators();
+    self.data.get(self.position).copied()
+  }
+
+  fn at_number(&mut self) -> bool {
+    matches!(self.peek(), Some(b'0'..=b'9' | b'.' | b'-' | b'+'))
+  }
+
+  fn error(&self, expected: &str) -> GestaltError {
+    GestaltError::InvalidArgument(format!("bad SVG path data: expected {} at offset {}", expected, self.position))
+  }
+
+  fn command(&mut self) -> Option<u8> {
+    let command = self.peek().filter(u8::is_ascii_alphabetic)?;
+    self.position += 1;
+    Some(command)
+  }
+
+  fn number(&mut self) -> Result<f64> {
+    self.skip_separators();
+    let start = self.position;
+    let digits = |lexer: &mut Lexer| {
+      let from = lexer.position;
+      while matches!(lexer.data.get(lexer.position), Some(b'0'..=b'9')) {
+        lexer.position += 1;
+      }
+      lexer.position > from
+    };
+    if matches!(self.data.get(self.position), Some(b'-' | b'+')) {
+      self.position += 1;
+    }
+    let mut any = digits(self);
+    if self.data.get(self.position) == Some(&b'.') {
+      self.position += 1;
+      any |= digits(self);
+    }
+    if !any {
+      self.position = start;
+      return Err(self.error("a number"));
+    }
+    // An `e` not followed by an exponent starts the next token.
+    if matches!(self.data.get(self.position), Some(b'e' | b'E')) {
+      let mark = self.position;
+      self.position += 1;
+      if matches!(self.data.get(self.position), Some(b'-' | b'+')) {
+        self.position += 1;
+      }
+      if !digits(self) {
+        self.position = mark;
+      }
+    }
+    let text = std::str::from_utf8(&self.data[start..self.position]).unwrap_or_default();
+    text.parse().map_err(|_| self.error("a number"))
+  }
+
+  // Arc flags are one digit and may run into the next number.
+  fn flag(&mut self) -> Result<bool> {
+    match self.peek() {
+      Some(b'0') => {
+        self.position += 1;
+        Ok(false)
+      }
+      Some(b'1') => {
+        self.position += 1;
+        Ok(true)
+      }
+      _ => Err(self.error("an arc flag")),
+    }
+  }
+
+  fn pair(&mut self) -> Result<(f64, f64)> {
+    Ok((self.number()?, self.number()?))
+  }
+}
+
+// The control point a smooth curve reflects, and whether it came from a
+// cubic or a quadratic.
+#[derive(Clone, Copy)]
+enum Control {
+  None,
+  Cubic(f64, f64),
+  Quadratic(f64, f64),
+}
+
+struct Flattener {
+  tolerance: f64,
+  contours: Vec<Vec<Point>>,
+  current: Vec<Point>,
+  position: (f64, f64),
+  start: (f64, f64),
+}
+
+impl Flattener {
+  fn move_to(&mut self, point: (f64, f64)) {
+    self.finish();
+    self.position = point;
+    self.start = point;
+    self.current.push((point.0 as f32, point.1 as f32));
+  }
+
+  fn line_to(&mut self, point: (f64, f64)) {
+    self.position = point;
+    self.current.push((point.0 as f32, point.1 as f32));
+  }
+
+  fn close(&mut self) {
+    let start = self.start;
+    if self.current.len() > 1 {
+      self.line_to(start);
+    }
+    self.finish();
+    self.move_to(start);
+  }
+
+  // Ends the contour; a lone point is dropped.
+  fn finish(&mut self) {
+    let contour = std::mem::take(&mut self.current);
+    if contour.len() > 1 {
+      self.contours.push(contour);
+    }
+  }
+
+  // Enough straight segments for a curve whose second derivative is at most
+  // `bend` to stay within the tolerance.
+  fn segments(&self, bend: f64) -> usize {
+    ((bend / (8.0 * self.tolerance)).sqrt().ceil() as usize).clamp(1, 256)
+  }
+
+  fn quadratic_to(&mut self, control: (f64, f64), end: (f64, f64)) {
+    let start = self.position;
+    let bend = 2.0 * length(start.0 - 2.0 * control.0 + end.0, start.1 - 2.0 * control.1 + end.1);
+    let segments = self.segments(bend);
+    for index in 1..=segments {
+      let t = index as f64 / segments as f64;
+      let (a, b, c) = ((1.0 - t) * (1.0 - t), 2.0 * t * (1.0 - t), t * t);
+      self.line_to((a * start.0 + b * control.0 + c * end.0, a * start.1 + b * control.1 + c * end.1));
+    }
+  }
+
+  fn cubic_to(&mut self, first: (f64, f64), second: (f64, f64), end: (f64, f64)) {
+    let start = self.position;
+    let bend = 6.0
+      * length(start.0 - 2.0 * first.0 + second.0, start.1 - 2.0 * first.1 + second.1)
+        .max(length(first.0 - 2.0 * second.0 + end.0, first.1 - 2.0 * second.1 + end.1));
+    let segments = self.segments(bend);
+    for index in 1..=segments {
+      let t = index as f64 / segments as f64;
+      let u = 1.0 - t;
+      let (a, b, c, d) = (u * u * u, 3.0 * t * u * u, 3.0 * t * t * u, t * t * t);
+      self.line_to((
+        a * start.0 + b * first.0 + c * second.0 + d * end.0,
+        a * start.1 + b * first.1 + c * second.1 + d * end.1,
+      ));
+    }
+  }
+
+  // Elliptical arc from the current point, converted to a centre and angles
+  // as in the SVG specification's implementation notes.
+  fn arc_to(&mut self, radii: (f64, f64), rotation: f64, large_arc: bool, sweep: bool, end: (f64, f64)) {
+    let start = self.position;
+    let (mut rx, mut ry) = (radii.0.abs(), radii.1.abs());
+    if start == end {
+      return;
+    }
+    if rx == 0.0 || ry == 0.0 {
+      self.line_to(end);
+      return;
+    }
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let (half_x, half_y) = ((start.0 - end.0) / 2.0, (start.1 - end.1) / 2.0);
+    let (x, y) = (cos * half_x + sin * half_y, -sin * half_x + cos * half_y);
+
+    // Radii too small to reach the end point are scaled up until they do.
+    let reach = (x * x) / (rx * rx) + (y * y) / (ry * ry);
+    if reach > 1.0 {
+      rx *= reach.sqrt();
+      ry *= reach.sqrt();
+    }
+    let numerator = rx * rx * ry * ry - rx * rx * y * y - ry * ry * x * x;
+    let denominator = rx * rx * y * y + ry * ry * x * x;
+    let sign = if large_arc == sweep { -1.0 } else { 1.0 };
+    let scale = sign * (numerator / denominator).max(0.0).sqrt();
+    let (center_x, center_y) = (scale * rx * y / ry, -scale * ry * x / rx);
+    let center = (
+      cos * center_x - sin * center_y + (start.0 + end.0) / 2.0,
+      sin * center_x + cos * center_y + (start.1 + end.1) / 2.0,
+    );
+
+    let angle = |ux: f64, uy: f64, vx: f64, vy: f64| (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+    let first = angle(1.0, 0.0, (x - center_x) / rx, (y - center_y) / ry);
+    let mut sweep_angle = angle((x - center_x) / rx, (y - center_y) / ry, (-x - center_x) / rx, (-y - center_y) / ry);
+    if !sweep && sweep_angle > 0.0 {
+      sweep_angle -= 2.0 * PI;
+    } else if sweep && sweep_angle < 0.0 {
+      sweep_angle += 2.0 * PI;
+    }
+
+    let radius = rx.max(ry);
+    let step = if self.tolerance < radius { 2.0 * (1.0 - self.tolerance / radius).acos() } else { PI / 2.0 };
+    let segments = ((sweep_angle.abs() / step).ceil() as usize).clamp(1, 512);
+    for index in 1..segments {
+      let (sin_t, cos_t) = (first + sweep_angle * index as f64 / segments as f64).sin_cos();
+      self.line_to((
+        center.0 + rx * cos_t * cos - ry * sin_t * sin,
+        center.1 + rx * cos_t * sin + ry * sin_t * cos,
+      ));
+    }
+    self.line_to(end);
+  }
+}
+
+fn length(x: f64, y: f64) -> f64 {
+  (x * x + y * y).sqrt()
+}
+
+// Flattens path data into polylines, one per subpath.
+fn flatten(data: &str, tolerance: f64) -> Result<Vec<Vec<Point>>> {
+  let mut lexer = Lexer::new(data);
+  let mut path = Flattener {
+    tolerance,
+    contours: Vec::new(),
+    current: Vec::new(),
+    position: (0.0, 0.0),
+    start: (0.0, 0.0),
+  };
+  let mut control = Control::None;
+  let mut command = match lexer.command() {
+    Some(command @ (b'M' | b'm')) => command,
+    None if lexer.peek().is_none() => return Ok(Vec::new()),
+    _ => return Err(lexer.error("a moveto")),
+  };
+
+  loop {
+    let relative = command.is_ascii_lowercase();
+    let origin = if relative { path.position } else { (0.0, 0.0) };
+    let offset = |(x, y): (f64, f64)| (x + origin.0, y + origin.1);
+    let mut next_control = Control::None;
+    match command.to_ascii_uppercase() {
+      b'M' => {
+        path.move_to(offset(lexer.pair()?));
+        // Further pairs are lines.
+        command = if relative { b'l' } else { b'L' };
+        control = Control::None;
+        if !lexer.at_number() {
+          match lexer.command() {
+            Some(next) => command = next,
+            None if lexer.peek().is_none() => break,
+            None => return Err(lexer.error("a command")),
+          }
+        }
+        continue;
+      }
+      b'L' => path.line_to(offset(lexer.pair()?)),
+      b'H' => {
+        let x = lexer.number()? + origin.0;
+        path.line_to((x, path.position.1));
+      }
+      b'V' => {
+        let y = lexer.number()? + origin.1;
+        path.line_to((path.position.0, y));
+      }
+      b'C' => {
+        let (first, second, end) = (offset(lexer.pair()?), offset(lexer.pair()?), offset(lexer.pair()?));
+        path.cubic_to(first, second, end);
+        next_control = Control::Cubic(second.0, second.1);
+      }
+      b'S' => {
+        let first = match control {
+          Control::Cubic(x, y) => (2.0 * path.position.0 - x, 2.0 * path.position.1 - y),
+          _ => path.position,
+        };
+        let (second, end) = (offset(lexer.pair()?), offset(lexer.pair()?));
+        path.cubic_to(first, second, end);
+        next_control = Control::Cubic(second.0, second.1);
+      }
+      b'Q' => {
+        let (point, end) = (offset(lexer.pair()?), offset(lexer.pair()?));
+        path.quadratic_to(point, end);
+        next_control = Control::Quadratic(point.0, point.1);
+      }
+      b'T' => {
+        let point = match control {
+          Control::Quadratic(x, y) => (2.0 * path.position.0 - x, 2.0 * path.position.1 - y),
+          _ => path.position,
+        };
+        let end = offset(lexer.pair()?);
+        path.quadratic_to(point, end);
+        next_control = Control::Quadratic(point.0, point.1);
+      }
+      b'A' => {
+        let radii = lexer.pair()?;
+        let rotation = lexer.number()?;
+        let (large_arc, sweep) = (lexer.flag()?, lexer.flag()?);
+        let end = offset(lexer.pair()?);
+        path.arc_to(radii, rotation, large_arc, sweep, end);
+      }
+      b'Z' => path.close(),
+      _ => return Err(GestaltError::InvalidArgument(format!("unknown SVG path command '{}'", command as char))),
+    }
+    control = next_control;
+
+    // Commands repeat while numbers follow, except closepath.
+    if command.eq_ignore_ascii_case(&b'Z') || !lexer.at_number() {
+      match lexer.command() {
+        Some(next) => command = next,
+        None if lexer.peek().is_none() => break,
+        None => return Err(lexer.error("a command")),
+      }
+    }
+  }
+  path.finish();
+  Ok(path.contours)
+}
+
+fn signed_area(points: &[Point]) -> f32 {
+  (0..points.len())
+    .map(|i| {
+      let (a, b) = (points[i], points[(i + 1) % points.len()]);
+      a.0 * b.1 - b.0 * a.1
+    })
+    .sum::<f32>()
+    / 2.0
+}
+
+fn contains(polygon: &[Point], point: Point) -> bool {
+  let mut inside = false;
+  for i in 0..polygon.len() {
+    let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+    if (a.1 > point.1) != (b.1 > point.1) && point.0 < a.0 + (point.1 - a.1) / (b.1 - a.1) * (b.0 - a.0) {
+      inside = !inside;
+    }
+  }
+  inside
+}
+
+// Whether segments ab and cd cross, not counting shared end points.
+fn crosses(a: Point, b: Point, c: Point, d: Point) -> bool {
+  if a == c || a == d || b == c || b == d {
+    return false;
+  }
+  let side = |p: Point, q: Point, r: Point| (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0);
+  let (d1, d2) = (side(a, b, c), side(a, b, d));
+  let (d3, d4) = (side(c, d, a), side(c, d, b));
+  (d1 > 0.0) != (d2 > 0.0) && (d3 > 0.0) != (d4 > 0.0)
+}
+
+// Without repeated points and with the closing point dropped, as fills
+// close their contours anyway.
+fn cleaned(contour: &[Point]) -> Vec<Point> {
+  let mut points: Vec<Point> = Vec::with_capacity(contour.len());
+  for &point in contour {
+    if points.last() != Some(&point) {
+      points.push(point);
+    }
+  }
+  while points.len() > 1 && points.first() == points.last() {
+    points.pop();
+  }
+  points
+}
+
+// Joins `hole` into `outer` along a bridge from the hole's rightmost point
+// to the nearest outer point it can see, leaving one polygon to clip ears
+// from.
+fn bridge(outer: &mut Vec<Point>, hole: &[Point], others: &[&Vec<Point>]) {
+  let (start, &from) = hole
+    .iter()
+    .enumerate()
+    .max_by(|(_, a), (_, b)| a.0.total_cmp(&b.0))
+    .unwrap_or((0, &hole[0]));
+  let distance = |point: Point| (point.0 - from.0).powi(2) + (point.1 - from.1).powi(2);
+  let mut candidates: Vec<usize> = (0..outer.len()).collect();
+  candidates.sort_by(|&a, &b| distance(outer[a]).total_cmp(&distance(outer[b])));
+  let visible = |to: Point| {
+    let edges = |polygon: &[Point]| (0..polygon.len()).map(move |i| (polygon[i], polygon[(i + 1) % polygon.len()])).collect::<Vec<_>>();
+    !edges(outer)
+      .into_iter()
+      .chain(edges(hole))
+      .chain(others.iter().flat_map(|other| edges(other)))
+      .any(|(a, b)| crosses(from, to, a, b))
+  };
+  let target = candidates.iter().copied().find(|&index| visible(outer[index])).unwrap_or(candidates[0]);
+
+  let mut joined = Vec::with_capacity(outer.len() + hole.len() + 2);
+  joined.extend_from_slice(&outer[..=target]);
+  joined.extend(hole[start..].iter().chain(&hole[..=start]));
+  joined.extend_from_slice(&outer[target..]);
+  *outer = joined;
+}
+
+// Triangles filling the contours under the even-odd rule: a contour inside
+// an odd number of others is a hole in the one just around it. Contours are
+// expected not to cross each other.
+fn fill(contours: &[Vec<Point>]) -> Vec<f32> {
+  let contours: Vec<Vec<Point>> = contours.iter().map(|contour| cleaned(contour)).filter(|contour| contour.len() >= 3).collect();
+  let parents: Vec<Vec<usize>> = contours
+    .iter()
+    .enumerate()
+    .map(|(index, contour)| {
+      (0..contours.len())
+        .filter(|&other| other != index && contains(&contours[other], contour[0]))
+        .collect()
+    })
+    .collect();
+
+  let mut vertices = Vec::new();
+  for (index, contour) in contours.iter().enumerate() {
+    if parents[index].len() % 2 == 1 {
+      continue;
+    }
+    let mut outer = contour.clone();
+    if signed_area(&outer) < 0.0 {
+      outer.reverse();
+    }
+    let mut holes: Vec<Vec<Point>> = (0..contours.len())
+      .filter(|&hole| parents[hole].len() == parents[index].len() + 1 && parents[hole].contains(&index))
+      .map(|hole| {
+        let mut hole = contours[hole].clone();
+        if signed_area(&hole) > 0.0 {
+          hole.reverse();
+        }
+        hole
+      })
+      .collect();
+    // Rightmost first, so later bridges do not have to cross earlier ones.
+    holes.sort_by(|a, b| {
+      let right = |hole: &Vec<Point>| hole.iter().map(|point| point.0).fold(f32::MIN, f32::max);
+      right(b).total_cmp(&right(a))
+    });
+    for (number, hole) in holes.iter().enumerate() {
+      let others: Vec<&Vec<Point>> = holes[number + 1..].iter().collect();
+      bridge(&mut outer, hole, &others);
+    }
+    for [a, b, c] in triangulate(&outer) {
+      for (x, y) in [outer[a], outer[b], outer[c]] {
+        vertices.push(x);
+        vertices.push(y);
+      }
+    }
+  }
+  vertices
+}
+
+// SVG path data (the `d` attribute) flattened into polylines, for shapes
+// and logos as stimuli: `triangles` fills them for `Scene::add_mesh` or
+// `Draw2D::fill_path`, `contour` gives their outlines for `LineRenderer`.
+// All commands are understood, curves and arcs being split into straight
+// segments. Coordinates stay in the path's units, y down as in SVG, until
+// transformed.
+#[wasm_bindgen]
+pub struct SvgPath {
+  contours: Vec<Vec<Point>>,
+}
+
+#[wasm_bindgen]
+impl SvgPath {
+
+  // `tolerance` is the largest distance, in path units, between a curve and
+  // the segments it is split into.
+  pub fn parse(data: &str, tolerance: f32) -> Result<SvgPath> {
+    if tolerance.is_nan() || tolerance <= 0.0 {
+      return Err(GestaltError::InvalidArgument(format!("tolerance must be positive, got {}", tolerance)));
+    }
+    Ok(SvgPath { contours: flatten(data, tolerance as f64)? })
+  }
+
+  // Subpaths, open ones included.
+  pub fn contour_count(&self) -> usize {
+    self.contours.len()
+  }
+
+  // Points of subpath `index` as a flat list of x, y pairs; closed ones end
+  // where they started.
+  pub fn contour(&self, index: usize) -> Result<Vec<f32>> {
+    let contour = self.contours.get(index).ok_or_else(|| {
+      GestaltError::InvalidArgument(format!("contour {} out of range ({} contours)", index, self.contours.len()))
+    })?;
+    Ok(contour.iter().flat_map(|&(x, y)| [x, y]).collect())
+  }
+
+  // Fill triangles as a flat list of x, y pairs, three to a triangle.
+  pub fn triangles(&self) -> Vec<f32> {
+    fill(&self.contours)
+  }
+
+  // `[min x, min y, max x, max y]`, or all zeros for an empty path.
+  pub fn bounds(&self) -> Vec<f32> {
+    let mut points = self.contours.iter().flatten();
+    let first = match points.next() {
+      Some(&first) => first,
+      None => return vec![0.0; 4],
+    };
+    let (min, max) = points.fold((first, first), |(min, max), &(x, y)| ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y))));
+    vec![min.0, min.1, max.0, max.1]
+  }
+
+  // Maps every point through the matrix `a c e / b d f`, as SVG's
+  // `matrix(a, b, c, d, e, f)`.
+  pub fn transform(&mut self, a: f32, b: f32, c: f32, d: f32, e: f32, f: f32) {
+    for point in self.contours.iter_mut().flatten() {
+      let (x, y) = *point;
+      *point = (a * x + c * y + e, b * x + d * y + f);
+    }
+  }
+
+  // Scales the path evenly to fit a `width` x `height` box centred on the
+  // origin. `flip_y` turns it upright for y-up coordinates, as in scenes.
+  pub fn fit(&mut self, width: f32, height: f32, flip_y: bool) -> Result<()> {
+    let bounds = self.bounds();
+    let (path_width, path_height) = (bounds[2] - bounds[0], bounds[3] - bounds[1]);
+    if path_width <= 0.0 && path_height <= 0.0 {
+      return Err(GestaltError::InvalidArgument("cannot fit a path without extent".into()));
+    }
+    let scale = (width / path_width).min(height / path_height);
+    let (center_x, center_y) = ((bounds[0] + bounds[2]) / 2.0, (bounds[1] + bounds[3]) / 2.0);
+    let scale_y = if flip_y { -scale } else { scale };
+    self.transform(scale, 0.0, 0.0, scale_y, -center_x * scale, -center_y * scale_y);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{flatten, Point};
+
+  fn contours(data: &str) -> Vec<Vec<Point>> {
+    flatten(data, 0.1).unwrap()
+  }
+
+  fn close(a: Point, b: Point) -> bool {
+    (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4
+  }
+
+  #[test]
+  fn commands_repeat_while_numbers_follow() {
+    assert_eq!(contours("M0 0 L10 0 20 0 20 10"), vec![vec![(0.0, 0.0), (10.0, 0.0), (20.0, 0.0), (20.0, 10.0)]]);
+    assert_eq!(contours("M0 0 H5 10 V3"), vec![vec![(0.0, 0.0), (5.0, 0.0), (10.0, 0.0), (10.0, 3.0)]]);
+  }
+
+  #[test]
+  fn pairs_after_a_moveto_are_lines() {
+    assert_eq!(contours("M0 0 10 0 10 10"), vec![vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]]);
+    assert_eq!(contours("m1 1 2 0 0 2"), vec![vec![(1.0, 1.0), (3.0, 1.0), (3.0, 3.0)]]);
+  }
+
+  #[test]
+  fn relative_commands_follow_the_current_point() {
+    assert_eq!(
+      contours("m10 10 l5 0 0 5 h-5 z"),
+      vec![vec![(10.0, 10.0), (15.0, 10.0), (15.0, 15.0), (10.0, 15.0), (10.0, 10.0)]]
+    );
+    assert_eq!(contours("M10 10 v-4 H2"), vec![vec![(10.0, 10.0), (10.0, 6.0), (2.0, 6.0)]]);
+  }
+
+  #[test]
+  fn closepath_returns_to_the_subpath_start() {
+    let paths = contours("M0 0 L4 0 L4 4 Z m2 2 l1 0");
+    assert_eq!(paths.len(), 2);
+    assert_eq!(paths[0].last(), Some(&(0.0, 0.0)));
+    assert_eq!(paths[1], vec![(2.0, 2.0), (3.0, 2.0)]);
+  }
+
+  #[test]
+  fn numbers_take_exponents() {
+    assert_eq!(contours("M1e-3 2E2 L.5e1 -1e+1"), vec![vec![(0.001, 200.0), (5.0, -10.0)]]);
+  }
+
+  #[test]
+  fn separators_are_optional_between_numbers() {
+    assert_eq!(contours("M10-5L.5.5"), vec![vec![(10.0, -5.0), (0.5, 0.5)]]);
+    assert_eq!(contours("M 0,0 , L 1 , 0\n\t2,0"), vec![vec![(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)]]);
+    assert_eq!(contours("M0+1L-2-3"), vec![vec![(0.0, 1.0), (-2.0, -3.0)]]);
+  }
+
+  #[test]
+  fn arc_flags_may_run_into_numbers() {
+    let paths = contours("M0 0a5 5 0 1010 0");
+    let last = *paths[0].last().unwrap();
+    assert!(close(last, (10.0, 0.0)), "{:?}", last);
+    // A half circle of radius 5, through y = 5 with the sweep flag off and
+    // y = -5 with it on.
+    assert!(paths[0].iter().any(|&(_, y)| y > 4.9), "{:?}", paths[0]);
+    let paths = contours("M0 0a5 5 0 0110 0");
+    assert!(close(*paths[0].last().unwrap(), (10.0, 0.0)));
+    assert!(paths[0].iter().any(|&(_, y)| y < -4.9), "{:?}", paths[0]);
+  }
+
+  #[test]
+  fn curves_end_on_their_end_points() {
+    for data in &["M0 0 C0 10 10 10 10 0 S20 -10 20 0", "M0 0 Q5 10 10 0 T20 0", "M0 0 q5 10 10 0 t10 0"] {
+      let paths = contours(data);
+      assert!(close(*paths[0].last().unwrap(), (20.0, 0.0)), "{}", data);
+    }
+  }
+
+  #[test]
+  fn empty_data_and_lone_points_make_no_contours() {
+    assert!(contours("").is_empty());
+    assert!(contours("  ").is_empty());
+    assert!(contours("M5 5").is_empty());
+  }
+
+  #[test]
+  fn malformed_data_is_rejected() {
+    for data in &["L0 0", "M0 0 X1 1", "M0 0 L1", "M0 0 L1 .", "M0 0 A1 1 0 2 0 1 1", "M0 0 L1e 2"] {
+      assert!(flatten(data, 0.1).is_err(), "{}", data);
+    }
+  }
+}
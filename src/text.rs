@@ -0,0 +1,555 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::{Clamped, JsCast};
+
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData, WebGl2RenderingContext};
+
+use crate::batch::{attribute_layout, VertexBatch};
+use crate::error::{GestaltError, Result};
+use crate::gl_state::{self, BlendMode};
+use crate::graphics::{document, Overlay, WebGlCanvas};
+use crate::layers::{Layer, LayerSlot};
+use crate::sdf;
+use crate::shader::ShaderProgram;
+use crate::texture::Texture;
+
+const TEXT_VERT_SHADER: &str = r##"#version 300 es
+
+in vec2 position;
+in vec2 uv;
+in vec4 color;
+
+uniform vec2 u_resolution;
+// The atlas may grow after glyphs were batched, so `uv` is in atlas pixels.
+uniform vec2 u_atlas_size;
+
+out vec2 v_uv;
+out vec4 v_color;
+
+void main()
+{
+  vec2 clip = position / u_resolution * 2.0 - 1.0;
+  gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+  v_uv = uv / u_atlas_size;
+  v_color = color;
+}
+"##;
+
+const TEXT_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_atlas;
+
+in vec2 v_uv;
+in vec4 v_color;
+
+out vec4 outColor;
+
+void main()
+{
+  outColor = vec4(v_color.rgb, v_color.a * texture(u_atlas, v_uv).a);
+}
+"##;
+
+// Distances are in atlas pixels, the scale the font was rasterized at, so
+// outlines and weight scale with the text.
+const TEXT_SDF_FRAG_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D u_atlas;
+uniform float u_spread;
+uniform float u_weight;
+uniform float u_outline_width;
+uniform vec4 u_outline_color;
+
+in vec2 v_uv;
+in vec4 v_color;
+
+out vec4 outColor;
+
+void main()
+{
+  // Distance to the glyph's edge, positive inside.
+  float edge = (texture(u_atlas, v_uv).a - 0.5) * 2.0 * u_spread + u_weight;
+  // Antialias over one screen pixel, whatever the scale.
+  float ramp = max(fwidth(edge), 1e-4);
+  float fill = clamp(edge / ramp + 0.5, 0.0, 1.0);
+  float outer = clamp((edge + u_outline_width) / ramp + 0.5, 0.0, 1.0);
+
+  vec4 color = mix(u_outline_color, v_color, fill);
+  outColor = vec4(color.rgb, color.a * outer);
+}
+"##;
+
+const ATLAS_WIDTH: u32 = 1024;
+const MAX_ATLAS_HEIGHT: u32 = 4096;
+// Empty pixels around each bitmap glyph, so linear filtering never picks up
+// a neighbour. Distance field glyphs are padded by their spread instead.
+const GLYPH_PADDING: u32 = 2;
+
+#[derive(Clone, Copy, Debug)]
+struct Glyph {
+  // Cell in the atlas, in pixels, padding included.
+  x: u32,
+  y: u32,
+  width: u32,
+  advance: f32,
+}
+
+// Glyphs of one font, rasterized on first use with a 2D canvas and packed
+// into rows of equally tall cells. The atlas canvas grows as it fills up and
+// is uploaded to a texture whenever glyphs were added.
+//
+// With a `spread`, each glyph is turned into a signed distance field
+// reaching that many pixels either side of its outline, kept in the alpha
+// channel.
+pub(crate) struct GlyphAtlas {
+  canvas: HtmlCanvasElement,
+  context2d: CanvasRenderingContext2d,
+  texture: Texture,
+  font: String,
+  font_size: f32,
+  spread: Option<f32>,
+  padding: u32,
+  ascent: f32,
+  cell_height: u32,
+  glyphs: HashMap<char, Glyph>,
+  cursor_x: u32,
+  cursor_y: u32,
+  dirty: bool,
+}
+
+impl GlyphAtlas {
+  pub(crate) fn new(
+    context: &WebGl2RenderingContext,
+    font_family: &str,
+    font_size: f32,
+    spread: Option<f32>,
+  ) -> Result<GlyphAtlas> {
+    let canvas = document()?
+      .create_element("canvas")?
+      .dyn_into::<HtmlCanvasElement>()
+      .map_err(|_| GestaltError::ResourceCreation("glyph atlas canvas"))?;
+    canvas.set_width(ATLAS_WIDTH);
+    canvas.set_height(256);
+    let context2d = canvas
+      .get_context("2d")?
+      .and_then(|context| context.dyn_into::<CanvasRenderingContext2d>().ok())
+      .ok_or(GestaltError::ResourceCreation("2D context for the glyph atlas"))?;
+
+    let font = format!("{}px {}", font_size, font_family);
+    context2d.set_font(&font);
+    let metrics = context2d.measure_text("Mg")?;
+    let (mut ascent, mut descent) = (metrics.font_bounding_box_ascent() as f32, metrics.font_bounding_box_descent() as f32);
+    // Older browsers do not report font metrics.
+    if ascent <= 0.0 {
+      ascent = font_size * 0.8;
+      descent = font_size * 0.2;
+    }
+
+    let padding = spread.map_or(GLYPH_PADDING, |spread| spread.ceil() as u32 + 1);
+    let atlas = GlyphAtlas {
+      canvas,
+      context2d,
+      texture: Texture::new(context)?,
+      font,
+      font_size,
+      spread,
+      padding,
+      ascent,
+      cell_height: (ascent + descent).ceil() as u32 + 2 * padding,
+      glyphs: HashMap::new(),
+      cursor_x: 0,
+      cursor_y: 0,
+      dirty: true,
+    };
+    atlas.reset_context();
+    Ok(atlas)
+  }
+
+  // Height of a line of text: ascent plus descent, in pixels.
+  pub(crate) fn line_height(&self) -> f32 {
+    (self.cell_height - 2 * self.padding) as f32
+  }
+
+  fn glyph(&mut self, ch: char) -> Result<Glyph> {
+    if let Some(glyph) = self.glyphs.get(&ch) {
+      return Ok(*glyph);
+    }
+
+    let mut buffer = [0; 4];
+    let text = ch.encode_utf8(&mut buffer);
+    let advance = self.context2d.measure_text(text)?.width() as f32;
+    let width = advance.ceil() as u32 + 2 * self.padding;
+
+    if self.cursor_x + width > ATLAS_WIDTH {
+      self.cursor_x = 0;
+      self.cursor_y += self.cell_height;
+    }
+    while self.cursor_y + self.cell_height > self.canvas.height() {
+      self.grow()?;
+    }
+
+    let glyph = Glyph {
+      x: self.cursor_x,
+      y: self.cursor_y,
+      width,
+      advance,
+    };
+    self.draw_glyph(ch, &glyph)?;
+    self.glyphs.insert(ch, glyph);
+    self.cursor_x += width;
+    self.dirty = true;
+    Ok(glyph)
+  }
+
+  // Width of `text` on a single line, in pixels at the atlas' font size.
+  pub(crate) fn measure(&mut self, text: &str) -> Result<f32> {
+    let mut width = 0.0;
+    for ch in text.chars() {
+      width += self.glyph(ch)?.advance;
+    }
+    Ok(width)
+  }
+
+  // Uploads the atlas if glyphs were added since the last upload.
+  pub(crate) fn upload(&mut self) -> Result<()> {
+    if self.dirty {
+      self.texture.upload_canvas(&self.canvas)?;
+      self.dirty = false;
+    }
+    Ok(())
+  }
+
+  pub(crate) fn texture(&self) -> &Texture {
+    &self.texture
+  }
+
+  pub(crate) fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.texture = Texture::new(context)?;
+    self.dirty = true;
+    Ok(())
+  }
+
+  // Doubles the height of the atlas. Resizing a canvas wipes it, so all
+  // glyphs are drawn again at their old positions.
+  fn grow(&mut self) -> Result<()> {
+    let height = self.canvas.height() * 2;
+    if height > MAX_ATLAS_HEIGHT {
+      return Err(GestaltError::InvalidArgument(format!("glyph atlas for '{}' is full", self.font)));
+    }
+    self.canvas.set_height(height);
+    self.reset_context();
+
+    let glyphs: Vec<(char, Glyph)> = self.glyphs.iter().map(|(&ch, &glyph)| (ch, glyph)).collect();
+    for (ch, glyph) in glyphs {
+      self.draw_glyph(ch, &glyph)?;
+    }
+    Ok(())
+  }
+
+  // Resizing a canvas also resets its drawing state.
+  fn reset_context(&self) {
+    self.context2d.set_font(&self.font);
+    self.context2d.set_fill_style_str("white");
+    self.context2d.set_text_baseline("alphabetic");
+  }
+
+  fn draw_glyph(&self, ch: char, glyph: &Glyph) -> Result<()> {
+    if ch.is_whitespace() {
+      return Ok(());
+    }
+    let mut buffer = [0; 4];
+    self.context2d.fill_text(
+      ch.encode_utf8(&mut buffer),
+      (glyph.x + self.padding) as f64,
+      (glyph.y + self.padding) as f64 + self.ascent as f64,
+    )?;
+
+    if let Some(spread) = self.spread {
+      let (x, y) = (glyph.x as f64, glyph.y as f64);
+      let (width, height) = (glyph.width as usize, self.cell_height as usize);
+      let pixels = self.context2d.get_image_data(x, y, width as f64, height as f64)?.data();
+      let coverage: Vec<u8> = pixels.chunks(4).map(|pixel| pixel[3]).collect();
+
+      let mut pixels = Vec::with_capacity(width * height * 4);
+      for distance in sdf::signed_distance_field(&coverage, width, height, spread) {
+        pixels.extend_from_slice(&[255, 255, 255, distance]);
+      }
+      let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&pixels), width as u32, height as u32)?;
+      self.context2d.put_image_data(&image, x, y)?;
+    }
+    Ok(())
+  }
+
+  // A glyph's cell in atlas pixels: left, top, right, bottom.
+  fn pixel_rect(&self, glyph: &Glyph) -> [f32; 4] {
+    [
+      glyph.x as f32,
+      glyph.y as f32,
+      (glyph.x + glyph.width) as f32,
+      (glyph.y + self.cell_height) as f32,
+    ]
+  }
+
+  fn size(&self) -> (f32, f32) {
+    (self.canvas.width() as f32, self.canvas.height() as f32)
+  }
+}
+
+// Horizontal alignment of text lines relative to the x position passed to
+// `TextRenderer::draw_text`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+  Left,
+  Center,
+  Right,
+}
+
+// Draws text inside the GL canvas, on top of its scene, in the same pixel
+// coordinates and with the same immediate-mode behaviour as `Draw2D`. Glyphs
+// are rasterized by the browser at the given font size. Bitmap text is sharp
+// at that size only; distance field text, see `sdf`, stays sharp when scaled
+// with `set_size` and can be outlined.
+#[wasm_bindgen]
+pub struct TextRenderer {
+  text: Rc<RefCell<Text>>,
+  layer: LayerSlot,
+}
+
+struct Text {
+  context: WebGl2RenderingContext,
+  program: ShaderProgram,
+  batch: VertexBatch,
+  atlas: GlyphAtlas,
+  // Drawn size over rasterized size.
+  scale: f32,
+  color: [f32; 4],
+  align: TextAlign,
+  max_width: f32,
+  line_spacing: f32,
+  auto_clear: bool,
+}
+
+#[wasm_bindgen]
+impl TextRenderer {
+
+  // `font_family` is a CSS font family list, e.g. "Helvetica, sans-serif".
+  // Web fonts must have finished loading before the first text is drawn.
+  pub fn new(canvas: &WebGlCanvas, font_family: &str, font_size: f32) -> Result<TextRenderer> {
+    TextRenderer::with_atlas(canvas, font_family, font_size, None)
+  }
+
+  // Like `new`, but with distance field glyphs. `font_size` is only the
+  // rasterization size; 32 to 64 pixels give good results at any size.
+  pub fn sdf(canvas: &WebGlCanvas, font_family: &str, font_size: f32) -> Result<TextRenderer> {
+    let spread = (font_size / 6.0).max(4.0);
+    TextRenderer::with_atlas(canvas, font_family, font_size, Some(spread))
+  }
+
+  pub fn set_color(&self, r: f32, g: f32, b: f32, a: f32) {
+    self.text.borrow_mut().color = [r, g, b, a];
+  }
+
+  // Font size to draw at, in pixels; the creation font size to begin with.
+  pub fn set_size(&self, size: f32) {
+    let mut text = self.text.borrow_mut();
+    text.scale = size / text.atlas.font_size;
+  }
+
+  // Distance field text only: surrounds glyphs with an outline `width`
+  // pixels wide at the rasterized font size, at most the field's spread.
+  // A width of 0 removes it.
+  pub fn set_outline(&self, width: f32, r: f32, g: f32, b: f32, a: f32) {
+    let mut text = self.text.borrow_mut();
+    let spread = text.atlas.spread.unwrap_or(0.0);
+    text.program.set_f32("u_outline_width", width.clamp(0.0, spread));
+    text.program.set_vec4("u_outline_color", r, g, b, a);
+  }
+
+  // Distance field text only: grows glyphs by `weight` pixels at the
+  // rasterized font size, or thins them if negative.
+  pub fn set_weight(&self, weight: f32) {
+    self.text.borrow_mut().program.set_f32("u_weight", weight);
+  }
+
+  pub fn set_align(&self, align: TextAlign) {
+    self.text.borrow_mut().align = align;
+  }
+
+  // Wraps lines at spaces to stay within `max_width` pixels; 0 turns
+  // wrapping off. Words longer than that are not broken.
+  pub fn set_max_width(&self, max_width: f32) {
+    self.text.borrow_mut().max_width = max_width.max(0.0);
+  }
+
+  // Distance between baselines as a multiple of the font's line height.
+  pub fn set_line_spacing(&self, line_spacing: f32) {
+    self.text.borrow_mut().line_spacing = line_spacing;
+  }
+
+  pub fn set_auto_clear(&self, auto_clear: bool) {
+    self.text.borrow_mut().auto_clear = auto_clear;
+  }
+
+  pub fn clear(&self) {
+    self.text.borrow_mut().batch.clear();
+  }
+
+  // Width of `text` as a single line, in pixels.
+  pub fn measure(&self, text: &str) -> Result<f32> {
+    let mut state = self.text.borrow_mut();
+    Ok(state.atlas.measure(text)? * state.scale)
+  }
+
+  // Draws `text` with the top of its first line at `y`. Lines break at
+  // newlines and, with a maximum width set, at spaces.
+  pub fn draw_text(&self, text: &str, x: f32, y: f32) -> Result<()> {
+    self.text.borrow_mut().draw(text, x, y)
+  }
+}
+
+impl TextRenderer {
+  fn with_atlas(canvas: &WebGlCanvas, font_family: &str, font_size: f32, spread: Option<f32>) -> Result<TextRenderer> {
+    let context = canvas.context();
+    let frag_src = if spread.is_some() { TEXT_SDF_FRAG_SHADER } else { TEXT_FRAG_SHADER };
+    let mut program = ShaderProgram::new(&context, TEXT_VERT_SHADER, frag_src)?;
+    let layout = attribute_layout(&program, &[("position", 2), ("uv", 2), ("color", 4)])?;
+    let batch = VertexBatch::new(&context, &layout)?;
+    program.set_i32("u_atlas", 0);
+    if let Some(spread) = spread {
+      program.set_f32("u_spread", spread);
+    }
+
+    let text = Rc::new(RefCell::new(Text {
+      atlas: GlyphAtlas::new(&context, font_family, font_size, spread)?,
+      context,
+      program,
+      batch,
+      scale: 1.0,
+      color: [1.0, 1.0, 1.0, 1.0],
+      align: TextAlign::Left,
+      max_width: 0.0,
+      line_spacing: 1.2,
+      auto_clear: true,
+    }));
+    let layer = canvas.add_overlay(text.clone(), Layer::Overlay);
+    Ok(TextRenderer { text, layer })
+  }
+
+  // Layer the text is drawn in, `Overlay` unless moved.
+  pub fn set_layer(&self, layer: Layer) {
+    self.layer.set(layer);
+  }
+}
+
+impl Text {
+  fn draw(&mut self, text: &str, x: f32, y: f32) -> Result<()> {
+    let scale = self.scale;
+    let line_advance = self.atlas.line_height() * self.line_spacing * scale;
+    let padding = self.atlas.padding as f32 * scale;
+    // Whole pixels keep bitmap glyphs crisp at their own size.
+    let snap = self.atlas.spread.is_none() && scale == 1.0;
+
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+      wrap_paragraph(&mut self.atlas, paragraph, self.max_width / scale, &mut lines)?;
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+      let width = self.atlas.measure(line)? * scale;
+      let mut pen_x = match self.align {
+        TextAlign::Left => x,
+        TextAlign::Center => x - width / 2.0,
+        TextAlign::Right => x - width,
+      };
+      let top = y + index as f32 * line_advance;
+
+      for ch in line.chars() {
+        let glyph = self.atlas.glyph(ch)?;
+        if !ch.is_whitespace() {
+          let (x, y) = if snap { (pen_x.round(), top.round()) } else { (pen_x, top) };
+          let left = x - padding;
+          let cell_top = y - padding;
+          let right = left + glyph.width as f32 * scale;
+          let bottom = cell_top + self.atlas.cell_height as f32 * scale;
+          let [u0, v0, u1, v1] = self.atlas.pixel_rect(&glyph);
+          let [red, green, blue, alpha] = self.color;
+          for (px, py, u, v) in [
+            (left, cell_top, u0, v0),
+            (right, cell_top, u1, v0),
+            (right, bottom, u1, v1),
+            (left, cell_top, u0, v0),
+            (right, bottom, u1, v1),
+            (left, bottom, u0, v1),
+          ] {
+            self.batch.push(&[px, py, u, v, red, green, blue, alpha]);
+          }
+        }
+        pen_x += glyph.advance * scale;
+      }
+    }
+    Ok(())
+  }
+}
+
+// Greedily breaks `paragraph` at spaces into lines no wider than
+// `max_width` (unless a single word is), appending them to `lines`.
+fn wrap_paragraph(atlas: &mut GlyphAtlas, paragraph: &str, max_width: f32, lines: &mut Vec<String>) -> Result<()> {
+  if max_width <= 0.0 {
+    lines.push(paragraph.to_string());
+    return Ok(());
+  }
+
+  let space = atlas.measure(" ")?;
+  let mut line = String::new();
+  let mut line_width = 0.0;
+  for word in paragraph.split(' ') {
+    let word_width = atlas.measure(word)?;
+    if !line.is_empty() && line_width + space + word_width > max_width {
+      lines.push(std::mem::take(&mut line));
+      line_width = 0.0;
+    }
+    if !line.is_empty() {
+      line.push(' ');
+      line_width += space;
+    }
+    line.push_str(word);
+    line_width += word_width;
+  }
+  lines.push(line);
+  Ok(())
+}
+
+impl Overlay for Text {
+  fn draw(&mut self, width: u32, height: u32, _time: f32) {
+    if !self.batch.is_empty() {
+      if let Err(error) = self.atlas.upload() {
+        web_sys::console::error_1(&format!("Failed to upload glyph atlas: {}", error).into());
+      }
+
+      let context = &self.context;
+      let (atlas_width, atlas_height) = self.atlas.size();
+      self.program.set_vec2("u_resolution", width as f32, height as f32);
+      self.program.set_vec2("u_atlas_size", atlas_width, atlas_height);
+      gl_state::bind_texture(context, 0, self.atlas.texture().raw());
+      gl_state::set_blend_mode(context, Some(BlendMode::Alpha));
+      self.batch.draw(WebGl2RenderingContext::TRIANGLES);
+    }
+
+    if self.auto_clear {
+      self.batch.clear();
+    }
+  }
+
+  fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.context = context.clone();
+    self.program.restore()?;
+    self.batch.restore(context)?;
+    self.atlas.restore(context)
+  }
+}
@@ -0,0 +1,359 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::{HtmlCanvasElement, HtmlImageElement, HtmlMediaElement, HtmlVideoElement, WebGl2RenderingContext, WebGlTexture};
+
+use crate::error::{GestaltError, Result};
+use crate::gl_state;
+
+// Channel layout of raw pixel data.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+  R,
+  Rg,
+  Rgb,
+  Rgba,
+}
+
+impl TextureFormat {
+  pub(crate) fn channels(self) -> usize {
+    match self {
+      TextureFormat::R => 1,
+      TextureFormat::Rg => 2,
+      TextureFormat::Rgb => 3,
+      TextureFormat::Rgba => 4,
+    }
+  }
+
+  fn format(self) -> u32 {
+    match self {
+      TextureFormat::R => WebGl2RenderingContext::RED,
+      TextureFormat::Rg => WebGl2RenderingContext::RG,
+      TextureFormat::Rgb => WebGl2RenderingContext::RGB,
+      TextureFormat::Rgba => WebGl2RenderingContext::RGBA,
+    }
+  }
+
+  fn u8_internal_format(self) -> u32 {
+    match self {
+      TextureFormat::R => WebGl2RenderingContext::R8,
+      TextureFormat::Rg => WebGl2RenderingContext::RG8,
+      TextureFormat::Rgb => WebGl2RenderingContext::RGB8,
+      TextureFormat::Rgba => WebGl2RenderingContext::RGBA8,
+    }
+  }
+
+  fn f32_internal_format(self) -> u32 {
+    match self {
+      TextureFormat::R => WebGl2RenderingContext::R32F,
+      TextureFormat::Rg => WebGl2RenderingContext::RG32F,
+      TextureFormat::Rgb => WebGl2RenderingContext::RGB32F,
+      TextureFormat::Rgba => WebGl2RenderingContext::RGBA32F,
+    }
+  }
+}
+
+// A 2D texture on the GPU. Bind it to a sampler uniform with
+// `WebGlCanvas::set_uniform_texture`.
+#[wasm_bindgen]
+pub struct Texture {
+  context: WebGl2RenderingContext,
+  texture: WebGlTexture,
+  width: u32,
+  height: u32,
+}
+
+#[wasm_bindgen]
+impl Texture {
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  // Replaces the contents with 8-bit pixel data, one byte per channel. Rows
+  // go bottom to top, as in GL texture coordinates. The size may change.
+  pub fn set_data(&mut self, width: u32, height: u32, format: TextureFormat, data: &[u8]) -> Result<()> {
+    check_data_len(width, height, format, data.len())?;
+
+    self.bind();
+    self.context.pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 1);
+    self.context
+      .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        WebGl2RenderingContext::TEXTURE_2D,
+        0,
+        format.u8_internal_format() as i32,
+        width as i32,
+        height as i32,
+        0,
+        format.format(),
+        WebGl2RenderingContext::UNSIGNED_BYTE,
+        Some(data),
+      )?;
+
+    self.width = width;
+    self.height = height;
+    Ok(())
+  }
+
+  // Like `set_data`, for 32-bit float channels. Without the
+  // `OES_texture_float_linear` extension float textures are sampled with
+  // nearest-neighbour filtering.
+  pub fn set_f32_data(&mut self, width: u32, height: u32, format: TextureFormat, data: &[f32]) -> Result<()> {
+    check_data_len(width, height, format, data.len())?;
+
+    self.bind();
+    self.context.pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 1);
+    // No allocations may happen while the view into wasm memory is alive.
+    let result = unsafe {
+      let data_view = js_sys::Float32Array::view(data);
+
+      self.context
+        .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+          WebGl2RenderingContext::TEXTURE_2D,
+          0,
+          format.f32_internal_format() as i32,
+          width as i32,
+          height as i32,
+          0,
+          format.format(),
+          WebGl2RenderingContext::FLOAT,
+          Some(&data_view),
+        )
+    };
+    result?;
+
+    if self.context.get_extension("OES_texture_float_linear")?.is_none() {
+      self.set_filter(WebGl2RenderingContext::NEAREST);
+    }
+
+    self.width = width;
+    self.height = height;
+    Ok(())
+  }
+}
+
+impl Texture {
+  // Creates an empty texture with linear filtering and edge clamping, which
+  // works for any size in WebGL2.
+  pub(crate) fn new(context: &WebGl2RenderingContext) -> Result<Texture> {
+    let texture = context
+      .create_texture()
+      .ok_or(GestaltError::ResourceCreation("texture"))?;
+
+    gl_state::bind_texture_for_update(context, &texture);
+    for (parameter, value) in [
+      (WebGl2RenderingContext::TEXTURE_MIN_FILTER, WebGl2RenderingContext::LINEAR),
+      (WebGl2RenderingContext::TEXTURE_MAG_FILTER, WebGl2RenderingContext::LINEAR),
+      (WebGl2RenderingContext::TEXTURE_WRAP_S, WebGl2RenderingContext::CLAMP_TO_EDGE),
+      (WebGl2RenderingContext::TEXTURE_WRAP_T, WebGl2RenderingContext::CLAMP_TO_EDGE),
+    ] {
+      context.tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, parameter, value as i32);
+    }
+
+    Ok(Texture {
+      context: context.clone(),
+      texture,
+      width: 0,
+      height: 0,
+    })
+  }
+
+  // Fetches and decodes the image at `url` and uploads it. Cross-origin
+  // images need CORS headers to be usable as textures.
+  pub(crate) async fn load(context: WebGl2RenderingContext, url: String) -> Result<Texture> {
+    let image = decode_image(&url).await?;
+    Texture::from_image(&context, &image)
+  }
+
+  // Uploads an image that is already decoded.
+  pub(crate) fn from_image(context: &WebGl2RenderingContext, image: &HtmlImageElement) -> Result<Texture> {
+    let mut texture = Texture::new(context)?;
+    texture.upload_image(image)?;
+    Ok(texture)
+  }
+
+  fn upload_image(&mut self, image: &HtmlImageElement) -> Result<()> {
+    let context = &self.context;
+    gl_state::bind_texture_for_update(context, &self.texture);
+    // Images are stored top row first, GL textures bottom row first.
+    context.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 1);
+    let result = context.tex_image_2d_with_u32_and_u32_and_html_image_element(
+      WebGl2RenderingContext::TEXTURE_2D,
+      0,
+      WebGl2RenderingContext::RGBA as i32,
+      WebGl2RenderingContext::RGBA,
+      WebGl2RenderingContext::UNSIGNED_BYTE,
+      image,
+    );
+    context.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 0);
+    result?;
+
+    self.width = image.natural_width();
+    self.height = image.natural_height();
+    Ok(())
+  }
+
+  // Uploads the contents of a 2D canvas as they are, top row first, so that
+  // texture coordinates match canvas coordinates scaled to 0..1.
+  pub(crate) fn upload_canvas(&mut self, canvas: &HtmlCanvasElement) -> Result<()> {
+    self.bind();
+    self.context.tex_image_2d_with_u32_and_u32_and_html_canvas_element(
+      WebGl2RenderingContext::TEXTURE_2D,
+      0,
+      WebGl2RenderingContext::RGBA as i32,
+      WebGl2RenderingContext::RGBA,
+      WebGl2RenderingContext::UNSIGNED_BYTE,
+      canvas,
+    )?;
+
+    self.width = canvas.width();
+    self.height = canvas.height();
+    Ok(())
+  }
+
+  // Allocates uninitialized storage, e.g. for rendering into.
+  pub(crate) fn allocate(&mut self, width: u32, height: u32, internal_format: u32, format: u32, type_: u32) -> Result<()> {
+    self.bind();
+    self.context
+      .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        WebGl2RenderingContext::TEXTURE_2D,
+        0,
+        internal_format as i32,
+        width as i32,
+        height as i32,
+        0,
+        format,
+        type_,
+        None,
+      )?;
+
+    self.width = width;
+    self.height = height;
+    Ok(())
+  }
+
+  // Uploads the video's current frame, if it has one. Returns whether an
+  // upload happened.
+  pub(crate) fn upload_video_frame(&mut self, video: &HtmlVideoElement) -> Result<bool> {
+    if video.ready_state() < HtmlMediaElement::HAVE_CURRENT_DATA {
+      return Ok(false);
+    }
+
+    let context = &self.context;
+    self.bind();
+    context.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 1);
+    let result = context.tex_image_2d_with_u32_and_u32_and_html_video_element(
+      WebGl2RenderingContext::TEXTURE_2D,
+      0,
+      WebGl2RenderingContext::RGBA as i32,
+      WebGl2RenderingContext::RGBA,
+      WebGl2RenderingContext::UNSIGNED_BYTE,
+      video,
+    );
+    context.pixel_storei(WebGl2RenderingContext::UNPACK_FLIP_Y_WEBGL, 0);
+    result?;
+
+    self.width = video.video_width();
+    self.height = video.video_height();
+    Ok(true)
+  }
+
+  pub(crate) fn bind(&self) {
+    gl_state::bind_texture_for_update(&self.context, &self.texture);
+  }
+
+  pub(crate) fn set_filter(&self, filter: u32) {
+    self.bind();
+    self.context.tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, WebGl2RenderingContext::TEXTURE_MIN_FILTER, filter as i32);
+    self.context.tex_parameteri(WebGl2RenderingContext::TEXTURE_2D, WebGl2RenderingContext::TEXTURE_MAG_FILTER, filter as i32);
+  }
+
+  pub(crate) fn raw(&self) -> &WebGlTexture {
+    &self.texture
+  }
+
+  // Fails unless the texture was made on `context`, for textures handed in
+  // from JavaScript, which may come from another canvas.
+  pub(crate) fn check_context(&self, context: &WebGl2RenderingContext) -> Result<()> {
+    if self.context != *context {
+      return Err(GestaltError::ForeignContext("texture"));
+    }
+    Ok(())
+  }
+}
+
+fn check_data_len(width: u32, height: u32, format: TextureFormat, len: usize) -> Result<()> {
+  let expected = width as usize * height as usize * format.channels();
+  if len != expected {
+    return Err(GestaltError::InvalidArgument(format!(
+      "{}x{} texture with {} channels needs {} values, got {}",
+      width,
+      height,
+      format.channels(),
+      expected,
+      len
+    )));
+  }
+  Ok(())
+}
+
+// A texture that follows a playing `<video>` element, re-uploaded whenever
+// the video has advanced to a new frame.
+pub(crate) struct VideoTexture {
+  pub(crate) video: HtmlVideoElement,
+  pub(crate) texture: Texture,
+  pub(crate) name: String,
+  pub(crate) unit: u32,
+  last_time: Option<f64>,
+}
+
+impl VideoTexture {
+  pub(crate) fn new(context: &WebGl2RenderingContext, video: HtmlVideoElement, name: &str, unit: u32) -> Result<VideoTexture> {
+    Ok(VideoTexture {
+      video,
+      texture: Texture::new(context)?,
+      name: name.to_string(),
+      unit,
+      last_time: None,
+    })
+  }
+
+  // Swaps in a fresh texture after a context loss.
+  pub(crate) fn restore(&mut self, context: &WebGl2RenderingContext) -> Result<()> {
+    self.texture = Texture::new(context)?;
+    self.last_time = None;
+    Ok(())
+  }
+
+  pub(crate) fn update(&mut self) -> Result<()> {
+    let time = self.video.current_time();
+    if self.last_time == Some(time) {
+      return Ok(());
+    }
+    if self.texture.upload_video_frame(&self.video)? {
+      self.last_time = Some(time);
+    }
+    Ok(())
+  }
+}
+
+impl Drop for Texture {
+  fn drop(&mut self) {
+    self.context.delete_texture(Some(&self.texture));
+  }
+}
+
+// Fetches the image at `url` and waits until it is decoded, requesting it
+// with CORS so that it can be uploaded to a texture.
+pub(crate) async fn decode_image(url: &str) -> Result<HtmlImageElement> {
+  let image = HtmlImageElement::new()?;
+  image.set_cross_origin(Some("anonymous"));
+  image.set_src(url);
+  JsFuture::from(image.decode()).await?;
+  Ok(image)
+}
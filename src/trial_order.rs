@@ -0,0 +1,292 @@
+use wasm_bindgen::prelude::*;
+
+use crate::error::{GestaltError, Result};
+use crate::random::Rng;
+
+// Shuffles tried before `shuffle_limited` gives up.
+const MAX_ATTEMPTS: usize = 1000;
+
+// Shuffles `items` so that no value comes more than `max_run` times in a
+// row, continuing `run`, the last value before them and how often it came.
+// Each position is drawn from the items still allowed there, starting over
+// at a dead end.
+fn shuffle_limited(rng: &mut Rng, items: &mut [u32], max_run: usize, run: Option<(u32, usize)>) -> Result<()> {
+  for _ in 0..MAX_ATTEMPTS {
+    let mut pool = items.to_vec();
+    let mut order = Vec::with_capacity(items.len());
+    let mut current = run;
+    while !pool.is_empty() {
+      let allowed: Vec<usize> = (0..pool.len())
+        .filter(|&index| !matches!(current, Some((value, count)) if pool[index] == value && count >= max_run))
+        .collect();
+      if allowed.is_empty() {
+        break;
+      }
+      let item = pool.swap_remove(allowed[rng.below(allowed.len())]);
+      current = match current {
+        Some((value, count)) if value == item => Some((value, count + 1)),
+        _ => Some((item, 1)),
+      };
+      order.push(item);
+    }
+    if order.len() == items.len() {
+      items.copy_from_slice(&order);
+      return Ok(());
+    }
+  }
+  Err(GestaltError::InvalidArgument(format!(
+    "found no order with at most {} identical conditions in a row",
+    max_run
+  )))
+}
+
+// The last value of `items` and how often it comes at the end in a row.
+fn final_run(items: &[u32]) -> Option<(u32, usize)> {
+  let &last = items.last()?;
+  Some((last, items.iter().rev().take_while(|&&item| item == last).count()))
+}
+
+// An order of conditions, by index, for the trials of a session: plain,
+// shuffled in blocks, or a row of a balanced Latin square picked by
+// participant. Hand it to `TrialRunner::add_trials` to schedule trials in
+// that order.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrialOrder {
+  conditions: Vec<u32>,
+}
+
+#[wasm_bindgen]
+impl TrialOrder {
+
+  // Exactly the order given.
+  #[wasm_bindgen(constructor)]
+  pub fn new(conditions: &[u32]) -> TrialOrder {
+    TrialOrder {
+      conditions: conditions.to_vec(),
+    }
+  }
+
+  // `blocks` blocks, each with every one of `conditions` conditions
+  // `repetitions` times, shuffled from `seed` within the block. With a
+  // `max_run` above 0, no condition comes more than that many times in a
+  // row, across block boundaries too.
+  pub fn blocked(conditions: u32, repetitions: u32, blocks: u32, max_run: u32, seed: u32) -> Result<TrialOrder> {
+    if conditions == 0 || repetitions == 0 || blocks == 0 {
+      return Err(GestaltError::InvalidArgument(format!(
+        "blocks need conditions and repetitions, got {} blocks of {} conditions x {}",
+        blocks, conditions, repetitions
+      )));
+    }
+    let mut rng = Rng::new(seed as u64);
+    let mut order = Vec::with_capacity((conditions * repetitions * blocks) as usize);
+    for _ in 0..blocks {
+      let start = order.len();
+      order.extend((0..conditions).flat_map(|condition| (0..repetitions).map(move |_| condition)));
+      let run = final_run(&order[..start]);
+      if max_run > 0 {
+        shuffle_limited(&mut rng, &mut order[start..], max_run as usize, run)?;
+      } else {
+        rng.shuffle(&mut order[start..]);
+      }
+    }
+    Ok(TrialOrder { conditions: order })
+  }
+
+  // The row of a Williams design for `participant`: every condition once,
+  // and over a full set of participants each condition comes first equally
+  // often and follows every other one equally often. That takes
+  // `conditions` participants for an even number of conditions and twice
+  // as many for an odd one; participants beyond wrap around.
+  pub fn latin_square(conditions: u32, participant: u32) -> Result<TrialOrder> {
+    if conditions == 0 {
+      return Err(GestaltError::InvalidArgument("a Latin square needs conditions".into()));
+    }
+    let rows = if conditions.is_multiple_of(2) { conditions } else { 2 * conditions };
+    let row = participant % rows;
+    // 0, 1, n - 1, 2, n - 2, ..., shifted by the row.
+    let mut order: Vec<u32> = (0..conditions)
+      .map(|position| {
+        let first = if position % 2 == 1 { position.div_ceil(2) } else { (conditions - position / 2) % conditions };
+        (first + row) % conditions
+      })
+      .collect();
+    if row >= conditions {
+      order.reverse();
+    }
+    Ok(TrialOrder { conditions: order })
+  }
+
+  // Shuffles the whole order from `seed`, with no condition more than
+  // `max_run` times in a row; 0 places no limit.
+  pub fn shuffle(&mut self, max_run: u32, seed: u32) -> Result<()> {
+    let mut rng = Rng::new(seed as u64);
+    if max_run > 0 {
+      shuffle_limited(&mut rng, &mut self.conditions, max_run as usize, None)
+    } else {
+      rng.shuffle(&mut self.conditions);
+      Ok(())
+    }
+  }
+
+  // Appends `other`, e.g. the next block.
+  pub fn append(&mut self, other: &TrialOrder) {
+    self.conditions.extend_from_slice(&other.conditions);
+  }
+
+  // The longest stretch of one condition in a row.
+  pub fn longest_run(&self) -> u32 {
+    let mut longest = 0;
+    let mut rest = &self.conditions[..];
+    while let Some((_, run)) = final_run(rest) {
+      longest = longest.max(run);
+      rest = &rest[..rest.len() - run];
+    }
+    longest as u32
+  }
+
+  pub fn len(&self) -> usize {
+    self.conditions.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.conditions.is_empty()
+  }
+
+  pub fn conditions(&self) -> Vec<u32> {
+    self.conditions.clone()
+  }
+}
+
+impl TrialOrder {
+  pub(crate) fn as_slice(&self) -> &[u32] {
+    &self.conditions
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::TrialOrder;
+
+  // Every row of the square for `conditions`, one per participant of a
+  // full set.
+  fn square(conditions: u32) -> Vec<Vec<u32>> {
+    let rows = if conditions.is_multiple_of(2) { conditions } else { 2 * conditions };
+    (0..rows)
+      .map(|participant| TrialOrder::latin_square(conditions, participant).unwrap().conditions())
+      .collect()
+  }
+
+  fn is_permutation(order: &[u32], conditions: u32) -> bool {
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+    sorted == (0..conditions).collect::<Vec<_>>()
+  }
+
+  fn counts(order: &[u32], conditions: u32) -> Vec<usize> {
+    (0..conditions).map(|condition| order.iter().filter(|&&item| item == condition).count()).collect()
+  }
+
+  #[test]
+  fn latin_square_rows_are_permutations() {
+    for conditions in 1..=9 {
+      for row in square(conditions) {
+        assert!(is_permutation(&row, conditions), "{} conditions: {:?}", conditions, row);
+      }
+    }
+  }
+
+  #[test]
+  fn latin_square_columns_hold_each_condition_equally_often() {
+    for conditions in 1..=9 {
+      let rows = square(conditions);
+      let per_column = rows.len() / conditions as usize;
+      for position in 0..conditions as usize {
+        let column: Vec<u32> = rows.iter().map(|row| row[position]).collect();
+        assert_eq!(counts(&column, conditions), vec![per_column; conditions as usize], "{} conditions", conditions);
+      }
+    }
+  }
+
+  #[test]
+  fn latin_square_balances_first_order_carryover() {
+    for conditions in 2..=9 {
+      let rows = square(conditions);
+      let per_pair = rows.len() / conditions as usize;
+      let n = conditions as usize;
+      let mut pairs = vec![vec![0; n]; n];
+      for row in &rows {
+        for pair in row.windows(2) {
+          pairs[pair[0] as usize][pair[1] as usize] += 1;
+        }
+      }
+      for (before, followers) in pairs.iter().enumerate() {
+        for (after, &count) in followers.iter().enumerate() {
+          let expected = if before == after { 0 } else { per_pair };
+          assert_eq!(count, expected, "{} conditions: {} then {}", conditions, before, after);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn latin_square_participants_wrap_around() {
+    for conditions in 1..=6 {
+      let rows = square(conditions);
+      for (participant, row) in rows.iter().enumerate() {
+        let again = TrialOrder::latin_square(conditions, (participant + rows.len()) as u32).unwrap();
+        assert_eq!(&again.conditions(), row);
+      }
+    }
+  }
+
+  #[test]
+  fn latin_square_needs_conditions() {
+    assert!(TrialOrder::latin_square(0, 0).is_err());
+  }
+
+  #[test]
+  fn blocked_keeps_runs_short_across_seeds() {
+    for max_run in 1..=3 {
+      for seed in 0..200 {
+        let order = TrialOrder::blocked(3, 4, 3, max_run, seed).unwrap();
+        assert!(order.longest_run() <= max_run, "seed {}: {:?}", seed, order.conditions());
+        for block in order.conditions().chunks(12) {
+          assert_eq!(counts(block, 3), vec![4, 4, 4]);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn shuffle_keeps_runs_short_across_seeds() {
+    let conditions: Vec<u32> = (0..4).flat_map(|condition| vec![condition; 5]).collect();
+    for seed in 0..200 {
+      let mut order = TrialOrder::new(&conditions);
+      order.shuffle(1, seed).unwrap();
+      assert_eq!(order.longest_run(), 1, "seed {}: {:?}", seed, order.conditions());
+      assert_eq!(counts(order.as_slice(), 4), vec![5; 4]);
+    }
+  }
+
+  #[test]
+  fn shuffle_is_repeatable_by_seed() {
+    let conditions: Vec<u32> = (0..6).collect();
+    let (mut first, mut second) = (TrialOrder::new(&conditions), TrialOrder::new(&conditions));
+    first.shuffle(0, 7).unwrap();
+    second.shuffle(0, 7).unwrap();
+    assert_eq!(first, second);
+  }
+
+  #[test]
+  fn impossible_run_limits_fail() {
+    let mut order = TrialOrder::new(&[0, 0, 0, 1]);
+    assert!(order.shuffle(1, 0).is_err());
+  }
+
+  #[test]
+  fn longest_run_counts_repeats() {
+    assert_eq!(TrialOrder::new(&[0, 1, 1, 2, 2, 2, 0]).longest_run(), 3);
+    assert_eq!(TrialOrder::new(&[]).longest_run(), 0);
+  }
+}
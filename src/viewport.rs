@@ -0,0 +1,47 @@
+use wasm_bindgen::prelude::*;
+
+// A rectangle of the drawing buffer in pixels from the top left, for
+// drawing into or clipping to part of a canvas, e.g. one of several
+// side-by-side panels.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+#[wasm_bindgen]
+impl Viewport {
+
+  #[wasm_bindgen(constructor)]
+  pub fn new(x: u32, y: u32, width: u32, height: u32) -> Viewport {
+    Viewport { x, y, width, height }
+  }
+
+  pub fn contains(&self, x: f32, y: f32) -> bool {
+    x >= self.x as f32 && y >= self.y as f32 && x < (self.x + self.width) as f32 && y < (self.y + self.height) as f32
+  }
+}
+
+impl Viewport {
+  // x, y, width and height as GL takes them, from the bottom left of a
+  // `drawing_height` pixel high buffer.
+  pub(crate) fn gl_rect(&self, drawing_height: u32) -> [i32; 4] {
+    let bottom = drawing_height as i32 - (self.y + self.height) as i32;
+    [self.x as i32, bottom, self.width as i32, self.height as i32]
+  }
+}
+
+// The overlap of two GL rectangles, where `None` is unclipped.
+pub(crate) fn intersect(a: [i32; 4], b: Option<[i32; 4]>) -> [i32; 4] {
+  let Some(b) = b else {
+    return a;
+  };
+  let left = a[0].max(b[0]);
+  let bottom = a[1].max(b[1]);
+  let right = (a[0] + a[2]).min(b[0] + b[2]);
+  let top = (a[1] + a[3]).min(b[1] + b[3]);
+  [left, bottom, (right - left).max(0), (top - bottom).max(0)]
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{
+  HtmlCanvasElement, OesVertexArrayObject, WebGlBuffer, WebGlProgram, WebGlRenderingContext, WebGlShader,
+  WebGlUniformLocation, WebGlVertexArrayObject,
+};
+
+use crate::color::Color;
+use crate::error::{GestaltError, Result};
+use crate::graphics::{document, DEFAULT_FRAG_SHADER, DEFAULT_VERT_SHADER};
+use crate::shader::{parse_info_log, FULLSCREEN_VERT_SHADER, POSITION_LOCATION};
+
+// Whether this browser can make a WebGL2 context, i.e. whether to use
+// `WebGlCanvas` or fall back to `WebGl1Canvas`.
+#[wasm_bindgen]
+pub fn webgl2_supported() -> bool {
+  let Ok(document) = document() else {
+    return false;
+  };
+  document
+    .create_element("canvas")
+    .ok()
+    .and_then(|canvas| canvas.dyn_into::<HtmlCanvasElement>().ok())
+    .and_then(|canvas| canvas.get_context("webgl2").ok().flatten())
+    .is_some()
+}
+
+// Fallback for browsers without WebGL2, such as older Safari and embedded
+// browsers: a single shader drawn over vertices on a WebGL1 context, enough
+// for basic stimuli. Shaders may be written for WebGL2 and are rewritten to
+// GLSL ES 1.00 where the languages overlap (see `to_glsl_100`), or given in
+// 1.00 already, e.g. as the second of two sources kept for either canvas.
+// Textures, post-processing, overlays and the other `WebGlCanvas` extras are
+// not available, and there is no `RenderLoop`; call `render` from
+// `requestAnimationFrame`. A lost context is not restored.
+#[wasm_bindgen]
+pub struct WebGl1Canvas {
+  canvas: HtmlCanvasElement,
+  context: WebGlRenderingContext,
+  // `OES_vertex_array_object`, which nearly every WebGL1 browser has.
+  // Without it the vertex layout is set up again for each draw.
+  vertex_arrays: Option<(OesVertexArrayObject, WebGlVertexArrayObject)>,
+  program: WebGlProgram,
+  vertex_buffer: WebGlBuffer,
+  components: i32,
+  vertex_count: i32,
+  uniforms: HashMap<String, Option<WebGlUniformLocation>>,
+  clear_color: Color,
+}
+
+#[wasm_bindgen]
+impl WebGl1Canvas {
+
+  pub fn new(canvas_id: &str) -> Result<WebGl1Canvas> {
+    WebGl1Canvas::with_shaders(canvas_id, DEFAULT_VERT_SHADER, DEFAULT_FRAG_SHADER)
+  }
+
+  // Like `WebGlCanvas::with_shaders`. Each source is either GLSL ES 3.00,
+  // rewritten for WebGL1, or GLSL ES 1.00, used as it is.
+  pub fn with_shaders(canvas_id: &str, vert_src: &str, frag_src: &str) -> Result<WebGl1Canvas> {
+    let canvas = document()?
+      .get_element_by_id(canvas_id)
+      .ok_or_else(|| GestaltError::CanvasNotFound(canvas_id.to_string()))?
+      .dyn_into::<HtmlCanvasElement>()
+      .map_err(|_| GestaltError::NotACanvas(canvas_id.to_string()))?;
+    let context = canvas
+      .get_context("webgl")?
+      .ok_or(GestaltError::ResourceCreation("WebGL context"))?
+      .dyn_into::<WebGlRenderingContext>()
+      .map_err(|_| GestaltError::ResourceCreation("WebGL context"))?;
+
+    let program = link(
+      &context,
+      &to_glsl_100(vert_src, WebGlRenderingContext::VERTEX_SHADER)?,
+      &to_glsl_100(frag_src, WebGlRenderingContext::FRAGMENT_SHADER)?,
+    )?;
+    let vertex_buffer = context
+      .create_buffer()
+      .ok_or(GestaltError::ResourceCreation("vertex buffer"))?;
+    let vertex_arrays = context
+      .get_extension("OES_vertex_array_object")?
+      .and_then(|extension| extension.dyn_into::<OesVertexArrayObject>().ok())
+      .and_then(|extension| extension.create_vertex_array_oes().map(|vao| (extension, vao)));
+
+    let mut canvas = WebGl1Canvas {
+      canvas,
+      context,
+      vertex_arrays,
+      program,
+      vertex_buffer,
+      components: 2,
+      vertex_count: 0,
+      uniforms: HashMap::new(),
+      clear_color: Color::new(0.0, 0.0, 0.0, 1.0),
+    };
+    // The same demo triangle `WebGlCanvas` starts with.
+    canvas.set_vertices(&[0.0,  0.5,
+                          0.5, -0.5,
+                         -0.5, -0.5 ], 2)?;
+    Ok(canvas)
+  }
+
+  // Like `WebGlCanvas::fullscreen_shader`.
+  pub fn fullscreen_shader(canvas_id: &str, frag_src: &str) -> Result<WebGl1Canvas> {
+    let mut canvas = WebGl1Canvas::with_shaders(canvas_id, FULLSCREEN_VERT_SHADER, frag_src)?;
+    canvas.set_vertices(&[-1.0, -1.0,
+                           1.0, -1.0,
+                           1.0,  1.0,
+                          -1.0, -1.0,
+                           1.0,  1.0,
+                          -1.0,  1.0 ], 2)?;
+    Ok(canvas)
+  }
+
+  pub fn set_vertices(&mut self, vertices: &[f32], components_per_vertex: u32) -> Result<()> {
+    if !(1..=4).contains(&components_per_vertex) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "vertices need 1 to 4 components, got {}",
+        components_per_vertex
+      )));
+    }
+    if !vertices.len().is_multiple_of(components_per_vertex as usize) {
+      return Err(GestaltError::InvalidArgument(format!(
+        "{} floats do not divide into vertices of {} components",
+        vertices.len(),
+        components_per_vertex
+      )));
+    }
+
+    self.components = components_per_vertex as i32;
+    self.vertex_count = (vertices.len() / components_per_vertex as usize) as i32;
+    let context = &self.context;
+    context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&self.vertex_buffer));
+    // No allocations while the view into wasm memory is alive.
+    unsafe {
+      let vertices_view = js_sys::Float32Array::view(vertices);
+      context.buffer_data_with_array_buffer_view(
+        WebGlRenderingContext::ARRAY_BUFFER,
+        &vertices_view,
+        WebGlRenderingContext::DYNAMIC_DRAW,
+      );
+    }
+    if let Some((extension, vao)) = &self.vertex_arrays {
+      extension.bind_vertex_array_oes(Some(vao));
+      self.set_up_position();
+    }
+    Ok(())
+  }
+
+  pub fn set_clear_color(&mut self, color: &Color) {
+    self.clear_color = *color;
+  }
+
+  // Uniform setters, ignoring names the program does not use like
+  // `WebGlCanvas` does.
+
+  pub fn set_uniform_f32(&mut self, name: &str, value: f32) {
+    let location = self.uniform_location(name);
+    self.context.uniform1f(location.as_ref(), value);
+  }
+
+  pub fn set_uniform_i32(&mut self, name: &str, value: i32) {
+    let location = self.uniform_location(name);
+    self.context.uniform1i(location.as_ref(), value);
+  }
+
+  pub fn set_uniform_vec2(&mut self, name: &str, x: f32, y: f32) {
+    let location = self.uniform_location(name);
+    self.context.uniform2f(location.as_ref(), x, y);
+  }
+
+  pub fn set_uniform_vec3(&mut self, name: &str, x: f32, y: f32, z: f32) {
+    let location = self.uniform_location(name);
+    self.context.uniform3f(location.as_ref(), x, y, z);
+  }
+
+  pub fn set_uniform_vec4(&mut self, name: &str, x: f32, y: f32, z: f32, w: f32) {
+    let location = self.uniform_location(name);
+    self.context.uniform4f(location.as_ref(), x, y, z, w);
+  }
+
+  pub fn set_uniform_color(&mut self, name: &str, color: &Color) {
+    let [r, g, b, a] = color.to_array();
+    self.set_uniform_vec4(name, r, g, b, a);
+  }
+
+  // `time` in milliseconds, as passed by `requestAnimationFrame`.
+  pub fn render(&mut self, time: f32) {
+    if self.context.is_context_lost() {
+      return;
+    }
+    let [r, g, b, a] = self.clear_color.to_array();
+    self.context.clear_color(r, g, b, a);
+    self.context.clear(WebGlRenderingContext::COLOR_BUFFER_BIT);
+    self.set_uniform_f32("u_time", time / 1000.0);
+
+    match &self.vertex_arrays {
+      Some((extension, vao)) => extension.bind_vertex_array_oes(Some(vao)),
+      None => {
+        self.context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&self.vertex_buffer));
+        self.set_up_position();
+      }
+    }
+    self.context.draw_arrays(WebGlRenderingContext::TRIANGLES, 0, self.vertex_count);
+  }
+
+  // Like `WebGlCanvas::handle_resize`.
+  pub fn handle_resize(&self) -> bool {
+    let ratio = web_sys::window().map(|window| window.device_pixel_ratio()).unwrap_or(1.0);
+    let width = ((self.canvas.client_width() as f64 * ratio).round() as u32).max(1);
+    let height = ((self.canvas.client_height() as f64 * ratio).round() as u32).max(1);
+
+    let changed = self.canvas.width() != width || self.canvas.height() != height;
+    if changed {
+      self.canvas.set_width(width);
+      self.canvas.set_height(height);
+    }
+    self.context.viewport(0, 0, width as i32, height as i32);
+    changed
+  }
+}
+
+impl WebGl1Canvas {
+  // Points `position` at the bound vertex buffer.
+  fn set_up_position(&self) {
+    self.context.vertex_attrib_pointer_with_i32(
+      POSITION_LOCATION,
+      self.components,
+      WebGlRenderingContext::FLOAT,
+      false,
+      0,
+      0,
+    );
+    self.context.enable_vertex_attrib_array(POSITION_LOCATION);
+  }
+
+  fn uniform_location(&mut self, name: &str) -> Option<WebGlUniformLocation> {
+    if let Some(location) = self.uniforms.get(name) {
+      return location.clone();
+    }
+    let location = self.context.get_uniform_location(&self.program, name);
+    self.uniforms.insert(name.to_string(), location.clone());
+    location
+  }
+}
+
+impl Drop for WebGl1Canvas {
+  fn drop(&mut self) {
+    if let Some((extension, vao)) = &self.vertex_arrays {
+      extension.delete_vertex_array_oes(Some(vao));
+    }
+    self.context.delete_buffer(Some(&self.vertex_buffer));
+    self.context.delete_program(Some(&self.program));
+  }
+}
+
+// Rewrites a GLSL ES 3.00 shader of the given stage to GLSL ES 1.00, as far
+// as the two overlap: `in` and `out` declarations become `attribute` and
+// `varying`, the fragment output becomes `gl_FragColor`, `texture` becomes
+// `texture2D` and `layout` qualifiers are dropped. Lines are kept where they
+// were, so compile errors point into the original source. Anything else
+// 3.00 only, such as integer textures or `flat`, is left for the compiler to
+// reject. Sources without `#version 300 es` are taken to be 1.00 already.
+pub(crate) fn to_glsl_100(src: &str, stage: u32) -> Result<String> {
+  let version = src.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with("//"));
+  match version {
+    Some(line) if line.starts_with("#version") => {
+      let number = line["#version".len()..].trim();
+      if number == "100" {
+        return Ok(src.to_string());
+      }
+      if number != "300 es" {
+        return Err(GestaltError::InvalidArgument(format!("cannot run GLSL version {} on WebGL1", number)));
+      }
+    }
+    _ => return Ok(src.to_string()),
+  }
+
+  let vertex = stage == WebGlRenderingContext::VERTEX_SHADER;
+  let mut output: Option<String> = None;
+  let mut lines = Vec::new();
+  for line in src.lines() {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let mut code = line.trim_start();
+    if code.starts_with("#version") {
+      lines.push(String::new());
+      continue;
+    }
+    if code.starts_with("layout") {
+      if let Some(end) = code.find(')') {
+        code = code[end + 1..].trim_start();
+      }
+    }
+
+    if let Some(declaration) = code.strip_prefix("in ") {
+      let qualifier = if vertex { "attribute" } else { "varying" };
+      lines.push(format!("{}{} {}", indent, qualifier, declaration));
+    } else if let Some(declaration) = code.strip_prefix("out ") {
+      if vertex {
+        lines.push(format!("{}varying {}", indent, declaration));
+        continue;
+      }
+      // `out vec4 name;`, which WebGL1 only has as `gl_FragColor`.
+      let name = declaration.trim_end().trim_end_matches(';').split_whitespace().last().unwrap_or("");
+      if output.is_some() {
+        return Err(GestaltError::InvalidArgument(String::from(
+          "WebGL1 fragment shaders have a single output",
+        )));
+      }
+      output = Some(name.to_string());
+      lines.push(String::new());
+    } else {
+      lines.push(format!("{}{}", indent, code));
+    }
+  }
+
+  let mut translated = replace_identifier(&lines.join("\n"), "texture", "texture2D");
+  if let Some(output) = output {
+    translated = replace_identifier(&translated, &output, "gl_FragColor");
+  }
+  Ok(translated)
+}
+
+// Replaces `from` wherever it stands as a whole identifier.
+fn replace_identifier(src: &str, from: &str, to: &str) -> String {
+  let is_identifier = |c: char| c.is_ascii_alphanumeric() || c == '_';
+  let mut replaced = String::with_capacity(src.len());
+  let mut rest = src;
+  while let Some(index) = rest.find(from) {
+    replaced.push_str(&rest[..index]);
+    rest = &rest[index + from.len()..];
+    let joined = replaced.chars().next_back().is_some_and(is_identifier)
+      || rest.chars().next().is_some_and(is_identifier);
+    replaced.push_str(if joined { from } else { to });
+  }
+  replaced.push_str(rest);
+  replaced
+}
+
+fn compile(context: &WebGlRenderingContext, stage: u32, source: &str) -> Result<WebGlShader> {
+  let shader = context
+    .create_shader(stage)
+    .ok_or(GestaltError::ResourceCreation("shader object"))?;
+  context.shader_source(&shader, source);
+  context.compile_shader(&shader);
+
+  if context
+    .get_shader_parameter(&shader, WebGlRenderingContext::COMPILE_STATUS)
+    .as_bool()
+    .unwrap_or(false)
+  {
+    return Ok(shader);
+  }
+  let log = context
+    .get_shader_info_log(&shader)
+    .unwrap_or_else(|| String::from("Unknown error creating shader"));
+  context.delete_shader(Some(&shader));
+  Err(GestaltError::ShaderCompile {
+    stage: if stage == WebGlRenderingContext::VERTEX_SHADER { "vertex" } else { "fragment" },
+    diagnostics: parse_info_log(&log),
+    log,
+  })
+}
+
+// Links the shaders into a program left in use, with `position` bound to
+// `POSITION_LOCATION` as in `WebGlCanvas`.
+fn link(context: &WebGlRenderingContext, vert_src: &str, frag_src: &str) -> Result<WebGlProgram> {
+  let vert_shader = compile(context, WebGlRenderingContext::VERTEX_SHADER, vert_src)?;
+  let frag_shader = compile(context, WebGlRenderingContext::FRAGMENT_SHADER, frag_src)?;
+  let program = context
+    .create_program()
+    .ok_or(GestaltError::ResourceCreation("program object"))?;
+  context.attach_shader(&program, &vert_shader);
+  context.attach_shader(&program, &frag_shader);
+  context.bind_attrib_location(&program, POSITION_LOCATION, "position");
+  context.link_program(&program);
+  context.delete_shader(Some(&vert_shader));
+  context.delete_shader(Some(&frag_shader));
+
+  if !context
+    .get_program_parameter(&program, WebGlRenderingContext::LINK_STATUS)
+    .as_bool()
+    .unwrap_or(false)
+  {
+    let log = context
+      .get_program_info_log(&program)
+      .unwrap_or_else(|| String::from("Unknown error creating program object"));
+    context.delete_program(Some(&program));
+    return Err(GestaltError::ProgramLink(log));
+  }
+  if context.get_attrib_location(&program, "position") < 0 {
+    context.delete_program(Some(&program));
+    return Err(GestaltError::MissingAttribute(String::from("position")));
+  }
+  context.use_program(Some(&program));
+  Ok(program)
+}
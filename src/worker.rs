@@ -0,0 +1,51 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::error::{GestaltError, Result};
+use crate::graphics::document;
+
+// Hands control of the canvas with id `canvas_id` to an `OffscreenCanvas`,
+// to be posted to a worker and drawn with `WebGlCanvas::from_offscreen`.
+// The page can no longer draw on the canvas itself afterwards, and a canvas
+// can only be transferred once.
+#[wasm_bindgen]
+pub fn transfer_canvas_to_offscreen(canvas_id: &str) -> Result<web_sys::OffscreenCanvas> {
+  let canvas = document()?
+    .get_element_by_id(canvas_id)
+    .ok_or_else(|| GestaltError::CanvasNotFound(canvas_id.to_string()))?
+    .dyn_into::<web_sys::HtmlCanvasElement>()
+    .map_err(|_| GestaltError::NotACanvas(canvas_id.to_string()))?;
+  Ok(canvas.transfer_control_to_offscreen()?)
+}
+
+// Where animation frames come from: the window on the page, or the global
+// scope of a dedicated worker, which has `requestAnimationFrame` as well.
+enum FrameSource {
+  Window(web_sys::Window),
+  Worker(web_sys::DedicatedWorkerGlobalScope),
+}
+
+fn frame_source() -> Result<FrameSource> {
+  if let Some(window) = web_sys::window() {
+    return Ok(FrameSource::Window(window));
+  }
+  js_sys::global()
+    .dyn_into::<web_sys::DedicatedWorkerGlobalScope>()
+    .map(FrameSource::Worker)
+    .map_err(|_| GestaltError::NoWindow)
+}
+
+pub(crate) fn request_animation_frame(callback: &js_sys::Function) -> Result<i32> {
+  Ok(match frame_source()? {
+    FrameSource::Window(window) => window.request_animation_frame(callback)?,
+    FrameSource::Worker(scope) => scope.request_animation_frame(callback)?,
+  })
+}
+
+pub(crate) fn cancel_animation_frame(id: i32) {
+  let _ = match frame_source() {
+    Ok(FrameSource::Window(window)) => window.cancel_animation_frame(id),
+    Ok(FrameSource::Worker(scope)) => scope.cancel_animation_frame(id),
+    Err(_) => Ok(()),
+  };
+}